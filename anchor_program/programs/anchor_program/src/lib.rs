@@ -1,101 +1,1838 @@
 #![allow(unexpected_cfgs)]
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::solana_program::program::{invoke, invoke_signed};
+use anchor_lang::solana_program::stake::{
+    self, instruction as stake_instruction,
+    state::{Authorized, Lockup, StakeStateV2},
+};
+use anchor_lang::solana_program::system_instruction;
 use anchor_lang::system_program;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{spl_token_2022, Mint, TokenAccount, TokenInterface},
+};
 
 declare_id!("Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB");
 
+// The heap-usage build, for the benchmarks, replaces the entrypoint #[program] declares
+// with one that also logs the heap each instruction used
+#[cfg(feature = "heap-usage")]
+anchor_lang::solana_program::entrypoint!(entry_with_heap_usage);
+
+#[cfg(feature = "heap-usage")]
+fn entry_with_heap_usage<'info>(
+    program_id: &Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+    data: &[u8],
+) -> anchor_lang::solana_program::entrypoint::ProgramResult {
+    use anchor_lang::solana_program::entrypoint::{HEAP_LENGTH, HEAP_START_ADDRESS};
+
+    let result = entry(program_id, accounts, data);
+
+    // The bump allocator hands out the heap from its end downwards and keeps its
+    // position in the first word, 0 until the first allocation
+    let position = unsafe { *(HEAP_START_ADDRESS as *const usize) };
+    let used = match position {
+        0 => 0,
+        position => HEAP_START_ADDRESS as usize + HEAP_LENGTH - position,
+    };
+    msg!("Heap used: {} bytes", used);
+
+    result
+}
+
 #[program]
 pub mod anchor_program {
     use super::*;
 
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        // Create or update user account data
-        let user_account = &mut ctx.accounts.user_account;
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        process_deposit(ctx, amount, None)
+    }
+
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        process_withdraw(ctx, Some(amount))
+    }
+
+    // Withdraw the whole vault balance, leaving the user account open
+    pub fn withdraw_all(ctx: Context<Withdraw>) -> Result<()> {
+        process_withdraw(ctx, None)
+    }
+
+    pub fn close(ctx: Context<Close>) -> Result<()> {
+        // Withdrawals stay open during a pause unless the admin also paused them
+        let config = read_config(&ctx.accounts.config)?;
+        check_not_paused(&config, true)?;
+
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        // Draining the vault would bypass the timelock, so it must be emptied
+        // through request_withdraw and claim_withdraw first
+        let vault_lamports = ctx.accounts.vault.lamports();
+        require!(
+            ctx.accounts.user_account.withdraw_delay == 0 || vault_lamports == 0,
+            VaultError::VaultTimelocked
+        );
+
+        // Draining the vault counts against the rate limit like any other withdrawal
+        consume_withdraw_limit(&mut ctx.accounts.user_account, vault_lamports)?;
+
+        // Drain whatever is left in the vault back to the user, less the protocol fee
+        // which goes to the treasury
+        if vault_lamports > 0 {
+            let user_key = ctx.accounts.user.key();
+            let seeds = [
+                b"vault".as_ref(),
+                user_key.as_ref(),
+                &[ctx.accounts.user_account.vault_bump],
+            ];
+            let signer_seeds = &[&seeds[..]];
+
+            let net_lamports = collect_fee(
+                ctx.accounts.vault.to_account_info(),
+                ctx.accounts.treasury.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                vault_lamports,
+                config.withdraw_fee_bps,
+                signer_seeds,
+            )?;
+
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.user.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_seeds);
+
+            system_program::transfer(cpi_ctx, net_lamports)?;
+
+            emit!(WithdrawEvent {
+                user: ctx.accounts.user.key(),
+                vault: ctx.accounts.vault.key(),
+                amount: vault_lamports,
+                fee: vault_lamports - net_lamports,
+                vault_balance: ctx.accounts.vault.lamports(),
+                slot: Clock::get()?.slot,
+            });
+        }
+
+        // The `close = user` constraint returns the user account rent
+        msg!("Closed vault, returned {} lamports", vault_lamports);
+
+        Ok(())
+    }
+
+    pub fn deposit_token<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DepositToken<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        // Deposits are blocked while the program is paused
+        let config = read_config(&ctx.accounts.config)?;
+        check_not_paused(&config, false)?;
+
+        // Create or update user account data
+        let user_account = &mut ctx.accounts.user_account;
+
+        // Initialize if this is the first time
+        if user_account.version == 0 {
+            user_account.user = ctx.accounts.user.key();
+            user_account.user_bump = ctx.bumps.user_account;
+            user_account.vault_bump = ctx.bumps.vault;
+            user_account.version = USER_ACCOUNT_VERSION;
+        }
+
+        // Transfer tokens to the vault token account. anchor-spl's transfer_checked
+        // drops remaining accounts, so use the Token-2022 helper which forwards the
+        // transfer hook accounts and works for both token programs.
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program.key,
+            ctx.accounts.user_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            ctx.accounts.mint.decimals,
+            &[],
+        )?;
+
+        // Transfer fees are withheld in the vault token account, so record what
+        // actually arrived rather than the requested amount
+        ctx.accounts.vault_token_account.reload()?;
+        let vault_balance = ctx.accounts.vault_token_account.amount;
+        let received = vault_balance
+            .checked_sub(balance_before)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        msg!(
+            "Deposited {} tokens to vault ({} requested)",
+            received,
+            amount
+        );
+
+        emit!(TokenDepositEvent {
+            user: ctx.accounts.user.key(),
+            vault: ctx.accounts.vault.key(),
+            mint: ctx.accounts.mint.key(),
+            amount,
+            received,
+            vault_balance,
+            slot: Clock::get()?.slot,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw_token<'info>(
+        ctx: Context<'_, '_, 'info, 'info, WithdrawToken<'info>>,
+        amount: u64,
+        signer_count: u8,
+    ) -> Result<()> {
+        // The first signer_count remaining accounts are multisig members, the rest are
+        // passed on to the token program for a transfer hook
+        require!(
+            ctx.remaining_accounts.len() >= signer_count as usize,
+            ErrorCode::AccountNotEnoughKeys
+        );
+        let (multisig_signers, transfer_hook_accounts) =
+            ctx.remaining_accounts.split_at(signer_count as usize);
+
+        // Withdrawals stay open during a pause unless the admin also paused them
+        let config = read_config(&ctx.accounts.config)?;
+        check_not_paused(&config, true)?;
+
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            multisig_signers,
+        )?;
+
+        // Check if the vault token account holds enough tokens
+        require!(
+            ctx.accounts.vault_token_account.amount >= amount,
+            VaultError::InsufficientFunds
+        );
+
+        // Create the vault signer seeds
+        let user_key = ctx.accounts.user.key();
+        let seeds = [
+            b"vault".as_ref(),
+            user_key.as_ref(),
+            &[ctx.accounts.user_account.vault_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        // Transfer tokens from the vault to the user via CPI with signer seeds
+        spl_token_2022::onchain::invoke_transfer_checked(
+            ctx.accounts.token_program.key,
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.user_token_account.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            transfer_hook_accounts,
+            amount,
+            ctx.accounts.mint.decimals,
+            signer_seeds,
+        )?;
+
+        msg!("Withdrew {} tokens from vault", amount);
+
+        Ok(())
+    }
+
+    pub fn deposit_with_delay(
+        ctx: Context<Deposit>,
+        amount: u64,
+        withdraw_delay: i64,
+    ) -> Result<()> {
+        process_deposit(ctx, amount, Some(withdraw_delay))
+    }
+
+    pub fn request_withdraw(ctx: Context<RequestWithdraw>, amount: u64) -> Result<()> {
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        let user_account = &mut ctx.accounts.user_account;
+
+        require!(amount > 0, VaultError::InvalidAmount);
+        // Only one withdrawal can be pending at a time
+        require!(
+            user_account.pending_withdrawal == 0,
+            VaultError::WithdrawalAlreadyPending
+        );
+
+        // Start the timelock from the current cluster time
+        let clock = Clock::get()?;
+        user_account.pending_withdrawal = amount;
+        user_account.unlock_timestamp = clock
+            .unix_timestamp
+            .checked_add(user_account.withdraw_delay)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        msg!(
+            "Requested withdrawal of {} lamports, unlocks at {}",
+            amount,
+            user_account.unlock_timestamp
+        );
+
+        Ok(())
+    }
+
+    pub fn claim_withdraw(ctx: Context<ClaimWithdraw>) -> Result<()> {
+        // Withdrawals stay open during a pause unless the admin also paused them
+        let config = read_config(&ctx.accounts.config)?;
+        check_not_paused(&config, true)?;
+
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        let amount = ctx.accounts.user_account.pending_withdrawal;
+        require!(amount > 0, VaultError::NoPendingWithdrawal);
+
+        // The timelock must have expired
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp >= ctx.accounts.user_account.unlock_timestamp,
+            VaultError::WithdrawalStillLocked
+        );
+        require!(
+            ctx.accounts.vault.lamports() >= amount,
+            VaultError::InsufficientFunds
+        );
+        check_vault_remainder(ctx.accounts.vault.lamports() - amount)?;
+
+        // Clear the pending withdrawal and spend from the rate limit before moving funds
+        let user_account = &mut ctx.accounts.user_account;
+        consume_withdraw_limit(user_account, amount)?;
+        record_withdraw(user_account, amount)?;
+        user_account.pending_withdrawal = 0;
+        user_account.unlock_timestamp = 0;
+
+        // Create the vault signer seeds
+        let user_key = ctx.accounts.user.key();
+        let seeds = [
+            b"vault".as_ref(),
+            user_key.as_ref(),
+            &[ctx.accounts.user_account.vault_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        // Route the protocol fee to the treasury with a second signed transfer
+        let net_amount = collect_fee(
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            amount,
+            config.withdraw_fee_bps,
+            signer_seeds,
+        )?;
+
+        // Transfer the rest from the vault to the user via CPI with signer seeds
+        let cpi_accounts = system_program::Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.system_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_seeds);
+
+        system_program::transfer(cpi_ctx, net_amount)?;
+
+        msg!(
+            "Claimed {} lamports from vault, {} lamports fee",
+            net_amount,
+            amount - net_amount
+        );
+
+        emit!(WithdrawEvent {
+            user: ctx.accounts.user.key(),
+            vault: ctx.accounts.vault.key(),
+            amount,
+            fee: amount - net_amount,
+            vault_balance: ctx.accounts.vault.lamports(),
+            slot: Clock::get()?.slot,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_withdraw(ctx: Context<CancelWithdraw>) -> Result<()> {
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        let user_account = &mut ctx.accounts.user_account;
+        require!(
+            user_account.pending_withdrawal > 0,
+            VaultError::NoPendingWithdrawal
+        );
+
+        user_account.pending_withdrawal = 0;
+        user_account.unlock_timestamp = 0;
+
+        msg!("Cancelled pending withdrawal");
+
+        Ok(())
+    }
+
+    pub fn set_multisig(
+        ctx: Context<SetMultisig>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        // Membership changes need the same approval as a withdrawal
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        // An empty signer set with a zero threshold hands control back to the user
+        require!(
+            signers.len() <= MAX_MULTISIG_SIGNERS,
+            VaultError::InvalidMultisig
+        );
+        require!(
+            (threshold == 0) == signers.is_empty() && threshold as usize <= signers.len(),
+            VaultError::InvalidMultisig
+        );
+        for (i, signer) in signers.iter().enumerate() {
+            require!(!signers[..i].contains(signer), VaultError::InvalidMultisig);
+        }
+
+        // The `realloc` constraint has already sized the account for the new signer set
+        let user_account = &mut ctx.accounts.user_account;
+        user_account.signers = signers;
+        user_account.threshold = threshold;
+
+        msg!(
+            "Vault now requires {} of {} signers",
+            user_account.threshold,
+            user_account.signers.len()
+        );
+
+        Ok(())
+    }
+
+    pub fn set_withdraw_authority(
+        ctx: Context<SetWithdrawAuthority>,
+        authority: Pubkey,
+        allowance: u64,
+    ) -> Result<()> {
+        // Delegating needs the same approval as a withdrawal
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        require!(
+            authority != Pubkey::default(),
+            VaultError::InvalidWithdrawAuthority
+        );
+
+        // Replacing the delegate also resets its allowance, u64::MAX leaves it uncapped
+        let user_account = &mut ctx.accounts.user_account;
+        user_account.withdraw_authority = authority;
+        user_account.withdraw_allowance = allowance;
+
+        msg!(
+            "Delegated withdrawals of up to {} lamports to {}",
+            allowance,
+            authority
+        );
+
+        Ok(())
+    }
+
+    pub fn revoke_withdraw_authority(ctx: Context<RevokeWithdrawAuthority>) -> Result<()> {
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        let user_account = &mut ctx.accounts.user_account;
+        require!(
+            user_account.withdraw_authority != Pubkey::default(),
+            VaultError::InvalidWithdrawAuthority
+        );
+
+        user_account.withdraw_authority = Pubkey::default();
+        user_account.withdraw_allowance = 0;
+
+        msg!("Revoked withdraw authority");
+
+        Ok(())
+    }
+
+    pub fn delegate_withdraw(ctx: Context<DelegateWithdraw>, amount: u64) -> Result<()> {
+        // Withdrawals stay open during a pause unless the admin also paused them
+        let config = read_config(&ctx.accounts.config)?;
+        check_not_paused(&config, true)?;
+
+        let user_account = &mut ctx.accounts.user_account;
+
+        // Timelocked vaults must go through request_withdraw and claim_withdraw
+        require!(
+            user_account.withdraw_delay == 0,
+            VaultError::VaultTimelocked
+        );
+        require!(
+            ctx.accounts.vault.lamports() >= amount,
+            VaultError::InsufficientFunds
+        );
+        check_vault_remainder(ctx.accounts.vault.lamports() - amount)?;
+
+        // Spend from the allowance unless the delegate is uncapped
+        if user_account.withdraw_allowance != u64::MAX {
+            require!(
+                amount <= user_account.withdraw_allowance,
+                VaultError::AllowanceExceeded
+            );
+            user_account.withdraw_allowance -= amount;
+        }
+
+        // Delegated withdrawals share the vault's rate limit
+        consume_withdraw_limit(user_account, amount)?;
+        record_withdraw(user_account, amount)?;
+
+        // The vault is still seeded on the depositing user
+        let user_key = ctx.accounts.user.key();
+        let seeds = [
+            b"vault".as_ref(),
+            user_key.as_ref(),
+            &[ctx.accounts.user_account.vault_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        // Route the protocol fee to the treasury with a second signed transfer
+        let net_amount = collect_fee(
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            amount,
+            config.withdraw_fee_bps,
+            signer_seeds,
+        )?;
+
+        // Transfer the rest from the vault to the delegate via CPI with signer seeds
+        let cpi_accounts = system_program::Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.system_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_seeds);
+
+        system_program::transfer(cpi_ctx, net_amount)?;
+
+        msg!(
+            "Delegate withdrew {} lamports from vault, {} lamports fee",
+            net_amount,
+            amount - net_amount
+        );
+
+        emit!(WithdrawEvent {
+            user: ctx.accounts.user.key(),
+            vault: ctx.accounts.vault.key(),
+            amount,
+            fee: amount - net_amount,
+            vault_balance: ctx.accounts.vault.lamports(),
+            slot: Clock::get()?.slot,
+        });
+
+        Ok(())
+    }
+
+    pub fn deposit_for(ctx: Context<DepositFor>, beneficiary: Pubkey, amount: u64) -> Result<()> {
+        // Deposits are blocked while the program is paused
+        let config = read_config(&ctx.accounts.config)?;
+        check_not_paused(&config, false)?;
+
+        // Create or update the beneficiary's account data, paid for by the funder
+        let user_account = &mut ctx.accounts.user_account;
+
+        // Initialize if this is the first time
+        if user_account.version == 0 {
+            user_account.user = beneficiary;
+            user_account.user_bump = ctx.bumps.user_account;
+            user_account.vault_bump = ctx.bumps.vault;
+            user_account.version = USER_ACCOUNT_VERSION;
+        }
+
+        // Route the protocol fee to the treasury with a second transfer
+        let net_amount = collect_fee(
+            ctx.accounts.funder.to_account_info(),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            amount,
+            config.deposit_fee_bps,
+            &[],
+        )?;
+
+        // Transfer the rest from the funder to the beneficiary's vault
+        let cpi_accounts = system_program::Transfer {
+            from: ctx.accounts.funder.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.system_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        system_program::transfer(cpi_ctx, net_amount)?;
+        record_deposit(&mut ctx.accounts.user_account, net_amount)?;
+        msg!(
+            "Deposited {} lamports to vault of {}, {} lamports fee",
+            net_amount,
+            beneficiary,
+            amount - net_amount
+        );
+
+        emit!(DepositEvent {
+            user: beneficiary,
+            vault: ctx.accounts.vault.key(),
+            amount,
+            fee: amount - net_amount,
+            vault_balance: ctx.accounts.vault.lamports(),
+            slot: Clock::get()?.slot,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_withdraw_limit(
+        ctx: Context<SetWithdrawLimit>,
+        limit: u64,
+        window: i64,
+    ) -> Result<()> {
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        // A limit needs a positive window, and no limit needs no window
+        require!(
+            (limit == 0 && window == 0) || (limit > 0 && window > 0),
+            VaultError::InvalidWithdrawLimit
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let user_account = &mut ctx.accounts.user_account;
+        apply_pending_withdraw_limit(user_account, now);
+
+        // A lower or equal limit over an equal or longer window never allows more
+        let tightens = limit > 0
+            && (user_account.withdraw_limit == 0 || limit <= user_account.withdraw_limit)
+            && window >= user_account.withdraw_window;
+
+        if tightens {
+            // Also drops any pending raise
+            user_account.withdraw_limit = limit;
+            user_account.withdraw_window = window;
+            user_account.pending_withdraw_limit = 0;
+            user_account.pending_withdraw_window = 0;
+            user_account.limit_unlock_timestamp = 0;
+            msg!(
+                "Withdraw limit set to {} lamports per {} seconds",
+                limit,
+                window
+            );
+        } else {
+            // Wait out a full window so a stolen key cannot lift the limit and drain at once
+            user_account.pending_withdraw_limit = limit;
+            user_account.pending_withdraw_window = window;
+            user_account.limit_unlock_timestamp = now
+                .checked_add(user_account.withdraw_window)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            msg!(
+                "Withdraw limit of {} lamports per {} seconds applies at {}",
+                limit,
+                window,
+                user_account.limit_unlock_timestamp
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn initialize_config(ctx: Context<InitializeConfig>, admin: Pubkey) -> Result<()> {
+        // The account constraints already checked the upgrade authority
+        require!(admin != Pubkey::default(), VaultError::InvalidAdmin);
+
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.paused = false;
+        config.withdrawals_paused = false;
+        config.bump = ctx.bumps.config;
+
+        // Fees start disabled, the treasury bump is stored so later checks stay cheap
+        let (_, treasury_bump) = Pubkey::find_program_address(&[b"treasury"], ctx.program_id);
+        config.treasury_bump = treasury_bump;
+        config.deposit_fee_bps = 0;
+        config.withdraw_fee_bps = 0;
+
+        msg!("Initialized config with admin {}", admin);
+
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<SetPaused>, deposits: bool, withdrawals: bool) -> Result<()> {
+        // Withdrawals can only be paused on top of deposits, never on their own
+        require!(deposits || !withdrawals, VaultError::InvalidPauseFlags);
+
+        let config = &mut ctx.accounts.config;
+        config.paused = deposits;
+        config.withdrawals_paused = withdrawals;
+
+        msg!(
+            "Set deposits paused to {}, withdrawals paused to {}",
+            deposits,
+            withdrawals
+        );
+
+        Ok(())
+    }
+
+    pub fn transfer_admin(ctx: Context<TransferAdmin>, new_admin: Pubkey) -> Result<()> {
+        require!(new_admin != Pubkey::default(), VaultError::InvalidAdmin);
+
+        ctx.accounts.config.admin = new_admin;
+
+        msg!("Transferred admin to {}", new_admin);
+
+        Ok(())
+    }
+
+    pub fn set_fees(ctx: Context<SetFees>, deposit_bps: u16, withdraw_bps: u16) -> Result<()> {
+        require!(
+            deposit_bps <= MAX_FEE_BPS && withdraw_bps <= MAX_FEE_BPS,
+            VaultError::InvalidFee
+        );
+
+        // Top the treasury up to the rent-exempt minimum, so that fees smaller than
+        // that can still be paid into it
+        let rent_lamports = Rent::get()?.minimum_balance(0);
+        let treasury_lamports = ctx.accounts.treasury.lamports();
+        if treasury_lamports < rent_lamports {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.admin.to_account_info(),
+                to: ctx.accounts.treasury.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+            system_program::transfer(cpi_ctx, rent_lamports - treasury_lamports)?;
+        }
+
+        let config = &mut ctx.accounts.config;
+        config.deposit_fee_bps = deposit_bps;
+        config.withdraw_fee_bps = withdraw_bps;
+
+        msg!(
+            "Set deposit fee to {} bps and withdraw fee to {} bps",
+            deposit_bps,
+            withdraw_bps
+        );
+
+        Ok(())
+    }
+
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+        // The treasury keeps its rent-exempt minimum so it can keep receiving fees
+        let rent_lamports = Rent::get()?.minimum_balance(0);
+        let available = ctx
+            .accounts
+            .treasury
+            .lamports()
+            .saturating_sub(rent_lamports);
+        require!(amount <= available, VaultError::InsufficientFees);
+
+        let seeds = [b"treasury".as_ref(), &[ctx.accounts.config.treasury_bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = system_program::Transfer {
+            from: ctx.accounts.treasury.to_account_info(),
+            to: ctx.accounts.admin.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.system_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_seeds);
+
+        system_program::transfer(cpi_ctx, amount)?;
+
+        msg!("Withdrew {} lamports of fees", amount);
+
+        Ok(())
+    }
+
+    pub fn migrate(ctx: Context<Migrate>) -> Result<()> {
+        // The realloc constraint has already grown a baseline account to the current
+        // size, with the user covering the extra rent. It was read with every later field
+        // zero, so writing it back at the current version rewrites it in the current
+        // layout, with the new fields and the totals starting from zero.
+        let user_account = &mut ctx.accounts.user_account;
+        if user_account.version == USER_ACCOUNT_VERSION {
+            msg!(
+                "User account is already at version {}",
+                USER_ACCOUNT_VERSION
+            );
+            return Ok(());
+        }
+
+        user_account.version = USER_ACCOUNT_VERSION;
+
+        msg!("Migrated user account to version {}", USER_ACCOUNT_VERSION);
+
+        Ok(())
+    }
+
+    pub fn delegate_stake(ctx: Context<DelegateStake>, amount: u64) -> Result<()> {
+        // Staking moves lamports out of the vault, so it stops whenever the program is paused
+        let config = read_config(&ctx.accounts.config)?;
+        check_not_paused(&config, false)?;
+
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        // A vault stakes through a single account, which must be withdrawn before the
+        // next delegation
+        require!(
+            ctx.accounts.stake.data_is_empty(),
+            VaultError::StakeAccountInUse
+        );
+
+        require!(amount > 0, VaultError::InvalidAmount);
+
+        // Lamports requested for a pending withdrawal stay in the vault
+        let vault_lamports = ctx.accounts.vault.lamports();
+        require!(
+            vault_lamports.saturating_sub(ctx.accounts.user_account.pending_withdrawal) >= amount,
+            VaultError::InsufficientFunds
+        );
+        check_vault_remainder(vault_lamports - amount)?;
+
+        let vault = ctx.accounts.vault.to_account_info();
+        let stake = ctx.accounts.stake.to_account_info();
+        let user_key = ctx.accounts.user.key();
+        let vault_seeds: &[&[u8]] = &[
+            b"vault",
+            user_key.as_ref(),
+            &[ctx.accounts.user_account.vault_bump],
+        ];
+        let stake_seeds: &[&[u8]] = &[b"stake", vault.key.as_ref(), &[ctx.bumps.stake]];
+
+        // Create the stake account with the lamports from the vault. The stake program
+        // keeps its rent-exempt reserve out of the delegation.
+        let system_program = ctx.accounts.system_program.to_account_info();
+        if stake.lamports() == 0 {
+            invoke_signed(
+                &system_instruction::create_account(
+                    vault.key,
+                    stake.key,
+                    amount,
+                    StakeStateV2::size_of() as u64,
+                    &stake::program::ID,
+                ),
+                &[vault.clone(), stake.clone(), system_program.clone()],
+                &[vault_seeds, stake_seeds],
+            )?;
+        } else {
+            // Anyone can send lamports to the predictable address, which would make
+            // create_account fail forever. Top it up and take it over step by step instead.
+            invoke_signed(
+                &system_instruction::transfer(vault.key, stake.key, amount),
+                &[vault.clone(), stake.clone(), system_program.clone()],
+                &[vault_seeds],
+            )?;
+            invoke_signed(
+                &system_instruction::allocate(stake.key, StakeStateV2::size_of() as u64),
+                &[stake.clone(), system_program.clone()],
+                &[stake_seeds],
+            )?;
+            invoke_signed(
+                &system_instruction::assign(stake.key, &stake::program::ID),
+                &[stake.clone(), system_program.clone()],
+                &[stake_seeds],
+            )?;
+        }
+
+        invoke(
+            &stake_instruction::initialize(
+                stake.key,
+                &Authorized {
+                    staker: vault.key(),
+                    withdrawer: vault.key(),
+                },
+                &Lockup::default(),
+            ),
+            &[
+                stake.clone(),
+                ctx.accounts.rent.to_account_info(),
+                ctx.accounts.stake_program.to_account_info(),
+            ],
+        )?;
+
+        invoke_signed(
+            &stake_instruction::delegate_stake(stake.key, vault.key, ctx.accounts.vote.key),
+            &[
+                stake.clone(),
+                ctx.accounts.vote.to_account_info(),
+                ctx.accounts.clock.to_account_info(),
+                ctx.accounts.stake_history.to_account_info(),
+                ctx.accounts.stake_config.to_account_info(),
+                vault.clone(),
+                ctx.accounts.stake_program.to_account_info(),
+            ],
+            &[vault_seeds],
+        )?;
+
+        msg!(
+            "Delegated {} lamports from vault to {}",
+            amount,
+            ctx.accounts.vote.key()
+        );
+
+        Ok(())
+    }
+
+    // The stake cools down over the following epochs and can be withdrawn once inactive
+    pub fn deactivate_stake(ctx: Context<DeactivateStake>) -> Result<()> {
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        let user_key = ctx.accounts.user.key();
+        invoke_signed(
+            &stake_instruction::deactivate_stake(ctx.accounts.stake.key, ctx.accounts.vault.key),
+            &[
+                ctx.accounts.stake.to_account_info(),
+                ctx.accounts.clock.to_account_info(),
+                ctx.accounts.vault.to_account_info(),
+                ctx.accounts.stake_program.to_account_info(),
+            ],
+            &[&[
+                b"vault",
+                user_key.as_ref(),
+                &[ctx.accounts.user_account.vault_bump],
+            ]],
+        )?;
+
+        msg!("Deactivated vault stake");
+
+        Ok(())
+    }
+
+    // Return the whole stake account balance, rewards included, to the vault. The stake
+    // program deletes the emptied account, so the vault can delegate again afterwards.
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+        // The owner, or enough multisig members, must approve
+        check_authority(
+            &ctx.accounts.user_account,
+            &ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+        )?;
+
+        let stake_lamports = ctx.accounts.stake.lamports();
+        require!(stake_lamports > 0, VaultError::InsufficientFunds);
+
+        let user_key = ctx.accounts.user.key();
+        invoke_signed(
+            &stake_instruction::withdraw(
+                ctx.accounts.stake.key,
+                ctx.accounts.vault.key,
+                ctx.accounts.vault.key,
+                stake_lamports,
+                None,
+            ),
+            &[
+                ctx.accounts.stake.to_account_info(),
+                ctx.accounts.vault.to_account_info(),
+                ctx.accounts.clock.to_account_info(),
+                ctx.accounts.stake_history.to_account_info(),
+                ctx.accounts.stake_program.to_account_info(),
+            ],
+            &[&[
+                b"vault",
+                user_key.as_ref(),
+                &[ctx.accounts.user_account.vault_bump],
+            ]],
+        )?;
+
+        msg!("Withdrew {} lamports of stake to vault", stake_lamports);
+
+        Ok(())
+    }
+}
+
+// Layout version written for new accounts and by migrate
+const USER_ACCOUNT_VERSION: u8 = 2;
+
+// Accounts created before the layout was versioned hold their always-true
+// is_initialized flag in the place of the version, so read as version 1
+const BASELINE_USER_ACCOUNT_VERSION: u8 = 1;
+
+// Baseline accounts after the discriminator: user + user_bump + vault_bump
+// + is_initialized
+const USER_ACCOUNT_BASELINE_SIZE: usize = 32 + 1 + 1 + 1;
+
+// Upper bound on multisig members, keeps the account small and signer checks cheap
+const MAX_MULTISIG_SIGNERS: usize = 10;
+
+// Upper bound on protocol fees, so a compromised admin cannot take a whole deposit
+const MAX_FEE_BPS: u16 = 1_000;
+
+// Basis points in one whole
+const BPS_DENOMINATOR: u128 = 10_000;
+
+// Check that the user signed, or that enough distinct multisig members signed
+fn check_authority(
+    user_account: &UserAccount,
+    user: &AccountInfo,
+    remaining_accounts: &[AccountInfo],
+) -> Result<()> {
+    if user_account.threshold == 0 {
+        require!(user.is_signer, ErrorCode::AccountNotSigner);
+        return Ok(());
+    }
+
+    // Count each member once, no matter how often its account is repeated
+    let signer_keys = remaining_accounts
+        .iter()
+        .filter(|account_info| account_info.is_signer)
+        .map(|account_info| account_info.key)
+        .chain(user.is_signer.then_some(user.key));
+    let mut approvals: Vec<&Pubkey> = Vec::with_capacity(user_account.signers.len());
+    for key in signer_keys {
+        if user_account.signers.contains(key) && !approvals.contains(&key) {
+            approvals.push(key);
+        }
+    }
+
+    require!(
+        approvals.len() >= user_account.threshold as usize,
+        VaultError::NotEnoughSigners
+    );
+
+    Ok(())
+}
+
+// Promote a pending withdraw limit once its delay has passed
+fn apply_pending_withdraw_limit(user_account: &mut UserAccount, now: i64) {
+    if user_account.limit_unlock_timestamp != 0 && now >= user_account.limit_unlock_timestamp {
+        user_account.withdraw_limit = user_account.pending_withdraw_limit;
+        user_account.withdraw_window = user_account.pending_withdraw_window;
+        user_account.pending_withdraw_limit = 0;
+        user_account.pending_withdraw_window = 0;
+        user_account.limit_unlock_timestamp = 0;
+    }
+}
+
+// Record a withdrawal against the rate limit, starting a new window when the current one
+// has elapsed
+fn consume_withdraw_limit(user_account: &mut UserAccount, amount: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    apply_pending_withdraw_limit(user_account, now);
+
+    if user_account.withdraw_limit == 0 {
+        return Ok(());
+    }
+
+    if now
+        >= user_account
+            .window_start
+            .saturating_add(user_account.withdraw_window)
+    {
+        user_account.window_start = now;
+        user_account.window_withdrawn = 0;
+    }
+
+    let remaining = user_account
+        .withdraw_limit
+        .saturating_sub(user_account.window_withdrawn);
+    require!(amount <= remaining, VaultError::WithdrawLimitExceeded);
+    user_account.window_withdrawn += amount;
+
+    Ok(())
+}
+
+// Count a deposit of lamports credited to the vault in the lifetime totals. Baseline
+// accounts are written back without them until they are migrated.
+fn record_deposit(user_account: &mut UserAccount, amount: u64) -> Result<()> {
+    let totals = &mut user_account.totals;
+    totals.total_deposited = totals
+        .total_deposited
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    totals.deposit_count = totals
+        .deposit_count
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    totals.last_deposit_slot = Clock::get()?.slot;
+
+    Ok(())
+}
+
+// Count lamports taken out of the vault in the lifetime totals
+fn record_withdraw(user_account: &mut UserAccount, amount: u64) -> Result<()> {
+    let totals = &mut user_account.totals;
+    totals.total_withdrawn = totals
+        .total_withdrawn
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    totals.last_withdraw_slot = Clock::get()?.slot;
+
+    Ok(())
+}
+
+// Space of a user account on a deposit. init_if_needed requires an existing account to
+// have exactly this size, which differs with the layout and the multisig members.
+fn user_account_space(user_account: &AccountInfo) -> usize {
+    if user_account.owner == &crate::ID {
+        user_account.data_len()
+    } else {
+        8 + UserAccount::INIT_SPACE
+    }
+}
+
+// Read the config from its PDA. Until the config is initialized the program runs
+// unpaused and without fees.
+fn read_config(config: &AccountInfo) -> Result<Config> {
+    if config.owner != &crate::ID {
+        return Ok(Config {
+            admin: Pubkey::default(),
+            paused: false,
+            withdrawals_paused: false,
+            bump: 0,
+            treasury_bump: 0,
+            deposit_fee_bps: 0,
+            withdraw_fee_bps: 0,
+        });
+    }
+
+    Config::try_deserialize(&mut &config.try_borrow_data()?[..])
+}
+
+// Reject deposits, or withdrawals when those are paused too
+fn check_not_paused(config: &Config, is_withdrawal: bool) -> Result<()> {
+    require!(
+        !config.paused || (is_withdrawal && !config.withdrawals_paused),
+        VaultError::ProgramPaused
+    );
+
+    Ok(())
+}
+
+// Fee on an amount in basis points, rounded down so users are never overcharged
+fn calculate_fee(amount: u64, fee_bps: u16) -> Result<u64> {
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        / BPS_DENOMINATOR;
+    Ok(u64::try_from(fee).map_err(|_| ProgramError::ArithmeticOverflow)?)
+}
+
+// Transfer the fee on an amount from the payer to the treasury and return what is
+// left. Pass the payer's seeds when it is a PDA, or no seeds when it signed itself.
+fn collect_fee<'info>(
+    from: AccountInfo<'info>,
+    treasury: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    amount: u64,
+    fee_bps: u16,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<u64> {
+    let fee = calculate_fee(amount, fee_bps)?;
+    if fee == 0 {
+        return Ok(amount);
+    }
+
+    let cpi_accounts = system_program::Transfer { from, to: treasury };
+    let cpi_ctx = CpiContext::new(system_program, cpi_accounts).with_signer(signer_seeds);
+
+    system_program::transfer(cpi_ctx, fee)?;
+
+    Ok(amount - fee)
+}
+
+// Shared by deposit and deposit_with_delay; the delay can only be chosen on first deposit
+fn process_deposit(ctx: Context<Deposit>, amount: u64, withdraw_delay: Option<i64>) -> Result<()> {
+    // Deposits are blocked while the program is paused
+    let config = read_config(&ctx.accounts.config)?;
+    check_not_paused(&config, false)?;
+
+    // A negative delay would unlock withdrawals in the past
+    require!(
+        withdraw_delay.unwrap_or(0) >= 0,
+        VaultError::InvalidWithdrawDelay
+    );
+
+    // Create or update user account data
+    let user_account = &mut ctx.accounts.user_account;
+
+    // Initialize if this is the first time
+    if user_account.version == 0 {
+        user_account.user = ctx.accounts.user.key();
+        user_account.user_bump = ctx.bumps.user_account;
+        user_account.vault_bump = ctx.bumps.vault;
+        user_account.version = USER_ACCOUNT_VERSION;
+        user_account.withdraw_delay = withdraw_delay.unwrap_or(0);
+    } else if let Some(withdraw_delay) = withdraw_delay {
+        // The delay is fixed at first deposit
+        require!(
+            user_account.withdraw_delay == withdraw_delay,
+            VaultError::InvalidWithdrawDelay
+        );
+    }
+
+    // Route the protocol fee to the treasury with a second transfer
+    let net_amount = collect_fee(
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.treasury.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+        amount,
+        config.deposit_fee_bps,
+        &[],
+    )?;
+
+    // Transfer the rest to the vault
+    let cpi_accounts = system_program::Transfer {
+        from: ctx.accounts.user.to_account_info(),
+        to: ctx.accounts.vault.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.system_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+    system_program::transfer(cpi_ctx, net_amount)?;
+    record_deposit(&mut ctx.accounts.user_account, net_amount)?;
+    msg!(
+        "Deposited {} lamports to vault, {} lamports fee",
+        net_amount,
+        amount - net_amount
+    );
+
+    emit!(DepositEvent {
+        user: ctx.accounts.user.key(),
+        vault: ctx.accounts.vault.key(),
+        amount,
+        fee: amount - net_amount,
+        vault_balance: ctx.accounts.vault.lamports(),
+        slot: Clock::get()?.slot,
+    });
+
+    Ok(())
+}
+
+// Shared by withdraw and withdraw_all, which passes no amount to take the whole balance
+fn process_withdraw(ctx: Context<Withdraw>, amount: Option<u64>) -> Result<()> {
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(&ctx.accounts.config)?;
+    check_not_paused(&config, true)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        &ctx.accounts.user_account,
+        &ctx.accounts.user.to_account_info(),
+        ctx.remaining_accounts,
+    )?;
+
+    // Timelocked vaults must go through request_withdraw and claim_withdraw
+    require!(
+        ctx.accounts.user_account.withdraw_delay == 0,
+        VaultError::VaultTimelocked
+    );
+
+    // withdraw_all takes whatever is in the vault
+    let vault_lamports = ctx.accounts.vault.lamports();
+    let amount = match amount {
+        Some(amount) => amount,
+        None => {
+            require!(vault_lamports > 0, VaultError::InsufficientFunds);
+            vault_lamports
+        }
+    };
+
+    // Check if the vault has enough lamports
+    require!(vault_lamports >= amount, VaultError::InsufficientFunds);
+    check_vault_remainder(vault_lamports - amount)?;
+
+    // Spend from the rate limit before moving funds
+    consume_withdraw_limit(&mut ctx.accounts.user_account, amount)?;
+    record_withdraw(&mut ctx.accounts.user_account, amount)?;
+
+    // Create the vault signer seeds
+    let user_key = ctx.accounts.user.key();
+    let seeds = [
+        b"vault".as_ref(),
+        user_key.as_ref(),
+        &[ctx.accounts.user_account.vault_bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    // Route the protocol fee to the treasury with a second signed transfer
+    let net_amount = collect_fee(
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.treasury.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+        amount,
+        config.withdraw_fee_bps,
+        signer_seeds,
+    )?;
+
+    // Transfer the rest from the vault to the user via CPI with signer seeds
+    let cpi_accounts = system_program::Transfer {
+        from: ctx.accounts.vault.to_account_info(),
+        to: ctx.accounts.user.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.system_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_seeds);
+
+    system_program::transfer(cpi_ctx, net_amount)?;
+
+    msg!(
+        "Withdrew {} lamports from vault, {} lamports fee",
+        net_amount,
+        amount - net_amount
+    );
+
+    emit!(WithdrawEvent {
+        user: ctx.accounts.user.key(),
+        vault: ctx.accounts.vault.key(),
+        amount,
+        fee: amount - net_amount,
+        vault_balance: ctx.accounts.vault.lamports(),
+        slot: Clock::get()?.slot,
+    });
+
+    Ok(())
+}
+
+// The vault is a system account, so a withdrawal must either drain it or leave it
+// rent-exempt. Anything in between would fail inside the system transfer with an
+// error that does not say how much may be withdrawn.
+fn check_vault_remainder(remaining: u64) -> Result<()> {
+    require!(
+        remaining == 0 || remaining >= Rent::get()?.minimum_balance(0),
+        VaultError::VaultBelowRentExempt
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = user_account_space(user_account),
+        seeds = [user.key().as_ref()],
+        bump
+    )]
+    pub user_account: Account<'info, AnyUserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
+        bump
+    )]
+    pub vault: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct DepositFor<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = funder,
+        space = user_account_space(user_account),
+        seeds = [beneficiary.as_ref()],
+        bump
+    )]
+    pub user_account: Account<'info, AnyUserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", beneficiary.as_ref()],
+        bump
+    )]
+    pub vault: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+    )]
+    pub user_account: Account<'info, AnyUserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
+        bump = user_account.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Close<'info> {
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
+        bump = user_account.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToken<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = user_account_space(user_account),
+        seeds = [user.key().as_ref()],
+        bump
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump
+    )]
+    pub vault: SystemAccount<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = mint,
+        associated_token::authority = vault,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawToken<'info> {
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump = user_account.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = vault,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RequestWithdraw<'info> {
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+}
+
+#[derive(Accounts)]
+pub struct CancelWithdraw<'info> {
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWithdraw<'info> {
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
 
-        // Initialize if this is the first time
-        if !user_account.is_initialized {
-            user_account.user = ctx.accounts.user.key();
-            user_account.user_bump = ctx.bumps.user_account;
-            user_account.vault_bump = ctx.bumps.vault;
-            user_account.is_initialized = true;
-        }
+    #[account(
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
+        bump = user_account.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
 
-        // Transfer lamports to the vault
-        let cpi_accounts = system_program::Transfer {
-            from: ctx.accounts.user.to_account_info(),
-            to: ctx.accounts.vault.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.system_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    pub system_program: Program<'info, System>,
 
-        system_program::transfer(cpi_ctx, amount)?;
-        msg!("Deposited {} lamports to vault", amount);
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
 
-        Ok(())
-    }
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+}
 
-    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-        // Check if the vault has enough lamports
-        let vault_lamports = ctx.accounts.vault.lamports();
-        require!(vault_lamports >= amount, VaultError::InsufficientFunds);
+#[derive(Accounts)]
+#[instruction(signers: Vec<Pubkey>)]
+pub struct SetMultisig<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
 
-        // Create the vault signer seeds
-        let user_key = ctx.accounts.user.key();
-        let seeds = [
-            b"vault".as_ref(),
-            user_key.as_ref(),
-            &[ctx.accounts.user_account.vault_bump],
-        ];
-        let signer_seeds = &[&seeds[..]];
+    pub user: SystemAccount<'info>,
 
-        // Transfer lamports from the vault to the user via CPI with signer seeds
-        let cpi_accounts = system_program::Transfer {
-            from: ctx.accounts.vault.to_account_info(),
-            to: ctx.accounts.user.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.system_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_seeds);
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+        realloc = UserAccount::size(signers.len()),
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub user_account: Account<'info, UserAccount>,
 
-        system_program::transfer(cpi_ctx, amount)?;
+    pub system_program: Program<'info, System>,
+}
 
-        msg!("Withdrew {} lamports from vault", amount);
+#[derive(Accounts)]
+pub struct SetWithdrawAuthority<'info> {
+    pub user: SystemAccount<'info>,
 
-        Ok(())
-    }
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
 }
 
 #[derive(Accounts)]
-pub struct Deposit<'info> {
+pub struct RevokeWithdrawAuthority<'info> {
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+}
+
+#[derive(Accounts)]
+pub struct DelegateWithdraw<'info> {
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub authority: Signer<'info>,
+
+    pub user: SystemAccount<'info>,
 
     #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + UserAccount::INIT_SPACE,
+        mut,
         seeds = [user.key().as_ref()],
-        bump
+        bump = user_account.user_bump,
+        has_one = user,
+        constraint = user_account.withdraw_authority != Pubkey::default()
+            && user_account.withdraw_authority == authority.key()
+            @ VaultError::InvalidWithdrawAuthority,
     )]
     pub user_account: Account<'info, UserAccount>,
 
     #[account(
         mut,
         seeds = [b"vault", user.key().as_ref()],
-        bump
+        bump = user_account.vault_bump,
     )]
     pub vault: SystemAccount<'info>,
 
     pub system_program: Program<'info, System>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
+pub struct SetWithdrawLimit<'info> {
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub upgrade_authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = upgrade_authority,
+        space = 8 + Config::INIT_SPACE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    // Only the upgrade authority recorded for this program may create the config
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(upgrade_authority.key())
+            @ VaultError::NotUpgradeAuthority,
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct TransferAdmin<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetFees<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury"],
+        bump = config.treasury_bump,
+    )]
+    pub treasury: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury"],
+        bump = config.treasury_bump,
+    )]
+    pub treasury: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Migrate<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    #[account(
+        mut,
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+        realloc = UserAccount::size(user_account.signers.len()),
+        realloc::payer = user,
+        realloc::zero = false,
+    )]
+    pub user_account: Account<'info, AnyUserAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DelegateStake<'info> {
+    pub user: SystemAccount<'info>,
+
     #[account(
         seeds = [user.key().as_ref()],
         bump = user_account.user_bump,
+        has_one = user,
     )]
     pub user_account: Account<'info, UserAccount>,
 
@@ -106,20 +1843,342 @@ pub struct Withdraw<'info> {
     )]
     pub vault: SystemAccount<'info>,
 
+    /// CHECK: the vault's stake account PDA, created and initialized in delegate_stake
+    #[account(mut, seeds = [b"stake", vault.key().as_ref()], bump)]
+    pub stake: UncheckedAccount<'info>,
+
+    /// CHECK: the validator's vote account, checked by the stake program
+    pub vote: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub rent: Sysvar<'info, Rent>,
+
+    /// CHECK: the stake history sysvar, read by the stake program
+    #[account(address = anchor_lang::solana_program::sysvar::stake_history::ID)]
+    pub stake_history: UncheckedAccount<'info>,
+
+    /// CHECK: the stake config account, read by the stake program
+    #[account(address = stake::config::ID)]
+    pub stake_config: UncheckedAccount<'info>,
+
     pub system_program: Program<'info, System>,
+
+    /// CHECK: the stake program
+    #[account(address = stake::program::ID @ VaultError::InvalidStakeProgram)]
+    pub stake_program: UncheckedAccount<'info>,
+
+    /// CHECK: the config PDA, which may not be initialized yet; read by read_config
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DeactivateStake<'info> {
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump = user_account.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    /// CHECK: the vault's stake account PDA, checked by the stake program
+    #[account(mut, seeds = [b"stake", vault.key().as_ref()], bump)]
+    pub stake: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    /// CHECK: the stake program
+    #[account(address = stake::program::ID @ VaultError::InvalidStakeProgram)]
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        seeds = [user.key().as_ref()],
+        bump = user_account.user_bump,
+        has_one = user,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
+        bump = user_account.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    /// CHECK: the vault's stake account PDA, checked by the stake program
+    #[account(mut, seeds = [b"stake", vault.key().as_ref()], bump)]
+    pub stake: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    /// CHECK: the stake history sysvar, read by the stake program
+    #[account(address = anchor_lang::solana_program::sysvar::stake_history::ID)]
+    pub stake_history: UncheckedAccount<'info>,
+
+    /// CHECK: the stake program
+    #[account(address = stake::program::ID @ VaultError::InvalidStakeProgram)]
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+// Global program settings, a singleton PDA seeded with b"config"
+#[account]
+#[derive(InitSpace)]
+pub struct Config {
+    pub admin: Pubkey, // 32 bytes, may pause the program and hand over the role
+    pub paused: bool,  // 1 byte, blocks deposits
+    pub withdrawals_paused: bool, // 1 byte, also blocks withdrawals, needs paused
+    pub bump: u8,      // 1 byte
+    pub treasury_bump: u8, // 1 byte, bump of the fee treasury PDA
+    pub deposit_fee_bps: u16, // 2 bytes, deposit fee in basis points
+    pub withdraw_fee_bps: u16, // 2 bytes, withdraw fee in basis points
 }
 
 #[account]
 #[derive(InitSpace)]
 pub struct UserAccount {
-    pub user: Pubkey,         // 32 bytes
-    pub user_bump: u8,        // 1 byte
-    pub vault_bump: u8,       // 1 byte
-    pub is_initialized: bool, // 1 byte
+    pub user: Pubkey,                 // 32 bytes
+    pub user_bump: u8,                // 1 byte
+    pub vault_bump: u8,               // 1 byte
+    pub version: u8,                  // 1 byte, layout version, baseline accounts stored true here
+    pub withdraw_delay: i64,          // 8 bytes, seconds between request and claim, 0 = disabled
+    pub pending_withdrawal: u64,      // 8 bytes, lamports requested but not yet claimed
+    pub unlock_timestamp: i64,        // 8 bytes, unix timestamp after which the claim is allowed
+    pub withdraw_authority: Pubkey,   // 32 bytes, delegate allowed to withdraw, default = none
+    pub withdraw_allowance: u64, // 8 bytes, lamports left for the delegate, u64::MAX = uncapped
+    pub withdraw_limit: u64,     // 8 bytes, lamports withdrawable per window, 0 = unlimited
+    pub withdraw_window: i64,    // 8 bytes, length of the rate limit window in seconds
+    pub window_start: i64,       // 8 bytes, unix timestamp at which the current window started
+    pub window_withdrawn: u64,   // 8 bytes, lamports withdrawn in the current window
+    pub pending_withdraw_limit: u64, // 8 bytes, raised limit waiting for its delay to pass
+    pub pending_withdraw_window: i64, // 8 bytes, window length that comes with the pending limit
+    pub limit_unlock_timestamp: i64, // 8 bytes, when the pending limit applies, 0 = none
+    pub threshold: u8,           // 1 byte, multisig approvals required, 0 = user alone
+    #[max_len(0)]
+    pub signers: Vec<Pubkey>, // 4 + 32 * n bytes, grown by set_multisig
+    pub totals: LifetimeTotals,  // 40 bytes
+}
+
+impl UserAccount {
+    // Account size for the given number of multisig members
+    fn size(signers_len: usize) -> usize {
+        8 + UserAccount::INIT_SPACE + signers_len * 32
+    }
+}
+
+// A user account in either layout, for the instructions that accept baseline accounts
+// as they are: deposits, withdrawals and migrate. A baseline account reads as version 1
+// with every later field zero, and is written back as the discriminator, the user and
+// the bumps until migrate raises its version.
+#[derive(Clone)]
+pub struct AnyUserAccount(UserAccount);
+
+impl AnyUserAccount {
+    fn is_baseline(data: &[u8]) -> bool {
+        data.len() == 8 + USER_ACCOUNT_BASELINE_SIZE
+            && data[8 + 32 + 1 + 1] == BASELINE_USER_ACCOUNT_VERSION
+    }
+}
+
+impl std::ops::Deref for AnyUserAccount {
+    type Target = UserAccount;
+
+    fn deref(&self) -> &UserAccount {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for AnyUserAccount {
+    fn deref_mut(&mut self) -> &mut UserAccount {
+        &mut self.0
+    }
+}
+
+impl Owner for AnyUserAccount {
+    fn owner() -> Pubkey {
+        crate::ID
+    }
+}
+
+impl anchor_lang::Discriminator for AnyUserAccount {
+    const DISCRIMINATOR: [u8; 8] = UserAccount::DISCRIMINATOR;
+}
+
+impl AccountDeserialize for AnyUserAccount {
+    fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+        if Self::is_baseline(buf) {
+            let mut data = buf.to_vec();
+            data.resize(UserAccount::size(0), 0);
+            return UserAccount::try_deserialize(&mut &data[..]).map(Self);
+        }
+        UserAccount::try_deserialize(buf).map(Self)
+    }
+
+    fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+        UserAccount::try_deserialize_unchecked(buf).map(Self)
+    }
+}
+
+impl AccountSerialize for AnyUserAccount {
+    fn try_serialize<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        if self.version != BASELINE_USER_ACCOUNT_VERSION {
+            return self.0.try_serialize(writer);
+        }
+        let mut data = Vec::with_capacity(UserAccount::size(0));
+        self.0.try_serialize(&mut data)?;
+        writer
+            .write_all(&data[..8 + USER_ACCOUNT_BASELINE_SIZE])
+            .map_err(|_| ErrorCode::AccountDidNotSerialize.into())
+    }
+}
+
+// Described in the IDL as the user account itself
+#[cfg(feature = "idl-build")]
+impl anchor_lang::IdlBuild for AnyUserAccount {
+    fn create_type() -> Option<anchor_lang::idl::types::IdlTypeDef> {
+        UserAccount::create_type()
+    }
+
+    fn insert_types(
+        types: &mut std::collections::BTreeMap<String, anchor_lang::idl::types::IdlTypeDef>,
+    ) {
+        UserAccount::insert_types(types);
+    }
+}
+
+// Lifetime activity of a vault, at the end of the user account
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace, Clone, Copy, Debug, Default)]
+pub struct LifetimeTotals {
+    pub total_deposited: u64, // 8 bytes, lamports ever credited to the vault, after fees
+    pub total_withdrawn: u64, // 8 bytes, lamports ever taken out of the vault, with fees
+    pub deposit_count: u64,   // 8 bytes, number of lamport deposits
+    pub last_deposit_slot: u64, // 8 bytes, slot of the latest lamport deposit, 0 = none
+    pub last_withdraw_slot: u64, // 8 bytes, slot of the latest lamport withdrawal, 0 = none
+}
+
+// Emitted when lamports are deposited into a vault
+#[event]
+pub struct DepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports paid in, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the deposit
+    pub slot: u64,
+}
+
+// Emitted when lamports leave a vault, whether withdrawn, claimed, drained on close
+// or paid to a delegate
+#[event]
+pub struct WithdrawEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports taken out of the vault, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the withdrawal
+    pub slot: u64,
+}
+
+// Emitted when tokens are deposited into a vault token account
+#[event]
+pub struct TokenDepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA, owner of the vault token account
+    pub mint: Pubkey,       // mint of the deposited tokens
+    pub amount: u64,        // tokens the user sent
+    pub received: u64,      // tokens that reached the vault, less any transfer fee
+    pub vault_balance: u64, // vault token account balance after the deposit
+    pub slot: u64,
 }
 
 #[error_code]
 pub enum VaultError {
     #[msg("Insufficient funds in the vault")]
     InsufficientFunds,
+    #[msg("Vault is timelocked, use request_withdraw instead")]
+    VaultTimelocked,
+    #[msg("Withdraw delay must be non-negative and can only be set on first deposit")]
+    InvalidWithdrawDelay,
+    #[msg("Amount must be greater than zero")]
+    InvalidAmount,
+    #[msg("A withdrawal is already pending")]
+    WithdrawalAlreadyPending,
+    #[msg("No pending withdrawal")]
+    NoPendingWithdrawal,
+    #[msg("Withdrawal is still timelocked")]
+    WithdrawalStillLocked,
+    #[msg("Not enough multisig signers approved")]
+    NotEnoughSigners,
+    #[msg("Threshold must be between 1 and the number of distinct signers")]
+    InvalidMultisig,
+    #[msg("Signer is not the withdraw authority")]
+    InvalidWithdrawAuthority,
+    #[msg("Amount exceeds the withdraw allowance")]
+    AllowanceExceeded,
+    #[msg("Amount exceeds the withdraw limit for the current window")]
+    WithdrawLimitExceeded,
+    #[msg("Withdraw window must be positive exactly when a limit is set")]
+    InvalidWithdrawLimit,
+    #[msg("Program is paused")]
+    ProgramPaused,
+    #[msg("Signer is not the program upgrade authority")]
+    NotUpgradeAuthority,
+    #[msg("Admin cannot be the default pubkey")]
+    InvalidAdmin,
+    #[msg("Withdrawals can only be paused together with deposits")]
+    InvalidPauseFlags,
+    #[msg("Fee exceeds the maximum")]
+    InvalidFee,
+    #[msg("Insufficient fees in the treasury")]
+    InsufficientFees,
+    // The variants below mirror the hand-written account checks of the native and
+    // pinocchio programs, keeping the custom error codes the same across all three
+    #[msg("Invalid system program")]
+    InvalidSystemProgram,
+    #[msg("Invalid user data account address")]
+    InvalidUserAccount,
+    #[msg("Invalid vault account address")]
+    InvalidVault,
+    #[msg("User account does not belong to the requesting user")]
+    UserMismatch,
+    #[msg("User data account is not owned by the program")]
+    InvalidAccountOwner,
+    #[msg("Failed to deserialize account data")]
+    DeserializationFailed,
+    #[msg("Invalid config account address")]
+    InvalidConfig,
+    #[msg("Invalid treasury account address")]
+    InvalidTreasury,
+    #[msg("Invalid vault token account address")]
+    InvalidVaultTokenAccount,
+    #[msg("Invalid token program")]
+    InvalidTokenProgram,
+    #[msg("Invalid associated token program")]
+    InvalidAssociatedTokenProgram,
+    #[msg("Invalid program data account")]
+    InvalidProgramData,
+    #[msg("Signer is not the admin")]
+    NotAdmin,
+    // New errors go after the account checks so that no existing code moves
+    #[msg("Withdrawal would leave the vault below the rent-exempt minimum, withdraw everything or leave at least the minimum")]
+    VaultBelowRentExempt,
+    #[msg("Invalid stake account address")]
+    InvalidStakeAccount,
+    #[msg("Invalid stake program")]
+    InvalidStakeProgram,
+    #[msg("The vault already has a stake account, withdraw it before delegating again")]
+    StakeAccountInUse,
 }
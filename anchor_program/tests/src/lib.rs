@@ -1,2 +1,26 @@
 #[cfg(test)]
+mod test_close;
+#[cfg(test)]
+mod test_config;
+#[cfg(test)]
+mod test_delegate;
+#[cfg(test)]
+mod test_deposit_for;
+#[cfg(test)]
 mod test_deposit_withdraw;
+#[cfg(test)]
+mod test_events;
+#[cfg(test)]
+mod test_fees;
+#[cfg(test)]
+mod test_litesvm;
+#[cfg(test)]
+mod test_migrate;
+#[cfg(test)]
+mod test_multisig;
+#[cfg(test)]
+mod test_timelock;
+#[cfg(test)]
+mod test_token_vault;
+#[cfg(test)]
+mod test_withdraw_limit;
@@ -0,0 +1,103 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+        signature::Keypair, signer::Signer, system_program,
+    },
+    Client, Cluster,
+};
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+#[test]
+fn test_close() {
+    // Use a fresh keypair so closing does not race with the other tests
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    // Deposit so that both the user account and the vault exist
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    program
+        .request()
+        .accounts(anchor_program::accounts::Deposit {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Deposit {
+            amount: deposit_amount,
+        })
+        .send()
+        .expect("Failed to deposit");
+
+    let balance_before_close = rpc_client
+        .get_balance(&user_pubkey)
+        .expect("Failed to get user balance");
+    let user_account_rent = rpc_client
+        .get_balance(&user_account_pda)
+        .expect("Failed to get user account balance");
+
+    // Close the vault and the user account
+    let tx = program
+        .request()
+        .accounts(anchor_program::accounts::Close {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Close {})
+        .send()
+        .expect("Failed to close");
+
+    println!("\nClose transaction signature: {}", tx);
+
+    // Both PDAs should be gone
+    assert_eq!(
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        0,
+        "Vault should be drained"
+    );
+    assert!(
+        rpc_client.get_account(&user_account_pda).is_err(),
+        "User account should be closed"
+    );
+
+    // The user gets the deposit and the rent back, minus the 5000 lamport signature fee
+    let balance_after_close = rpc_client
+        .get_balance(&user_pubkey)
+        .expect("Failed to get user balance");
+    assert_eq!(
+        balance_after_close,
+        balance_before_close + deposit_amount + user_account_rent - 5000,
+        "User should receive the vault balance and the user account rent"
+    );
+}
@@ -0,0 +1,171 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig,
+        native_token::LAMPORTS_PER_SOL,
+        pubkey::Pubkey,
+        signature::{read_keypair_file, Keypair},
+        signer::Signer,
+        system_program,
+    },
+    Client, Cluster,
+};
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_program_data_address, find_treasury_address,
+    find_user_account_address, find_vault_address,
+};
+
+// Pausing affects every vault, so this test would break the others when run in parallel
+#[test]
+#[ignore = "pauses the whole program, run on its own with --ignored"]
+fn test_config_pause() {
+    // The Anchor wallet deployed the program, so it is the upgrade authority
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let anchor_wallet = std::env::var("ANCHOR_WALLET").expect("Failed to get ANCHOR_WALLET");
+    let upgrade_authority = read_keypair_file(&anchor_wallet).expect("Failed to read keypair file");
+
+    // Use a fresh keypair for the vault, plus a new admin and an outsider
+    let payer = Keypair::new();
+    let admin = Keypair::new();
+    let outsider = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account, vault, config and program data PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+    let (program_data_pda, _) = find_program_data_address(&program_id);
+
+    let deposit = |amount: u64| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::Deposit {
+                user: user_pubkey,
+                user_account: user_account_pda,
+                vault: vault_pda,
+                system_program: system_program::ID,
+                config: config_pda,
+                treasury: treasury_pda,
+            })
+            .args(anchor_program::instruction::Deposit { amount })
+            .send()
+    };
+    let withdraw = |amount: u64| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::Withdraw {
+                user: user_pubkey,
+                user_account: user_account_pda,
+                vault: vault_pda,
+                system_program: system_program::ID,
+                config: config_pda,
+                treasury: treasury_pda,
+            })
+            .args(anchor_program::instruction::Withdraw { amount })
+            .send()
+    };
+    let initialize_config = |authority: &Keypair| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::InitializeConfig {
+                upgrade_authority: authority.pubkey(),
+                config: config_pda,
+                program_data: program_data_pda,
+                system_program: system_program::ID,
+            })
+            .args(anchor_program::instruction::InitializeConfig {
+                admin: authority.pubkey(),
+            })
+            .signer(authority)
+            .send()
+    };
+    let set_paused = |signer: &Keypair, deposits: bool, withdrawals: bool| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::SetPaused {
+                admin: signer.pubkey(),
+                config: config_pda,
+            })
+            .args(anchor_program::instruction::SetPaused {
+                deposits,
+                withdrawals,
+            })
+            .signer(signer)
+            .send()
+    };
+    let transfer_admin = |signer: &Keypair, new_admin: Pubkey| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::TransferAdmin {
+                admin: signer.pubkey(),
+                config: config_pda,
+            })
+            .args(anchor_program::instruction::TransferAdmin { new_admin })
+            .signer(signer)
+            .send()
+    };
+
+    // Deposit so that both the user account and the vault exist
+    let amount = LAMPORTS_PER_SOL / 10;
+    deposit(amount).expect("Failed to deposit");
+
+    // Only the upgrade authority can create the config
+    assert!(
+        initialize_config(&outsider).is_err(),
+        "A non-upgrade authority should not be able to initialize the config"
+    );
+    if rpc_client.get_account(&config_pda).is_err() {
+        initialize_config(&upgrade_authority).expect("Failed to initialize config");
+    }
+
+    // Hand the admin role to a fresh key for the rest of the test
+    assert!(
+        transfer_admin(&outsider, outsider.pubkey()).is_err(),
+        "A non-admin should not be able to transfer the admin role"
+    );
+    transfer_admin(&upgrade_authority, admin.pubkey()).expect("Failed to transfer admin");
+
+    // Only the admin can pause, and withdrawals cannot be paused on their own
+    assert!(
+        set_paused(&outsider, true, false).is_err(),
+        "A non-admin should not be able to pause"
+    );
+    assert!(
+        set_paused(&admin, false, true).is_err(),
+        "Withdrawals should not be pausable without deposits"
+    );
+
+    // A deposit pause still lets users withdraw
+    set_paused(&admin, true, false).expect("Failed to pause deposits");
+    assert!(deposit(amount).is_err(), "Deposit should fail while paused");
+    withdraw(amount / 2).expect("Failed to withdraw");
+
+    // A full pause blocks withdrawals too
+    set_paused(&admin, true, true).expect("Failed to pause withdrawals");
+    assert!(
+        withdraw(amount / 4).is_err(),
+        "Withdraw should fail while withdrawals are paused"
+    );
+
+    // Unpausing restores both
+    set_paused(&admin, false, false).expect("Failed to unpause");
+    deposit(amount / 2).expect("Failed to deposit");
+    withdraw(amount / 4).expect("Failed to withdraw");
+
+    // Give the admin role back to the upgrade authority
+    transfer_admin(&admin, upgrade_authority.pubkey()).expect("Failed to transfer admin back");
+}
@@ -0,0 +1,161 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+        signature::Keypair, signer::Signer, system_program,
+    },
+    Client, Cluster,
+};
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+#[test]
+fn test_delegate_withdraw() {
+    // Use a fresh keypair so the delegate does not affect the other tests
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    // A hot key that receives withdraw rights, and one that does not
+    let delegate = Keypair::new();
+    let outsider = Keypair::new();
+
+    let delegate_withdraw = |authority: &Keypair, amount: u64| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::DelegateWithdraw {
+                authority: authority.pubkey(),
+                user: user_pubkey,
+                user_account: user_account_pda,
+                vault: vault_pda,
+                system_program: system_program::ID,
+                config: config_pda,
+                treasury: treasury_pda,
+            })
+            .args(anchor_program::instruction::DelegateWithdraw { amount })
+            .signer(authority)
+            .send()
+    };
+
+    // Deposit so that both the user account and the vault exist
+    program
+        .request()
+        .accounts(anchor_program::accounts::Deposit {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Deposit {
+            amount: LAMPORTS_PER_SOL / 2,
+        })
+        .send()
+        .expect("Failed to deposit");
+
+    // Nobody can withdraw as a delegate before one is set
+    let withdraw_amount = LAMPORTS_PER_SOL / 20;
+    assert!(
+        delegate_withdraw(&delegate, withdraw_amount).is_err(),
+        "Delegate withdraw should fail without a withdraw authority"
+    );
+
+    // Delegate withdraw rights capped at two withdrawals
+    program
+        .request()
+        .accounts(anchor_program::accounts::SetWithdrawAuthority {
+            user: user_pubkey,
+            user_account: user_account_pda,
+        })
+        .args(anchor_program::instruction::SetWithdrawAuthority {
+            authority: delegate.pubkey(),
+            allowance: withdraw_amount * 2,
+        })
+        .send()
+        .expect("Failed to set withdraw authority");
+
+    // The delegate withdraws to itself, signed with the user's vault seeds
+    let vault_balance_before = rpc_client
+        .get_balance(&vault_pda)
+        .expect("Failed to get vault balance");
+    delegate_withdraw(&delegate, withdraw_amount).expect("Failed to withdraw as delegate");
+    assert_eq!(
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        vault_balance_before - withdraw_amount,
+        "Vault balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        rpc_client
+            .get_balance(&delegate.pubkey())
+            .expect("Failed to get delegate balance"),
+        withdraw_amount,
+        "Delegate should receive the withdrawal"
+    );
+
+    // Withdrawals beyond the remaining allowance fail
+    assert!(
+        delegate_withdraw(&delegate, withdraw_amount + 1).is_err(),
+        "Delegate withdraw should fail beyond the allowance"
+    );
+
+    // Only the delegate holds withdraw rights
+    assert!(
+        delegate_withdraw(&outsider, withdraw_amount).is_err(),
+        "A non-delegate should not be able to withdraw"
+    );
+
+    // The user keeps full control of the vault
+    program
+        .request()
+        .accounts(anchor_program::accounts::Withdraw {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Withdraw {
+            amount: withdraw_amount,
+        })
+        .send()
+        .expect("Failed to withdraw");
+
+    // After revoking, the delegate can no longer withdraw
+    program
+        .request()
+        .accounts(anchor_program::accounts::RevokeWithdrawAuthority {
+            user: user_pubkey,
+            user_account: user_account_pda,
+        })
+        .args(anchor_program::instruction::RevokeWithdrawAuthority {})
+        .send()
+        .expect("Failed to revoke withdraw authority");
+    assert!(
+        delegate_withdraw(&delegate, withdraw_amount).is_err(),
+        "Delegate withdraw should fail after revoking"
+    );
+}
@@ -0,0 +1,125 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, instruction::AccountMeta,
+        native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, signature::Keypair, signer::Signer,
+        system_program,
+    },
+    Client, Cluster,
+};
+use anchor_program::UserAccount;
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+#[test]
+fn test_deposit_for() {
+    // A funded sponsor onboards a beneficiary that holds no lamports at all
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let funder = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let client =
+        Client::new_with_options(Cluster::Localnet, &funder, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let funder_pubkey = funder.pubkey();
+    let beneficiary_pubkey = beneficiary.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&funder_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // The PDAs are derived from the beneficiary, not the funder
+    let (user_account_pda, _) = find_user_account_address(&beneficiary_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&beneficiary_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    let deposit_for = |amount: u64| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::DepositFor {
+                funder: funder_pubkey,
+                user_account: user_account_pda,
+                vault: vault_pda,
+                system_program: system_program::ID,
+                config: config_pda,
+                treasury: treasury_pda,
+            })
+            .args(anchor_program::instruction::DepositFor {
+                beneficiary: beneficiary_pubkey,
+                amount,
+            })
+            .send()
+    };
+
+    // First deposit creates the beneficiary's account, only the funder signs
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    deposit_for(deposit_amount).expect("Failed to deposit for beneficiary");
+
+    assert_eq!(
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        deposit_amount,
+        "Beneficiary vault should hold the deposit"
+    );
+    assert_eq!(
+        rpc_client
+            .get_balance(&beneficiary_pubkey)
+            .expect("Failed to get beneficiary balance"),
+        0,
+        "Beneficiary should not pay anything"
+    );
+    let user_account: UserAccount = program
+        .account(user_account_pda)
+        .expect("Failed to fetch user account");
+    assert_eq!(
+        user_account.user, beneficiary_pubkey,
+        "User account should belong to the beneficiary"
+    );
+
+    // Later sponsored deposits top up the existing vault
+    deposit_for(deposit_amount).expect("Failed to deposit for beneficiary");
+    assert_eq!(
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        deposit_amount * 2,
+        "Beneficiary vault should hold both deposits"
+    );
+
+    // The beneficiary controls the vault, with the funder covering the fee. The user
+    // account is not a declared signer, so mark the beneficiary as one explicitly.
+    let withdraw_amount = deposit_amount / 2;
+    program
+        .request()
+        .accounts(anchor_program::accounts::Withdraw {
+            user: beneficiary_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .accounts(vec![AccountMeta::new_readonly(beneficiary_pubkey, true)])
+        .args(anchor_program::instruction::Withdraw {
+            amount: withdraw_amount,
+        })
+        .signer(&beneficiary)
+        .send()
+        .expect("Failed to withdraw");
+    assert_eq!(
+        rpc_client
+            .get_balance(&beneficiary_pubkey)
+            .expect("Failed to get beneficiary balance"),
+        withdraw_amount,
+        "Beneficiary should receive the withdrawal"
+    );
+}
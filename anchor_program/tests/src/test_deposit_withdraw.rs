@@ -6,6 +6,9 @@ use anchor_client::{
     Client, Cluster,
 };
 use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
 
 #[test]
 fn test_deposit_withdraw() {
@@ -23,12 +26,11 @@ fn test_deposit_withdraw() {
 
     // Derive the user account PDA
     let user_pubkey = payer.pubkey();
-    let seeds = [user_pubkey.as_ref()];
-    let (user_account_pda, _) = Pubkey::find_program_address(&seeds, &program_id);
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
     // Derive the vault PDA
-    let vault_seed = b"vault";
-    let vault_seeds = [vault_seed.as_ref(), user_pubkey.as_ref()];
-    let (vault_pda, _) = Pubkey::find_program_address(&vault_seeds, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
 
     // Get vault initial balance
     let vault_initial_balance = match rpc_client.get_account(&vault_pda) {
@@ -51,6 +53,8 @@ fn test_deposit_withdraw() {
             user_account: user_account_pda,
             vault: vault_pda,
             system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
         })
         .args(anchor_program::instruction::Deposit {
             amount: deposit_amount,
@@ -94,6 +98,8 @@ fn test_deposit_withdraw() {
             user_account: user_account_pda,
             vault: vault_pda,
             system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
         })
         .args(anchor_program::instruction::Withdraw {
             amount: withdraw_amount,
@@ -0,0 +1,144 @@
+use anchor_client::{
+    anchor_lang::{AnchorDeserialize, Discriminator},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+        signature::Keypair, signature::Signature, signer::Signer, system_program,
+    },
+    Client, Cluster,
+};
+use anchor_program::{DepositEvent, WithdrawEvent};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_transaction_status::UiTransactionEncoding;
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+// Decode the events of one type from a transaction's logs. emit! logs the discriminator
+// and the borsh encoded event as a single "Program data:" field, the same encoding the
+// native and pinocchio programs use.
+fn fetch_events<T: Discriminator + AnchorDeserialize>(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+) -> Vec<T> {
+    let transaction = rpc_client
+        .get_transaction(signature, UiTransactionEncoding::Json)
+        .expect("Failed to get transaction");
+    let logs: Option<Vec<String>> = transaction
+        .transaction
+        .meta
+        .and_then(|meta| meta.log_messages.into());
+
+    logs.unwrap_or_default()
+        .iter()
+        .filter_map(|log| log.strip_prefix("Program data: "))
+        .filter_map(|data| STANDARD.decode(data).ok())
+        .filter_map(|bytes| {
+            let payload = bytes.strip_prefix(T::DISCRIMINATOR.as_slice())?;
+            T::try_from_slice(payload).ok()
+        })
+        .collect()
+}
+
+#[test]
+fn test_events() {
+    // Use a fresh keypair so the balances in the events are predictable
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account, vault, config and treasury PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    // A deposit emits a single deposit event carrying the new vault balance
+    let amount = LAMPORTS_PER_SOL / 10;
+    let deposit_signature = program
+        .request()
+        .accounts(anchor_program::accounts::Deposit {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Deposit { amount })
+        .send()
+        .expect("Failed to deposit");
+    let events: Vec<DepositEvent> = fetch_events(&rpc_client, &deposit_signature);
+    assert_eq!(events.len(), 1, "Deposit should emit a single event");
+    let deposit_event = &events[0];
+    assert_eq!(
+        deposit_event.user, user_pubkey,
+        "Event should name the user"
+    );
+    assert_eq!(
+        deposit_event.vault, vault_pda,
+        "Event should name the vault"
+    );
+    assert_eq!(
+        deposit_event.amount, amount,
+        "Event should carry the deposit amount"
+    );
+    assert_eq!(
+        deposit_event.vault_balance,
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        "Event should carry the vault balance after the deposit"
+    );
+    assert!(
+        deposit_event.slot > 0,
+        "Event should carry the slot it was emitted in"
+    );
+
+    // A withdrawal emits a single withdraw event and no deposit event
+    let withdraw_amount = amount / 2;
+    let withdraw_signature = program
+        .request()
+        .accounts(anchor_program::accounts::Withdraw {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Withdraw {
+            amount: withdraw_amount,
+        })
+        .send()
+        .expect("Failed to withdraw");
+    let events: Vec<WithdrawEvent> = fetch_events(&rpc_client, &withdraw_signature);
+    assert_eq!(events.len(), 1, "Withdraw should emit a single event");
+    assert_eq!(
+        events[0].amount, withdraw_amount,
+        "Event should carry the withdraw amount"
+    );
+    assert_eq!(
+        events[0].vault_balance,
+        deposit_event.vault_balance - withdraw_amount,
+        "Event should carry the vault balance after the withdrawal"
+    );
+    assert!(
+        fetch_events::<DepositEvent>(&rpc_client, &withdraw_signature).is_empty(),
+        "Withdraw should not emit a deposit event"
+    );
+}
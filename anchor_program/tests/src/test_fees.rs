@@ -0,0 +1,196 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig,
+        native_token::LAMPORTS_PER_SOL,
+        pubkey::Pubkey,
+        signature::{read_keypair_file, Keypair},
+        signer::Signer,
+        system_program,
+    },
+    Client, Cluster,
+};
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_program_data_address, find_treasury_address,
+    find_user_account_address, find_vault_address,
+};
+
+// Fees apply to every vault, so this test would break the others when run in parallel
+#[test]
+#[ignore = "charges fees program-wide, run on its own with --ignored"]
+fn test_fees() {
+    // The Anchor wallet deployed the program, so it is the upgrade authority and,
+    // unless handed over, the admin
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let anchor_wallet = std::env::var("ANCHOR_WALLET").expect("Failed to get ANCHOR_WALLET");
+    let admin = read_keypair_file(&anchor_wallet).expect("Failed to read keypair file");
+
+    // Use a fresh keypair for the vault, plus an outsider
+    let payer = Keypair::new();
+    let outsider = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account, vault, config, treasury and program data PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+    let (program_data_pda, _) = find_program_data_address(&program_id);
+
+    let get_balance = |pubkey: &Pubkey| {
+        rpc_client
+            .get_balance(pubkey)
+            .expect("Failed to get balance")
+    };
+    let set_fees = |signer: &Keypair, deposit_bps: u16, withdraw_bps: u16| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::SetFees {
+                admin: signer.pubkey(),
+                config: config_pda,
+                treasury: treasury_pda,
+                system_program: system_program::ID,
+            })
+            .args(anchor_program::instruction::SetFees {
+                deposit_bps,
+                withdraw_bps,
+            })
+            .signer(signer)
+            .send()
+    };
+    let withdraw_fees = |signer: &Keypair, amount: u64| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::WithdrawFees {
+                admin: signer.pubkey(),
+                config: config_pda,
+                treasury: treasury_pda,
+                system_program: system_program::ID,
+            })
+            .args(anchor_program::instruction::WithdrawFees { amount })
+            .signer(signer)
+            .send()
+    };
+
+    // Fees live in the config, so make sure it exists
+    if rpc_client.get_account(&config_pda).is_err() {
+        program
+            .request()
+            .accounts(anchor_program::accounts::InitializeConfig {
+                upgrade_authority: admin.pubkey(),
+                config: config_pda,
+                program_data: program_data_pda,
+                system_program: system_program::ID,
+            })
+            .args(anchor_program::instruction::InitializeConfig {
+                admin: admin.pubkey(),
+            })
+            .signer(&admin)
+            .send()
+            .expect("Failed to initialize config");
+    }
+
+    // Only the admin can set fees, and only up to the cap
+    assert!(
+        set_fees(&outsider, 100, 100).is_err(),
+        "A non-admin should not be able to set fees"
+    );
+    assert!(
+        set_fees(&admin, 1_001, 0).is_err(),
+        "A fee above the cap should be rejected"
+    );
+
+    // 1% on deposits and 2% on withdrawals
+    set_fees(&admin, 100, 200).expect("Failed to set fees");
+    let treasury_before = get_balance(&treasury_pda);
+
+    // The vault receives the deposit less the fee, the treasury receives the fee
+    let amount = LAMPORTS_PER_SOL / 10;
+    let deposit_fee = amount / 100;
+    program
+        .request()
+        .accounts(anchor_program::accounts::Deposit {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Deposit { amount })
+        .send()
+        .expect("Failed to deposit");
+    let vault_balance = get_balance(&vault_pda);
+    assert_eq!(
+        vault_balance,
+        amount - deposit_fee,
+        "Vault should receive the deposit less the fee"
+    );
+    assert_eq!(
+        get_balance(&treasury_pda),
+        treasury_before + deposit_fee,
+        "Treasury should receive the deposit fee"
+    );
+
+    // The whole withdrawal leaves the vault, the fee part goes to the treasury
+    let withdraw_amount = vault_balance / 2;
+    let withdraw_fee = withdraw_amount * 200 / 10_000;
+    program
+        .request()
+        .accounts(anchor_program::accounts::Withdraw {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Withdraw {
+            amount: withdraw_amount,
+        })
+        .send()
+        .expect("Failed to withdraw");
+    assert_eq!(
+        get_balance(&vault_pda),
+        vault_balance - withdraw_amount,
+        "Vault balance should decrease by the withdraw amount"
+    );
+    let collected = deposit_fee + withdraw_fee;
+    assert_eq!(
+        get_balance(&treasury_pda),
+        treasury_before + collected,
+        "Treasury should receive the withdraw fee"
+    );
+
+    // Only the admin can withdraw fees, and the treasury stays rent-exempt
+    assert!(
+        withdraw_fees(&outsider, collected).is_err(),
+        "A non-admin should not be able to withdraw fees"
+    );
+    assert!(
+        withdraw_fees(&admin, get_balance(&treasury_pda)).is_err(),
+        "Withdrawing fees should not drain the treasury below rent exemption"
+    );
+    withdraw_fees(&admin, collected).expect("Failed to withdraw fees");
+    assert_eq!(
+        get_balance(&treasury_pda),
+        treasury_before,
+        "Treasury should pay out the collected fees"
+    );
+
+    // Turn fees off again for the other tests
+    set_fees(&admin, 0, 0).expect("Failed to reset fees");
+}
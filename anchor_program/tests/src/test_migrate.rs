@@ -0,0 +1,85 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+        signature::Keypair, signer::Signer, system_program,
+    },
+    Client, Cluster,
+};
+use anchor_program::UserAccount;
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+#[test]
+fn test_migrate() {
+    // Use a fresh keypair so the user account is created by this test
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    // New accounts are created at the current version
+    program
+        .request()
+        .accounts(anchor_program::accounts::Deposit {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Deposit {
+            amount: LAMPORTS_PER_SOL / 10,
+        })
+        .send()
+        .expect("Failed to deposit");
+    let user_account: UserAccount = program
+        .account(user_account_pda)
+        .expect("Failed to fetch user account");
+    assert_eq!(
+        user_account.version, 2,
+        "User account should be at version 2"
+    );
+
+    // Migrating an up to date account leaves it untouched
+    let data_before = rpc_client
+        .get_account_data(&user_account_pda)
+        .expect("Failed to get user account");
+    program
+        .request()
+        .accounts(anchor_program::accounts::Migrate {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            system_program: system_program::ID,
+        })
+        .args(anchor_program::instruction::Migrate {})
+        .send()
+        .expect("Failed to migrate");
+    assert_eq!(
+        rpc_client
+            .get_account_data(&user_account_pda)
+            .expect("Failed to get user account"),
+        data_before,
+        "Migrate should not change a current account"
+    );
+}
@@ -0,0 +1,183 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, instruction::AccountMeta,
+        native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, signature::Keypair, signer::Signer,
+        system_program,
+    },
+    Client, Cluster,
+};
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+#[test]
+fn test_multisig_withdraw() {
+    // Use a fresh keypair so the multisig does not affect the other tests
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    // Three members, two of which must approve, plus an outsider
+    let members = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let member_keys: Vec<Pubkey> = members.iter().map(|member| member.pubkey()).collect();
+    let outsider = Keypair::new();
+
+    // Multisig approvers are passed as remaining signer accounts
+    let approver_metas = |approvers: &[&Keypair]| -> Vec<AccountMeta> {
+        approvers
+            .iter()
+            .map(|approver| AccountMeta::new_readonly(approver.pubkey(), true))
+            .collect()
+    };
+    let withdraw = |amount: u64, approvers: &[&Keypair]| {
+        let mut request = program
+            .request()
+            .accounts(anchor_program::accounts::Withdraw {
+                user: user_pubkey,
+                user_account: user_account_pda,
+                vault: vault_pda,
+                system_program: system_program::ID,
+                config: config_pda,
+                treasury: treasury_pda,
+            })
+            .accounts(approver_metas(approvers))
+            .args(anchor_program::instruction::Withdraw { amount });
+        for approver in approvers {
+            request = request.signer(*approver);
+        }
+        request.send()
+    };
+    let set_multisig = |signers: Vec<Pubkey>, threshold: u8, approvers: &[&Keypair]| {
+        let mut request = program
+            .request()
+            .accounts(anchor_program::accounts::SetMultisig {
+                payer: user_pubkey,
+                user: user_pubkey,
+                user_account: user_account_pda,
+                system_program: system_program::ID,
+            })
+            .accounts(approver_metas(approvers))
+            .args(anchor_program::instruction::SetMultisig { signers, threshold });
+        for approver in approvers {
+            request = request.signer(*approver);
+        }
+        request.send()
+    };
+
+    // Deposit so that both the user account and the vault exist
+    program
+        .request()
+        .accounts(anchor_program::accounts::Deposit {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Deposit {
+            amount: LAMPORTS_PER_SOL / 2,
+        })
+        .send()
+        .expect("Failed to deposit");
+
+    // A signer set with a duplicate member or an unreachable threshold is rejected
+    assert!(
+        set_multisig(vec![member_keys[0], member_keys[0]], 1, &[]).is_err(),
+        "Duplicate multisig members should be rejected"
+    );
+    assert!(
+        set_multisig(member_keys.clone(), 4, &[]).is_err(),
+        "A threshold above the member count should be rejected"
+    );
+
+    // The user hands control to a 2-of-3 multisig, growing the user account
+    let size_before = rpc_client
+        .get_account(&user_account_pda)
+        .expect("Failed to get user account")
+        .data
+        .len();
+    set_multisig(member_keys.clone(), 2, &[]).expect("Failed to set multisig");
+    assert_eq!(
+        rpc_client
+            .get_account(&user_account_pda)
+            .expect("Failed to get user account")
+            .data
+            .len(),
+        size_before + 3 * 32,
+        "User account should grow to hold the signer set"
+    );
+
+    // The user alone, a single member, a repeated member and a non-member all fall short
+    let withdraw_amount = LAMPORTS_PER_SOL / 10;
+    assert!(
+        withdraw(withdraw_amount, &[]).is_err(),
+        "The user alone should not satisfy the multisig"
+    );
+    assert!(
+        withdraw(withdraw_amount, &[&members[0]]).is_err(),
+        "One approval should not satisfy a 2-of-3 multisig"
+    );
+    assert!(
+        withdraw(withdraw_amount, &[&members[0], &members[0]]).is_err(),
+        "A duplicate approval should not count twice"
+    );
+    assert!(
+        withdraw(withdraw_amount, &[&members[0], &outsider]).is_err(),
+        "A non-member approval should not count"
+    );
+
+    // Two distinct members can withdraw
+    let vault_balance_before = rpc_client
+        .get_balance(&vault_pda)
+        .expect("Failed to get vault balance");
+    withdraw(withdraw_amount, &[&members[0], &members[2]]).expect("Failed to withdraw");
+    assert_eq!(
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        vault_balance_before - withdraw_amount,
+        "Vault balance should decrease by withdraw amount"
+    );
+
+    // Changing the signer set also needs threshold approval
+    assert!(
+        set_multisig(Vec::new(), 0, &[&members[1]]).is_err(),
+        "One approval should not change the signer set"
+    );
+
+    // Two members hand control back to the user, shrinking the user account
+    set_multisig(Vec::new(), 0, &[&members[0], &members[1]]).expect("Failed to clear multisig");
+    assert_eq!(
+        rpc_client
+            .get_account(&user_account_pda)
+            .expect("Failed to get user account")
+            .data
+            .len(),
+        size_before,
+        "User account should shrink back to its original size"
+    );
+
+    // The user can withdraw alone again
+    withdraw(withdraw_amount, &[]).expect("Failed to withdraw");
+}
@@ -0,0 +1,183 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+        signature::Keypair, signer::Signer, system_program,
+    },
+    Client, Cluster,
+};
+use std::{str::FromStr, thread, time::Duration};
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+#[test]
+fn test_timelocked_withdraw() {
+    // Use a fresh keypair so the timelock does not affect the other tests
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    let deposit_accounts = || anchor_program::accounts::Deposit {
+        user: user_pubkey,
+        user_account: user_account_pda,
+        vault: vault_pda,
+        system_program: system_program::ID,
+        config: config_pda,
+        treasury: treasury_pda,
+    };
+    let claim_accounts = || anchor_program::accounts::ClaimWithdraw {
+        user: user_pubkey,
+        user_account: user_account_pda,
+        vault: vault_pda,
+        system_program: system_program::ID,
+        config: config_pda,
+        treasury: treasury_pda,
+    };
+    let request_accounts = || anchor_program::accounts::RequestWithdraw {
+        user: user_pubkey,
+        user_account: user_account_pda,
+    };
+    let cancel_accounts = || anchor_program::accounts::CancelWithdraw {
+        user: user_pubkey,
+        user_account: user_account_pda,
+    };
+
+    // Deposit with a short withdraw delay
+    let withdraw_delay = 3;
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    program
+        .request()
+        .accounts(deposit_accounts())
+        .args(anchor_program::instruction::DepositWithDelay {
+            amount: deposit_amount,
+            withdraw_delay,
+        })
+        .send()
+        .expect("Failed to deposit");
+
+    // The delay cannot be changed after the first deposit
+    assert!(
+        program
+            .request()
+            .accounts(deposit_accounts())
+            .args(anchor_program::instruction::DepositWithDelay {
+                amount: 1,
+                withdraw_delay: 0,
+            })
+            .send()
+            .is_err(),
+        "Changing the withdraw delay should fail"
+    );
+
+    // Direct withdrawals bypass the timelock and are rejected
+    assert!(
+        program
+            .request()
+            .accounts(anchor_program::accounts::Withdraw {
+                user: user_pubkey,
+                user_account: user_account_pda,
+                vault: vault_pda,
+                system_program: system_program::ID,
+                config: config_pda,
+                treasury: treasury_pda,
+            })
+            .args(anchor_program::instruction::Withdraw { amount: 1 })
+            .send()
+            .is_err(),
+        "Withdraw should fail on a timelocked vault"
+    );
+
+    // Request, cancel, and request again
+    let withdraw_amount = deposit_amount / 2;
+    program
+        .request()
+        .accounts(request_accounts())
+        .args(anchor_program::instruction::RequestWithdraw {
+            amount: withdraw_amount,
+        })
+        .send()
+        .expect("Failed to request withdrawal");
+    program
+        .request()
+        .accounts(cancel_accounts())
+        .args(anchor_program::instruction::CancelWithdraw {})
+        .send()
+        .expect("Failed to cancel withdrawal");
+    program
+        .request()
+        .accounts(request_accounts())
+        .args(anchor_program::instruction::RequestWithdraw {
+            amount: withdraw_amount,
+        })
+        .send()
+        .expect("Failed to request withdrawal");
+
+    // Only one withdrawal can be pending
+    assert!(
+        program
+            .request()
+            .accounts(request_accounts())
+            .args(anchor_program::instruction::RequestWithdraw { amount: 1 })
+            .send()
+            .is_err(),
+        "A second request should fail while one is pending"
+    );
+
+    // Claiming before the delay has passed fails
+    assert!(
+        program
+            .request()
+            .accounts(claim_accounts())
+            .args(anchor_program::instruction::ClaimWithdraw {})
+            .send()
+            .is_err(),
+        "Claim should fail before the timelock expires"
+    );
+
+    // Wait for the cluster clock to pass the unlock time and claim
+    let vault_balance_before = rpc_client
+        .get_balance(&vault_pda)
+        .expect("Failed to get vault balance");
+    let mut claimed = false;
+    for _ in 0..30 {
+        thread::sleep(Duration::from_secs(1));
+        if program
+            .request()
+            .accounts(claim_accounts())
+            .args(anchor_program::instruction::ClaimWithdraw {})
+            .send()
+            .is_ok()
+        {
+            claimed = true;
+            break;
+        }
+    }
+    assert!(claimed, "Claim should succeed after the timelock expires");
+
+    assert_eq!(
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        vault_balance_before - withdraw_amount,
+        "Vault balance should decrease by the claimed amount"
+    );
+}
@@ -0,0 +1,310 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, program_pack::Pack,
+        pubkey::Pubkey, signature::Keypair, signer::Signer, system_instruction, system_program,
+    },
+    Client, Cluster,
+};
+use anchor_spl::{
+    associated_token::{
+        get_associated_token_address, get_associated_token_address_with_program_id,
+        spl_associated_token_account::instruction::create_associated_token_account,
+    },
+    token::spl_token::{
+        self, instruction as token_instruction,
+        state::{Account as TokenAccount, Mint},
+    },
+    token_2022::spl_token_2022::{
+        self,
+        extension::{
+            transfer_fee::instruction::initialize_transfer_fee_config, ExtensionType,
+            StateWithExtensions,
+        },
+        instruction as token_2022_instruction,
+        state::{Account as TokenAccount2022, Mint as Mint2022},
+    },
+};
+use std::str::FromStr;
+use vault_interface::{find_config_address, find_user_account_address, find_vault_address};
+
+#[test]
+fn test_token_deposit_withdraw() {
+    // Use a fresh keypair that also acts as the mint authority
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+
+    // Create a mint and fund the user's associated token account
+    let mint = Keypair::new();
+    let mint_rent = rpc_client
+        .get_minimum_balance_for_rent_exemption(Mint::LEN)
+        .expect("Failed to get mint rent");
+    let user_token_account = get_associated_token_address(&user_pubkey, &mint.pubkey());
+    let vault_token_account = get_associated_token_address(&vault_pda, &mint.pubkey());
+    let minted_amount = 1_000_000;
+
+    program
+        .request()
+        .instruction(system_instruction::create_account(
+            &user_pubkey,
+            &mint.pubkey(),
+            mint_rent,
+            Mint::LEN as u64,
+            &spl_token::ID,
+        ))
+        .instruction(
+            token_instruction::initialize_mint2(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &user_pubkey,
+                None,
+                6,
+            )
+            .expect("Failed to build initialize mint instruction"),
+        )
+        .instruction(create_associated_token_account(
+            &user_pubkey,
+            &user_pubkey,
+            &mint.pubkey(),
+            &spl_token::ID,
+        ))
+        .instruction(
+            token_instruction::mint_to(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &user_token_account,
+                &user_pubkey,
+                &[],
+                minted_amount,
+            )
+            .expect("Failed to build mint to instruction"),
+        )
+        .signer(&mint)
+        .send()
+        .expect("Failed to set up mint");
+
+    let token_balance = |address: &Pubkey| -> u64 {
+        let account = rpc_client
+            .get_account(address)
+            .expect("Failed to get token account");
+        TokenAccount::unpack(&account.data)
+            .expect("Failed to unpack token account")
+            .amount
+    };
+
+    // Deposit tokens, creating the vault token account on the way
+    let deposit_amount = 400_000;
+    let tx = program
+        .request()
+        .accounts(anchor_program::accounts::DepositToken {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            mint: mint.pubkey(),
+            user_token_account,
+            vault_token_account,
+            token_program: spl_token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: system_program::ID,
+            config: config_pda,
+        })
+        .args(anchor_program::instruction::DepositToken {
+            amount: deposit_amount,
+        })
+        .send()
+        .expect("Failed to deposit tokens");
+
+    println!("\nToken deposit transaction signature: {}", tx);
+    assert_eq!(
+        token_balance(&vault_token_account),
+        deposit_amount,
+        "Vault token balance should increase by deposit amount"
+    );
+
+    // Withdraw part of the tokens back to the user
+    let withdraw_amount = 150_000;
+    let tx = program
+        .request()
+        .accounts(anchor_program::accounts::WithdrawToken {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            mint: mint.pubkey(),
+            user_token_account,
+            vault_token_account,
+            token_program: spl_token::ID,
+            config: config_pda,
+        })
+        .args(anchor_program::instruction::WithdrawToken {
+            amount: withdraw_amount,
+            signer_count: 0,
+        })
+        .send()
+        .expect("Failed to withdraw tokens");
+
+    println!("\nToken withdraw transaction signature: {}", tx);
+    assert_eq!(
+        token_balance(&vault_token_account),
+        deposit_amount - withdraw_amount,
+        "Vault token balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        token_balance(&user_token_account),
+        minted_amount - deposit_amount + withdraw_amount,
+        "User token balance should reflect the deposit and withdrawal"
+    );
+}
+
+#[test]
+fn test_token_2022_transfer_fee_deposit() {
+    // Use a fresh keypair that also acts as the mint and fee authority
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+
+    // Create a Token-2022 mint with a 1% transfer fee
+    let mint = Keypair::new();
+    let mint_len =
+        ExtensionType::try_calculate_account_len::<Mint2022>(&[ExtensionType::TransferFeeConfig])
+            .expect("Failed to calculate mint size");
+    let mint_rent = rpc_client
+        .get_minimum_balance_for_rent_exemption(mint_len)
+        .expect("Failed to get mint rent");
+    let user_token_account = get_associated_token_address_with_program_id(
+        &user_pubkey,
+        &mint.pubkey(),
+        &spl_token_2022::ID,
+    );
+    let vault_token_account = get_associated_token_address_with_program_id(
+        &vault_pda,
+        &mint.pubkey(),
+        &spl_token_2022::ID,
+    );
+    let transfer_fee_basis_points = 100;
+
+    program
+        .request()
+        .instruction(system_instruction::create_account(
+            &user_pubkey,
+            &mint.pubkey(),
+            mint_rent,
+            mint_len as u64,
+            &spl_token_2022::ID,
+        ))
+        .instruction(
+            initialize_transfer_fee_config(
+                &spl_token_2022::ID,
+                &mint.pubkey(),
+                Some(&user_pubkey),
+                Some(&user_pubkey),
+                transfer_fee_basis_points,
+                u64::MAX,
+            )
+            .expect("Failed to build transfer fee instruction"),
+        )
+        .instruction(
+            token_2022_instruction::initialize_mint2(
+                &spl_token_2022::ID,
+                &mint.pubkey(),
+                &user_pubkey,
+                None,
+                6,
+            )
+            .expect("Failed to build initialize mint instruction"),
+        )
+        .instruction(create_associated_token_account(
+            &user_pubkey,
+            &user_pubkey,
+            &mint.pubkey(),
+            &spl_token_2022::ID,
+        ))
+        .instruction(
+            token_2022_instruction::mint_to(
+                &spl_token_2022::ID,
+                &mint.pubkey(),
+                &user_token_account,
+                &user_pubkey,
+                &[],
+                1_000_000,
+            )
+            .expect("Failed to build mint to instruction"),
+        )
+        .signer(&mint)
+        .send()
+        .expect("Failed to set up mint");
+
+    // Deposit through the Token-2022 program
+    let deposit_amount = 400_000;
+    let tx = program
+        .request()
+        .accounts(anchor_program::accounts::DepositToken {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            mint: mint.pubkey(),
+            user_token_account,
+            vault_token_account,
+            token_program: spl_token_2022::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: system_program::ID,
+            config: config_pda,
+        })
+        .args(anchor_program::instruction::DepositToken {
+            amount: deposit_amount,
+        })
+        .send()
+        .expect("Failed to deposit tokens");
+
+    println!("\nToken-2022 deposit transaction signature: {}", tx);
+
+    // The vault holds the deposit minus the withheld transfer fee
+    let vault_token = rpc_client
+        .get_account(&vault_token_account)
+        .expect("Failed to get vault token account");
+    let vault_amount = StateWithExtensions::<TokenAccount2022>::unpack(&vault_token.data)
+        .expect("Failed to unpack vault token account")
+        .base
+        .amount;
+    let expected_fee = deposit_amount * transfer_fee_basis_points as u64 / 10_000;
+    assert_eq!(
+        vault_amount,
+        deposit_amount - expected_fee,
+        "Vault should receive the deposit minus the transfer fee"
+    );
+}
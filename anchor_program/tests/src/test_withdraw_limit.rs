@@ -0,0 +1,135 @@
+use anchor_client::{
+    solana_sdk::{
+        commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+        signature::Keypair, signer::Signer, system_program,
+    },
+    Client, Cluster,
+};
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+};
+
+#[test]
+fn test_withdraw_limit() {
+    // Use a fresh keypair so the limit does not affect the other tests
+    let program_id_str = "Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB";
+    let payer = Keypair::new();
+
+    let client = Client::new_with_options(Cluster::Localnet, &payer, CommitmentConfig::confirmed());
+    let program_id = Pubkey::from_str(program_id_str).expect("Invalid program ID");
+    let program = client.program(program_id).expect("Failed to get program");
+    let rpc_client = program.rpc();
+
+    let user_pubkey = payer.pubkey();
+    let airdrop_signature = rpc_client
+        .request_airdrop(&user_pubkey, LAMPORTS_PER_SOL * 2)
+        .expect("Failed to request airdrop");
+    while !rpc_client
+        .confirm_transaction(&airdrop_signature)
+        .expect("Failed to confirm airdrop")
+    {}
+
+    // Derive the user account and vault PDAs
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    let withdraw = |amount: u64| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::Withdraw {
+                user: user_pubkey,
+                user_account: user_account_pda,
+                vault: vault_pda,
+                system_program: system_program::ID,
+                config: config_pda,
+                treasury: treasury_pda,
+            })
+            .args(anchor_program::instruction::Withdraw { amount })
+            .send()
+    };
+    let set_withdraw_limit = |limit: u64, window: i64| {
+        program
+            .request()
+            .accounts(anchor_program::accounts::SetWithdrawLimit {
+                user: user_pubkey,
+                user_account: user_account_pda,
+            })
+            .args(anchor_program::instruction::SetWithdrawLimit { limit, window })
+            .send()
+    };
+
+    // Deposit so that both the user account and the vault exist
+    program
+        .request()
+        .accounts(anchor_program::accounts::Deposit {
+            user: user_pubkey,
+            user_account: user_account_pda,
+            vault: vault_pda,
+            system_program: system_program::ID,
+            config: config_pda,
+            treasury: treasury_pda,
+        })
+        .args(anchor_program::instruction::Deposit {
+            amount: LAMPORTS_PER_SOL,
+        })
+        .send()
+        .expect("Failed to deposit");
+
+    // A limit without a window is rejected
+    let limit = LAMPORTS_PER_SOL / 10;
+    let window = 10;
+    assert!(
+        set_withdraw_limit(limit, 0).is_err(),
+        "A limit without a window should be rejected"
+    );
+
+    // Limiting an unlimited vault applies immediately
+    set_withdraw_limit(limit, window).expect("Failed to set withdraw limit");
+    withdraw(limit / 2).expect("Failed to withdraw");
+    assert!(
+        withdraw(limit / 2 + 1).is_err(),
+        "Withdraw should fail beyond the remaining limit"
+    );
+
+    // Lowering the limit also applies immediately, the window is already spent
+    set_withdraw_limit(limit / 2, window).expect("Failed to lower withdraw limit");
+    assert!(
+        withdraw(1).is_err(),
+        "Withdraw should fail once the lowered limit is spent"
+    );
+
+    // Raising the limit only applies once the current window length has passed
+    set_withdraw_limit(limit * 2, window).expect("Failed to raise withdraw limit");
+    assert!(
+        withdraw(limit).is_err(),
+        "A raised limit should not apply immediately"
+    );
+
+    // Wait for the cluster clock to pass the raise and withdraw under the new limit
+    let vault_balance_before = rpc_client
+        .get_balance(&vault_pda)
+        .expect("Failed to get vault balance");
+    let mut withdrew = false;
+    for _ in 0..30 {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        if withdraw(limit).is_ok() {
+            withdrew = true;
+            break;
+        }
+    }
+    assert!(
+        withdrew,
+        "Withdraw should succeed once the raised limit applies"
+    );
+
+    assert_eq!(
+        rpc_client
+            .get_balance(&vault_pda)
+            .expect("Failed to get vault balance"),
+        vault_balance_before - limit,
+        "Vault balance should decrease by withdraw amount"
+    );
+}
@@ -0,0 +1,314 @@
+// Compute-unit, heap and binary-size benchmarks of the three implementations of the
+// vault program. Each implementation runs the same scenarios in its own LiteSVM. The
+// results are printed as a Markdown table, optionally written as JSON, and compared
+// against the baseline checked in next to this crate, so that a change which makes a
+// program bigger or more expensive shows up as a failure.
+//
+// Neither LiteSVM nor Mollusk expose how much of the heap frame a program used, so each
+// program also has a heap-usage build that logs it after every instruction. The
+// scenarios run a second time against that build, as the logging costs compute units.
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use differential::{Implementation, IMPLEMENTATIONS};
+use litesvm::{types::TransactionMetadata, LiteSVM};
+use serde::{Deserialize, Serialize};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use solana_sdk::{
+    native_token::LAMPORTS_PER_SOL,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use std::{collections::BTreeMap, fmt::Write, fs, path::PathBuf};
+use vault_interface::{find_vault_address, instruction};
+
+#[cfg(test)]
+mod test;
+
+const BASELINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/baseline.json");
+
+#[derive(Parser)]
+#[command(about = "Compute-unit and binary-size benchmarks of the vault implementations")]
+struct Cli {
+    /// Also write the results as JSON to this file
+    #[arg(long)]
+    json: Option<PathBuf>,
+
+    /// Baseline to compare against
+    #[arg(long, default_value = BASELINE)]
+    baseline: PathBuf,
+
+    /// Overwrite the baseline with the results instead of comparing
+    #[arg(long)]
+    update_baseline: bool,
+
+    /// Percentage by which a number may grow before it counts as a regression
+    #[arg(long, default_value_t = 0.0)]
+    tolerance: f64,
+}
+
+// A measured instruction. The scenarios run in order on the same SVM, so each one
+// starts from the state the previous ones left.
+struct Scenario {
+    name: &'static str,
+    build: fn(&Pubkey, &Pubkey) -> Instruction, // from the program ID and the user
+    succeeds: bool,
+}
+
+const SCENARIOS: [Scenario; 6] = [
+    // Creates the user account and the vault
+    Scenario {
+        name: "deposit_first",
+        build: |program_id, user| instruction::deposit(program_id, user, LAMPORTS_PER_SOL),
+        succeeds: true,
+    },
+    Scenario {
+        name: "deposit",
+        build: |program_id, user| instruction::deposit(program_id, user, LAMPORTS_PER_SOL),
+        succeeds: true,
+    },
+    Scenario {
+        name: "withdraw",
+        build: |program_id, user| instruction::withdraw(program_id, user, LAMPORTS_PER_SOL),
+        succeeds: true,
+    },
+    Scenario {
+        name: "withdraw_insufficient_funds",
+        build: |program_id, user| instruction::withdraw(program_id, user, 100 * LAMPORTS_PER_SOL),
+        succeeds: false,
+    },
+    Scenario {
+        name: "withdraw_wrong_vault",
+        build: |program_id, user| {
+            let mut instruction = instruction::withdraw(program_id, user, 1);
+            instruction.accounts[2].pubkey = find_vault_address(&Pubkey::default(), program_id).0;
+            instruction
+        },
+        succeeds: false,
+    },
+    Scenario {
+        name: "close",
+        build: |program_id, user| instruction::close(program_id, user),
+        succeeds: true,
+    },
+];
+
+// Results of one implementation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Results {
+    size: u64,                            // bytes of the compiled program
+    compute_units: BTreeMap<String, u64>, // by scenario
+    heap: BTreeMap<String, u64>,          // bytes of heap used, by scenario
+}
+
+// Results by implementation
+type Report = BTreeMap<String, Results>;
+
+// The heap-usage build of an implementation, built next to its deploy build
+fn heap_usage_path(implementation: &Implementation) -> String {
+    implementation
+        .path
+        .replace("/target/deploy/", "/target/heap-usage/")
+}
+
+// Run the scenarios against the program at the given path and return the metadata of
+// each transaction, in scenario order
+fn run_scenarios(implementation: &Implementation, path: &str) -> Result<Vec<TransactionMetadata>> {
+    let mut svm = LiteSVM::new();
+    svm.add_program_from_file(implementation.program_id, path)
+        .with_context(|| {
+            format!(
+                "Missing the {} program at {}, build it first",
+                implementation.name, path
+            )
+        })?;
+    let user = Keypair::new();
+    svm.airdrop(&user.pubkey(), 10 * LAMPORTS_PER_SOL)
+        .map_err(|failed| anyhow!("Airdrop failed: {:?}", failed.err))?;
+
+    let mut results = Vec::new();
+    for scenario in &SCENARIOS {
+        let instruction =
+            implementation.encode((scenario.build)(&implementation.program_id, &user.pubkey()));
+        svm.expire_blockhash();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user.pubkey()),
+            &[&user],
+            svm.latest_blockhash(),
+        );
+        let (succeeded, meta) = match svm.send_transaction(transaction) {
+            Ok(meta) => (true, meta),
+            Err(failed) => (false, failed.meta),
+        };
+        if succeeded != scenario.succeeds {
+            bail!(
+                "{} {} {} unexpectedly:\n{}",
+                implementation.name,
+                scenario.name,
+                if succeeded { "succeeded" } else { "failed" },
+                meta.logs.join("\n")
+            );
+        }
+        results.push(meta);
+    }
+    Ok(results)
+}
+
+// The heap used as logged by the heap-usage build, None in any other build
+fn heap_used(logs: &[String]) -> Option<u64> {
+    logs.iter().rev().find_map(|log| {
+        log.strip_prefix("Program log: Heap used: ")?
+            .strip_suffix(" bytes")?
+            .parse()
+            .ok()
+    })
+}
+
+fn run(implementation: &Implementation) -> Result<Results> {
+    let size = fs::metadata(implementation.path)
+        .with_context(|| {
+            format!(
+                "Missing the {} program at {}, build it first",
+                implementation.name, implementation.path
+            )
+        })?
+        .len();
+    let compute_units = SCENARIOS
+        .iter()
+        .zip(run_scenarios(implementation, implementation.path)?)
+        .map(|(scenario, meta)| (scenario.name.to_string(), meta.compute_units_consumed))
+        .collect();
+    let heap = SCENARIOS
+        .iter()
+        .zip(run_scenarios(
+            implementation,
+            &heap_usage_path(implementation),
+        )?)
+        .map(|(scenario, meta)| {
+            let heap = heap_used(&meta.logs).ok_or_else(|| {
+                anyhow!(
+                    "{} {} logged no heap usage, is it the heap-usage build?",
+                    implementation.name,
+                    scenario.name
+                )
+            })?;
+            Ok((scenario.name.to_string(), heap))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Results {
+        size,
+        compute_units,
+        heap,
+    })
+}
+
+// One row per metric, one column per implementation
+fn markdown(report: &Report) -> String {
+    let implementations: Vec<&String> = report.keys().collect();
+    let mut table = String::from("| |");
+    let mut separator = String::from("|---|");
+    for name in &implementations {
+        write!(table, " {} |", name).unwrap();
+        separator.push_str("---:|");
+    }
+    writeln!(table, "\n{}", separator).unwrap();
+
+    let mut row = |label: &str, value: &dyn Fn(&Results) -> Option<u64>| {
+        write!(table, "| {} |", label).unwrap();
+        for name in &implementations {
+            match value(&report[*name]) {
+                Some(value) => write!(table, " {} |", value).unwrap(),
+                None => table.push_str(" - |"),
+            }
+        }
+        table.push('\n');
+    };
+    row("Binary size (bytes)", &|results| Some(results.size));
+    for scenario in &SCENARIOS {
+        row(&format!("{} (CU)", scenario.name), &|results| {
+            results.compute_units.get(scenario.name).copied()
+        });
+    }
+    for scenario in &SCENARIOS {
+        row(&format!("{} (heap bytes)", scenario.name), &|results| {
+            results.heap.get(scenario.name).copied()
+        });
+    }
+    table
+}
+
+// Every number that grew by more than the tolerance, in percent, over the baseline.
+// Implementations and scenarios missing from the baseline are new and not compared.
+fn regressions(report: &Report, baseline: &Report, tolerance: f64) -> Vec<String> {
+    let mut regressions = Vec::new();
+    for (name, results) in report {
+        let base = match baseline.get(name) {
+            Some(base) => base,
+            None => continue,
+        };
+        let mut check = |metric: &str, value: u64, base: u64| {
+            if value as f64 > base as f64 * (1.0 + tolerance / 100.0) {
+                regressions.push(format!(
+                    "{} {}: {} -> {} (+{:.1}%)",
+                    name,
+                    metric,
+                    base,
+                    value,
+                    (value as f64 / base as f64 - 1.0) * 100.0
+                ));
+            }
+        };
+        check("size", results.size, base.size);
+        for (scenario, &units) in &results.compute_units {
+            if let Some(&base_units) = base.compute_units.get(scenario) {
+                check(scenario, units, base_units);
+            }
+        }
+        for (scenario, &heap) in &results.heap {
+            if let Some(&base_heap) = base.heap.get(scenario) {
+                check(&format!("{} heap", scenario), heap, base_heap);
+            }
+        }
+    }
+    regressions
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let report = IMPLEMENTATIONS
+        .iter()
+        .map(|implementation| Ok((implementation.name.to_string(), run(implementation)?)))
+        .collect::<Result<Report>>()?;
+    print!("{}", markdown(&report));
+
+    let json = serde_json::to_string_pretty(&report)? + "\n";
+    if let Some(path) = &cli.json {
+        fs::write(path, &json).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    if cli.update_baseline {
+        fs::write(&cli.baseline, &json)
+            .with_context(|| format!("Failed to write {}", cli.baseline.display()))?;
+        println!("\nBaseline written to {}", cli.baseline.display());
+        return Ok(());
+    }
+
+    let baseline: Report = match fs::read_to_string(&cli.baseline) {
+        Ok(baseline) => serde_json::from_str(&baseline)
+            .with_context(|| format!("Failed to parse {}", cli.baseline.display()))?,
+        Err(_) => bail!(
+            "No baseline at {}, run with --update-baseline to create it",
+            cli.baseline.display()
+        ),
+    };
+    let regressions = regressions(&report, &baseline, cli.tolerance);
+    if regressions.is_empty() {
+        println!("\nNo regressions against the baseline");
+        return Ok(());
+    }
+    println!("\nRegressions against the baseline:");
+    for regression in &regressions {
+        println!("- {}", regression);
+    }
+    bail!("{} regressions against the baseline", regressions.len())
+}
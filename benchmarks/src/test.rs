@@ -0,0 +1,74 @@
+use crate::{heap_used, markdown, regressions, Report, Results};
+use std::collections::BTreeMap;
+
+fn report(size: u64, deposit: u64) -> Report {
+    BTreeMap::from([(
+        "native".to_string(),
+        Results {
+            size,
+            compute_units: BTreeMap::from([("deposit".to_string(), deposit)]),
+            heap: BTreeMap::from([("deposit".to_string(), 256)]),
+        },
+    )])
+}
+
+#[test]
+fn test_regressions() {
+    let baseline = report(1_000, 2_000);
+    assert!(regressions(&report(1_000, 2_000), &baseline, 0.0).is_empty());
+    assert!(regressions(&report(900, 1_500), &baseline, 0.0).is_empty());
+    assert_eq!(
+        regressions(&report(1_000, 2_100), &baseline, 0.0),
+        vec!["native deposit: 2000 -> 2100 (+5.0%)"]
+    );
+    assert_eq!(regressions(&report(1_001, 2_100), &baseline, 5.0).len(), 0);
+    assert_eq!(regressions(&report(1_100, 2_101), &baseline, 5.0).len(), 2);
+
+    // Heap counts like the other numbers
+    let mut grown = report(1_000, 2_000);
+    grown
+        .get_mut("native")
+        .unwrap()
+        .heap
+        .insert("deposit".to_string(), 320);
+    assert_eq!(
+        regressions(&grown, &baseline, 0.0),
+        vec!["native deposit heap: 256 -> 320 (+25.0%)"]
+    );
+
+    // New implementations and scenarios have nothing to regress from
+    let mut grown = report(1_000, 2_000);
+    grown.insert(
+        "pinocchio".to_string(),
+        report(5_000, 5_000)["native"].clone(),
+    );
+    grown
+        .get_mut("native")
+        .unwrap()
+        .compute_units
+        .insert("withdraw".to_string(), 3_000);
+    assert!(regressions(&grown, &baseline, 0.0).is_empty());
+}
+
+#[test]
+fn test_markdown() {
+    let table = markdown(&report(1_000, 2_000));
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines[0], "| | native |");
+    assert_eq!(lines[1], "|---|---:|");
+    assert_eq!(lines[2], "| Binary size (bytes) | 1000 |");
+    assert!(lines.contains(&"| deposit (CU) | 2000 |"));
+    assert!(lines.contains(&"| withdraw (CU) | - |"));
+    assert!(lines.contains(&"| deposit (heap bytes) | 256 |"));
+}
+
+#[test]
+fn test_heap_used() {
+    let logs = [
+        "Program DPFTib3APrmJaBYjYmVamEpsPiHQ4cSkYLYXiGQmYUja invoke [1]".to_string(),
+        "Program log: Heap used: 1024 bytes".to_string(),
+        "Program DPFTib3APrmJaBYjYmVamEpsPiHQ4cSkYLYXiGQmYUja success".to_string(),
+    ];
+    assert_eq!(heap_used(&logs), Some(1024));
+    assert_eq!(heap_used(&logs[..1]), None);
+}
@@ -0,0 +1,606 @@
+// Differential harness for the three implementations of the vault program. One sequence
+// of steps is replayed against the native, pinocchio and Anchor programs, each loaded
+// into its own LiteSVM with the same users, and after every step the harness compares
+// whether it succeeded, what it failed with, the user, vault and treasury lamports, the
+// decoded user accounts and the raw config account. The first step on which the implementations disagree is
+// reported with the values each of them ended up with.
+//
+// Build the programs first: cargo build-sbf in native_program/program and in
+// pinocchio_program/program, and anchor build in anchor_program.
+//
+// Some differences are by design and normalized before comparing:
+// - Anchor rejects bad accounts through its constraints, with framework error codes,
+//   where the native and pinocchio programs return InvalidVault, a missing signature
+//   and the like, so every failed account check compares as InvalidAccounts
+// - the bumps depend on the program ID, so they are cleared from the user accounts and
+//   the config
+// - the user account headers differ between layouts, which UserAccount::unpack hides
+use litesvm::LiteSVM;
+use solana_program::{
+    bpf_loader_upgradeable,
+    instruction::{Instruction, InstructionError},
+    pubkey,
+    pubkey::Pubkey,
+};
+use solana_sdk::{
+    account::Account,
+    native_token::LAMPORTS_PER_SOL,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use std::fmt::Write;
+use vault_interface::{
+    find_config_address, find_program_data_address, find_treasury_address,
+    find_user_account_address, find_vault_address, instruction, ProgramInstruction, UserAccount,
+};
+
+#[cfg(test)]
+mod test;
+
+// How an implementation expects its instruction data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Borsh,  // borsh variant index, then the fields
+    Anchor, // 8-byte discriminator, then the fields
+}
+
+pub struct Implementation {
+    pub name: &'static str,
+    pub program_id: Pubkey,
+    pub path: &'static str, // compiled program
+    pub encoding: Encoding,
+}
+
+impl Implementation {
+    // Re-encode an instruction from the vault_interface builders for this implementation
+    pub fn encode(&self, mut instruction: Instruction) -> Instruction {
+        if self.encoding == Encoding::Anchor {
+            instruction.data = ProgramInstruction::unpack(&instruction.data)
+                .expect("Invalid instruction data")
+                .pack_anchor();
+        }
+        instruction
+    }
+}
+
+pub const IMPLEMENTATIONS: [Implementation; 3] = [
+    Implementation {
+        name: "native",
+        program_id: pubkey!("DPFTib3APrmJaBYjYmVamEpsPiHQ4cSkYLYXiGQmYUja"),
+        path: concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../native_program/program/target/deploy/program.so"
+        ),
+        encoding: Encoding::Borsh,
+    },
+    Implementation {
+        name: "pinocchio",
+        program_id: pubkey!("G7isKoAvjaMXi7CSDZTspXvUaD2dfVNwZyrWYTe6nfoj"),
+        path: concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../pinocchio_program/program/target/deploy/pinocchio_program.so"
+        ),
+        encoding: Encoding::Borsh,
+    },
+    Implementation {
+        name: "anchor",
+        program_id: pubkey!("Hai1ivWmZHQD9aWuVzDQSGovam7p3ttdsFTmmiTVvAvB"),
+        path: concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../anchor_program/target/deploy/anchor_program.so"
+        ),
+        encoding: Encoding::Anchor,
+    },
+];
+
+// Vault errors from InvalidSystemProgram to NotAdmin mirror Anchor's account constraints
+const FIRST_ACCOUNT_CHECK_ERROR: u32 = 6019;
+const LAST_ACCOUNT_CHECK_ERROR: u32 = 6031;
+
+// Rent-exempt minimum of an empty system account, such as a vault
+const VAULT_RENT: u64 = 890_880;
+
+// Offsets of the config and treasury bumps in the config account: discriminator, admin,
+// paused and withdrawals_paused come first
+const CONFIG_BUMP_OFFSET: usize = 8 + 32 + 1 + 1;
+const TREASURY_BUMP_OFFSET: usize = CONFIG_BUMP_OFFSET + 1;
+
+// The harness user recorded as the upgrade authority of every program
+pub const UPGRADE_AUTHORITY: usize = 0;
+
+// One transaction of a sequence. Users are indexes into the harness users.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    Deposit {
+        user: usize,
+        amount: u64,
+    },
+    Withdraw {
+        user: usize,
+        amount: u64,
+    },
+    Close {
+        user: usize,
+    },
+    WithdrawAll {
+        user: usize,
+    },
+    DepositFor {
+        funder: usize,
+        beneficiary: usize,
+        amount: u64,
+    },
+    // Withdraw signed by the user, but from the vault of the owner
+    WithdrawFromVaultOf {
+        user: usize,
+        owner: usize,
+        amount: u64,
+    },
+    // Withdraw signed by the user, but with the user account of the owner
+    WithdrawWithUserAccountOf {
+        user: usize,
+        owner: usize,
+        amount: u64,
+    },
+    // Deposit with another account in place of the system program
+    DepositWithFakeSystemProgram {
+        user: usize,
+        amount: u64,
+    },
+    // Withdraw the user did not sign, paid for by the payer
+    UnsignedWithdraw {
+        user: usize,
+        payer: usize,
+        amount: u64,
+    },
+    // Create the config with the user as admin, only the upgrade authority may
+    InitializeConfig {
+        user: usize,
+    },
+    SetPaused {
+        admin: usize,
+        deposits: bool,
+        withdrawals: bool,
+    },
+    SetFees {
+        admin: usize,
+        deposit_bps: u16,
+        withdraw_bps: u16,
+    },
+}
+
+// Outcome of a transaction, normalized so that the same failure compares equal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Succeeded,
+    // Rejected with a vault error every implementation returns, or with a custom
+    // error of the system program
+    Error(u32),
+    // Rejected by a check on the accounts passed in
+    InvalidAccounts,
+    // Any other transaction error
+    Failed(String),
+}
+
+pub fn normalize(error: &TransactionError) -> Status {
+    match error {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => match *code {
+            // Anchor framework errors, raised by its account constraints
+            100..=5999 => Status::InvalidAccounts,
+            FIRST_ACCOUNT_CHECK_ERROR..=LAST_ACCOUNT_CHECK_ERROR => Status::InvalidAccounts,
+            code => Status::Error(code),
+        },
+        // The native and pinocchio programs reject some accounts with builtin errors
+        TransactionError::InstructionError(
+            _,
+            InstructionError::MissingRequiredSignature
+            | InstructionError::IncorrectProgramId
+            | InstructionError::InvalidAccountData
+            | InstructionError::InvalidAccountOwner
+            | InstructionError::IllegalOwner
+            | InstructionError::InvalidSeeds
+            | InstructionError::UninitializedAccount
+            | InstructionError::NotEnoughAccountKeys,
+        ) => Status::InvalidAccounts,
+        error => Status::Failed(error.to_string()),
+    }
+}
+
+// Everything the harness compares after a step, as seen by one implementation. The
+// vectors are indexed by user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub status: Status,
+    pub user_lamports: Vec<u64>,
+    pub vault_lamports: Vec<u64>,
+    pub user_account_lamports: Vec<u64>,
+    pub user_accounts: Vec<Option<UserAccount>>, // bumps cleared
+    pub treasury_lamports: u64,
+    pub config: Option<Vec<u8>>, // raw account data, bumps cleared
+}
+
+pub struct Harness {
+    users: Vec<Keypair>,
+    svms: Vec<LiteSVM>, // in the order of IMPLEMENTATIONS
+}
+
+impl Harness {
+    // Load every implementation into its own SVM and fund the same users in each. The
+    // first user is the upgrade authority of every program.
+    pub fn new(user_count: usize, lamports: u64) -> Self {
+        let users: Vec<Keypair> = (0..user_count).map(|_| Keypair::new()).collect();
+        let svms = IMPLEMENTATIONS
+            .iter()
+            .map(|implementation| {
+                let mut svm = LiteSVM::new();
+                svm.add_program_from_file(implementation.program_id, implementation.path)
+                    .unwrap_or_else(|error| {
+                        panic!(
+                            "Failed to load the {} program from {}, build it first: {}",
+                            implementation.name, implementation.path, error
+                        )
+                    });
+                for user in &users {
+                    svm.airdrop(&user.pubkey(), lamports)
+                        .expect("Airdrop failed");
+                }
+                set_program_data(&mut svm, implementation, &users[UPGRADE_AUTHORITY].pubkey());
+                svm
+            })
+            .collect();
+        Self { users, svms }
+    }
+
+    // Send a step to every implementation and return the state each one ended up in
+    pub fn apply(&mut self, step: &Step) -> Vec<Snapshot> {
+        let users = &self.users;
+        IMPLEMENTATIONS
+            .iter()
+            .zip(&mut self.svms)
+            .map(|(implementation, svm)| {
+                // Every step is signed by the payer alone
+                let (instruction, payer) = build(step, implementation, users);
+                let payer = &users[payer];
+                svm.expire_blockhash();
+                let transaction = Transaction::new_signed_with_payer(
+                    &[instruction],
+                    Some(&payer.pubkey()),
+                    &[payer],
+                    svm.latest_blockhash(),
+                );
+                let status = match svm.send_transaction(transaction) {
+                    Ok(_) => Status::Succeeded,
+                    Err(failed) => normalize(&failed.err),
+                };
+                snapshot(svm, implementation, users, status)
+            })
+            .collect()
+    }
+
+    // Send the step at the given index of a sequence and return the state every
+    // implementation agrees on, or a report of where they differ
+    pub fn step(&mut self, index: usize, step: &Step) -> Result<Snapshot, String> {
+        let mut snapshots = self.apply(step);
+        if snapshots.iter().any(|snapshot| snapshot != &snapshots[0]) {
+            return Err(report(index, step, &snapshots));
+        }
+        Ok(snapshots.swap_remove(0))
+    }
+
+    // Replay the steps and stop at the first one on which the implementations disagree
+    pub fn run(&mut self, steps: &[Step]) -> Result<(), String> {
+        for (index, step) in steps.iter().enumerate() {
+            self.step(index, step)?;
+        }
+        Ok(())
+    }
+}
+
+// Write the ProgramData account that InitializeConfig reads the upgrade authority from:
+// the account type, the deployment slot, then an optional upgrade authority
+fn set_program_data(
+    svm: &mut LiteSVM,
+    implementation: &Implementation,
+    upgrade_authority: &Pubkey,
+) {
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(upgrade_authority.as_ref());
+    let (program_data, _) = find_program_data_address(&implementation.program_id);
+    svm.set_account(
+        program_data,
+        Account {
+            lamports: svm.minimum_balance_for_rent_exemption(data.len()),
+            data,
+            owner: bpf_loader_upgradeable::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    )
+    .expect("Failed to write the program data account");
+}
+
+// Build the instruction for a step against one implementation, along with the user
+// who signs and pays for it
+fn build(step: &Step, implementation: &Implementation, users: &[Keypair]) -> (Instruction, usize) {
+    let program_id = &implementation.program_id;
+    let key = |user: usize| users[user].pubkey();
+    let (instruction, payer) = match *step {
+        Step::Deposit { user, amount } => {
+            (instruction::deposit(program_id, &key(user), amount), user)
+        }
+        Step::Withdraw { user, amount } => {
+            (instruction::withdraw(program_id, &key(user), amount), user)
+        }
+        Step::Close { user } => (instruction::close(program_id, &key(user)), user),
+        Step::WithdrawAll { user } => (instruction::withdraw_all(program_id, &key(user)), user),
+        Step::DepositFor {
+            funder,
+            beneficiary,
+            amount,
+        } => (
+            instruction::deposit_for(program_id, &key(funder), &key(beneficiary), amount),
+            funder,
+        ),
+        Step::WithdrawFromVaultOf {
+            user,
+            owner,
+            amount,
+        } => {
+            let mut instruction = instruction::withdraw(program_id, &key(user), amount);
+            instruction.accounts[2].pubkey = find_vault_address(&key(owner), program_id).0;
+            (instruction, user)
+        }
+        Step::WithdrawWithUserAccountOf {
+            user,
+            owner,
+            amount,
+        } => {
+            let mut instruction = instruction::withdraw(program_id, &key(user), amount);
+            instruction.accounts[1].pubkey = find_user_account_address(&key(owner), program_id).0;
+            (instruction, user)
+        }
+        Step::DepositWithFakeSystemProgram { user, amount } => {
+            let mut instruction = instruction::deposit(program_id, &key(user), amount);
+            instruction.accounts[3].pubkey = Pubkey::new_unique();
+            (instruction, user)
+        }
+        Step::UnsignedWithdraw {
+            user,
+            payer,
+            amount,
+        } => {
+            let mut instruction = instruction::withdraw(program_id, &key(user), amount);
+            instruction.accounts[0].is_signer = false;
+            (instruction, payer)
+        }
+        Step::InitializeConfig { user } => (
+            instruction::initialize_config(program_id, &key(user), &key(user)),
+            user,
+        ),
+        Step::SetPaused {
+            admin,
+            deposits,
+            withdrawals,
+        } => (
+            instruction::set_paused(program_id, &key(admin), deposits, withdrawals),
+            admin,
+        ),
+        Step::SetFees {
+            admin,
+            deposit_bps,
+            withdraw_bps,
+        } => (
+            instruction::set_fees(program_id, &key(admin), deposit_bps, withdraw_bps),
+            admin,
+        ),
+    };
+    (implementation.encode(instruction), payer)
+}
+
+fn snapshot(
+    svm: &LiteSVM,
+    implementation: &Implementation,
+    users: &[Keypair],
+    status: Status,
+) -> Snapshot {
+    let program_id = &implementation.program_id;
+    let balance = |address: &Pubkey| svm.get_balance(address).unwrap_or(0);
+    let mut snapshot = Snapshot {
+        status,
+        user_lamports: Vec::new(),
+        vault_lamports: Vec::new(),
+        user_account_lamports: Vec::new(),
+        user_accounts: Vec::new(),
+        treasury_lamports: balance(&find_treasury_address(program_id).0),
+        config: svm
+            .get_account(&find_config_address(program_id).0)
+            .filter(|account| account.lamports > 0)
+            .map(|account| {
+                let mut data = account.data;
+                if data.len() > TREASURY_BUMP_OFFSET {
+                    data[CONFIG_BUMP_OFFSET] = 0;
+                    data[TREASURY_BUMP_OFFSET] = 0;
+                }
+                data
+            }),
+    };
+    for user in users {
+        let (user_account_pda, _) = find_user_account_address(&user.pubkey(), program_id);
+        let (vault_pda, _) = find_vault_address(&user.pubkey(), program_id);
+        snapshot.user_lamports.push(balance(&user.pubkey()));
+        snapshot.vault_lamports.push(balance(&vault_pda));
+        snapshot
+            .user_account_lamports
+            .push(balance(&user_account_pda));
+        // A closed account may linger without lamports until the end of the slot
+        let user_account = svm
+            .get_account(&user_account_pda)
+            .filter(|account| account.lamports > 0)
+            .and_then(|account| UserAccount::unpack(&account.data).ok())
+            .map(|user_account| UserAccount {
+                user_bump: 0,
+                vault_bump: 0,
+                ..user_account
+            });
+        snapshot.user_accounts.push(user_account);
+    }
+    snapshot
+}
+
+// Describe the fields the implementations disagree on after a step
+fn report(index: usize, step: &Step, snapshots: &[Snapshot]) -> String {
+    let mut fields: Vec<(String, Vec<String>)> = vec![(
+        "status".to_string(),
+        snapshots
+            .iter()
+            .map(|snapshot| format!("{:?}", snapshot.status))
+            .collect(),
+    )];
+    for user in 0..snapshots[0].user_lamports.len() {
+        let field = |name: &str, show: &dyn Fn(&Snapshot) -> String| {
+            (
+                format!("{} of user {}", name, user),
+                snapshots.iter().map(show).collect(),
+            )
+        };
+        fields.push(field("lamports", &|snapshot| {
+            snapshot.user_lamports[user].to_string()
+        }));
+        fields.push(field("vault lamports", &|snapshot| {
+            snapshot.vault_lamports[user].to_string()
+        }));
+        fields.push(field("user account lamports", &|snapshot| {
+            snapshot.user_account_lamports[user].to_string()
+        }));
+        fields.push(field("user account", &|snapshot| {
+            format!("{:?}", snapshot.user_accounts[user])
+        }));
+    }
+
+    fields.push((
+        "treasury lamports".to_string(),
+        snapshots
+            .iter()
+            .map(|snapshot| snapshot.treasury_lamports.to_string())
+            .collect(),
+    ));
+    fields.push((
+        "config".to_string(),
+        snapshots
+            .iter()
+            .map(|snapshot| format!("{:?}", snapshot.config))
+            .collect(),
+    ));
+
+    let mut report = format!("Step {} diverged: {:?}\n", index, step);
+    for (field, values) in fields {
+        if values.iter().all(|value| value == &values[0]) {
+            continue;
+        }
+        writeln!(report, "  {}:", field).unwrap();
+        for (implementation, value) in IMPLEMENTATIONS.iter().zip(values) {
+            writeln!(report, "    {:<10}{}", implementation.name, value).unwrap();
+        }
+    }
+    report
+}
+
+// Small deterministic generator, so that a failing seed can be replayed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift never leaves zero, so keep the low bit set
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+// Fees biased towards the edges: none, the maximum and just above it
+fn random_fee(rng: &mut Rng) -> u16 {
+    match rng.below(4) {
+        0 => 0,
+        1 => 1_000,
+        2 => 1_001,
+        _ => rng.below(1_000) as u16,
+    }
+}
+
+// Amounts biased towards the edges: nothing, a single lamport, around the rent-exempt
+// minimum of the vault, and more than any user holds
+fn random_amount(rng: &mut Rng) -> u64 {
+    match rng.below(8) {
+        0 => 0,
+        1 => 1,
+        2 => VAULT_RENT - 1,
+        3 => VAULT_RENT,
+        4 => LAMPORTS_PER_SOL,
+        5 => 1_000 * LAMPORTS_PER_SOL,
+        _ => rng.below(LAMPORTS_PER_SOL),
+    }
+}
+
+// A random sequence over at least two users. Most steps move lamports in and out of the
+// vaults, the rest close them, pass accounts that should be rejected or, now and then,
+// change the config.
+pub fn random_steps(seed: u64, user_count: usize, count: usize) -> Vec<Step> {
+    assert!(user_count >= 2, "Invalid account steps need a second user");
+    let mut rng = Rng::new(seed);
+    (0..count)
+        .map(|_| {
+            let user = rng.below(user_count as u64) as usize;
+            let other = (user + 1 + rng.below(user_count as u64 - 1) as usize) % user_count;
+            let amount = random_amount(&mut rng);
+            match rng.below(20) {
+                0..=4 => Step::Deposit { user, amount },
+                5..=9 => Step::Withdraw { user, amount },
+                10 => Step::Close { user },
+                11 => Step::WithdrawAll { user },
+                12 => Step::DepositFor {
+                    funder: user,
+                    beneficiary: other,
+                    amount,
+                },
+                13 => Step::WithdrawFromVaultOf {
+                    user,
+                    owner: other,
+                    amount,
+                },
+                14 => Step::WithdrawWithUserAccountOf {
+                    user,
+                    owner: other,
+                    amount,
+                },
+                15 => Step::DepositWithFakeSystemProgram { user, amount },
+                16 => Step::UnsignedWithdraw {
+                    user,
+                    payer: other,
+                    amount,
+                },
+                17 => Step::InitializeConfig { user },
+                18 => Step::SetPaused {
+                    admin: user,
+                    deposits: rng.below(2) == 0,
+                    withdrawals: rng.below(2) == 0,
+                },
+                _ => Step::SetFees {
+                    admin: user,
+                    deposit_bps: random_fee(&mut rng),
+                    withdraw_bps: random_fee(&mut rng),
+                },
+            }
+        })
+        .collect()
+}
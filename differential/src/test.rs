@@ -0,0 +1,390 @@
+use crate::{normalize, random_steps, Harness, Status, Step, UPGRADE_AUTHORITY, VAULT_RENT};
+use proptest::prelude::*;
+use solana_program::instruction::InstructionError;
+use solana_sdk::{native_token::LAMPORTS_PER_SOL, transaction::TransactionError};
+
+const USERS: usize = 3;
+const LAMPORTS: u64 = 10 * LAMPORTS_PER_SOL;
+const SEEDS: u64 = 8;
+const STEPS: usize = 150;
+const INSUFFICIENT_FUNDS: u32 = 6000;
+const PROGRAM_PAUSED: u32 = 6013;
+const NOT_UPGRADE_AUTHORITY: u32 = 6014;
+const INVALID_FEE: u32 = 6017;
+const VAULT_BELOW_RENT_EXEMPT: u32 = 6032;
+
+fn instruction_error(error: InstructionError) -> TransactionError {
+    TransactionError::InstructionError(0, error)
+}
+
+#[test]
+fn test_normalize() {
+    // Anchor constraint errors, such as seeds, and the vault errors that mirror them
+    assert_eq!(
+        normalize(&instruction_error(InstructionError::Custom(2006))),
+        Status::InvalidAccounts
+    );
+    assert_eq!(
+        normalize(&instruction_error(InstructionError::Custom(6021))),
+        Status::InvalidAccounts
+    );
+    assert_eq!(
+        normalize(&instruction_error(
+            InstructionError::MissingRequiredSignature
+        )),
+        Status::InvalidAccounts
+    );
+    // Vault and system program errors are compared as they are
+    assert_eq!(
+        normalize(&instruction_error(InstructionError::Custom(
+            INSUFFICIENT_FUNDS
+        ))),
+        Status::Error(INSUFFICIENT_FUNDS)
+    );
+    assert_eq!(
+        normalize(&instruction_error(InstructionError::Custom(
+            VAULT_BELOW_RENT_EXEMPT
+        ))),
+        Status::Error(VAULT_BELOW_RENT_EXEMPT)
+    );
+    assert_eq!(
+        normalize(&instruction_error(InstructionError::Custom(1))),
+        Status::Error(1)
+    );
+    assert!(matches!(
+        normalize(&TransactionError::InsufficientFundsForFee),
+        Status::Failed(_)
+    ));
+}
+
+#[test]
+fn test_random_steps() {
+    assert_eq!(random_steps(7, USERS, STEPS), random_steps(7, USERS, STEPS));
+    assert_ne!(random_steps(7, USERS, STEPS), random_steps(8, USERS, STEPS));
+}
+
+// Every kind of step, checking the outcome as well so that the three implementations
+// cannot agree by all failing the same way
+#[test]
+fn test_scripted() {
+    let steps = [
+        (
+            Step::Deposit {
+                user: 0,
+                amount: LAMPORTS_PER_SOL,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::Deposit {
+                user: 1,
+                amount: LAMPORTS_PER_SOL / 2,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::Withdraw {
+                user: 0,
+                amount: LAMPORTS_PER_SOL / 4,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::Withdraw {
+                user: 0,
+                amount: 100 * LAMPORTS_PER_SOL,
+            },
+            Status::Error(INSUFFICIENT_FUNDS),
+        ),
+        (
+            Step::Withdraw {
+                user: 0,
+                amount: LAMPORTS_PER_SOL * 3 / 4 - 1,
+            },
+            Status::Error(VAULT_BELOW_RENT_EXEMPT),
+        ),
+        (
+            Step::DepositFor {
+                funder: 2,
+                beneficiary: 0,
+                amount: LAMPORTS_PER_SOL / 10,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::WithdrawFromVaultOf {
+                user: 0,
+                owner: 1,
+                amount: 1_000,
+            },
+            Status::InvalidAccounts,
+        ),
+        (
+            Step::WithdrawWithUserAccountOf {
+                user: 0,
+                owner: 1,
+                amount: 1_000,
+            },
+            Status::InvalidAccounts,
+        ),
+        (
+            Step::DepositWithFakeSystemProgram {
+                user: 0,
+                amount: 1_000,
+            },
+            Status::InvalidAccounts,
+        ),
+        (
+            Step::UnsignedWithdraw {
+                user: 0,
+                payer: 1,
+                amount: 1_000,
+            },
+            Status::InvalidAccounts,
+        ),
+        (Step::Close { user: 1 }, Status::Succeeded),
+        (
+            Step::Withdraw { user: 1, amount: 1 },
+            Status::InvalidAccounts,
+        ),
+        (
+            Step::Deposit {
+                user: 1,
+                amount: LAMPORTS_PER_SOL,
+            },
+            Status::Succeeded,
+        ),
+        (Step::WithdrawAll { user: 0 }, Status::Succeeded),
+        (
+            Step::WithdrawAll { user: 0 },
+            Status::Error(INSUFFICIENT_FUNDS),
+        ),
+    ];
+
+    let mut harness = Harness::new(USERS, LAMPORTS);
+    for (index, (step, status)) in steps.iter().enumerate() {
+        let snapshot = harness
+            .step(index, step)
+            .unwrap_or_else(|report| panic!("{}", report));
+        assert_eq!(snapshot.status, *status, "Step {}: {:?}", index, step);
+    }
+}
+
+// The admin instructions, comparing the raw config accounts and the fees that reach the
+// treasury
+#[test]
+fn test_config() {
+    let admin = UPGRADE_AUTHORITY;
+    let user = 1;
+    let steps = [
+        (
+            Step::InitializeConfig { user },
+            Status::Error(NOT_UPGRADE_AUTHORITY),
+        ),
+        (Step::InitializeConfig { user: admin }, Status::Succeeded),
+        (
+            Step::SetFees {
+                admin: user,
+                deposit_bps: 25,
+                withdraw_bps: 50,
+            },
+            Status::InvalidAccounts,
+        ),
+        (
+            Step::SetFees {
+                admin,
+                deposit_bps: 1_001,
+                withdraw_bps: 0,
+            },
+            Status::Error(INVALID_FEE),
+        ),
+        (
+            Step::SetFees {
+                admin,
+                deposit_bps: 25,
+                withdraw_bps: 50,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::Deposit {
+                user,
+                amount: LAMPORTS_PER_SOL,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::Withdraw {
+                user,
+                amount: LAMPORTS_PER_SOL / 2,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::SetPaused {
+                admin,
+                deposits: true,
+                withdrawals: false,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::Deposit {
+                user,
+                amount: LAMPORTS_PER_SOL,
+            },
+            Status::Error(PROGRAM_PAUSED),
+        ),
+        (
+            Step::Withdraw {
+                user,
+                amount: LAMPORTS_PER_SOL / 4,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::SetPaused {
+                admin,
+                deposits: true,
+                withdrawals: true,
+            },
+            Status::Succeeded,
+        ),
+        (
+            Step::Withdraw {
+                user,
+                amount: LAMPORTS_PER_SOL / 8,
+            },
+            Status::Error(PROGRAM_PAUSED),
+        ),
+    ];
+
+    let mut harness = Harness::new(USERS, LAMPORTS);
+    for (index, (step, status)) in steps.iter().enumerate() {
+        let snapshot = harness
+            .step(index, step)
+            .unwrap_or_else(|report| panic!("{}", report));
+        assert_eq!(snapshot.status, *status, "Step {}: {:?}", index, step);
+        if index > 0 {
+            assert!(snapshot.config.is_some(), "Step {}: no config", index);
+        }
+    }
+}
+
+// Random sequences over a few seeds. Set DIFFERENTIAL_SEED to replay a single one.
+#[test]
+fn test_random() {
+    let seeds = match std::env::var("DIFFERENTIAL_SEED") {
+        Ok(seed) => {
+            let seed = seed.parse().expect("DIFFERENTIAL_SEED is not a number");
+            seed..seed + 1
+        }
+        Err(_) => 0..SEEDS,
+    };
+    for seed in seeds {
+        let mut harness = Harness::new(USERS, LAMPORTS);
+        if let Err(report) = harness.run(&random_steps(seed, USERS, STEPS)) {
+            panic!("Seed {}: {}", seed, report);
+        }
+    }
+}
+
+fn amount() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        Just(0),
+        Just(1),
+        Just(VAULT_RENT),
+        0..LAMPORTS_PER_SOL,
+        Just(100 * LAMPORTS_PER_SOL),
+    ]
+}
+
+// A user other than the given one
+fn other(user: usize, offset: usize) -> usize {
+    (user + 1 + offset) % USERS
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    let user = 0..USERS;
+    let offset = 0..USERS - 1;
+    prop_oneof![
+        4 => (user.clone(), amount()).prop_map(|(user, amount)| Step::Deposit { user, amount }),
+        4 => (user.clone(), amount()).prop_map(|(user, amount)| Step::Withdraw { user, amount }),
+        1 => user.clone().prop_map(|user| Step::Close { user }),
+        1 => user.clone().prop_map(|user| Step::WithdrawAll { user }),
+        1 => (user.clone(), user.clone(), amount()).prop_map(|(funder, beneficiary, amount)| {
+            Step::DepositFor { funder, beneficiary, amount }
+        }),
+        1 => (user.clone(), offset.clone(), amount()).prop_map(|(user, offset, amount)| {
+            Step::WithdrawFromVaultOf { user, owner: other(user, offset), amount }
+        }),
+        1 => (user.clone(), offset.clone(), amount()).prop_map(|(user, offset, amount)| {
+            Step::WithdrawWithUserAccountOf { user, owner: other(user, offset), amount }
+        }),
+        1 => (user.clone(), amount()).prop_map(|(user, amount)| {
+            Step::DepositWithFakeSystemProgram { user, amount }
+        }),
+        1 => (user, offset, amount()).prop_map(|(user, offset, amount)| {
+            Step::UnsignedWithdraw { user, payer: other(user, offset), amount }
+        }),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    // Every vault holds exactly what was deposited into it minus what was withdrawn, so
+    // no step moves lamports in or out of a vault it was not aimed at, and none of them
+    // leaves a vault with lamports below its rent-exempt minimum. The lifetime totals of
+    // every user account add up to the balance of its vault.
+    #[test]
+    fn test_vault_balances(steps in prop::collection::vec(step(), 1..40)) {
+        let mut harness = Harness::new(USERS, LAMPORTS);
+        let mut expected = [0; USERS];
+        for (index, step) in steps.iter().enumerate() {
+            let snapshot = harness.step(index, step).map_err(TestCaseError::fail)?;
+            if snapshot.status == Status::Succeeded {
+                match *step {
+                    Step::Deposit { user, amount } => expected[user] += amount,
+                    Step::Withdraw { user, amount } => expected[user] -= amount,
+                    Step::Close { user } | Step::WithdrawAll { user } => expected[user] = 0,
+                    Step::DepositFor {
+                        beneficiary,
+                        amount,
+                        ..
+                    } => expected[beneficiary] += amount,
+                    _ => prop_assert!(false, "Step {} passed invalid accounts: {:?}", index, step),
+                }
+            }
+            prop_assert_eq!(
+                &snapshot.vault_lamports[..],
+                &expected[..],
+                "Step {}: {:?}",
+                index,
+                step
+            );
+            // Withdrawals either drain a vault or leave it rent-exempt
+            prop_assert!(
+                snapshot
+                    .vault_lamports
+                    .iter()
+                    .all(|&lamports| lamports == 0 || lamports >= VAULT_RENT),
+                "Step {} left a vault below rent: {:?}",
+                index,
+                step
+            );
+            for (user, user_account) in snapshot.user_accounts.iter().enumerate() {
+                if let Some(user_account) = user_account {
+                    prop_assert_eq!(
+                        user_account.total_deposited - user_account.total_withdrawn,
+                        snapshot.vault_lamports[user],
+                        "Step {}: totals of user {} do not match the vault: {:?}",
+                        index,
+                        user,
+                        step
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// Fuzzing of the three vault programs with hostile instructions. Every input is a
+// single instruction with arbitrary data and an arbitrary account list drawn from a
+// small pool: the users and their PDAs, so that accounts show up duplicated, in the
+// wrong slot or without their signature, plus accounts with the wrong owner or a
+// broken layout. The instruction runs in a LiteSVM where the first user already has a
+// vault, and the run fails when
+// - the program panics or reads out of bounds instead of returning an error
+// - lamports are created or destroyed among the accounts in the pool
+// - a vault loses lamports although its owner did not sign
+use arbitrary::Arbitrary;
+use differential::{Encoding, IMPLEMENTATIONS};
+use litesvm::{types::TransactionResult, LiteSVM};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::{
+    account::Account,
+    native_token::LAMPORTS_PER_SOL,
+    signature::{keypair_from_seed, Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+    instruction::{self, ANCHOR_DISCRIMINATORS},
+};
+
+// Users in the pool, the first one has a vault before the fuzzed instruction runs
+const USERS: usize = 2;
+
+// Longest account list tried, past the longest any instruction takes
+const MAX_ACCOUNTS: usize = 16;
+
+const GARBAGE: Pubkey = Pubkey::new_from_array([0xa1; 32]);
+const IMPOSTOR: Pubkey = Pubkey::new_from_array([0xa2; 32]);
+const STRANGER: Pubkey = Pubkey::new_from_array([0xa3; 32]);
+
+#[derive(Arbitrary, Debug)]
+pub enum Data {
+    Raw(Vec<u8>),
+    // A valid tag or discriminator followed by arbitrary fields, to get past the decoder
+    Tagged { variant: u8, fields: Vec<u8> },
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum Key {
+    User(u8),
+    UserAccount(u8),
+    Vault(u8),
+    SystemProgram,
+    Program,
+    Config,
+    Treasury,
+    // Owned by the program, with arbitrary data in place of a user account
+    Garbage,
+    // Owned by the system program, with a copy of the first user's account data
+    Impostor,
+    // A system account none of the instructions expect
+    Stranger,
+}
+
+#[derive(Arbitrary, Debug)]
+pub struct Meta {
+    key: Key,
+    is_signer: bool, // only honored for the users
+    is_writable: bool,
+}
+
+#[derive(Arbitrary, Debug)]
+pub struct Input {
+    data: Data,
+    accounts: Vec<Meta>,
+    garbage: Vec<u8>, // data of the Garbage account
+}
+
+struct Pool {
+    program_id: Pubkey,
+    users: Vec<Pubkey>,
+}
+
+impl Pool {
+    fn address(&self, key: Key) -> Pubkey {
+        let user = |index: u8| &self.users[index as usize % USERS];
+        match key {
+            Key::User(index) => *user(index),
+            Key::UserAccount(index) => find_user_account_address(user(index), &self.program_id).0,
+            Key::Vault(index) => find_vault_address(user(index), &self.program_id).0,
+            Key::SystemProgram => system_program::id(),
+            Key::Program => self.program_id,
+            Key::Config => find_config_address(&self.program_id).0,
+            Key::Treasury => find_treasury_address(&self.program_id).0,
+            Key::Garbage => GARBAGE,
+            Key::Impostor => IMPOSTOR,
+            Key::Stranger => STRANGER,
+        }
+    }
+
+    // Every account an instruction can reference
+    fn addresses(&self) -> Vec<Pubkey> {
+        let mut keys = vec![
+            Key::SystemProgram,
+            Key::Program,
+            Key::Config,
+            Key::Treasury,
+            Key::Garbage,
+            Key::Impostor,
+            Key::Stranger,
+        ];
+        for index in 0..USERS as u8 {
+            keys.extend([Key::User(index), Key::UserAccount(index), Key::Vault(index)]);
+        }
+        keys.into_iter().map(|key| self.address(key)).collect()
+    }
+}
+
+fn send(
+    svm: &mut LiteSVM,
+    payer: &Keypair,
+    instruction: Instruction,
+    signers: &[&Keypair],
+) -> TransactionResult {
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &all_signers,
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(transaction)
+}
+
+// Run one input against the implementation with the given name
+pub fn run(name: &str, input: Input) {
+    let implementation = IMPLEMENTATIONS
+        .iter()
+        .find(|implementation| implementation.name == name)
+        .expect("Unknown implementation");
+    let program_id = implementation.program_id;
+    // Fixed keys, so that a crashing input reproduces
+    let payer = keypair_from_seed(&[0; 32]).unwrap();
+    let users: Vec<Keypair> = (1..=USERS as u8)
+        .map(|seed| keypair_from_seed(&[seed; 32]).unwrap())
+        .collect();
+    let pool = Pool {
+        program_id,
+        users: users.iter().map(|user| user.pubkey()).collect(),
+    };
+
+    let mut svm = LiteSVM::new();
+    svm.add_program_from_file(program_id, implementation.path)
+        .unwrap_or_else(|error| {
+            panic!(
+                "Failed to load the {} program from {}, build it first: {}",
+                name, implementation.path, error
+            )
+        });
+    svm.airdrop(&payer.pubkey(), 100 * LAMPORTS_PER_SOL)
+        .unwrap();
+    for user in &pool.users {
+        svm.airdrop(user, 10 * LAMPORTS_PER_SOL).unwrap();
+    }
+    svm.airdrop(&STRANGER, LAMPORTS_PER_SOL).unwrap();
+    let deposit = instruction::deposit(&program_id, &pool.users[0], LAMPORTS_PER_SOL);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(deposit),
+        &[&users[0]],
+    )
+    .expect("Setup deposit failed");
+    let user_account = svm.get_account(&pool.address(Key::UserAccount(0))).unwrap();
+    svm.set_account(
+        IMPOSTOR,
+        Account {
+            owner: system_program::id(),
+            ..user_account
+        },
+    )
+    .unwrap();
+    svm.set_account(
+        GARBAGE,
+        Account {
+            lamports: LAMPORTS_PER_SOL,
+            data: input.garbage,
+            owner: program_id,
+            executable: false,
+            rent_epoch: 0,
+        },
+    )
+    .unwrap();
+
+    let data = match input.data {
+        Data::Raw(data) => data,
+        Data::Tagged { variant, fields } => {
+            let variant = variant as usize % ANCHOR_DISCRIMINATORS.len();
+            let mut data = match implementation.encoding {
+                Encoding::Borsh => vec![variant as u8],
+                Encoding::Anchor => ANCHOR_DISCRIMINATORS[variant].to_vec(),
+            };
+            data.extend(fields);
+            data
+        }
+    };
+    let accounts: Vec<AccountMeta> = input
+        .accounts
+        .iter()
+        .take(MAX_ACCOUNTS)
+        .map(|meta| AccountMeta {
+            pubkey: pool.address(meta.key),
+            is_signer: meta.is_signer && matches!(meta.key, Key::User(_)),
+            is_writable: meta.is_writable,
+        })
+        .collect();
+    let signers: Vec<&Keypair> = users
+        .iter()
+        .filter(|user| {
+            accounts
+                .iter()
+                .any(|account| account.is_signer && account.pubkey == user.pubkey())
+        })
+        .collect();
+
+    let addresses = pool.addresses();
+    let balances = |svm: &LiteSVM, addresses: &[Pubkey]| -> Vec<u64> {
+        addresses
+            .iter()
+            .map(|address| svm.get_balance(address).unwrap_or(0))
+            .collect()
+    };
+    let vaults: Vec<Pubkey> = (0..USERS as u8)
+        .map(|index| pool.address(Key::Vault(index)))
+        .collect();
+    let lamports_before = balances(&svm, &addresses);
+    let vaults_before = balances(&svm, &vaults);
+    svm.expire_blockhash();
+    let result = send(
+        &mut svm,
+        &payer,
+        Instruction {
+            program_id,
+            accounts,
+            data,
+        },
+        &signers,
+    );
+    let lamports_after = balances(&svm, &addresses);
+    let vaults_after = balances(&svm, &vaults);
+
+    if let Err(failed) = &result {
+        if let TransactionError::InstructionError(_, InstructionError::ProgramFailedToComplete) =
+            failed.err
+        {
+            panic!(
+                "The program crashed instead of returning an error:\n{}",
+                failed.meta.logs.join("\n")
+            );
+        }
+    }
+    assert_eq!(
+        lamports_before.iter().sum::<u64>(),
+        lamports_after.iter().sum::<u64>(),
+        "Lamports were created or destroyed"
+    );
+    for (index, user) in pool.users.iter().enumerate() {
+        if signers.iter().any(|signer| signer.pubkey() == *user) {
+            continue;
+        }
+        assert!(
+            vaults_after[index] >= vaults_before[index],
+            "The vault of user {} lost lamports without their signature",
+            index
+        );
+    }
+}
@@ -0,0 +1,24 @@
+// Glue between the RPC client and the vault program's error codes, see
+// vault_interface::VaultError
+use solana_client::client_error::ClientError;
+use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+use vault_interface::VaultError;
+
+// Extract the vault error a failed transaction was rejected with, if any
+pub fn vault_error(error: &ClientError) -> Option<VaultError> {
+    match error.get_transaction_error()? {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            VaultError::from_code(code)
+        }
+        _ => None,
+    }
+}
+
+// Replace a failed transaction's error with the vault error it carries, so callers
+// see the name instead of a bare custom code
+pub fn explain(error: ClientError) -> anyhow::Error {
+    match vault_error(&error) {
+        Some(vault_error) => vault_error.into(),
+        None => error.into(),
+    }
+}
@@ -0,0 +1,92 @@
+// cargo build-sbf in ../program first, then: cargo test --test test_litesvm
+//
+// Runs the LiteSVM scenarios of the scenarios crate against the native program, so they
+// need neither a local validator nor a funded keypair.
+use anyhow::Result;
+
+const IMPLEMENTATION: &str = "native";
+
+#[test]
+fn test_deposit_withdraw() -> Result<()> {
+    scenarios::deposit_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_close() -> Result<()> {
+    scenarios::close(IMPLEMENTATION)
+}
+
+#[test]
+fn test_withdraw_all() -> Result<()> {
+    scenarios::withdraw_all(IMPLEMENTATION)
+}
+
+#[test]
+fn test_lifetime_totals() -> Result<()> {
+    scenarios::lifetime_totals(IMPLEMENTATION)
+}
+
+#[test]
+fn test_deposit_for() -> Result<()> {
+    scenarios::deposit_for(IMPLEMENTATION)
+}
+
+#[test]
+fn test_timelocked_withdraw() -> Result<()> {
+    scenarios::timelocked_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_multisig_withdraw() -> Result<()> {
+    scenarios::multisig_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_forged_user_account() -> Result<()> {
+    scenarios::forged_user_account(IMPLEMENTATION)
+}
+
+#[test]
+fn test_delegate_withdraw() -> Result<()> {
+    scenarios::delegate_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_withdraw_limit() -> Result<()> {
+    scenarios::withdraw_limit(IMPLEMENTATION)
+}
+
+#[test]
+fn test_config_pause() -> Result<()> {
+    scenarios::config_pause(IMPLEMENTATION)
+}
+
+#[test]
+fn test_fees() -> Result<()> {
+    scenarios::fees(IMPLEMENTATION)
+}
+
+#[test]
+fn test_token_deposit_withdraw() -> Result<()> {
+    scenarios::token_deposit_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_token_hook_multisig() -> Result<()> {
+    scenarios::token_hook_multisig(IMPLEMENTATION)
+}
+
+#[test]
+fn test_migrate_baseline() -> Result<()> {
+    scenarios::migrate_baseline(IMPLEMENTATION)
+}
+
+#[test]
+fn test_stake() -> Result<()> {
+    scenarios::stake(IMPLEMENTATION)
+}
+
+#[test]
+fn test_stake_prefunded() -> Result<()> {
+    scenarios::stake_prefunded(IMPLEMENTATION)
+}
@@ -6,47 +6,400 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::AccountInfo,
-    entrypoint,
+    bpf_loader_upgradeable, entrypoint,
     entrypoint::ProgramResult,
+    log::sol_log_data,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
     pubkey::Pubkey,
     rent::Rent,
+    stake::{
+        self, instruction as stake_instruction,
+        state::{Authorized, Lockup, StakeStateV2},
+    },
     system_instruction, system_program,
-    sysvar::Sysvar,
+    sysvar::{clock::Clock, Sysvar},
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::{
+    extension::StateWithExtensions,
+    onchain::invoke_transfer_checked,
+    state::{Account as TokenAccount, Mint},
 };
 
-// Declare program entrypoint
+// Declare program entrypoint. The heap-usage build, for the benchmarks, also logs the
+// heap each instruction used.
+#[cfg(not(feature = "heap-usage"))]
 entrypoint!(process_instruction);
+#[cfg(feature = "heap-usage")]
+entrypoint!(process_instruction_with_heap_usage);
+
+#[cfg(feature = "heap-usage")]
+fn process_instruction_with_heap_usage(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let result = process_instruction(program_id, accounts, instruction_data);
+
+    // The bump allocator hands out the heap from its end downwards and keeps its
+    // position in the first word, 0 until the first allocation
+    let position = unsafe { *(entrypoint::HEAP_START_ADDRESS as *const usize) };
+    let used = match position {
+        0 => 0,
+        position => entrypoint::HEAP_START_ADDRESS as usize + entrypoint::HEAP_LENGTH - position,
+    };
+    msg!("Heap used: {} bytes", used);
+
+    result
+}
 
 // Program instruction enum
 #[derive(Debug, BorshDeserialize)]
 enum ProgramInstruction {
     Deposit { amount: u64 },
     Withdraw { amount: u64 },
+    Close,
+    DepositToken { amount: u64 },
+    WithdrawToken { amount: u64, signer_count: u8 },
+    DepositWithDelay { amount: u64, withdraw_delay: i64 },
+    RequestWithdraw { amount: u64 },
+    ClaimWithdraw,
+    CancelWithdraw,
+    SetMultisig { signers: Vec<Pubkey>, threshold: u8 },
+    SetWithdrawAuthority { authority: Pubkey, allowance: u64 },
+    RevokeWithdrawAuthority,
+    DelegateWithdraw { amount: u64 },
+    DepositFor { beneficiary: Pubkey, amount: u64 },
+    SetWithdrawLimit { limit: u64, window: i64 },
+    InitializeConfig { admin: Pubkey },
+    SetPaused { deposits: bool, withdrawals: bool },
+    TransferAdmin { new_admin: Pubkey },
+    SetFees { deposit_bps: u16, withdraw_bps: u16 },
+    WithdrawFees { amount: u64 },
+    Migrate,
+    WithdrawAll,
+    DelegateStake { amount: u64 },
+    DeactivateStake,
+    WithdrawStake,
 }
 
 impl ProgramInstruction {
-    // Deserialize the instruction data using Borsh
+    // Deserialize the instruction data using Borsh. With the anchor-discriminators
+    // feature, an Anchor discriminator in place of the variant index is accepted too.
     fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        #[cfg(feature = "anchor-discriminators")]
+        if let Some(index) = ANCHOR_INSTRUCTION_DISCRIMINATORS
+            .iter()
+            .position(|discriminator| input.starts_with(discriminator))
+        {
+            // The arguments are encoded the same way, only the prefix differs
+            let mut data = Vec::with_capacity(input.len() - 7);
+            data.push(index as u8);
+            data.extend_from_slice(&input[8..]);
+            return Self::try_from_slice(&data).map_err(|_| ProgramError::InvalidInstructionData);
+        }
+
         Self::try_from_slice(input).map_err(|_| ProgramError::InvalidInstructionData)
     }
 }
 
+// Anchor instruction discriminators, the first 8 bytes of sha256("global:<name>"), in
+// variant order. Only deposit_token's starts with a byte that is also a variant index,
+// and that variant encodes to a single byte, so no valid borsh encoding is mistaken
+// for an Anchor one.
+#[cfg(feature = "anchor-discriminators")]
+const ANCHOR_INSTRUCTION_DISCRIMINATORS: [[u8; 8]; 25] = [
+    [242, 35, 198, 137, 82, 225, 242, 182],  // deposit
+    [183, 18, 70, 156, 148, 109, 161, 34],   // withdraw
+    [98, 165, 201, 177, 108, 65, 206, 96],   // close
+    [11, 156, 96, 218, 39, 163, 180, 19],    // deposit_token
+    [136, 235, 181, 5, 101, 109, 57, 81],    // withdraw_token
+    [110, 109, 250, 169, 189, 179, 8, 163],  // deposit_with_delay
+    [137, 95, 187, 96, 250, 138, 31, 182],   // request_withdraw
+    [232, 89, 154, 117, 16, 204, 182, 224],  // claim_withdraw
+    [112, 53, 226, 58, 158, 30, 37, 168],    // cancel_withdraw
+    [251, 6, 245, 35, 115, 42, 77, 186],     // set_multisig
+    [199, 146, 140, 67, 1, 90, 8, 222],      // set_withdraw_authority
+    [192, 28, 85, 211, 241, 241, 81, 159],   // revoke_withdraw_authority
+    [89, 6, 67, 206, 216, 140, 118, 63],     // delegate_withdraw
+    [193, 39, 228, 88, 160, 254, 92, 53],    // deposit_for
+    [198, 158, 141, 22, 248, 120, 105, 42],  // set_withdraw_limit
+    [208, 127, 21, 1, 194, 190, 196, 70],    // initialize_config
+    [91, 60, 125, 192, 176, 225, 166, 218],  // set_paused
+    [42, 242, 66, 106, 228, 10, 111, 156],   // transfer_admin
+    [137, 178, 49, 58, 0, 245, 242, 190],    // set_fees
+    [198, 212, 171, 109, 144, 215, 174, 89], // withdraw_fees
+    [155, 234, 231, 146, 236, 158, 162, 30], // migrate
+    [96, 246, 166, 130, 229, 50, 43, 70],    // withdraw_all
+    [50, 110, 95, 179, 194, 75, 140, 246],   // delegate_stake
+    [165, 158, 229, 97, 168, 220, 187, 225], // deactivate_stake
+    [153, 8, 22, 138, 105, 176, 87, 66],     // withdraw_stake
+];
+
 // User account data structure compatible with borsh
 #[derive(Debug, BorshSerialize, BorshDeserialize)]
 struct UserAccount {
     pub user: Pubkey,
     pub user_bump: u8,
     pub vault_bump: u8,
-    pub is_initialized: bool,
+    pub version: u8, // layout version, baseline accounts hold their always-true is_initialized flag here
+    pub withdraw_delay: i64, // seconds between RequestWithdraw and ClaimWithdraw, 0 = disabled
+    pub pending_withdrawal: u64, // lamports requested but not yet claimed
+    pub unlock_timestamp: i64, // unix timestamp after which the pending withdrawal can be claimed
+    pub withdraw_authority: Pubkey, // delegate allowed to withdraw, Pubkey::default() = none
+    pub withdraw_allowance: u64, // lamports the delegate may still withdraw, u64::MAX = uncapped
+    pub withdraw_limit: u64, // lamports withdrawable per window, 0 = unlimited
+    pub withdraw_window: i64, // length of the rate limit window in seconds
+    pub window_start: i64, // unix timestamp at which the current window started
+    pub window_withdrawn: u64, // lamports withdrawn in the current window
+    pub pending_withdraw_limit: u64, // raised limit waiting for its delay to pass
+    pub pending_withdraw_window: i64, // window length that comes with the pending limit
+    pub limit_unlock_timestamp: i64, // unix timestamp at which the pending limit applies, 0 = none
+    pub threshold: u8, // multisig approvals required, 0 = the user alone controls the vault
+    pub signers: Vec<Pubkey>, // multisig members
+    pub total_deposited: u64, // lamports ever credited to the vault, after fees
+    pub total_withdrawn: u64, // lamports ever taken out of the vault, including fees
+    pub deposit_count: u64, // number of lamport deposits
+    pub last_deposit_slot: u64, // slot of the latest lamport deposit, 0 = none
+    pub last_withdraw_slot: u64, // slot of the latest lamport withdrawal, 0 = none
 }
 
 impl UserAccount {
-    const SIZE: usize = 32 + 1 + 1 + 1; // pubkey + user_bump + vault_bump + is_initialized
+    // pubkey + user_bump + vault_bump + version + withdraw_delay + pending_withdrawal
+    // + unlock_timestamp + withdraw_authority + withdraw_allowance + withdraw_limit
+    // + withdraw_window + window_start + window_withdrawn + pending_withdraw_limit
+    // + pending_withdraw_window + limit_unlock_timestamp + threshold + empty signers vec
+    // + lifetime totals
+    const SIZE: usize = FIELDS_SIZE + 4 + TOTALS_SIZE;
+
+    // Account size including the header and the multisig members
+    fn size(&self) -> usize {
+        USER_ACCOUNT_DISCRIMINATOR.len() + Self::SIZE + self.signers.len() * 32
+    }
+
+    // Read an account in the current layout
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        data.strip_prefix(&USER_ACCOUNT_DISCRIMINATOR)
+            .filter(|fields| fields.get(VERSION_OFFSET) == Some(&USER_ACCOUNT_VERSION))
+            .and_then(|fields| Self::try_from_slice(fields).ok())
+            .ok_or_else(|| VaultError::DeserializationFailed.into())
+    }
+
+    // Read an account in the current layout, or in the baseline one from before the
+    // layout was versioned, which reads as version 1 with every later field zero. Only
+    // deposits, withdrawals and Migrate accept baseline accounts.
+    fn unpack_any(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() != BASELINE_SIZE || data[VERSION_OFFSET] != BASELINE_USER_ACCOUNT_VERSION {
+            return Self::unpack(data);
+        }
+        let mut fields = data.to_vec();
+        fields.resize(Self::SIZE, 0);
+        Self::try_from_slice(&fields).map_err(|_| VaultError::DeserializationFailed.into())
+    }
+
+    // Write the account back in the layout of its version. Baseline accounts only have
+    // room for the user and the bumps, so the lifetime totals only count once Migrate
+    // has grown the account.
+    fn pack(&self, data: &mut [u8]) -> ProgramResult {
+        let fields = borsh::to_vec(self)?;
+        if self.version == BASELINE_USER_ACCOUNT_VERSION {
+            data[..BASELINE_SIZE].copy_from_slice(&fields[..BASELINE_SIZE]);
+            return Ok(());
+        }
+        let header_size = USER_ACCOUNT_DISCRIMINATOR.len();
+        data[..header_size].copy_from_slice(&USER_ACCOUNT_DISCRIMINATOR);
+        data[header_size..header_size + fields.len()].copy_from_slice(&fields);
+        Ok(())
+    }
+}
+
+// Anchor-style account discriminator, the first 8 bytes of sha256("account:UserAccount"),
+// so current accounts share the Anchor program's layout
+const USER_ACCOUNT_DISCRIMINATOR: [u8; 8] = [211, 33, 136, 16, 186, 110, 242, 127];
+
+// Accounts created before the layout was versioned. Their version byte is the old
+// is_initialized flag, which was always true.
+const BASELINE_USER_ACCOUNT_VERSION: u8 = 1;
+
+// Layout written for new accounts and by Migrate
+const USER_ACCOUNT_VERSION: u8 = 2;
+
+// Offset of the version after the header, and the size of the fields before the
+// multisig members
+const VERSION_OFFSET: usize = 32 + 1 + 1;
+const FIELDS_SIZE: usize = 32 + 1 + 1 + 1 + 8 + 8 + 8 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+
+// total_deposited + total_withdrawn + deposit_count + last_deposit_slot
+// + last_withdraw_slot
+const TOTALS_SIZE: usize = 8 + 8 + 8 + 8 + 8;
+
+// Accounts from before the layout was versioned: user + user_bump + vault_bump
+// + is_initialized, the flag in the place of the version
+const BASELINE_SIZE: usize = 32 + 1 + 1 + 1;
+
+// Anchor-style account discriminator, the first 8 bytes of sha256("account:Config")
+const CONFIG_DISCRIMINATOR: [u8; 8] = [155, 12, 170, 224, 30, 250, 204, 130];
+
+// Global program settings, a singleton PDA seeded with b"config"
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+struct Config {
+    pub admin: Pubkey,            // may pause the program and hand over the admin role
+    pub paused: bool,             // blocks deposits
+    pub withdrawals_paused: bool, // also blocks withdrawals, only set together with paused
+    pub bump: u8,
+    pub treasury_bump: u8,     // bump of the fee treasury PDA
+    pub deposit_fee_bps: u16,  // deposit fee in basis points
+    pub withdraw_fee_bps: u16, // withdraw fee in basis points
+}
+
+impl Config {
+    // admin + paused + withdrawals_paused + bump + treasury_bump + deposit_fee_bps
+    // + withdraw_fee_bps
+    const SIZE: usize = 32 + 1 + 1 + 1 + 1 + 2 + 2;
+
+    // Account size including the discriminator
+    const LEN: usize = CONFIG_DISCRIMINATOR.len() + Self::SIZE;
+
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        data.strip_prefix(&CONFIG_DISCRIMINATOR)
+            .and_then(|fields| Self::try_from_slice(fields).ok())
+            .ok_or_else(|| VaultError::DeserializationFailed.into())
+    }
+
+    fn pack(&self, data: &mut [u8]) -> ProgramResult {
+        let header_size = CONFIG_DISCRIMINATOR.len();
+        data[..header_size].copy_from_slice(&CONFIG_DISCRIMINATOR);
+        self.serialize(&mut &mut data[header_size..])?;
+        Ok(())
+    }
+}
+
+// Typed events, logged with sol_log_data as an 8-byte discriminator followed by the
+// borsh encoded event. The discriminator is the first 8 bytes of
+// sha256("event:<name>"), the same encoding Anchor's emit! uses, so one decoder
+// reads the events of every implementation.
+trait Event: BorshSerialize {
+    const DISCRIMINATOR: [u8; 8];
+
+    fn emit(&self) -> ProgramResult {
+        let mut data = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut data)?;
+        sol_log_data(&[&data]);
+        Ok(())
+    }
+}
+
+// Emitted when lamports are deposited into a vault
+#[derive(BorshSerialize, Debug)]
+struct DepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports paid in, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the deposit
+    pub slot: u64,
+}
+
+impl Event for DepositEvent {
+    const DISCRIMINATOR: [u8; 8] = [120, 248, 61, 83, 31, 142, 107, 144];
+}
+
+// Emitted when lamports leave a vault, whether withdrawn, claimed, drained on close
+// or paid to a delegate
+#[derive(BorshSerialize, Debug)]
+struct WithdrawEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports taken out of the vault, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the withdrawal
+    pub slot: u64,
+}
+
+impl Event for WithdrawEvent {
+    const DISCRIMINATOR: [u8; 8] = [22, 9, 133, 26, 160, 44, 71, 192];
+}
+
+// Emitted when tokens are deposited into a vault token account
+#[derive(BorshSerialize, Debug)]
+struct TokenDepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA, owner of the vault token account
+    pub mint: Pubkey,       // mint of the deposited tokens
+    pub amount: u64,        // tokens the user sent
+    pub received: u64,      // tokens that reached the vault, less any transfer fee
+    pub vault_balance: u64, // vault token account balance after the deposit
+    pub slot: u64,
+}
+
+impl Event for TokenDepositEvent {
+    const DISCRIMINATOR: [u8; 8] = [27, 43, 27, 81, 3, 244, 105, 252];
+}
+
+// Upper bound on multisig members, keeps the account small and signer checks cheap
+const MAX_MULTISIG_SIGNERS: usize = 10;
+
+// Program errors, returned as ProgramError::Custom. The codes match the Anchor
+// program's VaultError so clients can decode failures from every implementation.
+// Append new variants at the end, reordering changes the codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum VaultError {
+    InsufficientFunds = 6000,
+    VaultTimelocked,
+    InvalidWithdrawDelay,
+    InvalidAmount,
+    WithdrawalAlreadyPending,
+    NoPendingWithdrawal,
+    WithdrawalStillLocked,
+    NotEnoughSigners,
+    InvalidMultisig,
+    InvalidWithdrawAuthority,
+    AllowanceExceeded,
+    WithdrawLimitExceeded,
+    InvalidWithdrawLimit,
+    ProgramPaused,
+    NotUpgradeAuthority,
+    InvalidAdmin,
+    InvalidPauseFlags,
+    InvalidFee,
+    InsufficientFees,
+    InvalidSystemProgram,
+    InvalidUserAccount,
+    InvalidVault,
+    UserMismatch,
+    InvalidAccountOwner,
+    DeserializationFailed,
+    InvalidConfig,
+    InvalidTreasury,
+    InvalidVaultTokenAccount,
+    InvalidTokenProgram,
+    InvalidAssociatedTokenProgram,
+    InvalidProgramData,
+    NotAdmin,
+    VaultBelowRentExempt,
+    InvalidStakeAccount,
+    InvalidStakeProgram,
+    StakeAccountInUse,
 }
 
+impl From<VaultError> for ProgramError {
+    fn from(e: VaultError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+// Upper bound on protocol fees, so a compromised admin cannot take a whole deposit
+const MAX_FEE_BPS: u16 = 1_000;
+
+// Basis points in one whole
+const BPS_DENOMINATOR: u128 = 10_000;
+
 // Main instruction processor
 fn process_instruction(
     program_id: &Pubkey,
@@ -56,142 +409,366 @@ fn process_instruction(
     let instruction = ProgramInstruction::unpack(instruction_data)?;
 
     match instruction {
-        ProgramInstruction::Deposit { amount } => process_deposit(program_id, accounts, amount),
-        ProgramInstruction::Withdraw { amount } => process_withdraw(program_id, accounts, amount),
+        ProgramInstruction::Deposit { amount } => {
+            process_deposit(program_id, accounts, amount, None)
+        }
+        ProgramInstruction::Withdraw { amount } => {
+            process_withdraw(program_id, accounts, Some(amount))
+        }
+        ProgramInstruction::Close => process_close(program_id, accounts),
+        ProgramInstruction::DepositToken { amount } => {
+            process_deposit_token(program_id, accounts, amount)
+        }
+        ProgramInstruction::WithdrawToken {
+            amount,
+            signer_count,
+        } => process_withdraw_token(program_id, accounts, amount, signer_count),
+        ProgramInstruction::DepositWithDelay {
+            amount,
+            withdraw_delay,
+        } => process_deposit(program_id, accounts, amount, Some(withdraw_delay)),
+        ProgramInstruction::RequestWithdraw { amount } => {
+            process_request_withdraw(program_id, accounts, amount)
+        }
+        ProgramInstruction::ClaimWithdraw => process_claim_withdraw(program_id, accounts),
+        ProgramInstruction::CancelWithdraw => process_cancel_withdraw(program_id, accounts),
+        ProgramInstruction::SetMultisig { signers, threshold } => {
+            process_set_multisig(program_id, accounts, signers, threshold)
+        }
+        ProgramInstruction::SetWithdrawAuthority {
+            authority,
+            allowance,
+        } => process_set_withdraw_authority(program_id, accounts, authority, allowance),
+        ProgramInstruction::RevokeWithdrawAuthority => {
+            process_revoke_withdraw_authority(program_id, accounts)
+        }
+        ProgramInstruction::DelegateWithdraw { amount } => {
+            process_delegate_withdraw(program_id, accounts, amount)
+        }
+        ProgramInstruction::DepositFor {
+            beneficiary,
+            amount,
+        } => process_deposit_for(program_id, accounts, beneficiary, amount),
+        ProgramInstruction::SetWithdrawLimit { limit, window } => {
+            process_set_withdraw_limit(program_id, accounts, limit, window)
+        }
+        ProgramInstruction::InitializeConfig { admin } => {
+            process_initialize_config(program_id, accounts, admin)
+        }
+        ProgramInstruction::SetPaused {
+            deposits,
+            withdrawals,
+        } => process_set_paused(program_id, accounts, deposits, withdrawals),
+        ProgramInstruction::TransferAdmin { new_admin } => {
+            process_transfer_admin(program_id, accounts, new_admin)
+        }
+        ProgramInstruction::SetFees {
+            deposit_bps,
+            withdraw_bps,
+        } => process_set_fees(program_id, accounts, deposit_bps, withdraw_bps),
+        ProgramInstruction::WithdrawFees { amount } => {
+            process_withdraw_fees(program_id, accounts, amount)
+        }
+        ProgramInstruction::Migrate => process_migrate(program_id, accounts),
+        ProgramInstruction::WithdrawAll => process_withdraw(program_id, accounts, None),
+        ProgramInstruction::DelegateStake { amount } => {
+            process_delegate_stake(program_id, accounts, amount)
+        }
+        ProgramInstruction::DeactivateStake => process_deactivate_stake(program_id, accounts),
+        ProgramInstruction::WithdrawStake => process_withdraw_stake(program_id, accounts),
     }
 }
 
-// Process deposit instruction
-fn process_deposit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info] =
+// Process deposit instruction, optionally setting the withdraw timelock on first deposit
+fn process_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    withdraw_delay: Option<i64>,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info] =
         accounts
     else {
         return Err(ProgramError::NotEnoughAccountKeys);
     };
 
+    // Deposits are blocked while the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
     // Check that the user signed the transaction
     if !user_account_info.is_signer {
         msg!("User must sign the transaction");
         return Err(ProgramError::MissingRequiredSignature);
     }
 
+    deposit_lamports(
+        program_id,
+        user_account_info,
+        user_account_info.key,
+        user_data_account_info,
+        vault_account_info,
+        system_program_account_info,
+        treasury_account_info,
+        &config,
+        amount,
+        withdraw_delay,
+    )
+}
+
+// Process deposit for instruction, funding another user's vault without their signature
+fn process_deposit_for(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    beneficiary: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    let [funder_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Deposits are blocked while the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
+    // Check that the funder signed the transaction
+    if !funder_account_info.is_signer {
+        msg!("Funder must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    deposit_lamports(
+        program_id,
+        funder_account_info,
+        &beneficiary,
+        user_data_account_info,
+        vault_account_info,
+        system_program_account_info,
+        treasury_account_info,
+        &config,
+        amount,
+        None,
+    )
+}
+
+// Move lamports from the funder into the user's vault, creating the user data PDA
+// on first deposit. The PDAs are always derived from the user, who need not sign.
+#[allow(clippy::too_many_arguments)]
+fn deposit_lamports<'a>(
+    program_id: &Pubkey,
+    funder_account_info: &AccountInfo<'a>,
+    user: &Pubkey,
+    user_data_account_info: &AccountInfo<'a>,
+    vault_account_info: &AccountInfo<'a>,
+    system_program_account_info: &AccountInfo<'a>,
+    treasury_account_info: &AccountInfo<'a>,
+    config: &Config,
+    amount: u64,
+    withdraw_delay: Option<i64>,
+) -> ProgramResult {
     // Check that the system program is valid
     if system_program_account_info.key != &system_program::id() {
         msg!("Invalid system program");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidSystemProgram.into());
     }
 
     // Derive and verify the user data PDA
     let (expected_user_data_pubkey, user_data_bump) =
-        Pubkey::find_program_address(&[user_account_info.key.as_ref()], program_id);
+        Pubkey::find_program_address(&[user.as_ref()], program_id);
     if user_data_account_info.key != &expected_user_data_pubkey {
         msg!("Invalid user data account address");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidUserAccount.into());
     }
 
     // Derive and verify the vault PDA
     let (expected_vault_pubkey, vault_bump) =
-        Pubkey::find_program_address(&[b"vault", user_account_info.key.as_ref()], program_id);
+        Pubkey::find_program_address(&[b"vault", user.as_ref()], program_id);
     if vault_account_info.key != &expected_vault_pubkey {
         msg!("Invalid vault account address");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidVault.into());
+    }
+
+    // A negative delay would unlock withdrawals in the past
+    if withdraw_delay.is_some_and(|delay| delay < 0) {
+        msg!("Withdraw delay cannot be negative");
+        return Err(VaultError::InvalidWithdrawDelay.into());
     }
 
     // Initialize user account if needed
     if user_data_account_info.owner != program_id {
-        msg!("Creating user data account");
-        // Calculate rent
-        let rent = Rent::get()?;
-        let rent_lamports = rent.minimum_balance(UserAccount::SIZE);
-
-        // Create the account
-        invoke_signed(
-            &system_instruction::create_account(
-                user_account_info.key,
-                user_data_account_info.key,
-                rent_lamports,
-                UserAccount::SIZE as u64,
-                program_id,
-            ),
-            &[
-                user_account_info.clone(),
-                user_data_account_info.clone(),
-                system_program_account_info.clone(),
-            ],
-            &[&[user_account_info.key.as_ref(), &[user_data_bump]]],
+        initialize_user_account(
+            program_id,
+            funder_account_info,
+            user,
+            user_data_account_info,
+            system_program_account_info,
+            user_data_bump,
+            vault_bump,
+            withdraw_delay.unwrap_or(0),
         )?;
-
-        // Initialize the account data using borsh
-        let user_data = UserAccount {
-            user: *user_account_info.key,
-            user_bump: user_data_bump,
-            vault_bump: vault_bump,
-            is_initialized: true,
-        };
-
-        user_data.serialize(&mut *user_data_account_info.try_borrow_mut_data()?)?;
+    } else if let Some(withdraw_delay) = withdraw_delay {
+        // The delay is fixed at first deposit. The address was verified above,
+        // so the stored data belongs to this user.
+        let user_data = UserAccount::unpack_any(&user_data_account_info.try_borrow_data()?)?;
+        if user_data.withdraw_delay != withdraw_delay {
+            msg!("Withdraw delay can only be set on first deposit");
+            return Err(VaultError::InvalidWithdrawDelay.into());
+        }
     }
 
-    // Transfer lamports to the vault
+    // Route the protocol fee to the treasury with a second transfer
+    let net_amount = collect_fee(
+        program_id,
+        funder_account_info,
+        treasury_account_info,
+        system_program_account_info,
+        amount,
+        config.deposit_fee_bps,
+        config.treasury_bump,
+        &[],
+    )?;
+
+    // Transfer the rest to the vault
     invoke(
-        &system_instruction::transfer(user_account_info.key, vault_account_info.key, amount),
+        &system_instruction::transfer(funder_account_info.key, vault_account_info.key, net_amount),
         &[
-            user_account_info.clone(),
+            funder_account_info.clone(),
             vault_account_info.clone(),
             system_program_account_info.clone(),
         ],
     )?;
 
-    msg!("Deposited {} lamports to vault", amount);
+    // Count the deposit in the user's lifetime totals, baseline accounts are deposited
+    // into as they are
+    let mut user_data = UserAccount::unpack_any(&user_data_account_info.try_borrow_data()?)?;
+    record_deposit(&mut user_data, net_amount)?;
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    msg!(
+        "Deposited {} lamports to vault, {} lamports fee",
+        net_amount,
+        amount - net_amount
+    );
+
+    DepositEvent {
+        user: *user,
+        vault: *vault_account_info.key,
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Create the user data PDA, paid for by the payer, and store the bumps on first deposit
+#[allow(clippy::too_many_arguments)]
+fn initialize_user_account<'a>(
+    program_id: &Pubkey,
+    payer_account_info: &AccountInfo<'a>,
+    user: &Pubkey,
+    user_data_account_info: &AccountInfo<'a>,
+    system_program_account_info: &AccountInfo<'a>,
+    user_data_bump: u8,
+    vault_bump: u8,
+    withdraw_delay: i64,
+) -> ProgramResult {
+    msg!("Creating user data account");
+    // Calculate rent
+    let rent = Rent::get()?;
+    let space = USER_ACCOUNT_DISCRIMINATOR.len() + UserAccount::SIZE;
+    let rent_lamports = rent.minimum_balance(space);
+
+    // Create the account
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account_info.key,
+            user_data_account_info.key,
+            rent_lamports,
+            space as u64,
+            program_id,
+        ),
+        &[
+            payer_account_info.clone(),
+            user_data_account_info.clone(),
+            system_program_account_info.clone(),
+        ],
+        &[&[user.as_ref(), &[user_data_bump]]],
+    )?;
+
+    // Initialize the account data using borsh
+    let user_data = UserAccount {
+        user: *user,
+        user_bump: user_data_bump,
+        vault_bump,
+        version: USER_ACCOUNT_VERSION,
+        withdraw_delay,
+        pending_withdrawal: 0,
+        unlock_timestamp: 0,
+        withdraw_authority: Pubkey::default(),
+        withdraw_allowance: 0,
+        withdraw_limit: 0,
+        withdraw_window: 0,
+        window_start: 0,
+        window_withdrawn: 0,
+        pending_withdraw_limit: 0,
+        pending_withdraw_window: 0,
+        limit_unlock_timestamp: 0,
+        threshold: 0,
+        total_deposited: 0,
+        total_withdrawn: 0,
+        deposit_count: 0,
+        last_deposit_slot: 0,
+        last_withdraw_slot: 0,
+        signers: Vec::new(),
+    };
+
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
 
     Ok(())
 }
 
-// Process withdraw instruction
-fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info] =
+// Process withdraw instruction, or withdraw the whole vault balance for WithdrawAll
+fn process_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: Option<u64>,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info, multisig_signer_account_infos @ ..] =
         accounts
     else {
         return Err(ProgramError::NotEnoughAccountKeys);
     };
 
-    // Check that the user signed the transaction
-    if !user_account_info.is_signer {
-        msg!("User must sign the transaction");
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
 
     // Check that the system program is valid
     if system_program_account_info.key != &system_program::id() {
         msg!("Invalid system program");
-        return Err(ProgramError::InvalidAccountData);
-    }
-
-    // Verify user account data using borsh
-    let data = user_data_account_info.try_borrow_data()?;
-    let user_data = match UserAccount::try_from_slice(&data) {
-        Ok(data) => data,
-        Err(_) => {
-            msg!("Failed to deserialize user account data");
-            return Err(ProgramError::InvalidAccountData);
-        }
-    };
-
-    // Check that the user account belongs to the requesting user
-    if user_data.user != *user_account_info.key {
-        msg!("User account does not belong to the requesting user");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidSystemProgram.into());
     }
 
-    // Derive and user data PDA
-    let expected_user_data_pubkey = Pubkey::create_program_address(
-        &[user_account_info.key.as_ref(), &[user_data.user_bump]],
+    // Baseline accounts are withdrawn from as they are
+    let mut user_data = load_user_account_with(
         program_id,
+        user_account_info,
+        user_data_account_info,
+        UserAccount::unpack_any,
     )?;
 
-    if user_data_account_info.key != &expected_user_data_pubkey {
-        msg!("Invalid user data account address");
-        return Err(ProgramError::InvalidAccountData);
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    // Timelocked vaults must go through RequestWithdraw and ClaimWithdraw
+    if user_data.withdraw_delay > 0 {
+        msg!("Vault is timelocked, use RequestWithdraw instead");
+        return Err(VaultError::VaultTimelocked.into());
     }
 
     // Derive and verify the vault PDA
@@ -206,8 +783,30 @@ fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64)
 
     if vault_account_info.key != &expected_vault_pubkey {
         msg!("Invalid vault account address");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidVault.into());
+    }
+
+    let vault_lamports = vault_account_info.lamports();
+    let amount = match amount {
+        Some(amount) => amount,
+        None if vault_lamports > 0 => vault_lamports,
+        None => {
+            msg!("Vault is empty");
+            return Err(VaultError::InsufficientFunds.into());
+        }
+    };
+
+    // Fail with a clear error instead of letting the system transfer run dry
+    if vault_lamports < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
     }
+    check_vault_remainder(vault_lamports - amount)?;
+
+    // Spend from the rate limit before moving funds
+    consume_withdraw_limit(&mut user_data, amount)?;
+    record_withdraw(&mut user_data, amount)?;
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
 
     // Derive and verify the vault PDA
     let signer_seeds = [
@@ -216,11 +815,23 @@ fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64)
         &[user_data.vault_bump],
     ];
 
+    // Route the protocol fee to the treasury with a second signed transfer
+    let net_amount = collect_fee(
+        program_id,
+        vault_account_info,
+        treasury_account_info,
+        system_program_account_info,
+        amount,
+        config.withdraw_fee_bps,
+        config.treasury_bump,
+        &[&signer_seeds],
+    )?;
+
     // Create the transfer instruction
     let transfer_instruction = system_instruction::transfer(
-        &vault_account_info.key, // from
-        &user_account_info.key,  // to
-        amount,                  // amount
+        vault_account_info.key, // from
+        user_account_info.key,  // to
+        net_amount,             // amount
     );
 
     // Execute the transfer with the vault's PDA authority
@@ -234,7 +845,1878 @@ fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64)
         &[&signer_seeds],
     )?;
 
-    msg!("Withdrew {} lamports from vault", amount);
+    msg!(
+        "Withdrew {} lamports from vault, {} lamports fee",
+        net_amount,
+        amount - net_amount
+    );
+
+    WithdrawEvent {
+        user: *user_account_info.key,
+        vault: *vault_account_info.key,
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process close instruction
+fn process_close(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+
+    // Draining the vault would bypass the timelock, so it must be emptied
+    // through RequestWithdraw and ClaimWithdraw first
+    let vault_lamports = vault_account_info.lamports();
+    if user_data.withdraw_delay > 0 && vault_lamports > 0 {
+        msg!("Vault is timelocked, claim the remaining balance before closing");
+        return Err(VaultError::VaultTimelocked.into());
+    }
+
+    // Draining the vault counts against the rate limit like any other withdrawal
+    consume_withdraw_limit(&mut user_data, vault_lamports)?;
+
+    // Drain the vault back to the user with the vault's PDA authority, less the
+    // protocol fee which goes to the treasury
+    if vault_lamports > 0 {
+        let signer_seeds = [
+            b"vault",
+            user_account_info.key.as_ref(),
+            &[user_data.vault_bump],
+        ];
+
+        let net_lamports = collect_fee(
+            program_id,
+            vault_account_info,
+            treasury_account_info,
+            system_program_account_info,
+            vault_lamports,
+            config.withdraw_fee_bps,
+            config.treasury_bump,
+            &[&signer_seeds],
+        )?;
+
+        invoke_signed(
+            &system_instruction::transfer(
+                vault_account_info.key,
+                user_account_info.key,
+                net_lamports,
+            ),
+            &[
+                vault_account_info.clone(),
+                user_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+            &[&signer_seeds],
+        )?;
+
+        WithdrawEvent {
+            user: *user_account_info.key,
+            vault: *vault_account_info.key,
+            amount: vault_lamports,
+            fee: vault_lamports - net_lamports,
+            vault_balance: vault_account_info.lamports(),
+            slot: Clock::get()?.slot,
+        }
+        .emit()?;
+    }
+
+    // Return the user data account rent to the user
+    let rent_lamports = user_data_account_info.lamports();
+    **user_account_info.try_borrow_mut_lamports()? = user_account_info
+        .lamports()
+        .checked_add(rent_lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **user_data_account_info.try_borrow_mut_lamports()? = 0;
+
+    // Zero the data, shrink it to nothing and hand the account back to the
+    // system program so it cannot be revived with stale state
+    user_data_account_info.try_borrow_mut_data()?.fill(0);
+    user_data_account_info.resize(0)?;
+    user_data_account_info.assign(&system_program::id());
+
+    msg!(
+        "Closed vault, returned {} lamports and {} lamports of rent",
+        vault_lamports,
+        rent_lamports
+    );
+
+    Ok(())
+}
+
+// Process token deposit instruction
+fn process_deposit_token(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, mint_account_info, user_token_account_info, vault_token_account_info, token_program_account_info, associated_token_program_account_info, system_program_account_info, config_account_info, transfer_hook_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Deposits are blocked while the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
+    // Check that the user signed the transaction
+    if !user_account_info.is_signer {
+        msg!("User must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    // Check that the token programs are valid
+    check_token_program(token_program_account_info, mint_account_info)?;
+
+    if associated_token_program_account_info.key != &spl_associated_token_account::id() {
+        msg!("Invalid associated token program");
+        return Err(VaultError::InvalidAssociatedTokenProgram.into());
+    }
+
+    // Derive and verify the user data PDA
+    let (expected_user_data_pubkey, user_data_bump) =
+        Pubkey::find_program_address(&[user_account_info.key.as_ref()], program_id);
+    if user_data_account_info.key != &expected_user_data_pubkey {
+        msg!("Invalid user data account address");
+        return Err(VaultError::InvalidUserAccount.into());
+    }
+
+    // Derive and verify the vault PDA
+    let (expected_vault_pubkey, vault_bump) =
+        Pubkey::find_program_address(&[b"vault", user_account_info.key.as_ref()], program_id);
+    if vault_account_info.key != &expected_vault_pubkey {
+        msg!("Invalid vault account address");
+        return Err(VaultError::InvalidVault.into());
+    }
+
+    // The vault token account is the vault PDA's associated token account
+    let expected_vault_token_pubkey = get_associated_token_address_with_program_id(
+        vault_account_info.key,
+        mint_account_info.key,
+        token_program_account_info.key,
+    );
+    if vault_token_account_info.key != &expected_vault_token_pubkey {
+        msg!("Invalid vault token account address");
+        return Err(VaultError::InvalidVaultTokenAccount.into());
+    }
+
+    // Initialize user account if needed
+    if user_data_account_info.owner != program_id {
+        initialize_user_account(
+            program_id,
+            user_account_info,
+            user_account_info.key,
+            user_data_account_info,
+            system_program_account_info,
+            user_data_bump,
+            vault_bump,
+            0,
+        )?;
+    }
+
+    // Create the vault token account if it does not exist yet
+    if vault_token_account_info.data_is_empty() {
+        msg!("Creating vault token account");
+        invoke(
+            &create_associated_token_account_idempotent(
+                user_account_info.key,
+                vault_account_info.key,
+                mint_account_info.key,
+                token_program_account_info.key,
+            ),
+            &[
+                user_account_info.clone(),
+                vault_token_account_info.clone(),
+                vault_account_info.clone(),
+                mint_account_info.clone(),
+                system_program_account_info.clone(),
+                token_program_account_info.clone(),
+                associated_token_program_account_info.clone(),
+            ],
+        )?;
+    }
+
+    // Read the mint decimals for the checked transfer
+    let decimals = StateWithExtensions::<Mint>::unpack(&mint_account_info.try_borrow_data()?)?
+        .base
+        .decimals;
+
+    // Transfer tokens from the user to the vault, forwarding any transfer hook accounts
+    let balance_before = token_account_amount(vault_token_account_info)?;
+    invoke_transfer_checked(
+        token_program_account_info.key,
+        user_token_account_info.clone(),
+        mint_account_info.clone(),
+        vault_token_account_info.clone(),
+        user_account_info.clone(),
+        transfer_hook_account_infos,
+        amount,
+        decimals,
+        &[],
+    )?;
+
+    // Transfer fees are withheld in the vault token account, so record what
+    // actually arrived rather than the requested amount
+    let vault_balance = token_account_amount(vault_token_account_info)?;
+    let received = vault_balance
+        .checked_sub(balance_before)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    msg!(
+        "Deposited {} tokens to vault ({} requested)",
+        received,
+        amount
+    );
+
+    TokenDepositEvent {
+        user: *user_account_info.key,
+        vault: *vault_account_info.key,
+        mint: *mint_account_info.key,
+        amount,
+        received,
+        vault_balance,
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process token withdraw instruction
+fn process_withdraw_token(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    signer_count: u8,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, mint_account_info, user_token_account_info, vault_token_account_info, token_program_account_info, config_account_info, remaining_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // The first signer_count extra accounts are multisig members, the rest are passed
+    // on to the token program for a transfer hook
+    if remaining_account_infos.len() < signer_count as usize {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let (multisig_signer_account_infos, transfer_hook_account_infos) =
+        remaining_account_infos.split_at(signer_count as usize);
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the token program is valid
+    check_token_program(token_program_account_info, mint_account_info)?;
+
+    let user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+
+    // The vault token account is the vault PDA's associated token account
+    let expected_vault_token_pubkey = get_associated_token_address_with_program_id(
+        vault_account_info.key,
+        mint_account_info.key,
+        token_program_account_info.key,
+    );
+    if vault_token_account_info.key != &expected_vault_token_pubkey {
+        msg!("Invalid vault token account address");
+        return Err(VaultError::InvalidVaultTokenAccount.into());
+    }
+
+    // Read the mint decimals for the checked transfer
+    let decimals = StateWithExtensions::<Mint>::unpack(&mint_account_info.try_borrow_data()?)?
+        .base
+        .decimals;
+
+    let signer_seeds = [
+        b"vault",
+        user_account_info.key.as_ref(),
+        &[user_data.vault_bump],
+    ];
+
+    // Transfer tokens from the vault to the user with the vault's PDA authority,
+    // forwarding any transfer hook accounts
+    invoke_transfer_checked(
+        token_program_account_info.key,
+        vault_token_account_info.clone(),
+        mint_account_info.clone(),
+        user_token_account_info.clone(),
+        vault_account_info.clone(),
+        transfer_hook_account_infos,
+        amount,
+        decimals,
+        &[&signer_seeds],
+    )?;
+
+    msg!("Withdrew {} tokens from vault", amount);
+
+    Ok(())
+}
+
+// Accept either the Token or Token-2022 program, as long as it owns the mint
+fn check_token_program(
+    token_program_account_info: &AccountInfo,
+    mint_account_info: &AccountInfo,
+) -> ProgramResult {
+    if token_program_account_info.key != &spl_token::id()
+        && token_program_account_info.key != &spl_token_2022::id()
+    {
+        msg!("Invalid token program");
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+
+    if mint_account_info.owner != token_program_account_info.key {
+        msg!("Mint is not owned by the token program");
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+
+    Ok(())
+}
+
+// Read the balance of a Token or Token-2022 account, treating a missing account as empty
+fn token_account_amount(token_account_info: &AccountInfo) -> Result<u64, ProgramError> {
+    if token_account_info.data_is_empty() {
+        return Ok(0);
+    }
+
+    let data = token_account_info.try_borrow_data()?;
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&data)?
+        .base
+        .amount)
+}
+
+// Process request withdraw instruction
+fn process_request_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    if amount == 0 {
+        msg!("Withdraw amount must be greater than zero");
+        return Err(VaultError::InvalidAmount.into());
+    }
+
+    // Only one withdrawal can be pending at a time
+    if user_data.pending_withdrawal > 0 {
+        msg!("A withdrawal is already pending");
+        return Err(VaultError::WithdrawalAlreadyPending.into());
+    }
+
+    // Start the timelock from the current cluster time
+    let clock = Clock::get()?;
+    user_data.pending_withdrawal = amount;
+    user_data.unlock_timestamp = clock
+        .unix_timestamp
+        .checked_add(user_data.withdraw_delay)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    msg!(
+        "Requested withdrawal of {} lamports, unlocks at {}",
+        amount,
+        user_data.unlock_timestamp
+    );
+
+    Ok(())
+}
+
+// Process claim withdraw instruction
+fn process_claim_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+
+    let amount = user_data.pending_withdrawal;
+    if amount == 0 {
+        msg!("No pending withdrawal");
+        return Err(VaultError::NoPendingWithdrawal.into());
+    }
+
+    // The timelock must have expired
+    let clock = Clock::get()?;
+    if clock.unix_timestamp < user_data.unlock_timestamp {
+        msg!(
+            "Withdrawal is timelocked until {}",
+            user_data.unlock_timestamp
+        );
+        return Err(VaultError::WithdrawalStillLocked.into());
+    }
+
+    // Fail with a clear error instead of letting the system transfer run dry
+    if vault_account_info.lamports() < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+    check_vault_remainder(vault_account_info.lamports() - amount)?;
+
+    // Clear the pending withdrawal and spend from the rate limit before moving funds
+    consume_withdraw_limit(&mut user_data, amount)?;
+    record_withdraw(&mut user_data, amount)?;
+    user_data.pending_withdrawal = 0;
+    user_data.unlock_timestamp = 0;
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    let signer_seeds = [
+        b"vault",
+        user_account_info.key.as_ref(),
+        &[user_data.vault_bump],
+    ];
+
+    // Route the protocol fee to the treasury with a second signed transfer
+    let net_amount = collect_fee(
+        program_id,
+        vault_account_info,
+        treasury_account_info,
+        system_program_account_info,
+        amount,
+        config.withdraw_fee_bps,
+        config.treasury_bump,
+        &[&signer_seeds],
+    )?;
+
+    // Execute the transfer with the vault's PDA authority
+    invoke_signed(
+        &system_instruction::transfer(vault_account_info.key, user_account_info.key, net_amount),
+        &[
+            vault_account_info.clone(),
+            user_account_info.clone(),
+            system_program_account_info.clone(),
+        ],
+        &[&signer_seeds],
+    )?;
+
+    msg!(
+        "Claimed {} lamports from vault, {} lamports fee",
+        net_amount,
+        amount - net_amount
+    );
+
+    WithdrawEvent {
+        user: *user_account_info.key,
+        vault: *vault_account_info.key,
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process cancel withdraw instruction
+fn process_cancel_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    if user_data.pending_withdrawal == 0 {
+        msg!("No pending withdrawal");
+        return Err(VaultError::NoPendingWithdrawal.into());
+    }
+
+    user_data.pending_withdrawal = 0;
+    user_data.unlock_timestamp = 0;
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    msg!("Cancelled pending withdrawal");
+
+    Ok(())
+}
+
+// Process set multisig instruction, replacing the signer set and threshold
+fn process_set_multisig(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    signers: Vec<Pubkey>,
+    threshold: u8,
+) -> ProgramResult {
+    // The payer funds the extra rent when the account grows and receives the
+    // excess when it shrinks
+    let [payer_account_info, user_account_info, user_data_account_info, system_program_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Check that the payer signed the transaction
+    if !payer_account_info.is_signer {
+        msg!("Payer must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // Membership changes need the same approval as a withdrawal
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    // An empty signer set with a zero threshold hands control back to the user
+    if signers.len() > MAX_MULTISIG_SIGNERS {
+        msg!(
+            "At most {} multisig signers are allowed",
+            MAX_MULTISIG_SIGNERS
+        );
+        return Err(VaultError::InvalidMultisig.into());
+    }
+    if (threshold == 0) != signers.is_empty() || threshold as usize > signers.len() {
+        msg!("Threshold must be between 1 and the number of signers");
+        return Err(VaultError::InvalidMultisig.into());
+    }
+    for (i, signer) in signers.iter().enumerate() {
+        if signers[..i].contains(signer) {
+            msg!("Duplicate multisig signer {}", signer);
+            return Err(VaultError::InvalidMultisig.into());
+        }
+    }
+
+    user_data.signers = signers;
+    user_data.threshold = threshold;
+
+    // Resize the account to fit the new signer set and keep it rent exempt
+    let new_size = user_data.size();
+    let rent_lamports = Rent::get()?.minimum_balance(new_size);
+    let current_lamports = user_data_account_info.lamports();
+    if rent_lamports > current_lamports {
+        invoke(
+            &system_instruction::transfer(
+                payer_account_info.key,
+                user_data_account_info.key,
+                rent_lamports - current_lamports,
+            ),
+            &[
+                payer_account_info.clone(),
+                user_data_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+        )?;
+    }
+    user_data_account_info.resize(new_size)?;
+    if current_lamports > rent_lamports {
+        let excess = current_lamports - rent_lamports;
+        **user_data_account_info.try_borrow_mut_lamports()? = rent_lamports;
+        let payer_lamports = payer_account_info.lamports();
+        **payer_account_info.try_borrow_mut_lamports()? = payer_lamports
+            .checked_add(excess)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    msg!(
+        "Vault now requires {} of {} signers",
+        user_data.threshold,
+        user_data.signers.len()
+    );
+
+    Ok(())
+}
+
+// Process set withdraw authority instruction, delegating withdraw rights to another key
+fn process_set_withdraw_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority: Pubkey,
+    allowance: u64,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // Delegating needs the same approval as a withdrawal
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    if authority == Pubkey::default() {
+        msg!("Use RevokeWithdrawAuthority to remove the withdraw authority");
+        return Err(VaultError::InvalidWithdrawAuthority.into());
+    }
+
+    // Replacing the delegate also resets its allowance, u64::MAX leaves it uncapped
+    user_data.withdraw_authority = authority;
+    user_data.withdraw_allowance = allowance;
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    msg!(
+        "Delegated withdrawals of up to {} lamports to {}",
+        allowance,
+        authority
+    );
+
+    Ok(())
+}
+
+// Process revoke withdraw authority instruction
+fn process_revoke_withdraw_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    if user_data.withdraw_authority == Pubkey::default() {
+        msg!("No withdraw authority set");
+        return Err(VaultError::InvalidWithdrawAuthority.into());
+    }
+
+    user_data.withdraw_authority = Pubkey::default();
+    user_data.withdraw_allowance = 0;
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    msg!("Revoked withdraw authority");
+
+    Ok(())
+}
+
+// Process delegate withdraw instruction, paying out to the withdraw authority
+fn process_delegate_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let [authority_account_info, user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the withdraw authority signed the transaction
+    if !authority_account_info.is_signer {
+        msg!("Withdraw authority must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+
+    // Only the current delegate may withdraw
+    if user_data.withdraw_authority == Pubkey::default()
+        || user_data.withdraw_authority != *authority_account_info.key
+    {
+        msg!("Signer is not the withdraw authority");
+        return Err(VaultError::InvalidWithdrawAuthority.into());
+    }
+
+    // Timelocked vaults must go through RequestWithdraw and ClaimWithdraw
+    if user_data.withdraw_delay > 0 {
+        msg!("Vault is timelocked, use RequestWithdraw instead");
+        return Err(VaultError::VaultTimelocked.into());
+    }
+
+    // Fail with a clear error instead of letting the system transfer run dry
+    if vault_account_info.lamports() < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+    check_vault_remainder(vault_account_info.lamports() - amount)?;
+
+    // Spend from the allowance unless the delegate is uncapped
+    if user_data.withdraw_allowance != u64::MAX {
+        if amount > user_data.withdraw_allowance {
+            msg!("Amount exceeds the withdraw allowance");
+            return Err(VaultError::AllowanceExceeded.into());
+        }
+        user_data.withdraw_allowance -= amount;
+    }
+
+    // Delegated withdrawals share the vault's rate limit
+    consume_withdraw_limit(&mut user_data, amount)?;
+    record_withdraw(&mut user_data, amount)?;
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    // The vault is still seeded on the depositing user
+    let signer_seeds = [
+        b"vault",
+        user_account_info.key.as_ref(),
+        &[user_data.vault_bump],
+    ];
+
+    // Route the protocol fee to the treasury with a second signed transfer
+    let net_amount = collect_fee(
+        program_id,
+        vault_account_info,
+        treasury_account_info,
+        system_program_account_info,
+        amount,
+        config.withdraw_fee_bps,
+        config.treasury_bump,
+        &[&signer_seeds],
+    )?;
+
+    // Execute the transfer to the delegate with the vault's PDA authority
+    invoke_signed(
+        &system_instruction::transfer(
+            vault_account_info.key,
+            authority_account_info.key,
+            net_amount,
+        ),
+        &[
+            vault_account_info.clone(),
+            authority_account_info.clone(),
+            system_program_account_info.clone(),
+        ],
+        &[&signer_seeds],
+    )?;
+
+    msg!(
+        "Delegate withdrew {} lamports from vault, {} lamports fee",
+        net_amount,
+        amount - net_amount
+    );
+
+    WithdrawEvent {
+        user: *user_account_info.key,
+        vault: *vault_account_info.key,
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process set withdraw limit instruction. Tightening the limit applies immediately,
+// loosening it only once the current window length has passed.
+fn process_set_withdraw_limit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    limit: u64,
+    window: i64,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    // A limit needs a positive window, and no limit needs no window
+    if (limit == 0 && window != 0) || (limit > 0 && window <= 0) {
+        msg!("Withdraw window must be positive when a limit is set");
+        return Err(VaultError::InvalidWithdrawLimit.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    apply_pending_withdraw_limit(&mut user_data, now);
+
+    // A lower or equal limit over an equal or longer window never allows more
+    let tightens = limit > 0
+        && (user_data.withdraw_limit == 0 || limit <= user_data.withdraw_limit)
+        && window >= user_data.withdraw_window;
+
+    if tightens {
+        // Also drops any pending raise
+        user_data.withdraw_limit = limit;
+        user_data.withdraw_window = window;
+        user_data.pending_withdraw_limit = 0;
+        user_data.pending_withdraw_window = 0;
+        user_data.limit_unlock_timestamp = 0;
+        msg!(
+            "Withdraw limit set to {} lamports per {} seconds",
+            limit,
+            window
+        );
+    } else {
+        // Wait out a full window so a stolen key cannot lift the limit and drain at once
+        user_data.pending_withdraw_limit = limit;
+        user_data.pending_withdraw_window = window;
+        user_data.limit_unlock_timestamp = now
+            .checked_add(user_data.withdraw_window)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        msg!(
+            "Withdraw limit of {} lamports per {} seconds applies at {}",
+            limit,
+            window,
+            user_data.limit_unlock_timestamp
+        );
+    }
+
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+// Promote a pending withdraw limit once its delay has passed
+fn apply_pending_withdraw_limit(user_data: &mut UserAccount, now: i64) {
+    if user_data.limit_unlock_timestamp != 0 && now >= user_data.limit_unlock_timestamp {
+        user_data.withdraw_limit = user_data.pending_withdraw_limit;
+        user_data.withdraw_window = user_data.pending_withdraw_window;
+        user_data.pending_withdraw_limit = 0;
+        user_data.pending_withdraw_window = 0;
+        user_data.limit_unlock_timestamp = 0;
+    }
+}
+
+// Record a withdrawal against the rate limit, starting a new window when the current one
+// has elapsed. The caller is responsible for saving the user data.
+fn consume_withdraw_limit(user_data: &mut UserAccount, amount: u64) -> ProgramResult {
+    let now = Clock::get()?.unix_timestamp;
+    apply_pending_withdraw_limit(user_data, now);
+
+    if user_data.withdraw_limit == 0 {
+        return Ok(());
+    }
+
+    if now
+        >= user_data
+            .window_start
+            .saturating_add(user_data.withdraw_window)
+    {
+        user_data.window_start = now;
+        user_data.window_withdrawn = 0;
+    }
+
+    let remaining = user_data
+        .withdraw_limit
+        .saturating_sub(user_data.window_withdrawn);
+    if amount > remaining {
+        msg!(
+            "Withdrawal exceeds the remaining limit of {} lamports",
+            remaining
+        );
+        return Err(VaultError::WithdrawLimitExceeded.into());
+    }
+    user_data.window_withdrawn += amount;
+
+    Ok(())
+}
+
+// Count a deposit of lamports credited to the vault in the lifetime totals. The caller
+// is responsible for saving the user data.
+fn record_deposit(user_data: &mut UserAccount, amount: u64) -> ProgramResult {
+    user_data.total_deposited = user_data
+        .total_deposited
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    user_data.deposit_count = user_data
+        .deposit_count
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    user_data.last_deposit_slot = Clock::get()?.slot;
+
+    Ok(())
+}
+
+// Count lamports taken out of the vault in the lifetime totals. The caller is
+// responsible for saving the user data.
+fn record_withdraw(user_data: &mut UserAccount, amount: u64) -> ProgramResult {
+    user_data.total_withdrawn = user_data
+        .total_withdrawn
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    user_data.last_withdraw_slot = Clock::get()?.slot;
+
+    Ok(())
+}
+
+// Process initialize config instruction, only the program's upgrade authority may
+// create the config and pick the first admin
+fn process_initialize_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    admin: Pubkey,
+) -> ProgramResult {
+    let [upgrade_authority_account_info, config_account_info, program_data_account_info, system_program_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Check that the upgrade authority signed the transaction
+    if !upgrade_authority_account_info.is_signer {
+        msg!("Upgrade authority must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    // The signer must be the authority recorded in this program's ProgramData account
+    let upgrade_authority = read_upgrade_authority(program_id, program_data_account_info)?;
+    if upgrade_authority != Some(*upgrade_authority_account_info.key) {
+        msg!("Signer is not the program upgrade authority");
+        return Err(VaultError::NotUpgradeAuthority.into());
+    }
+
+    if admin == Pubkey::default() {
+        msg!("Admin cannot be the default pubkey");
+        return Err(VaultError::InvalidAdmin.into());
+    }
+
+    // Derive and verify the config PDA
+    let (expected_config_pubkey, config_bump) =
+        Pubkey::find_program_address(&[b"config"], program_id);
+    if config_account_info.key != &expected_config_pubkey {
+        msg!("Invalid config account address");
+        return Err(VaultError::InvalidConfig.into());
+    }
+    if config_account_info.owner == program_id {
+        msg!("Config is already initialized");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    // Create the config account, paid for by the upgrade authority
+    let rent = Rent::get()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            upgrade_authority_account_info.key,
+            config_account_info.key,
+            rent.minimum_balance(Config::LEN),
+            Config::LEN as u64,
+            program_id,
+        ),
+        &[
+            upgrade_authority_account_info.clone(),
+            config_account_info.clone(),
+            system_program_account_info.clone(),
+        ],
+        &[&[b"config", &[config_bump]]],
+    )?;
+
+    // Fees start disabled, the treasury bump is stored so later checks stay cheap
+    let (_, treasury_bump) = Pubkey::find_program_address(&[b"treasury"], program_id);
+    let config = Config {
+        admin,
+        paused: false,
+        withdrawals_paused: false,
+        bump: config_bump,
+        treasury_bump,
+        deposit_fee_bps: 0,
+        withdraw_fee_bps: 0,
+    };
+    config.pack(&mut config_account_info.try_borrow_mut_data()?)?;
+
+    msg!("Initialized config with admin {}", admin);
+
+    Ok(())
+}
+
+// Process set paused instruction
+fn process_set_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    deposits: bool,
+    withdrawals: bool,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    // Withdrawals can only be paused on top of deposits, never on their own
+    if withdrawals && !deposits {
+        msg!("Withdrawals can only be paused together with deposits");
+        return Err(VaultError::InvalidPauseFlags.into());
+    }
+
+    config.paused = deposits;
+    config.withdrawals_paused = withdrawals;
+    config.pack(&mut config_account_info.try_borrow_mut_data()?)?;
+
+    msg!(
+        "Set deposits paused to {}, withdrawals paused to {}",
+        deposits,
+        withdrawals
+    );
+
+    Ok(())
+}
+
+// Process transfer admin instruction
+fn process_transfer_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    if new_admin == Pubkey::default() {
+        msg!("Admin cannot be the default pubkey");
+        return Err(VaultError::InvalidAdmin.into());
+    }
+
+    config.admin = new_admin;
+    config.pack(&mut config_account_info.try_borrow_mut_data()?)?;
+
+    msg!("Transferred admin to {}", new_admin);
+
+    Ok(())
+}
+
+// Process set fees instruction. The treasury is topped up to the rent-exempt minimum
+// here, so that fees smaller than that can still be paid into it.
+fn process_set_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    deposit_bps: u16,
+    withdraw_bps: u16,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info, treasury_account_info, system_program_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    if deposit_bps > MAX_FEE_BPS || withdraw_bps > MAX_FEE_BPS {
+        msg!("Fee cannot exceed {} basis points", MAX_FEE_BPS);
+        return Err(VaultError::InvalidFee.into());
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    check_treasury_address(program_id, treasury_account_info, config.treasury_bump)?;
+
+    let rent_lamports = Rent::get()?.minimum_balance(0);
+    let treasury_lamports = treasury_account_info.lamports();
+    if treasury_lamports < rent_lamports {
+        invoke(
+            &system_instruction::transfer(
+                admin_account_info.key,
+                treasury_account_info.key,
+                rent_lamports - treasury_lamports,
+            ),
+            &[
+                admin_account_info.clone(),
+                treasury_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+        )?;
+    }
+
+    config.deposit_fee_bps = deposit_bps;
+    config.withdraw_fee_bps = withdraw_bps;
+    config.pack(&mut config_account_info.try_borrow_mut_data()?)?;
+
+    msg!(
+        "Set deposit fee to {} bps and withdraw fee to {} bps",
+        deposit_bps,
+        withdraw_bps
+    );
+
+    Ok(())
+}
+
+// Process withdraw fees instruction, paying collected fees out to the admin
+fn process_withdraw_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info, treasury_account_info, system_program_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    check_treasury_address(program_id, treasury_account_info, config.treasury_bump)?;
+
+    // The treasury keeps its rent-exempt minimum so it can keep receiving fees
+    let rent_lamports = Rent::get()?.minimum_balance(0);
+    let available = treasury_account_info
+        .lamports()
+        .saturating_sub(rent_lamports);
+    if amount > available {
+        msg!("Insufficient fees in treasury");
+        return Err(VaultError::InsufficientFees.into());
+    }
+
+    invoke_signed(
+        &system_instruction::transfer(treasury_account_info.key, admin_account_info.key, amount),
+        &[
+            treasury_account_info.clone(),
+            admin_account_info.clone(),
+            system_program_account_info.clone(),
+        ],
+        &[&[b"treasury", &[config.treasury_bump]]],
+    )?;
+
+    msg!("Withdrew {} lamports of fees", amount);
+
+    Ok(())
+}
+
+// Rewrite a baseline user account, which holds nothing but the user and the bumps, in
+// the current layout. Deposits and withdrawals accept baseline accounts as they are,
+// but they only record the lifetime totals once migrated, and every other instruction
+// needs the fields added since. The account grows to the current size, with the user
+// covering the extra rent. The new fields and the totals start from zero.
+fn process_migrate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let [user_account_info, user_data_account_info, system_program_account_info] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // The user pays for the larger account
+    if !user_account_info.is_signer {
+        msg!("User must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut user_data = load_user_account_with(
+        program_id,
+        user_account_info,
+        user_data_account_info,
+        UserAccount::unpack_any,
+    )?;
+
+    // Migrating twice is harmless
+    if user_data.version == USER_ACCOUNT_VERSION {
+        msg!(
+            "User account is already at version {}",
+            USER_ACCOUNT_VERSION
+        );
+        return Ok(());
+    }
+
+    user_data.version = USER_ACCOUNT_VERSION;
+
+    // Grow the account for the new fields and keep it rent exempt
+    let new_size = user_data.size();
+    let rent_lamports = Rent::get()?.minimum_balance(new_size);
+    let current_lamports = user_data_account_info.lamports();
+    if rent_lamports > current_lamports {
+        invoke(
+            &system_instruction::transfer(
+                user_account_info.key,
+                user_data_account_info.key,
+                rent_lamports - current_lamports,
+            ),
+            &[
+                user_account_info.clone(),
+                user_data_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+        )?;
+    }
+    user_data_account_info.resize(new_size)?;
+
+    user_data.pack(&mut user_data_account_info.try_borrow_mut_data()?)?;
+
+    msg!("Migrated user account to version {}", USER_ACCOUNT_VERSION);
+
+    Ok(())
+}
+
+// Process delegate stake instruction. The vault funds its stake account, which is
+// created here, and stays its staker and withdrawer, so staked lamports can only ever
+// return to the vault. They do not count as withdrawn.
+fn process_delegate_stake(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, stake_account_info, vote_account_info, clock_sysvar_account_info, rent_sysvar_account_info, stake_history_sysvar_account_info, stake_config_account_info, system_program_account_info, stake_program_account_info, config_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Staking moves lamports out of the vault, so it stops whenever the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
+    // Check that the system and stake programs are valid
+    if system_program_account_info.key != &system_program::id() {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+    check_stake_program(stake_program_account_info)?;
+
+    let user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+    let stake_bump = check_stake_address(program_id, vault_account_info, stake_account_info)?;
+
+    // A vault stakes through a single account, which must be withdrawn before the
+    // next delegation
+    if !stake_account_info.data_is_empty() {
+        msg!("Stake account already in use");
+        return Err(VaultError::StakeAccountInUse.into());
+    }
+
+    if amount == 0 {
+        msg!("Amount must be greater than zero");
+        return Err(VaultError::InvalidAmount.into());
+    }
+
+    // Lamports requested for a pending withdrawal stay in the vault
+    let vault_lamports = vault_account_info.lamports();
+    if vault_lamports.saturating_sub(user_data.pending_withdrawal) < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+    check_vault_remainder(vault_lamports - amount)?;
+
+    let vault_seeds: &[&[u8]] = &[
+        b"vault",
+        user_account_info.key.as_ref(),
+        &[user_data.vault_bump],
+    ];
+    let stake_seeds: &[&[u8]] = &[b"stake", vault_account_info.key.as_ref(), &[stake_bump]];
+
+    // Create the stake account with the lamports from the vault. The stake program
+    // keeps its rent-exempt reserve out of the delegation.
+    if stake_account_info.lamports() == 0 {
+        invoke_signed(
+            &system_instruction::create_account(
+                vault_account_info.key,
+                stake_account_info.key,
+                amount,
+                StakeStateV2::size_of() as u64,
+                &stake::program::id(),
+            ),
+            &[
+                vault_account_info.clone(),
+                stake_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+            &[vault_seeds, stake_seeds],
+        )?;
+    } else {
+        // Anyone can send lamports to the predictable address, which would make
+        // create_account fail forever. Top it up and take it over step by step instead.
+        invoke_signed(
+            &system_instruction::transfer(vault_account_info.key, stake_account_info.key, amount),
+            &[
+                vault_account_info.clone(),
+                stake_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+            &[vault_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::allocate(stake_account_info.key, StakeStateV2::size_of() as u64),
+            &[
+                stake_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+            &[stake_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(stake_account_info.key, &stake::program::id()),
+            &[
+                stake_account_info.clone(),
+                system_program_account_info.clone(),
+            ],
+            &[stake_seeds],
+        )?;
+    }
+
+    invoke(
+        &stake_instruction::initialize(
+            stake_account_info.key,
+            &Authorized {
+                staker: *vault_account_info.key,
+                withdrawer: *vault_account_info.key,
+            },
+            &Lockup::default(),
+        ),
+        &[
+            stake_account_info.clone(),
+            rent_sysvar_account_info.clone(),
+            stake_program_account_info.clone(),
+        ],
+    )?;
+
+    invoke_signed(
+        &stake_instruction::delegate_stake(
+            stake_account_info.key,
+            vault_account_info.key,
+            vote_account_info.key,
+        ),
+        &[
+            stake_account_info.clone(),
+            vote_account_info.clone(),
+            clock_sysvar_account_info.clone(),
+            stake_history_sysvar_account_info.clone(),
+            stake_config_account_info.clone(),
+            vault_account_info.clone(),
+            stake_program_account_info.clone(),
+        ],
+        &[vault_seeds],
+    )?;
+
+    msg!(
+        "Delegated {} lamports from vault to {}",
+        amount,
+        vote_account_info.key
+    );
+
+    Ok(())
+}
+
+// Process deactivate stake instruction. The stake cools down over the following epochs
+// and can be withdrawn once inactive.
+fn process_deactivate_stake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, stake_account_info, clock_sysvar_account_info, stake_program_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    check_stake_program(stake_program_account_info)?;
+
+    let user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+    check_stake_address(program_id, vault_account_info, stake_account_info)?;
+
+    invoke_signed(
+        &stake_instruction::deactivate_stake(stake_account_info.key, vault_account_info.key),
+        &[
+            stake_account_info.clone(),
+            clock_sysvar_account_info.clone(),
+            vault_account_info.clone(),
+            stake_program_account_info.clone(),
+        ],
+        &[&[
+            b"vault",
+            user_account_info.key.as_ref(),
+            &[user_data.vault_bump],
+        ]],
+    )?;
+
+    msg!("Deactivated vault stake");
+
+    Ok(())
+}
+
+// Process withdraw stake instruction, returning the whole stake account balance,
+// rewards included, to the vault. The stake program deletes the emptied account, so the
+// vault can delegate again afterwards.
+fn process_withdraw_stake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let [user_account_info, user_data_account_info, vault_account_info, stake_account_info, clock_sysvar_account_info, stake_history_sysvar_account_info, stake_program_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    check_stake_program(stake_program_account_info)?;
+
+    let user_data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(&user_data, user_account_info, multisig_signer_account_infos)?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+    check_stake_address(program_id, vault_account_info, stake_account_info)?;
+
+    let stake_lamports = stake_account_info.lamports();
+    if stake_lamports == 0 {
+        msg!("Vault has no stake account");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+
+    invoke_signed(
+        &stake_instruction::withdraw(
+            stake_account_info.key,
+            vault_account_info.key,
+            vault_account_info.key,
+            stake_lamports,
+            None,
+        ),
+        &[
+            stake_account_info.clone(),
+            vault_account_info.clone(),
+            clock_sysvar_account_info.clone(),
+            stake_history_sysvar_account_info.clone(),
+            stake_program_account_info.clone(),
+        ],
+        &[&[
+            b"vault",
+            user_account_info.key.as_ref(),
+            &[user_data.vault_bump],
+        ]],
+    )?;
+
+    msg!("Withdrew {} lamports of stake to vault", stake_lamports);
+
+    Ok(())
+}
+
+// Read the upgrade authority from the program's ProgramData account. The account is
+// bincode encoded: a u32 variant tag (3 = ProgramData), the deploy slot as u64, then
+// the authority as an Option<Pubkey>.
+fn read_upgrade_authority(
+    program_id: &Pubkey,
+    program_data_account_info: &AccountInfo,
+) -> Result<Option<Pubkey>, ProgramError> {
+    let (expected_program_data_pubkey, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if program_data_account_info.key != &expected_program_data_pubkey
+        || program_data_account_info.owner != &bpf_loader_upgradeable::id()
+    {
+        msg!("Invalid program data account");
+        return Err(VaultError::InvalidProgramData.into());
+    }
+
+    let data = program_data_account_info.try_borrow_data()?;
+    if data.len() < 45 || data[0..4] != 3u32.to_le_bytes() {
+        msg!("Invalid program data account");
+        return Err(VaultError::InvalidProgramData.into());
+    }
+
+    // A program without an upgrade authority is immutable
+    if data[12] == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Pubkey::new_from_array(
+        data[13..45]
+            .try_into()
+            .map_err(|_| VaultError::InvalidProgramData)?,
+    )))
+}
+
+// Load the config and check that the admin signed
+fn load_config(
+    program_id: &Pubkey,
+    admin_account_info: &AccountInfo,
+    config_account_info: &AccountInfo,
+) -> Result<Config, ProgramError> {
+    if !admin_account_info.is_signer {
+        msg!("Admin must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if config_account_info.owner != program_id {
+        msg!("Config is not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let config = Config::unpack(&config_account_info.try_borrow_data()?)?;
+
+    // The bump is only stored in the real config PDA
+    let expected_config_pubkey =
+        Pubkey::create_program_address(&[b"config", &[config.bump]], program_id)
+            .map_err(|_| VaultError::InvalidConfig)?;
+    if config_account_info.key != &expected_config_pubkey {
+        msg!("Invalid config account address");
+        return Err(VaultError::InvalidConfig.into());
+    }
+
+    if config.admin != *admin_account_info.key {
+        msg!("Signer is not the admin");
+        return Err(VaultError::NotAdmin.into());
+    }
+
+    Ok(config)
+}
+
+// Read the config from its PDA. Until the config is initialized the program runs
+// unpaused and without fees.
+fn read_config(
+    program_id: &Pubkey,
+    config_account_info: &AccountInfo,
+) -> Result<Config, ProgramError> {
+    let (expected_config_pubkey, _) = Pubkey::find_program_address(&[b"config"], program_id);
+    if config_account_info.key != &expected_config_pubkey {
+        msg!("Invalid config account address");
+        return Err(VaultError::InvalidConfig.into());
+    }
+
+    if config_account_info.owner != program_id {
+        return Ok(Config {
+            admin: Pubkey::default(),
+            paused: false,
+            withdrawals_paused: false,
+            bump: 0,
+            treasury_bump: 0,
+            deposit_fee_bps: 0,
+            withdraw_fee_bps: 0,
+        });
+    }
+
+    Config::unpack(&config_account_info.try_borrow_data()?)
+}
+
+// Reject deposits, or withdrawals when those are paused too
+fn check_not_paused(config: &Config, is_withdrawal: bool) -> ProgramResult {
+    if config.paused && (!is_withdrawal || config.withdrawals_paused) {
+        msg!("Program is paused");
+        return Err(VaultError::ProgramPaused.into());
+    }
+
+    Ok(())
+}
+
+// Fee on an amount in basis points, rounded down so users are never overcharged
+fn calculate_fee(amount: u64, fee_bps: u16) -> Result<u64, ProgramError> {
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        / BPS_DENOMINATOR;
+    u64::try_from(fee).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+// Transfer the fee on an amount from the payer to the treasury and return what is
+// left. Pass the payer's seeds when it is a PDA, or no seeds when it signed itself.
+#[allow(clippy::too_many_arguments)]
+fn collect_fee<'a>(
+    program_id: &Pubkey,
+    from_account_info: &AccountInfo<'a>,
+    treasury_account_info: &AccountInfo<'a>,
+    system_program_account_info: &AccountInfo<'a>,
+    amount: u64,
+    fee_bps: u16,
+    treasury_bump: u8,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<u64, ProgramError> {
+    let fee = calculate_fee(amount, fee_bps)?;
+    if fee == 0 {
+        return Ok(amount);
+    }
+
+    check_treasury_address(program_id, treasury_account_info, treasury_bump)?;
+
+    invoke_signed(
+        &system_instruction::transfer(from_account_info.key, treasury_account_info.key, fee),
+        &[
+            from_account_info.clone(),
+            treasury_account_info.clone(),
+            system_program_account_info.clone(),
+        ],
+        signer_seeds,
+    )?;
+
+    Ok(amount - fee)
+}
+
+// Verify the treasury PDA using the stored bump
+fn check_treasury_address(
+    program_id: &Pubkey,
+    treasury_account_info: &AccountInfo,
+    treasury_bump: u8,
+) -> ProgramResult {
+    let expected_treasury_pubkey =
+        Pubkey::create_program_address(&[b"treasury", &[treasury_bump]], program_id)
+            .map_err(|_| VaultError::InvalidTreasury)?;
+    if treasury_account_info.key != &expected_treasury_pubkey {
+        msg!("Invalid treasury account address");
+        return Err(VaultError::InvalidTreasury.into());
+    }
+
+    Ok(())
+}
+
+// Check that the user signed, or that enough distinct multisig members signed
+fn check_authority<'a>(
+    user_data: &UserAccount,
+    user_account_info: &AccountInfo<'a>,
+    multisig_signer_account_infos: &[AccountInfo<'a>],
+) -> ProgramResult {
+    if user_data.threshold == 0 {
+        if !user_account_info.is_signer {
+            msg!("User must sign the transaction");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        return Ok(());
+    }
+
+    // Count each member once, no matter how often its account is repeated
+    let mut approvals: Vec<&Pubkey> = Vec::with_capacity(user_data.signers.len());
+    for account_info in std::iter::once(user_account_info).chain(multisig_signer_account_infos) {
+        if account_info.is_signer
+            && user_data.signers.contains(account_info.key)
+            && !approvals.contains(&account_info.key)
+        {
+            approvals.push(account_info.key);
+        }
+    }
+
+    if approvals.len() < user_data.threshold as usize {
+        msg!(
+            "Only {} of the required {} multisig signers approved",
+            approvals.len(),
+            user_data.threshold
+        );
+        return Err(VaultError::NotEnoughSigners.into());
+    }
+
+    Ok(())
+}
+
+// Deserialize the user data account and verify it belongs to the user
+fn load_user_account(
+    program_id: &Pubkey,
+    user_account_info: &AccountInfo,
+    user_data_account_info: &AccountInfo,
+) -> Result<UserAccount, ProgramError> {
+    load_user_account_with(
+        program_id,
+        user_account_info,
+        user_data_account_info,
+        UserAccount::unpack,
+    )
+}
+
+// As load_user_account, deserializing with the given function
+fn load_user_account_with(
+    program_id: &Pubkey,
+    user_account_info: &AccountInfo,
+    user_data_account_info: &AccountInfo,
+    unpack: fn(&[u8]) -> Result<UserAccount, ProgramError>,
+) -> Result<UserAccount, ProgramError> {
+    if user_data_account_info.owner != program_id {
+        msg!("User data account is not owned by the program");
+        return Err(VaultError::InvalidAccountOwner.into());
+    }
+
+    let user_data = match unpack(&user_data_account_info.try_borrow_data()?) {
+        Ok(data) => data,
+        Err(_) => {
+            msg!("Failed to deserialize user account data");
+            return Err(VaultError::DeserializationFailed.into());
+        }
+    };
+
+    // Check that the user account belongs to the requesting user
+    if user_data.user != *user_account_info.key {
+        msg!("User account does not belong to the requesting user");
+        return Err(VaultError::UserMismatch.into());
+    }
+
+    // Derive and verify the user data PDA
+    let expected_user_data_pubkey = Pubkey::create_program_address(
+        &[user_account_info.key.as_ref(), &[user_data.user_bump]],
+        program_id,
+    )?;
+
+    if user_data_account_info.key != &expected_user_data_pubkey {
+        msg!("Invalid user data account address");
+        return Err(VaultError::InvalidUserAccount.into());
+    }
+
+    Ok(user_data)
+}
+
+// The vault is a system account, so a withdrawal must either drain it or leave it
+// rent-exempt. Anything in between would fail inside the system transfer with an
+// error that does not say how much may be withdrawn.
+fn check_vault_remainder(remaining: u64) -> ProgramResult {
+    let minimum = Rent::get()?.minimum_balance(0);
+    if remaining > 0 && remaining < minimum {
+        msg!(
+            "Withdrawal would leave {} lamports in the vault, below the rent-exempt minimum of {}, withdraw everything or leave at least the minimum",
+            remaining,
+            minimum
+        );
+        return Err(VaultError::VaultBelowRentExempt.into());
+    }
+
+    Ok(())
+}
+
+// Verify the vault PDA using the stored bump
+fn check_vault_address(
+    program_id: &Pubkey,
+    user_account_info: &AccountInfo,
+    vault_account_info: &AccountInfo,
+    vault_bump: u8,
+) -> ProgramResult {
+    let expected_vault_pubkey = Pubkey::create_program_address(
+        &[b"vault", user_account_info.key.as_ref(), &[vault_bump]],
+        program_id,
+    )?;
+
+    if vault_account_info.key != &expected_vault_pubkey {
+        msg!("Invalid vault account address");
+        return Err(VaultError::InvalidVault.into());
+    }
+
+    Ok(())
+}
+
+// Verify the stake PDA of the vault, returning its bump
+fn check_stake_address(
+    program_id: &Pubkey,
+    vault_account_info: &AccountInfo,
+    stake_account_info: &AccountInfo,
+) -> Result<u8, ProgramError> {
+    let (expected_stake_pubkey, stake_bump) =
+        Pubkey::find_program_address(&[b"stake", vault_account_info.key.as_ref()], program_id);
+
+    if stake_account_info.key != &expected_stake_pubkey {
+        msg!("Invalid stake account address");
+        return Err(VaultError::InvalidStakeAccount.into());
+    }
+
+    Ok(stake_bump)
+}
+
+fn check_stake_program(stake_program_account_info: &AccountInfo) -> ProgramResult {
+    if stake_program_account_info.key != &stake::program::id() {
+        msg!("Invalid stake program");
+        return Err(VaultError::InvalidStakeProgram.into());
+    }
 
     Ok(())
 }
@@ -0,0 +1,23 @@
+// Fetch the typed events the vault program logged in a transaction, see
+// vault_interface::event
+use anyhow::Result;
+use solana_client::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::UiTransactionEncoding;
+use vault_interface::event::{decode_events, VaultEvent};
+
+// Fetch a confirmed transaction and decode the vault's events from its logs
+pub fn fetch_events(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    signature: &Signature,
+) -> Result<Vec<VaultEvent>> {
+    let transaction = rpc_client.get_transaction(signature, UiTransactionEncoding::Json)?;
+    let logs: Option<Vec<String>> = transaction
+        .transaction
+        .meta
+        .and_then(|meta| meta.log_messages.into());
+
+    Ok(decode_events(program_id, &logs.unwrap_or_default()))
+}
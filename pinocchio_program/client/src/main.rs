@@ -1,182 +1,362 @@
-use anyhow::Result;
-use borsh::BorshSerialize;
-use borsh_derive::BorshSerialize as BorshSerializeDerive;
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand};
+use errors::explain;
+use events::fetch_events;
+use solana_cli_config::{Config as CliConfig, CONFIG_FILE};
 use solana_client::rpc_client::RpcClient;
-use solana_program::{
-    instruction::{AccountMeta, Instruction},
-    pubkey::Pubkey,
-    system_program,
-};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
 use solana_sdk::{
     commitment_config::CommitmentConfig,
     native_token::LAMPORTS_PER_SOL,
-    signature::{Keypair, Signer},
+    signature::{read_keypair_file, Keypair, Signer},
     transaction::Transaction,
 };
-use std::{str::FromStr, thread, time::Duration};
+use std::str::FromStr;
+use vault_interface::{
+    find_config_address, find_user_account_address, find_vault_address, instruction, Config,
+    UserAccount,
+};
+
+mod errors;
+mod events;
 
 // Include the test module
 #[cfg(test)]
 mod test;
+#[cfg(test)]
+mod test_litesvm;
 
 const PROGRAM_ID: &str = "G7isKoAvjaMXi7CSDZTspXvUaD2dfVNwZyrWYTe6nfoj";
-const RPC_URL: &str = "http://127.0.0.1:8899";
 
-#[derive(Debug, BorshSerializeDerive)]
-pub enum ProgramInstruction {
-    Deposit { amount: u64 },
-    Withdraw { amount: u64 },
-}
+// Anything not passed on the command line is read from the Solana CLI config
+#[derive(Parser)]
+#[command(about = "Command-line client for the pinocchio vault program")]
+struct Cli {
+    /// Solana CLI config file [default: ~/.config/solana/cli/config.yml]
+    #[arg(long, short = 'C', global = true)]
+    config: Option<String>,
 
-impl ProgramInstruction {
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        BorshSerialize::serialize(self, &mut data).unwrap();
-        data
-    }
-}
-// Helper functions for finding PDAs
-fn find_user_account_address(user_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[user_pubkey.as_ref()], program_id)
-}
+    /// RPC URL or moniker (localhost, devnet, testnet, mainnet-beta)
+    #[arg(long, short = 'u', global = true)]
+    url: Option<String>,
+
+    /// Keypair of the vault owner, which also pays the fees
+    #[arg(long, short = 'k', global = true)]
+    keypair: Option<String>,
+
+    /// Address of the deployed vault program
+    #[arg(long, global = true, default_value = PROGRAM_ID)]
+    program_id: Pubkey,
+
+    /// Commitment level to read and confirm at
+    #[arg(long, global = true, value_parser = ["processed", "confirmed", "finalized"])]
+    commitment: Option<String>,
 
-fn find_vault_address(user_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[b"vault", user_pubkey.as_ref()], program_id)
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn main() -> Result<()> {
-    // Create connection
-    let commitment_config = CommitmentConfig::confirmed();
-    let connection = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
-
-    // Setup payer and request airdrop
-    let payer = Keypair::new();
-    let airdrop_signature = connection.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
-    connection.confirm_transaction(&airdrop_signature)?;
-    thread::sleep(Duration::from_secs(1));
-
-    // Get the program ID from the PROGRAM_ID constant
-    let program_id = Pubkey::from_str(PROGRAM_ID)?;
-
-    // Get the user and vault PDAs
-    let user_pubkey = payer.pubkey();
-    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
-    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
-
-    // Get initial balances
-    let user_initial_balance = connection.get_balance(&user_pubkey)?;
-    let vault_initial_balance = match connection.get_account(&vault_pda) {
-        Ok(account) => account.lamports,
-        Err(_) => 0,
-    };
+#[derive(Subcommand)]
+enum Command {
+    /// Deposit into the vault, creating it on first use
+    Deposit {
+        /// Amount in SOL, or in lamports with a "lamports" suffix
+        #[arg(value_parser = parse_amount)]
+        amount: u64,
 
-    println!("User data PDA: {}", user_account_pda);
-    println!("Vault PDA: {}", vault_pda);
-    println!(
-        "User initial balance: {} SOL",
-        user_initial_balance as f64 / LAMPORTS_PER_SOL as f64
-    );
-    println!(
-        "Vault initial balance: {} SOL",
-        vault_initial_balance as f64 / LAMPORTS_PER_SOL as f64
-    );
+        /// Fund this user's vault instead, without their signature
+        #[arg(long = "for", value_name = "BENEFICIARY")]
+        beneficiary: Option<Pubkey>,
+    },
+    /// Withdraw from the vault
+    Withdraw {
+        /// Amount in SOL, or in lamports with a "lamports" suffix
+        #[arg(value_parser = parse_amount, required_unless_present = "all")]
+        amount: Option<u64>,
 
-    // Amount to deposit
-    let deposit_amount = LAMPORTS_PER_SOL / 10; // 0.1 SOL
+        /// Withdraw the whole balance, leaving the vault empty
+        #[arg(long, conflicts_with = "amount")]
+        all: bool,
+    },
+    /// Show the balance of a vault
+    Balance {
+        /// Owner of the vault [default: the keypair's pubkey]
+        user: Option<Pubkey>,
+    },
+    /// Close the vault, returning its lamports and the user account rent
+    Close,
+    /// Show the settings of a vault and of the program
+    Info {
+        /// Owner of the vault [default: the keypair's pubkey]
+        user: Option<Pubkey>,
+    },
+}
 
-    // Create deposit instruction
-    let instruction_data = ProgramInstruction::Deposit {
-        amount: deposit_amount,
+// Parse an amount in SOL with up to 9 decimals, or in lamports with a "lamports"
+// suffix. SOL amounts are converted exactly, without going through a float.
+fn parse_amount(value: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid amount: {}", value);
+    if let Some(lamports) = value.strip_suffix("lamports") {
+        return lamports.trim().parse().map_err(|_| invalid());
+    }
+    let sol = value
+        .strip_suffix("SOL")
+        .or_else(|| value.strip_suffix("sol"))
+        .unwrap_or(value)
+        .trim();
+    let (whole, fraction) = sol.split_once('.').unwrap_or((sol, ""));
+    let digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty())
+        || fraction.len() > 9
+        || !digits(whole)
+        || !digits(fraction)
+    {
+        return Err(invalid());
     }
-    .serialize();
-
-    let deposit_instruction = Instruction {
-        program_id,
-        accounts: vec![
-            AccountMeta::new(user_pubkey, true), // User (signer, writable)
-            AccountMeta::new(user_account_pda, false), // User account PDA (writable)
-            AccountMeta::new(vault_pda, false),  // Vault PDA (writable)
-            AccountMeta::new_readonly(system_program::id(), false), // System program
-        ],
-        data: instruction_data,
+    let whole: u64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().map_err(|_| invalid())?
     };
+    let fraction: u64 = format!("{:0<9}", fraction).parse().map_err(|_| invalid())?;
+    whole
+        .checked_mul(LAMPORTS_PER_SOL)
+        .and_then(|lamports| lamports.checked_add(fraction))
+        .ok_or_else(invalid)
+}
 
-    // Send deposit transaction
-    let recent_blockhash = connection.get_latest_blockhash()?;
-    let deposit_transaction = Transaction::new_signed_with_payer(
-        &[deposit_instruction],
-        Some(&user_pubkey),
-        &[&payer],
-        recent_blockhash,
-    );
-
-    let deposit_signature = connection.send_and_confirm_transaction(&deposit_transaction)?;
-    println!("\nDeposit transaction signature: {}", deposit_signature);
+// Expand the cluster monikers the Solana CLI accepts
+fn normalize_url(url: &str) -> String {
+    match url {
+        "l" | "localhost" => "http://localhost:8899",
+        "d" | "devnet" => "https://api.devnet.solana.com",
+        "t" | "testnet" => "https://api.testnet.solana.com",
+        "m" | "mainnet-beta" => "https://api.mainnet-beta.solana.com",
+        url => url,
+    }
+    .to_string()
+}
 
-    // Get balances after deposit
-    thread::sleep(Duration::from_secs(1));
-    let user_after_deposit = connection.get_balance(&user_pubkey)?;
-    let vault_account = connection.get_account(&vault_pda)?;
-    let vault_after_deposit = vault_account.lamports;
+fn sol(lamports: u64) -> String {
+    format!("{} SOL", lamports as f64 / LAMPORTS_PER_SOL as f64)
+}
 
-    println!(
-        "User balance after deposit: {} SOL",
-        user_after_deposit as f64 / LAMPORTS_PER_SOL as f64
-    );
-    println!(
-        "Vault balance after deposit: {} SOL",
-        vault_after_deposit as f64 / LAMPORTS_PER_SOL as f64
+// Sign with the keypair, send, and print the signature and the program's events
+fn send(
+    connection: &RpcClient,
+    program_id: &Pubkey,
+    payer: &Keypair,
+    instruction: Instruction,
+) -> Result<()> {
+    let recent_blockhash = connection.get_latest_blockhash()?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
     );
+    let signature = connection
+        .send_and_confirm_transaction(&transaction)
+        .map_err(explain)?;
+    println!("Signature: {}", signature);
+    for event in fetch_events(connection, program_id, &signature)? {
+        println!("Event: {}", event);
+    }
+    Ok(())
+}
 
-    // Wait a bit before withdrawing
-    thread::sleep(Duration::from_secs(2));
+fn print_info(connection: &RpcClient, program_id: &Pubkey, user: &Pubkey) -> Result<()> {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    let (vault_pda, _) = find_vault_address(user, program_id);
+    println!("User account: {}", user_account_pda);
+    println!("Vault: {}", vault_pda);
 
-    // Now withdraw half of what was deposited
-    let withdraw_amount = deposit_amount / 2;
+    match connection.get_account(&user_account_pda) {
+        Ok(account) => {
+            let user_account =
+                UserAccount::unpack(&account.data).context("Failed to decode the user account")?;
+            println!("Balance: {}", sol(connection.get_balance(&vault_pda)?));
+            println!(
+                "Layout version: {}{}",
+                user_account.version,
+                if user_account.is_baseline() {
+                    " (baseline, run Migrate to record lifetime totals)"
+                } else {
+                    ""
+                }
+            );
+            if !user_account.is_baseline() {
+                let slot = |slot: u64| match slot {
+                    0 => "never".to_string(),
+                    slot => format!("slot {}", slot),
+                };
+                println!(
+                    "Deposited: {} in {} deposits, last at {}",
+                    sol(user_account.total_deposited),
+                    user_account.deposit_count,
+                    slot(user_account.last_deposit_slot)
+                );
+                println!(
+                    "Withdrawn: {}, last at {}",
+                    sol(user_account.total_withdrawn),
+                    slot(user_account.last_withdraw_slot)
+                );
+            }
+            if user_account.withdraw_delay > 0 {
+                println!("Withdraw delay: {} s", user_account.withdraw_delay);
+            }
+            if user_account.pending_withdrawal > 0 {
+                println!(
+                    "Pending withdrawal: {}, unlocks at {}",
+                    sol(user_account.pending_withdrawal),
+                    user_account.unlock_timestamp
+                );
+            }
+            if user_account.withdraw_authority != Pubkey::default() {
+                let allowance = match user_account.withdraw_allowance {
+                    u64::MAX => "uncapped".to_string(),
+                    allowance => sol(allowance),
+                };
+                println!(
+                    "Withdraw authority: {} (allowance {})",
+                    user_account.withdraw_authority, allowance
+                );
+            }
+            if user_account.withdraw_limit > 0 {
+                println!(
+                    "Withdraw limit: {} per {} s, {} used in the current window",
+                    sol(user_account.withdraw_limit),
+                    user_account.withdraw_window,
+                    sol(user_account.window_withdrawn)
+                );
+            }
+            if user_account.limit_unlock_timestamp > 0 {
+                println!(
+                    "Pending withdraw limit: {} per {} s, applies at {}",
+                    sol(user_account.pending_withdraw_limit),
+                    user_account.pending_withdraw_window,
+                    user_account.limit_unlock_timestamp
+                );
+            }
+            if user_account.threshold > 0 {
+                println!(
+                    "Multisig: {} of {}",
+                    user_account.threshold,
+                    user_account.signers.len()
+                );
+                for signer in &user_account.signers {
+                    println!("  {}", signer);
+                }
+            }
+        }
+        Err(_) => println!("No vault yet, the first deposit creates it"),
+    }
 
-    // Create withdraw instruction
-    let instruction_data = ProgramInstruction::Withdraw {
-        amount: withdraw_amount,
+    let (config_pda, _) = find_config_address(program_id);
+    if let Ok(account) = connection.get_account(&config_pda) {
+        let config = Config::unpack(&account.data).context("Failed to decode the config")?;
+        println!("Admin: {}", config.admin);
+        println!(
+            "Paused: deposits {}, withdrawals {}",
+            config.paused, config.withdrawals_paused
+        );
+        println!(
+            "Fees: {} bps on deposits, {} bps on withdrawals",
+            config.deposit_fee_bps, config.withdraw_fee_bps
+        );
     }
-    .serialize();
-
-    let withdraw_instruction = Instruction {
-        program_id,
-        accounts: vec![
-            AccountMeta::new(user_pubkey, true), // User (signer, writable)
-            AccountMeta::new(user_account_pda, false), // User account PDA (readable)
-            AccountMeta::new(vault_pda, false),  // Vault PDA (writable)
-            AccountMeta::new_readonly(system_program::id(), false), // System program
-        ],
-        data: instruction_data,
-    };
+    Ok(())
+}
 
-    // Send withdraw transaction
-    let recent_blockhash = connection.get_latest_blockhash()?;
-    let withdraw_transaction = Transaction::new_signed_with_payer(
-        &[withdraw_instruction],
-        Some(&user_pubkey),
-        &[&payer],
-        recent_blockhash,
-    );
+fn main() -> Result<()> {
+    let cli = Cli::parse();
 
-    let withdraw_signature = connection.send_and_confirm_transaction(&withdraw_transaction)?;
-    println!("\nWithdraw transaction signature: {}", withdraw_signature);
+    // Settings from the command line win over the Solana CLI config, which falls
+    // back to the CLI defaults when there is no config file
+    let cli_config = match &cli.config {
+        Some(path) => CliConfig::load(path)
+            .with_context(|| format!("Failed to read the config file {}", path))?,
+        None => CONFIG_FILE
+            .as_ref()
+            .and_then(|path| CliConfig::load(path).ok())
+            .unwrap_or_default(),
+    };
+    let url = normalize_url(cli.url.as_deref().unwrap_or(&cli_config.json_rpc_url));
+    let commitment = cli.commitment.as_deref().unwrap_or(&cli_config.commitment);
+    let commitment_config = CommitmentConfig::from_str(commitment)
+        .map_err(|_| anyhow!("Invalid commitment: {}", commitment))?;
+    let keypair_path = cli.keypair.unwrap_or(cli_config.keypair_path);
+    let load_keypair = || {
+        read_keypair_file(&keypair_path)
+            .map_err(|error| anyhow!("Failed to read keypair {}: {}", keypair_path, error))
+    };
 
-    // Get balances after withdrawal
-    thread::sleep(Duration::from_secs(1));
-    let user_after_withdraw = connection.get_balance(&user_pubkey)?;
-    let vault_account = connection.get_account(&vault_pda)?;
-    let vault_after_withdraw = vault_account.lamports;
+    let connection = RpcClient::new_with_commitment(url, commitment_config);
+    let program_id = cli.program_id;
 
-    println!(
-        "User balance after withdrawal: {} SOL",
-        user_after_withdraw as f64 / LAMPORTS_PER_SOL as f64
-    );
-    println!(
-        "Vault balance after withdrawal: {} SOL",
-        vault_after_withdraw as f64 / LAMPORTS_PER_SOL as f64
-    );
+    match cli.command {
+        Command::Deposit {
+            amount,
+            beneficiary,
+        } => {
+            let payer = load_keypair()?;
+            let user = beneficiary.unwrap_or(payer.pubkey());
+            let instruction = match beneficiary {
+                Some(beneficiary) => {
+                    instruction::deposit_for(&program_id, &payer.pubkey(), &beneficiary, amount)
+                }
+                None => instruction::deposit(&program_id, &payer.pubkey(), amount),
+            };
+            send(&connection, &program_id, &payer, instruction)?;
+            let (vault_pda, _) = find_vault_address(&user, &program_id);
+            println!(
+                "Vault balance: {}",
+                sol(connection.get_balance(&vault_pda)?)
+            );
+        }
+        Command::Withdraw { amount, .. } => {
+            let payer = load_keypair()?;
+            // Without an amount, --all is set
+            let instruction = match amount {
+                Some(amount) => instruction::withdraw(&program_id, &payer.pubkey(), amount),
+                None => instruction::withdraw_all(&program_id, &payer.pubkey()),
+            };
+            send(&connection, &program_id, &payer, instruction)?;
+            let (vault_pda, _) = find_vault_address(&payer.pubkey(), &program_id);
+            println!(
+                "Vault balance: {}",
+                sol(connection.get_balance(&vault_pda)?)
+            );
+        }
+        Command::Balance { user } => {
+            let user = match user {
+                Some(user) => user,
+                None => load_keypair()?.pubkey(),
+            };
+            let (vault_pda, _) = find_vault_address(&user, &program_id);
+            println!("{}", sol(connection.get_balance(&vault_pda)?));
+        }
+        Command::Close => {
+            let payer = load_keypair()?;
+            send(
+                &connection,
+                &program_id,
+                &payer,
+                instruction::close(&program_id, &payer.pubkey()),
+            )?;
+            println!(
+                "Wallet balance: {}",
+                sol(connection.get_balance(&payer.pubkey())?)
+            );
+        }
+        Command::Info { user } => {
+            let user = match user {
+                Some(user) => user,
+                None => load_keypair()?.pubkey(),
+            };
+            print_info(&connection, &program_id, &user)?;
+        }
+    }
 
     Ok(())
 }
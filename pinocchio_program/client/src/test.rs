@@ -1,48 +1,48 @@
 // cargo test test_deposit_withdraw -- --nocapture
+use crate::errors::vault_error;
+use crate::events::fetch_events;
 use anyhow::Result;
-use borsh::BorshSerialize;
-use borsh_derive::BorshSerialize as BorshSerializeDerive;
+use borsh::BorshDeserialize;
+use borsh_derive::{
+    BorshDeserialize as BorshDeserializeDerive, BorshSerialize as BorshSerializeDerive,
+};
+use pinocchio_program::instruction::{ProgramInstruction, ANCHOR_DISCRIMINATORS};
 use solana_client::rpc_client::RpcClient;
 use solana_program::{
-    instruction::{AccountMeta, Instruction},
-    pubkey::Pubkey,
+    hash::hash, instruction::Instruction, program_pack::Pack, pubkey::Pubkey, system_instruction,
     system_program,
 };
 use solana_sdk::{
     commitment_config::CommitmentConfig,
     native_token::LAMPORTS_PER_SOL,
-    signature::{read_keypair_file, Signer},
+    signature::{read_keypair_file, Keypair, Signer},
     transaction::Transaction,
 };
+use spl_associated_token_account::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account,
+};
+use spl_token::{
+    instruction as token_instruction,
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::instruction::initialize_transfer_fee_config, ExtensionType,
+        StateWithExtensions,
+    },
+    instruction as token_2022_instruction,
+    state::{Account as TokenAccount2022, Mint as Mint2022},
+};
 use std::{env, str::FromStr};
+use vault_interface::{
+    find_config_address, find_treasury_address, find_user_account_address, find_vault_address,
+    instruction, UserAccount, VaultError, VaultEvent,
+};
 
 const PROGRAM_ID_STR: &str = "G7isKoAvjaMXi7CSDZTspXvUaD2dfVNwZyrWYTe6nfoj";
 const RPC_URL: &str = "http://127.0.0.1:8899";
 
-// Instruction types for serialization
-#[derive(Debug, BorshSerializeDerive)]
-pub enum ProgramInstruction {
-    Deposit { amount: u64 },
-    Withdraw { amount: u64 },
-}
-
-impl ProgramInstruction {
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        BorshSerialize::serialize(self, &mut data).unwrap();
-        data
-    }
-}
-
-// Helper functions for finding PDAs
-fn find_user_account_address(user_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[user_pubkey.as_ref()], program_id)
-}
-
-fn find_vault_address(user_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[b"vault", user_pubkey.as_ref()], program_id)
-}
-
 #[test]
 fn test_deposit_withdraw() -> Result<()> {
     // Setup - connect to local Solana testnet
@@ -86,22 +86,8 @@ fn test_deposit_withdraw() -> Result<()> {
     // Amount to deposit
     let deposit_amount = LAMPORTS_PER_SOL; // 1 SOL
 
-    // Create deposit instruction using Borsh serialization
-    let instruction_data = ProgramInstruction::Deposit {
-        amount: deposit_amount,
-    }
-    .serialize();
-
-    let deposit_instruction = Instruction {
-        program_id,
-        accounts: vec![
-            AccountMeta::new(payer.pubkey(), true), // User (signer, writable)
-            AccountMeta::new(user_account_pda, false), // User account PDA (writable)
-            AccountMeta::new(vault_pda, false),     // Vault PDA (writable)
-            AccountMeta::new_readonly(system_program::id(), false), // System program
-        ],
-        data: instruction_data,
-    };
+    // Create deposit instruction
+    let deposit_instruction = instruction::deposit(&program_id, &user_pubkey, deposit_amount);
 
     // Send deposit transaction
     let recent_blockhash = rpc_client.get_latest_blockhash()?;
@@ -137,27 +123,13 @@ fn test_deposit_withdraw() -> Result<()> {
     // Now withdraw half of what was deposited
     let withdraw_amount = deposit_amount / 2;
 
-    // Create withdraw instruction using Borsh serialization
-    let instruction_data = ProgramInstruction::Withdraw {
-        amount: withdraw_amount,
-    }
-    .serialize();
-
-    let withdraw_instruction = Instruction {
-        program_id,
-        accounts: vec![
-            AccountMeta::new(payer.pubkey(), true), // User (signer, writable)
-            AccountMeta::new(user_account_pda, false), // User account PDA (readable)
-            AccountMeta::new(vault_pda, false),     // Vault PDA (writable)
-            AccountMeta::new_readonly(system_program::id(), false), // System program
-        ],
-        data: instruction_data,
-    };
+    // Create withdraw instruction
+    let withdraw_instruction = instruction::withdraw(&program_id, &user_pubkey, withdraw_amount);
 
     // Send withdraw transaction
     let recent_blockhash = rpc_client.get_latest_blockhash()?;
     let withdraw_transaction = Transaction::new_signed_with_payer(
-        &[withdraw_instruction],
+        std::slice::from_ref(&withdraw_instruction),
         Some(&payer.pubkey()),
         &[&payer],
         recent_blockhash,
@@ -189,5 +161,1764 @@ fn test_deposit_withdraw() -> Result<()> {
         "User balance should increase after withdrawal"
     );
 
+    // Withdrawing more than the vault holds fails with a dedicated error
+    let overdraw_instruction =
+        instruction::withdraw(&program_id, &user_pubkey, vault_after_withdraw + 1);
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let overdraw_transaction = Transaction::new_signed_with_payer(
+        &[overdraw_instruction],
+        Some(&payer.pubkey()),
+        &[&payer],
+        recent_blockhash,
+    );
+    let overdraw_error = rpc_client
+        .send_and_confirm_transaction(&overdraw_transaction)
+        .expect_err("Withdraw should fail beyond the vault balance");
+    assert_eq!(
+        vault_error(&overdraw_error),
+        Some(VaultError::InsufficientFunds),
+        "Overdrawing should fail with InsufficientFunds"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_close() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair so closing does not race with the other tests
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Deposit so that both the user account and the vault exist
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    let deposit_instruction = instruction::deposit(&program_id, &user_pubkey, deposit_amount);
+
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let deposit_transaction = Transaction::new_signed_with_payer(
+        &[deposit_instruction],
+        Some(&user_pubkey),
+        &[&payer],
+        recent_blockhash,
+    );
+    rpc_client.send_and_confirm_transaction(&deposit_transaction)?;
+
+    let balance_before_close = rpc_client.get_balance(&user_pubkey)?;
+    let user_account_rent = rpc_client.get_balance(&user_account_pda)?;
+
+    // Close the vault and the user account
+    let close_instruction = instruction::close(&program_id, &user_pubkey);
+
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let close_transaction = Transaction::new_signed_with_payer(
+        &[close_instruction],
+        Some(&user_pubkey),
+        &[&payer],
+        recent_blockhash,
+    );
+
+    let close_signature = rpc_client.send_and_confirm_transaction(&close_transaction)?;
+    println!("\nClose transaction signature: {}", close_signature);
+
+    // Both PDAs should be gone
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        0,
+        "Vault should be drained"
+    );
+    assert_eq!(
+        rpc_client.get_balance(&user_account_pda)?,
+        0,
+        "User account rent should be reclaimed"
+    );
+
+    // The user gets the deposit and the rent back, minus the transaction fee
+    let balance_after_close = rpc_client.get_balance(&user_pubkey)?;
+    let fee = rpc_client.get_fee_for_message(&close_transaction.message)?;
+    assert_eq!(
+        balance_after_close,
+        balance_before_close + deposit_amount + user_account_rent - fee,
+        "User should receive the vault balance and the user account rent"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_token_deposit_withdraw() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair that also acts as the mint authority
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Create a mint and fund the user's associated token account
+    let mint = Keypair::new();
+    let mint_rent = rpc_client.get_minimum_balance_for_rent_exemption(Mint::LEN)?;
+    let user_token_account = get_associated_token_address(&user_pubkey, &mint.pubkey());
+    let vault_token_account = get_associated_token_address(&vault_pda, &mint.pubkey());
+    let minted_amount = 1_000_000;
+
+    let setup_instructions = [
+        system_instruction::create_account(
+            &user_pubkey,
+            &mint.pubkey(),
+            mint_rent,
+            Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        token_instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &user_pubkey,
+            None,
+            6,
+        )?,
+        create_associated_token_account(
+            &user_pubkey,
+            &user_pubkey,
+            &mint.pubkey(),
+            &spl_token::id(),
+        ),
+        token_instruction::mint_to(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &user_token_account,
+            &user_pubkey,
+            &[],
+            minted_amount,
+        )?,
+    ];
+
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let setup_transaction = Transaction::new_signed_with_payer(
+        &setup_instructions,
+        Some(&user_pubkey),
+        &[&payer, &mint],
+        recent_blockhash,
+    );
+    rpc_client.send_and_confirm_transaction(&setup_transaction)?;
+
+    // Deposit tokens, creating the vault token account on the way
+    let deposit_amount = 400_000;
+    let deposit_instruction = instruction::deposit_token(
+        &program_id,
+        &user_pubkey,
+        &mint.pubkey(),
+        &user_token_account,
+        &spl_token::id(),
+        deposit_amount,
+    );
+
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let deposit_transaction = Transaction::new_signed_with_payer(
+        &[deposit_instruction],
+        Some(&user_pubkey),
+        &[&payer],
+        recent_blockhash,
+    );
+
+    let deposit_signature = rpc_client.send_and_confirm_transaction(&deposit_transaction)?;
+    println!(
+        "\nToken deposit transaction signature: {}",
+        deposit_signature
+    );
+
+    let token_balance = |address: &Pubkey| -> Result<u64> {
+        let account = rpc_client.get_account(address)?;
+        Ok(TokenAccount::unpack(&account.data)?.amount)
+    };
+
+    assert_eq!(
+        token_balance(&vault_token_account)?,
+        deposit_amount,
+        "Vault token balance should increase by deposit amount"
+    );
+
+    // Withdraw part of the tokens back to the user
+    let withdraw_amount = 150_000;
+    let withdraw_instruction = instruction::withdraw_token(
+        &program_id,
+        &user_pubkey,
+        &mint.pubkey(),
+        &user_token_account,
+        &spl_token::id(),
+        withdraw_amount,
+        &[],
+    );
+
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let withdraw_transaction = Transaction::new_signed_with_payer(
+        &[withdraw_instruction],
+        Some(&user_pubkey),
+        &[&payer],
+        recent_blockhash,
+    );
+
+    let withdraw_signature = rpc_client.send_and_confirm_transaction(&withdraw_transaction)?;
+    println!(
+        "\nToken withdraw transaction signature: {}",
+        withdraw_signature
+    );
+
+    assert_eq!(
+        token_balance(&vault_token_account)?,
+        deposit_amount - withdraw_amount,
+        "Vault token balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        token_balance(&user_token_account)?,
+        minted_amount - deposit_amount + withdraw_amount,
+        "User token balance should reflect the deposit and withdrawal"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_token_2022_transfer_fee_deposit() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair that also acts as the mint and fee authority
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Create a Token-2022 mint with a 1% transfer fee
+    let mint = Keypair::new();
+    let mint_len =
+        ExtensionType::try_calculate_account_len::<Mint2022>(&[ExtensionType::TransferFeeConfig])?;
+    let mint_rent = rpc_client.get_minimum_balance_for_rent_exemption(mint_len)?;
+    let user_token_account = get_associated_token_address_with_program_id(
+        &user_pubkey,
+        &mint.pubkey(),
+        &spl_token_2022::id(),
+    );
+    let vault_token_account = get_associated_token_address_with_program_id(
+        &vault_pda,
+        &mint.pubkey(),
+        &spl_token_2022::id(),
+    );
+    let transfer_fee_basis_points = 100;
+
+    let setup_instructions = [
+        system_instruction::create_account(
+            &user_pubkey,
+            &mint.pubkey(),
+            mint_rent,
+            mint_len as u64,
+            &spl_token_2022::id(),
+        ),
+        initialize_transfer_fee_config(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            Some(&user_pubkey),
+            Some(&user_pubkey),
+            transfer_fee_basis_points,
+            u64::MAX,
+        )?,
+        token_2022_instruction::initialize_mint2(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            &user_pubkey,
+            None,
+            6,
+        )?,
+        create_associated_token_account(
+            &user_pubkey,
+            &user_pubkey,
+            &mint.pubkey(),
+            &spl_token_2022::id(),
+        ),
+        token_2022_instruction::mint_to(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            &user_token_account,
+            &user_pubkey,
+            &[],
+            1_000_000,
+        )?,
+    ];
+
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let setup_transaction = Transaction::new_signed_with_payer(
+        &setup_instructions,
+        Some(&user_pubkey),
+        &[&payer, &mint],
+        recent_blockhash,
+    );
+    rpc_client.send_and_confirm_transaction(&setup_transaction)?;
+
+    // Deposit through the Token-2022 program
+    let deposit_amount = 400_000;
+    let deposit_instruction = instruction::deposit_token(
+        &program_id,
+        &user_pubkey,
+        &mint.pubkey(),
+        &user_token_account,
+        &spl_token_2022::id(),
+        deposit_amount,
+    );
+
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let deposit_transaction = Transaction::new_signed_with_payer(
+        &[deposit_instruction],
+        Some(&user_pubkey),
+        &[&payer],
+        recent_blockhash,
+    );
+
+    let deposit_signature = rpc_client.send_and_confirm_transaction(&deposit_transaction)?;
+    println!(
+        "\nToken-2022 deposit transaction signature: {}",
+        deposit_signature
+    );
+
+    // The vault holds the deposit minus the withheld transfer fee
+    let vault_token = rpc_client.get_account(&vault_token_account)?;
+    let vault_amount = StateWithExtensions::<TokenAccount2022>::unpack(&vault_token.data)?
+        .base
+        .amount;
+    let expected_fee = deposit_amount * transfer_fee_basis_points as u64 / 10_000;
+    assert_eq!(
+        vault_amount,
+        deposit_amount - expected_fee,
+        "Vault should receive the deposit minus the transfer fee"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_timelocked_withdraw() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair so the timelock does not affect the other tests
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    let send = |instruction: Instruction| {
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &[&payer],
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+
+    // Deposit with a short withdraw delay
+    let withdraw_delay = 3;
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(instruction::deposit_with_delay(
+        &program_id,
+        &user_pubkey,
+        deposit_amount,
+        withdraw_delay,
+    ))?;
+
+    // The delay cannot be changed after the first deposit
+    assert!(
+        send(instruction::deposit_with_delay(
+            &program_id,
+            &user_pubkey,
+            1,
+            0
+        ))
+        .is_err(),
+        "Changing the withdraw delay should fail"
+    );
+
+    // Direct withdrawals and closing bypass the timelock, so both are rejected
+    assert!(
+        send(instruction::withdraw(&program_id, &user_pubkey, 1)).is_err(),
+        "Withdraw should fail on a timelocked vault"
+    );
+    assert!(
+        send(instruction::close(&program_id, &user_pubkey)).is_err(),
+        "Close should fail on a timelocked vault with funds"
+    );
+
+    // Cancelling with nothing pending fails
+    assert!(
+        send(instruction::cancel_withdraw(&program_id, &user_pubkey)).is_err(),
+        "Cancel should fail without a pending withdrawal"
+    );
+
+    // Request, cancel, and request again
+    let withdraw_amount = deposit_amount / 2;
+    send(instruction::request_withdraw(
+        &program_id,
+        &user_pubkey,
+        withdraw_amount,
+    ))?;
+    send(instruction::cancel_withdraw(&program_id, &user_pubkey))?;
+    send(instruction::request_withdraw(
+        &program_id,
+        &user_pubkey,
+        withdraw_amount,
+    ))?;
+
+    // Only one withdrawal can be pending
+    assert!(
+        send(instruction::request_withdraw(&program_id, &user_pubkey, 1)).is_err(),
+        "A second request should fail while one is pending"
+    );
+
+    // Claiming before the delay has passed fails
+    assert!(
+        send(instruction::claim_withdraw(&program_id, &user_pubkey)).is_err(),
+        "Claim should fail before the timelock expires"
+    );
+
+    // Wait for the cluster clock to pass the unlock time and claim
+    let vault_balance_before = rpc_client.get_balance(&vault_pda)?;
+    let mut claimed = false;
+    for _ in 0..30 {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        if send(instruction::claim_withdraw(&program_id, &user_pubkey)).is_ok() {
+            claimed = true;
+            break;
+        }
+    }
+    assert!(claimed, "Claim should succeed after the timelock expires");
+
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        vault_balance_before - withdraw_amount,
+        "Vault balance should decrease by the claimed amount"
+    );
+
+    // The pending withdrawal is cleared after claiming
+    assert!(
+        send(instruction::claim_withdraw(&program_id, &user_pubkey)).is_err(),
+        "A second claim should fail"
+    );
+
     Ok(())
 }
+
+#[test]
+fn test_multisig_withdraw() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair so the multisig does not affect the other tests
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Three members, two of which must approve, plus an outsider
+    let members = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let outsider = Keypair::new();
+
+    // The payer signs every transaction, members only sign to approve
+    let send = |instruction: Instruction, approvers: &[&Keypair]| {
+        let mut signers = vec![&payer];
+        signers.extend_from_slice(approvers);
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &signers,
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+    // Multisig approvers are passed as extra signer accounts
+    let approver_keys = |approvers: &[&Keypair]| -> Vec<Pubkey> {
+        approvers.iter().map(|approver| approver.pubkey()).collect()
+    };
+    let withdraw_instruction = |amount: u64, approvers: &[&Keypair]| {
+        instruction::with_signers(
+            instruction::withdraw(&program_id, &user_pubkey, amount),
+            &approver_keys(approvers),
+        )
+    };
+    let set_multisig_instruction = |signers: Vec<Pubkey>, threshold: u8, approvers: &[&Keypair]| {
+        instruction::with_signers(
+            instruction::set_multisig(&program_id, &user_pubkey, &user_pubkey, signers, threshold),
+            &approver_keys(approvers),
+        )
+    };
+
+    // Deposit so that both the user account and the vault exist
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(
+        instruction::deposit(&program_id, &user_pubkey, deposit_amount),
+        &[],
+    )?;
+
+    // A signer set with a duplicate member or an unreachable threshold is rejected
+    let member_keys: Vec<Pubkey> = members.iter().map(|member| member.pubkey()).collect();
+    assert!(
+        send(
+            set_multisig_instruction(vec![member_keys[0], member_keys[0]], 1, &[]),
+            &[]
+        )
+        .is_err(),
+        "Duplicate multisig members should be rejected"
+    );
+    assert!(
+        send(set_multisig_instruction(member_keys.clone(), 4, &[]), &[]).is_err(),
+        "A threshold above the member count should be rejected"
+    );
+
+    // The user hands control to a 2-of-3 multisig, growing the user account
+    let size_before = rpc_client.get_account(&user_account_pda)?.data.len();
+    send(set_multisig_instruction(member_keys.clone(), 2, &[]), &[])?;
+    assert_eq!(
+        rpc_client.get_account(&user_account_pda)?.data.len(),
+        size_before + 3 * 32,
+        "User account should grow to hold the signer set"
+    );
+
+    // The user alone can no longer withdraw
+    let withdraw_amount = LAMPORTS_PER_SOL / 10;
+    assert!(
+        send(withdraw_instruction(withdraw_amount, &[]), &[]).is_err(),
+        "The user alone should not satisfy the multisig"
+    );
+
+    // Under threshold
+    assert!(
+        send(
+            withdraw_instruction(withdraw_amount, &[&members[0]]),
+            &[&members[0]]
+        )
+        .is_err(),
+        "One approval should not satisfy a 2-of-3 multisig"
+    );
+
+    // The same member passed twice only counts once
+    assert!(
+        send(
+            withdraw_instruction(withdraw_amount, &[&members[0], &members[0]]),
+            &[&members[0]]
+        )
+        .is_err(),
+        "A duplicate approval should not count twice"
+    );
+
+    // Non-members do not count towards the threshold
+    assert!(
+        send(
+            withdraw_instruction(withdraw_amount, &[&members[0], &outsider]),
+            &[&members[0], &outsider]
+        )
+        .is_err(),
+        "A non-member approval should not count"
+    );
+
+    // Two distinct members can withdraw
+    let vault_balance_before = rpc_client.get_balance(&vault_pda)?;
+    send(
+        withdraw_instruction(withdraw_amount, &[&members[0], &members[2]]),
+        &[&members[0], &members[2]],
+    )?;
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        vault_balance_before - withdraw_amount,
+        "Vault balance should decrease by withdraw amount"
+    );
+
+    // Changing the signer set also needs threshold approval
+    assert!(
+        send(
+            set_multisig_instruction(Vec::new(), 0, &[&members[1]]),
+            &[&members[1]]
+        )
+        .is_err(),
+        "One approval should not change the signer set"
+    );
+
+    // Two members hand control back to the user, shrinking the user account
+    send(
+        set_multisig_instruction(Vec::new(), 0, &[&members[0], &members[1]]),
+        &[&members[0], &members[1]],
+    )?;
+    assert_eq!(
+        rpc_client.get_account(&user_account_pda)?.data.len(),
+        size_before,
+        "User account should shrink back to its original size"
+    );
+
+    // The user can withdraw alone again
+    let withdraw_instruction = instruction::withdraw(&program_id, &user_pubkey, withdraw_amount);
+    send(withdraw_instruction, &[])?;
+
+    Ok(())
+}
+
+#[test]
+fn test_delegate_withdraw() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair so the delegate does not affect the other tests
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // A hot key that receives withdraw rights, and one that does not
+    let delegate = Keypair::new();
+    let outsider = Keypair::new();
+
+    // The payer signs every transaction, the delegate only signs its withdrawals
+    let send = |instruction: Instruction, extra_signers: &[&Keypair]| {
+        let mut signers = vec![&payer];
+        signers.extend_from_slice(extra_signers);
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &signers,
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+    let delegate_withdraw_instruction = |authority: &Pubkey, amount: u64| {
+        instruction::delegate_withdraw(&program_id, authority, &user_pubkey, amount)
+    };
+
+    // Deposit so that both the user account and the vault exist
+    send(
+        instruction::deposit(&program_id, &user_pubkey, LAMPORTS_PER_SOL / 2),
+        &[],
+    )?;
+
+    // Nobody can withdraw as a delegate before one is set
+    let withdraw_amount = LAMPORTS_PER_SOL / 20;
+    assert!(
+        send(
+            delegate_withdraw_instruction(&delegate.pubkey(), withdraw_amount),
+            &[&delegate]
+        )
+        .is_err(),
+        "Delegate withdraw should fail without a withdraw authority"
+    );
+
+    // Delegate withdraw rights capped at two withdrawals
+    send(
+        instruction::set_withdraw_authority(
+            &program_id,
+            &user_pubkey,
+            &delegate.pubkey(),
+            withdraw_amount * 2,
+        ),
+        &[],
+    )?;
+
+    // The delegate withdraws to itself, signed with the user's vault seeds
+    let vault_balance_before = rpc_client.get_balance(&vault_pda)?;
+    send(
+        delegate_withdraw_instruction(&delegate.pubkey(), withdraw_amount),
+        &[&delegate],
+    )?;
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        vault_balance_before - withdraw_amount,
+        "Vault balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        rpc_client.get_balance(&delegate.pubkey())?,
+        withdraw_amount,
+        "Delegate should receive the withdrawal"
+    );
+
+    // Withdrawals beyond the remaining allowance fail
+    assert!(
+        send(
+            delegate_withdraw_instruction(&delegate.pubkey(), withdraw_amount + 1),
+            &[&delegate]
+        )
+        .is_err(),
+        "Delegate withdraw should fail beyond the allowance"
+    );
+
+    // Only the delegate holds withdraw rights
+    assert!(
+        send(
+            delegate_withdraw_instruction(&outsider.pubkey(), withdraw_amount),
+            &[&outsider]
+        )
+        .is_err(),
+        "A non-delegate should not be able to withdraw"
+    );
+
+    // The user keeps full control of the vault
+    send(
+        instruction::withdraw(&program_id, &user_pubkey, withdraw_amount),
+        &[],
+    )?;
+
+    // After revoking, the delegate can no longer withdraw
+    send(
+        instruction::revoke_withdraw_authority(&program_id, &user_pubkey),
+        &[],
+    )?;
+    assert!(
+        send(
+            delegate_withdraw_instruction(&delegate.pubkey(), withdraw_amount),
+            &[&delegate]
+        )
+        .is_err(),
+        "Delegate withdraw should fail after revoking"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_deposit_for() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // A funded sponsor onboards a beneficiary that holds no lamports at all
+    let funder = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&funder.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+    let beneficiary = Keypair::new();
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // The PDAs are derived from the beneficiary, not the funder
+    let funder_pubkey = funder.pubkey();
+    let beneficiary_pubkey = beneficiary.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&beneficiary_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&beneficiary_pubkey, &program_id);
+
+    let deposit_for_instruction = |amount: u64| {
+        instruction::deposit_for(&program_id, &funder_pubkey, &beneficiary_pubkey, amount)
+    };
+
+    // First deposit creates the beneficiary's account, only the funder signs
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    let funder_balance_before = rpc_client.get_balance(&funder_pubkey)?;
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let deposit_transaction = Transaction::new_signed_with_payer(
+        &[deposit_for_instruction(deposit_amount)],
+        Some(&funder_pubkey),
+        &[&funder],
+        recent_blockhash,
+    );
+    let deposit_signature = rpc_client.send_and_confirm_transaction(&deposit_transaction)?;
+    println!("\nDeposit for transaction signature: {}", deposit_signature);
+
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        deposit_amount,
+        "Beneficiary vault should hold the deposit"
+    );
+    assert_eq!(
+        rpc_client.get_balance(&beneficiary_pubkey)?,
+        0,
+        "Beneficiary should not pay anything"
+    );
+
+    // The funder paid the deposit, the user account rent and the fee
+    let user_account = rpc_client.get_account(&user_account_pda)?;
+    assert_eq!(
+        UserAccount::unpack(&user_account.data)?.user,
+        beneficiary_pubkey,
+        "User account should belong to the beneficiary"
+    );
+    let fee = rpc_client.get_fee_for_message(&deposit_transaction.message)?;
+    assert_eq!(
+        rpc_client.get_balance(&funder_pubkey)?,
+        funder_balance_before - deposit_amount - user_account.lamports - fee,
+        "Funder should pay the deposit and the user account rent"
+    );
+
+    // Later sponsored deposits top up the existing vault
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    rpc_client.send_and_confirm_transaction(&Transaction::new_signed_with_payer(
+        &[deposit_for_instruction(deposit_amount)],
+        Some(&funder_pubkey),
+        &[&funder],
+        recent_blockhash,
+    ))?;
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        deposit_amount * 2,
+        "Beneficiary vault should hold both deposits"
+    );
+
+    // The beneficiary controls the vault, with the funder covering the fee
+    let withdraw_amount = deposit_amount / 2;
+    let withdraw_instruction =
+        instruction::withdraw(&program_id, &beneficiary_pubkey, withdraw_amount);
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    rpc_client.send_and_confirm_transaction(&Transaction::new_signed_with_payer(
+        &[withdraw_instruction],
+        Some(&funder_pubkey),
+        &[&funder, &beneficiary],
+        recent_blockhash,
+    ))?;
+    assert_eq!(
+        rpc_client.get_balance(&beneficiary_pubkey)?,
+        withdraw_amount,
+        "Beneficiary should receive the withdrawal"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_withdraw_limit() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair so the limit does not affect the other tests
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    let send = |instruction: Instruction| {
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &[&payer],
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+    let set_withdraw_limit_instruction = |limit: u64, window: i64| {
+        instruction::set_withdraw_limit(&program_id, &user_pubkey, limit, window)
+    };
+    let withdraw = |amount: u64| send(instruction::withdraw(&program_id, &user_pubkey, amount));
+
+    // Deposit so that both the user account and the vault exist
+    send(instruction::deposit(
+        &program_id,
+        &user_pubkey,
+        LAMPORTS_PER_SOL,
+    ))?;
+
+    // A limit without a window is rejected
+    let limit = LAMPORTS_PER_SOL / 10;
+    let window = 10;
+    assert!(
+        send(set_withdraw_limit_instruction(limit, 0)).is_err(),
+        "A limit without a window should be rejected"
+    );
+
+    // Limiting an unlimited vault applies immediately
+    send(set_withdraw_limit_instruction(limit, window))?;
+    withdraw(limit / 2)?;
+    assert_eq!(
+        withdraw(limit / 2 + 1).err().as_ref().and_then(vault_error),
+        Some(VaultError::WithdrawLimitExceeded),
+        "Withdraw should fail beyond the remaining limit"
+    );
+
+    // Lowering the limit also applies immediately, the window is already spent
+    send(set_withdraw_limit_instruction(limit / 2, window))?;
+    assert!(
+        withdraw(1).is_err(),
+        "Withdraw should fail once the lowered limit is spent"
+    );
+
+    // Raising the limit only applies once the current window length has passed
+    send(set_withdraw_limit_instruction(limit * 2, window))?;
+    assert!(
+        withdraw(limit).is_err(),
+        "A raised limit should not apply immediately"
+    );
+
+    // Wait for the cluster clock to pass the raise and withdraw under the new limit
+    let vault_balance_before = rpc_client.get_balance(&vault_pda)?;
+    let mut withdrew = false;
+    for _ in 0..30 {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        if withdraw(limit).is_ok() {
+            withdrew = true;
+            break;
+        }
+    }
+    assert!(
+        withdrew,
+        "Withdraw should succeed once the raised limit applies"
+    );
+
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        vault_balance_before - limit,
+        "Vault balance should decrease by withdraw amount"
+    );
+
+    Ok(())
+}
+
+// Pausing affects every vault, so this test would break the others when run in parallel
+#[test]
+#[ignore = "pauses the whole program, run on its own with --ignored"]
+fn test_config_pause() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // The default keypair deployed the program, so it is the upgrade authority
+    let home = env::var("HOME").expect("Failed to get HOME env var");
+    let upgrade_authority_keypair_path = format!("{}/.config/solana/id.json", home);
+    let upgrade_authority =
+        read_keypair_file(&upgrade_authority_keypair_path).expect("Failed to read keypair file");
+
+    // Use a fresh keypair for the vault, plus a new admin and an outsider
+    let payer = Keypair::new();
+    let admin = Keypair::new();
+    let outsider = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the config PDA
+    let user_pubkey = payer.pubkey();
+    let (config_pda, _) = find_config_address(&program_id);
+
+    // The payer covers every fee, other keys only sign
+    let send = |instruction: Instruction, extra_signers: &[&Keypair]| {
+        let mut signers = vec![&payer];
+        signers.extend_from_slice(extra_signers);
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &signers,
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+    let initialize_config_instruction =
+        |authority: &Pubkey| instruction::initialize_config(&program_id, authority, authority);
+    let set_paused = |signer: &Keypair, deposits: bool, withdrawals: bool| {
+        send(
+            instruction::set_paused(&program_id, &signer.pubkey(), deposits, withdrawals),
+            &[signer],
+        )
+    };
+
+    // Deposit so that both the user account and the vault exist
+    let amount = LAMPORTS_PER_SOL / 10;
+    send(instruction::deposit(&program_id, &user_pubkey, amount), &[])?;
+
+    // Only the upgrade authority can create the config
+    assert!(
+        send(
+            initialize_config_instruction(&outsider.pubkey()),
+            &[&outsider]
+        )
+        .is_err(),
+        "A non-upgrade authority should not be able to initialize the config"
+    );
+    if rpc_client.get_account(&config_pda).is_err() {
+        send(
+            initialize_config_instruction(&upgrade_authority.pubkey()),
+            &[&upgrade_authority],
+        )?;
+    }
+
+    // Hand the admin role to a fresh key for the rest of the test
+    assert!(
+        send(
+            instruction::transfer_admin(&program_id, &outsider.pubkey(), &outsider.pubkey()),
+            &[&outsider]
+        )
+        .is_err(),
+        "A non-admin should not be able to transfer the admin role"
+    );
+    send(
+        instruction::transfer_admin(&program_id, &upgrade_authority.pubkey(), &admin.pubkey()),
+        &[&upgrade_authority],
+    )?;
+
+    // Only the admin can pause, and withdrawals cannot be paused on their own
+    assert!(
+        set_paused(&outsider, true, false).is_err(),
+        "A non-admin should not be able to pause"
+    );
+    assert!(
+        set_paused(&admin, false, true).is_err(),
+        "Withdrawals should not be pausable without deposits"
+    );
+
+    // A deposit pause still lets users withdraw
+    set_paused(&admin, true, false)?;
+    assert_eq!(
+        send(instruction::deposit(&program_id, &user_pubkey, amount), &[])
+            .err()
+            .as_ref()
+            .and_then(vault_error),
+        Some(VaultError::ProgramPaused),
+        "Deposit should fail while paused"
+    );
+    send(
+        instruction::withdraw(&program_id, &user_pubkey, amount / 2),
+        &[],
+    )?;
+
+    // A full pause blocks withdrawals too
+    set_paused(&admin, true, true)?;
+    assert!(
+        send(
+            instruction::withdraw(&program_id, &user_pubkey, amount / 4),
+            &[]
+        )
+        .is_err(),
+        "Withdraw should fail while withdrawals are paused"
+    );
+
+    // Unpausing restores both
+    set_paused(&admin, false, false)?;
+    send(
+        instruction::deposit(&program_id, &user_pubkey, amount / 2),
+        &[],
+    )?;
+    send(
+        instruction::withdraw(&program_id, &user_pubkey, amount / 4),
+        &[],
+    )?;
+
+    // Give the admin role back to the upgrade authority
+    send(
+        instruction::transfer_admin(&program_id, &admin.pubkey(), &upgrade_authority.pubkey()),
+        &[&admin],
+    )?;
+
+    Ok(())
+}
+
+#[test]
+#[ignore = "charges fees program-wide, run on its own with --ignored"]
+fn test_fees() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // The default keypair deployed the program, so it is the upgrade authority and,
+    // unless handed over, the admin
+    let home = env::var("HOME").expect("Failed to get HOME env var");
+    let admin_keypair_path = format!("{}/.config/solana/id.json", home);
+    let admin = read_keypair_file(&admin_keypair_path).expect("Failed to read keypair file");
+
+    // Use a fresh keypair for the vault, plus an outsider
+    let payer = Keypair::new();
+    let outsider = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL * 2)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the vault, config and treasury PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (config_pda, _) = find_config_address(&program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    // The payer covers every fee, other keys only sign
+    let send = |instruction: Instruction, extra_signers: &[&Keypair]| {
+        let mut signers = vec![&payer];
+        signers.extend_from_slice(extra_signers);
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &signers,
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+    let set_fees = |signer: &Keypair, deposit_bps: u16, withdraw_bps: u16| {
+        send(
+            instruction::set_fees(&program_id, &signer.pubkey(), deposit_bps, withdraw_bps),
+            &[signer],
+        )
+    };
+
+    // Fees live in the config, so make sure it exists
+    if rpc_client.get_account(&config_pda).is_err() {
+        send(
+            instruction::initialize_config(&program_id, &admin.pubkey(), &admin.pubkey()),
+            &[&admin],
+        )?;
+    }
+
+    // Only the admin can set fees, and only up to the cap
+    assert!(
+        set_fees(&outsider, 100, 100).is_err(),
+        "A non-admin should not be able to set fees"
+    );
+    assert!(
+        set_fees(&admin, 1_001, 0).is_err(),
+        "A fee above the cap should be rejected"
+    );
+
+    // 1% on deposits and 2% on withdrawals
+    set_fees(&admin, 100, 200)?;
+    let treasury_before = rpc_client.get_balance(&treasury_pda)?;
+
+    // The vault receives the deposit less the fee, the treasury receives the fee
+    let amount = LAMPORTS_PER_SOL / 10;
+    let deposit_fee = amount / 100;
+    send(instruction::deposit(&program_id, &user_pubkey, amount), &[])?;
+    let vault_balance = rpc_client.get_balance(&vault_pda)?;
+    assert_eq!(
+        vault_balance,
+        amount - deposit_fee,
+        "Vault should receive the deposit less the fee"
+    );
+    assert_eq!(
+        rpc_client.get_balance(&treasury_pda)?,
+        treasury_before + deposit_fee,
+        "Treasury should receive the deposit fee"
+    );
+
+    // The whole withdrawal leaves the vault, the fee part goes to the treasury
+    let withdraw_amount = vault_balance / 2;
+    let withdraw_fee = withdraw_amount * 200 / 10_000;
+    send(
+        instruction::withdraw(&program_id, &user_pubkey, withdraw_amount),
+        &[],
+    )?;
+    assert_eq!(
+        rpc_client.get_balance(&vault_pda)?,
+        vault_balance - withdraw_amount,
+        "Vault balance should decrease by the withdraw amount"
+    );
+    let collected = deposit_fee + withdraw_fee;
+    assert_eq!(
+        rpc_client.get_balance(&treasury_pda)?,
+        treasury_before + collected,
+        "Treasury should receive the withdraw fee"
+    );
+
+    // Only the admin can withdraw fees, and the treasury stays rent-exempt
+    assert!(
+        send(
+            instruction::withdraw_fees(&program_id, &outsider.pubkey(), collected),
+            &[&outsider]
+        )
+        .is_err(),
+        "A non-admin should not be able to withdraw fees"
+    );
+    let treasury_balance = rpc_client.get_balance(&treasury_pda)?;
+    assert!(
+        send(
+            instruction::withdraw_fees(&program_id, &admin.pubkey(), treasury_balance),
+            &[&admin]
+        )
+        .is_err(),
+        "Withdrawing fees should not drain the treasury below rent exemption"
+    );
+    send(
+        instruction::withdraw_fees(&program_id, &admin.pubkey(), collected),
+        &[&admin],
+    )?;
+    assert_eq!(
+        rpc_client.get_balance(&treasury_pda)?,
+        treasury_before,
+        "Treasury should pay out the collected fees"
+    );
+
+    // Turn fees off again for the other tests
+    set_fees(&admin, 0, 0)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_events() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair so the balances in the events are predictable
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    let send = |instruction: Instruction| {
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &[&payer],
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+
+    // A deposit emits a single deposit event carrying the new vault balance
+    let amount = LAMPORTS_PER_SOL / 10;
+    let deposit_signature = send(instruction::deposit(&program_id, &user_pubkey, amount))?;
+    let events = fetch_events(&rpc_client, &program_id, &deposit_signature)?;
+    let [VaultEvent::Deposit(deposit_event)] = events.as_slice() else {
+        panic!("Expected a single deposit event, got {:?}", events);
+    };
+    assert_eq!(
+        deposit_event.user, user_pubkey,
+        "Event should name the user"
+    );
+    assert_eq!(
+        deposit_event.vault, vault_pda,
+        "Event should name the vault"
+    );
+    assert_eq!(
+        deposit_event.amount, amount,
+        "Event should carry the deposit amount"
+    );
+    assert_eq!(
+        deposit_event.vault_balance,
+        rpc_client.get_balance(&vault_pda)?,
+        "Event should carry the vault balance after the deposit"
+    );
+    assert!(
+        deposit_event.slot > 0 && deposit_event.slot <= rpc_client.get_slot()?,
+        "Event should carry the slot it was emitted in"
+    );
+
+    // A withdrawal emits a single withdraw event
+    let withdraw_amount = amount / 2;
+    let withdraw_signature = send(instruction::withdraw(
+        &program_id,
+        &user_pubkey,
+        withdraw_amount,
+    ))?;
+    let events = fetch_events(&rpc_client, &program_id, &withdraw_signature)?;
+    let [VaultEvent::Withdraw(withdraw_event)] = events.as_slice() else {
+        panic!("Expected a single withdraw event, got {:?}", events);
+    };
+    assert_eq!(
+        withdraw_event.user, user_pubkey,
+        "Event should name the user"
+    );
+    assert_eq!(
+        withdraw_event.amount, withdraw_amount,
+        "Event should carry the withdraw amount"
+    );
+    assert_eq!(
+        withdraw_event.vault_balance,
+        deposit_event.vault_balance - withdraw_amount,
+        "Event should carry the vault balance after the withdrawal"
+    );
+
+    // Events of other programs in the same logs are ignored
+    assert!(
+        fetch_events(&rpc_client, &system_program::id(), &withdraw_signature)?.is_empty(),
+        "Vault events should only be attributed to the vault program"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_migrate() -> Result<()> {
+    // Setup - connect to local Solana testnet
+    let commitment_config = CommitmentConfig::confirmed();
+    let rpc_client = RpcClient::new_with_commitment(RPC_URL.to_string(), commitment_config);
+
+    // Use a fresh keypair so the user account is created by this test
+    let payer = Keypair::new();
+    let airdrop_signature = rpc_client.request_airdrop(&payer.pubkey(), LAMPORTS_PER_SOL)?;
+    while !rpc_client.confirm_transaction(&airdrop_signature)? {}
+
+    // Get the program ID from the PROGRAM_ID constant
+    let program_id = Pubkey::from_str(PROGRAM_ID_STR).expect("Invalid program ID");
+
+    // Get the user account PDA
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+
+    let send = |instruction: Instruction| {
+        let recent_blockhash = rpc_client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&user_pubkey),
+            &[&payer],
+            recent_blockhash,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction)
+    };
+
+    // New accounts are created in the current layout: the Anchor-style discriminator,
+    // then the fields with the version after the user key and the two bumps
+    send(instruction::deposit(
+        &program_id,
+        &user_pubkey,
+        LAMPORTS_PER_SOL / 10,
+    ))?;
+    let data_before = rpc_client.get_account_data(&user_account_pda)?;
+    assert_eq!(
+        data_before[..8],
+        [211, 33, 136, 16, 186, 110, 242, 127],
+        "User account should start with the discriminator"
+    );
+    assert_eq!(
+        data_before[8 + 34],
+        2,
+        "User account should be at version 2"
+    );
+
+    // Migrating an up to date account leaves it untouched
+    send(instruction::migrate(&program_id, &user_pubkey))?;
+    assert_eq!(
+        rpc_client.get_account_data(&user_account_pda)?,
+        data_before,
+        "Migrate should not change a current account"
+    );
+
+    Ok(())
+}
+
+// Borsh encoding of the instruction enum that clients sent before the hand-rolled
+// decoder, kept as the reference the shared ProgramInstruction must match
+#[derive(Debug, Clone, PartialEq, BorshSerializeDerive, BorshDeserializeDerive)]
+enum BorshInstruction {
+    Deposit {
+        amount: u64,
+    },
+    Withdraw {
+        amount: u64,
+    },
+    Close,
+    DepositToken {
+        amount: u64,
+    },
+    WithdrawToken {
+        amount: u64,
+        signer_count: u8,
+    },
+    DepositWithDelay {
+        amount: u64,
+        withdraw_delay: i64,
+    },
+    RequestWithdraw {
+        amount: u64,
+    },
+    ClaimWithdraw,
+    CancelWithdraw,
+    SetMultisig {
+        signers: Vec<[u8; 32]>,
+        threshold: u8,
+    },
+    SetWithdrawAuthority {
+        authority: [u8; 32],
+        allowance: u64,
+    },
+    RevokeWithdrawAuthority,
+    DelegateWithdraw {
+        amount: u64,
+    },
+    DepositFor {
+        beneficiary: [u8; 32],
+        amount: u64,
+    },
+    SetWithdrawLimit {
+        limit: u64,
+        window: i64,
+    },
+    InitializeConfig {
+        admin: [u8; 32],
+    },
+    SetPaused {
+        deposits: bool,
+        withdrawals: bool,
+    },
+    TransferAdmin {
+        new_admin: [u8; 32],
+    },
+    SetFees {
+        deposit_bps: u16,
+        withdraw_bps: u16,
+    },
+    WithdrawFees {
+        amount: u64,
+    },
+    Migrate,
+    WithdrawAll,
+    DelegateStake {
+        amount: u64,
+    },
+    DeactivateStake,
+    WithdrawStake,
+}
+
+impl BorshInstruction {
+    // The same instruction as the shared type, borrowing the multisig members
+    fn as_shared(&self) -> ProgramInstruction<'_> {
+        match *self {
+            Self::Deposit { amount } => ProgramInstruction::Deposit { amount },
+            Self::Withdraw { amount } => ProgramInstruction::Withdraw { amount },
+            Self::Close => ProgramInstruction::Close,
+            Self::DepositToken { amount } => ProgramInstruction::DepositToken { amount },
+            Self::WithdrawToken {
+                amount,
+                signer_count,
+            } => ProgramInstruction::WithdrawToken {
+                amount,
+                signer_count,
+            },
+            Self::DepositWithDelay {
+                amount,
+                withdraw_delay,
+            } => ProgramInstruction::DepositWithDelay {
+                amount,
+                withdraw_delay,
+            },
+            Self::RequestWithdraw { amount } => ProgramInstruction::RequestWithdraw { amount },
+            Self::ClaimWithdraw => ProgramInstruction::ClaimWithdraw,
+            Self::CancelWithdraw => ProgramInstruction::CancelWithdraw,
+            Self::SetMultisig {
+                ref signers,
+                threshold,
+            } => ProgramInstruction::SetMultisig { signers, threshold },
+            Self::SetWithdrawAuthority {
+                authority,
+                allowance,
+            } => ProgramInstruction::SetWithdrawAuthority {
+                authority,
+                allowance,
+            },
+            Self::RevokeWithdrawAuthority => ProgramInstruction::RevokeWithdrawAuthority,
+            Self::DelegateWithdraw { amount } => ProgramInstruction::DelegateWithdraw { amount },
+            Self::DepositFor {
+                beneficiary,
+                amount,
+            } => ProgramInstruction::DepositFor {
+                beneficiary,
+                amount,
+            },
+            Self::SetWithdrawLimit { limit, window } => {
+                ProgramInstruction::SetWithdrawLimit { limit, window }
+            }
+            Self::InitializeConfig { admin } => ProgramInstruction::InitializeConfig { admin },
+            Self::SetPaused {
+                deposits,
+                withdrawals,
+            } => ProgramInstruction::SetPaused {
+                deposits,
+                withdrawals,
+            },
+            Self::TransferAdmin { new_admin } => ProgramInstruction::TransferAdmin { new_admin },
+            Self::SetFees {
+                deposit_bps,
+                withdraw_bps,
+            } => ProgramInstruction::SetFees {
+                deposit_bps,
+                withdraw_bps,
+            },
+            Self::WithdrawFees { amount } => ProgramInstruction::WithdrawFees { amount },
+            Self::Migrate => ProgramInstruction::Migrate,
+            Self::WithdrawAll => ProgramInstruction::WithdrawAll,
+            Self::DelegateStake { amount } => ProgramInstruction::DelegateStake { amount },
+            Self::DeactivateStake => ProgramInstruction::DeactivateStake,
+            Self::WithdrawStake => ProgramInstruction::WithdrawStake,
+        }
+    }
+}
+
+// Small deterministic xorshift generator, so a failing case can be replayed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    // Favor the boundaries, where encodings most often go wrong
+    fn u64(&mut self) -> u64 {
+        match self.below(4) {
+            0 => 0,
+            1 => u64::MAX,
+            _ => self.next(),
+        }
+    }
+
+    fn pubkey(&mut self) -> [u8; 32] {
+        let mut pubkey = [0u8; 32];
+        for chunk in pubkey.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes());
+        }
+        pubkey
+    }
+
+    fn instruction(&mut self) -> BorshInstruction {
+        use BorshInstruction::*;
+        match self.below(25) {
+            0 => Deposit { amount: self.u64() },
+            1 => Withdraw { amount: self.u64() },
+            2 => Close,
+            3 => DepositToken { amount: self.u64() },
+            4 => WithdrawToken {
+                amount: self.u64(),
+                signer_count: self.next() as u8,
+            },
+            5 => DepositWithDelay {
+                amount: self.u64(),
+                withdraw_delay: self.u64() as i64,
+            },
+            6 => RequestWithdraw { amount: self.u64() },
+            7 => ClaimWithdraw,
+            8 => CancelWithdraw,
+            9 => SetMultisig {
+                signers: (0..self.below(12)).map(|_| self.pubkey()).collect(),
+                threshold: self.next() as u8,
+            },
+            10 => SetWithdrawAuthority {
+                authority: self.pubkey(),
+                allowance: self.u64(),
+            },
+            11 => RevokeWithdrawAuthority,
+            12 => DelegateWithdraw { amount: self.u64() },
+            13 => DepositFor {
+                beneficiary: self.pubkey(),
+                amount: self.u64(),
+            },
+            14 => SetWithdrawLimit {
+                limit: self.u64(),
+                window: self.u64() as i64,
+            },
+            15 => InitializeConfig {
+                admin: self.pubkey(),
+            },
+            16 => SetPaused {
+                deposits: self.below(2) == 1,
+                withdrawals: self.below(2) == 1,
+            },
+            17 => TransferAdmin {
+                new_admin: self.pubkey(),
+            },
+            18 => SetFees {
+                deposit_bps: self.next() as u16,
+                withdraw_bps: self.next() as u16,
+            },
+            19 => WithdrawFees { amount: self.u64() },
+            20 => Migrate,
+            21 => WithdrawAll,
+            22 => DelegateStake { amount: self.u64() },
+            23 => DeactivateStake,
+            _ => WithdrawStake,
+        }
+    }
+}
+
+#[test]
+fn test_instruction_encoding_matches_borsh() {
+    // Runs without a validator: every valid instruction must encode to the same
+    // bytes as borsh and decode back to itself
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..10_000 {
+        let instruction = rng.instruction();
+        let borsh_data = borsh::to_vec(&instruction).unwrap();
+        assert_eq!(
+            instruction.as_shared().pack(),
+            borsh_data,
+            "Encoding of {:?} should match borsh",
+            instruction
+        );
+        assert_eq!(
+            ProgramInstruction::unpack(&borsh_data).ok(),
+            Some(instruction.as_shared()),
+            "Borsh encoding of {:?} should decode to the same instruction",
+            instruction
+        );
+    }
+}
+
+#[test]
+fn test_instruction_decoding_rejects_what_borsh_rejects() {
+    // Corrupt valid encodings by truncating, extending or overwriting bytes, and check
+    // that the decoder accepts exactly the inputs borsh accepts, with the same result
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..10_000 {
+        let mut data = borsh::to_vec(&rng.instruction()).unwrap();
+        match rng.below(3) {
+            0 => data.truncate(rng.below(data.len() as u64 + 1) as usize),
+            1 => data.extend((0..=rng.below(4)).map(|_| rng.next() as u8)),
+            _ => {
+                let index = rng.below(data.len() as u64) as usize;
+                data[index] = rng.next() as u8;
+            }
+        }
+
+        let expected = BorshInstruction::try_from_slice(&data).ok();
+        assert_eq!(
+            ProgramInstruction::unpack(&data).ok(),
+            expected.as_ref().map(BorshInstruction::as_shared),
+            "Decoding {:?} should agree with borsh",
+            data
+        );
+    }
+}
+
+#[test]
+fn test_anchor_discriminators() {
+    // The table must hold the Anchor sighash of each instruction, in variant order
+    let names = [
+        "deposit",
+        "withdraw",
+        "close",
+        "deposit_token",
+        "withdraw_token",
+        "deposit_with_delay",
+        "request_withdraw",
+        "claim_withdraw",
+        "cancel_withdraw",
+        "set_multisig",
+        "set_withdraw_authority",
+        "revoke_withdraw_authority",
+        "delegate_withdraw",
+        "deposit_for",
+        "set_withdraw_limit",
+        "initialize_config",
+        "set_paused",
+        "transfer_admin",
+        "set_fees",
+        "withdraw_fees",
+        "migrate",
+        "withdraw_all",
+        "delegate_stake",
+        "deactivate_stake",
+        "withdraw_stake",
+    ];
+    assert_eq!(names.len(), ANCHOR_DISCRIMINATORS.len());
+    for (name, discriminator) in names.iter().zip(ANCHOR_DISCRIMINATORS) {
+        assert_eq!(
+            hash(format!("global:{name}").as_bytes()).to_bytes()[..8],
+            discriminator,
+            "Discriminator of {} should be its Anchor sighash",
+            name
+        );
+    }
+
+    // Anchor-framed instructions decode to the same instruction as the borsh ones
+    let mut rng = Rng(0x6a09_e667_f3bc_c908);
+    for _ in 0..10_000 {
+        let instruction = rng.instruction();
+        assert_eq!(
+            ProgramInstruction::unpack(&instruction.as_shared().pack_anchor()).ok(),
+            Some(instruction.as_shared()),
+            "Anchor encoding of {:?} should decode to the same instruction",
+            instruction
+        );
+    }
+}
+
+#[test]
+fn test_parse_amount() {
+    // Plain numbers are SOL, converted exactly
+    assert_eq!(crate::parse_amount("1"), Ok(LAMPORTS_PER_SOL));
+    assert_eq!(crate::parse_amount("0.5"), Ok(LAMPORTS_PER_SOL / 2));
+    assert_eq!(crate::parse_amount(".000000001"), Ok(1));
+    assert_eq!(crate::parse_amount("2.1 SOL"), Ok(2_100_000_000));
+    assert_eq!(crate::parse_amount("2.1sol"), Ok(2_100_000_000));
+
+    // Lamports need the suffix
+    assert_eq!(crate::parse_amount("1500lamports"), Ok(1_500));
+    assert_eq!(crate::parse_amount("1500 lamports"), Ok(1_500));
+
+    // Anything that would lose precision or overflow is rejected
+    for invalid in [
+        "",
+        ".",
+        "abc",
+        "1.0000000001",
+        "-1",
+        "1.5lamports",
+        "1e9",
+        "18446744074",
+    ] {
+        assert!(
+            crate::parse_amount(invalid).is_err(),
+            "{:?} should be rejected",
+            invalid
+        );
+    }
+}
@@ -0,0 +1,175 @@
+// cargo build-sbf in ../program first, then: cargo test test_litesvm
+//
+// Runs the LiteSVM scenarios of the scenarios crate against the pinocchio program, so
+// they need neither a local validator nor a funded keypair.
+use anyhow::{anyhow, Result};
+use differential::Implementation;
+use scenarios::{funded_keypair, implementation, send, setup};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::{native_token::LAMPORTS_PER_SOL, signature::Signer};
+use vault_interface::instruction;
+
+const IMPLEMENTATION: &str = "pinocchio";
+
+// The program as it was before the zero-copy UserAccount, when it still decoded its
+// accounts with borsh. Build it with cargo build-sbf from the parent of the commit
+// "Read and write the pinocchio UserAccount in place and drop borsh from the program"
+// and copy the .so here, test_compute_units compares against it.
+const BORSH_PROGRAM_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../program/target/deploy/pinocchio_program_borsh.so"
+);
+
+#[test]
+fn test_deposit_withdraw() -> Result<()> {
+    scenarios::deposit_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_close() -> Result<()> {
+    scenarios::close(IMPLEMENTATION)
+}
+
+#[test]
+fn test_withdraw_all() -> Result<()> {
+    scenarios::withdraw_all(IMPLEMENTATION)
+}
+
+#[test]
+fn test_lifetime_totals() -> Result<()> {
+    scenarios::lifetime_totals(IMPLEMENTATION)
+}
+
+#[test]
+fn test_deposit_for() -> Result<()> {
+    scenarios::deposit_for(IMPLEMENTATION)
+}
+
+#[test]
+fn test_timelocked_withdraw() -> Result<()> {
+    scenarios::timelocked_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_multisig_withdraw() -> Result<()> {
+    scenarios::multisig_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_forged_user_account() -> Result<()> {
+    scenarios::forged_user_account(IMPLEMENTATION)
+}
+
+#[test]
+fn test_delegate_withdraw() -> Result<()> {
+    scenarios::delegate_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_withdraw_limit() -> Result<()> {
+    scenarios::withdraw_limit(IMPLEMENTATION)
+}
+
+#[test]
+fn test_config_pause() -> Result<()> {
+    scenarios::config_pause(IMPLEMENTATION)
+}
+
+#[test]
+fn test_fees() -> Result<()> {
+    scenarios::fees(IMPLEMENTATION)
+}
+
+#[test]
+fn test_token_deposit_withdraw() -> Result<()> {
+    scenarios::token_deposit_withdraw(IMPLEMENTATION)
+}
+
+#[test]
+fn test_token_hook_multisig() -> Result<()> {
+    scenarios::token_hook_multisig(IMPLEMENTATION)
+}
+
+#[test]
+fn test_migrate_baseline() -> Result<()> {
+    scenarios::migrate_baseline(IMPLEMENTATION)
+}
+
+#[test]
+fn test_stake() -> Result<()> {
+    scenarios::stake(IMPLEMENTATION)
+}
+
+#[test]
+fn test_stake_prefunded() -> Result<()> {
+    scenarios::stake_prefunded(IMPLEMENTATION)
+}
+
+// Compute units of a repeat Deposit, a Withdraw and a RequestWithdraw against the
+// given build. RequestWithdraw only updates the user account, so it isolates the cost
+// of the layout.
+fn compute_units(implementation: &Implementation) -> Result<[u64; 3]> {
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL);
+    let user_pubkey = payer.pubkey();
+
+    // The first deposit also creates the user account, so measure a second one,
+    // which reads the account like every other instruction
+    let deposit = instruction::deposit(&program_id, &user_pubkey, LAMPORTS_PER_SOL / 10);
+    send(&mut svm, &payer, deposit.clone(), &[])?;
+    let deposit = send(&mut svm, &payer, deposit, &[])?;
+    let withdraw = send(
+        &mut svm,
+        &payer,
+        instruction::withdraw(&program_id, &user_pubkey, LAMPORTS_PER_SOL / 20),
+        &[],
+    )?;
+    let request_withdraw = send(
+        &mut svm,
+        &payer,
+        instruction::request_withdraw(&program_id, &user_pubkey, LAMPORTS_PER_SOL / 20),
+        &[],
+    )?;
+    Ok([
+        deposit.compute_units_consumed,
+        withdraw.compute_units_consumed,
+        request_withdraw.compute_units_consumed,
+    ])
+}
+
+#[test]
+#[ignore = "needs the borsh build of the program, see the README"]
+fn test_compute_units() -> Result<()> {
+    // The zero-copy build must stay cheaper and smaller than the borsh one
+    let current = implementation(IMPLEMENTATION);
+    let borsh = Implementation {
+        path: BORSH_PROGRAM_PATH,
+        ..*current
+    };
+    let before = compute_units(&borsh)?;
+    let after = compute_units(current)?;
+    for ((name, before), after) in ["Deposit", "Withdraw", "RequestWithdraw"]
+        .into_iter()
+        .zip(before)
+        .zip(after)
+    {
+        println!("{} compute units: {} before, {} after", name, before, after);
+        assert!(
+            after < before,
+            "{} should use fewer compute units than the borsh build",
+            name
+        );
+    }
+
+    let size = |path: &str| {
+        std::fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .map_err(|error| anyhow!("Failed to read {}: {}", path, error))
+    };
+    let (before, after) = (size(borsh.path)?, size(current.path)?);
+    println!(".so size in bytes: {} before, {} after", before, after);
+    assert!(after < before, ".so should be smaller than the borsh build");
+
+    Ok(())
+}
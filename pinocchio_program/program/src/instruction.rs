@@ -0,0 +1,402 @@
+// Instruction data of the vault program, shared with the client so both sides agree on
+// the format. The encoding is the one borsh produces for this enum, so clients that
+// still serialize with borsh keep working: a one-byte variant index followed by the
+// fields in little-endian order, with the multisig members prefixed by a u32 count.
+// Decoding borrows from the input and never allocates. With the anchor-discriminators
+// feature, instructions built for the Anchor program decode too: they carry the same
+// arguments behind an 8-byte discriminator instead of the variant index.
+use pinocchio::{program_error::ProgramError, pubkey::Pubkey};
+
+// Anchor instruction discriminators, the first 8 bytes of sha256("global:<name>"), in
+// variant order. Only deposit_token's starts with a byte that is also a variant index,
+// and that variant encodes to a single byte, so no valid borsh encoding is mistaken
+// for an Anchor one.
+pub const ANCHOR_DISCRIMINATORS: [[u8; 8]; 25] = [
+    [242, 35, 198, 137, 82, 225, 242, 182],  // deposit
+    [183, 18, 70, 156, 148, 109, 161, 34],   // withdraw
+    [98, 165, 201, 177, 108, 65, 206, 96],   // close
+    [11, 156, 96, 218, 39, 163, 180, 19],    // deposit_token
+    [136, 235, 181, 5, 101, 109, 57, 81],    // withdraw_token
+    [110, 109, 250, 169, 189, 179, 8, 163],  // deposit_with_delay
+    [137, 95, 187, 96, 250, 138, 31, 182],   // request_withdraw
+    [232, 89, 154, 117, 16, 204, 182, 224],  // claim_withdraw
+    [112, 53, 226, 58, 158, 30, 37, 168],    // cancel_withdraw
+    [251, 6, 245, 35, 115, 42, 77, 186],     // set_multisig
+    [199, 146, 140, 67, 1, 90, 8, 222],      // set_withdraw_authority
+    [192, 28, 85, 211, 241, 241, 81, 159],   // revoke_withdraw_authority
+    [89, 6, 67, 206, 216, 140, 118, 63],     // delegate_withdraw
+    [193, 39, 228, 88, 160, 254, 92, 53],    // deposit_for
+    [198, 158, 141, 22, 248, 120, 105, 42],  // set_withdraw_limit
+    [208, 127, 21, 1, 194, 190, 196, 70],    // initialize_config
+    [91, 60, 125, 192, 176, 225, 166, 218],  // set_paused
+    [42, 242, 66, 106, 228, 10, 111, 156],   // transfer_admin
+    [137, 178, 49, 58, 0, 245, 242, 190],    // set_fees
+    [198, 212, 171, 109, 144, 215, 174, 89], // withdraw_fees
+    [155, 234, 231, 146, 236, 158, 162, 30], // migrate
+    [96, 246, 166, 130, 229, 50, 43, 70],    // withdraw_all
+    [50, 110, 95, 179, 194, 75, 140, 246],   // delegate_stake
+    [165, 158, 229, 97, 168, 220, 187, 225], // deactivate_stake
+    [153, 8, 22, 138, 105, 176, 87, 66],     // withdraw_stake
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramInstruction<'a> {
+    Deposit {
+        amount: u64,
+    },
+    Withdraw {
+        amount: u64,
+    },
+    Close,
+    DepositToken {
+        amount: u64,
+    },
+    WithdrawToken {
+        amount: u64,
+        signer_count: u8,
+    },
+    DepositWithDelay {
+        amount: u64,
+        withdraw_delay: i64,
+    },
+    RequestWithdraw {
+        amount: u64,
+    },
+    ClaimWithdraw,
+    CancelWithdraw,
+    SetMultisig {
+        signers: &'a [Pubkey],
+        threshold: u8,
+    },
+    SetWithdrawAuthority {
+        authority: Pubkey,
+        allowance: u64,
+    },
+    RevokeWithdrawAuthority,
+    DelegateWithdraw {
+        amount: u64,
+    },
+    DepositFor {
+        beneficiary: Pubkey,
+        amount: u64,
+    },
+    SetWithdrawLimit {
+        limit: u64,
+        window: i64,
+    },
+    InitializeConfig {
+        admin: Pubkey,
+    },
+    SetPaused {
+        deposits: bool,
+        withdrawals: bool,
+    },
+    TransferAdmin {
+        new_admin: Pubkey,
+    },
+    SetFees {
+        deposit_bps: u16,
+        withdraw_bps: u16,
+    },
+    WithdrawFees {
+        amount: u64,
+    },
+    Migrate,
+    WithdrawAll,
+    DelegateStake {
+        amount: u64,
+    },
+    DeactivateStake,
+    WithdrawStake,
+}
+
+impl<'a> ProgramInstruction<'a> {
+    // Decode instruction data, rejecting short input and trailing bytes
+    pub fn unpack(input: &'a [u8]) -> Result<Self, ProgramError> {
+        let (tag, rest) = match anchor_tag(input) {
+            Some(tag) => (tag, &input[8..]),
+            None => {
+                let (&tag, rest) = input
+                    .split_first()
+                    .ok_or(ProgramError::InvalidInstructionData)?;
+                (tag, rest)
+            }
+        };
+        let mut data = InstructionData(rest);
+
+        let instruction = match tag {
+            0 => Self::Deposit {
+                amount: data.read_u64()?,
+            },
+            1 => Self::Withdraw {
+                amount: data.read_u64()?,
+            },
+            2 => Self::Close,
+            3 => Self::DepositToken {
+                amount: data.read_u64()?,
+            },
+            4 => Self::WithdrawToken {
+                amount: data.read_u64()?,
+                signer_count: data.read_u8()?,
+            },
+            5 => Self::DepositWithDelay {
+                amount: data.read_u64()?,
+                withdraw_delay: data.read_i64()?,
+            },
+            6 => Self::RequestWithdraw {
+                amount: data.read_u64()?,
+            },
+            7 => Self::ClaimWithdraw,
+            8 => Self::CancelWithdraw,
+            9 => Self::SetMultisig {
+                signers: data.read_pubkeys()?,
+                threshold: data.read_u8()?,
+            },
+            10 => Self::SetWithdrawAuthority {
+                authority: data.read_pubkey()?,
+                allowance: data.read_u64()?,
+            },
+            11 => Self::RevokeWithdrawAuthority,
+            12 => Self::DelegateWithdraw {
+                amount: data.read_u64()?,
+            },
+            13 => Self::DepositFor {
+                beneficiary: data.read_pubkey()?,
+                amount: data.read_u64()?,
+            },
+            14 => Self::SetWithdrawLimit {
+                limit: data.read_u64()?,
+                window: data.read_i64()?,
+            },
+            15 => Self::InitializeConfig {
+                admin: data.read_pubkey()?,
+            },
+            16 => Self::SetPaused {
+                deposits: data.read_bool()?,
+                withdrawals: data.read_bool()?,
+            },
+            17 => Self::TransferAdmin {
+                new_admin: data.read_pubkey()?,
+            },
+            18 => Self::SetFees {
+                deposit_bps: data.read_u16()?,
+                withdraw_bps: data.read_u16()?,
+            },
+            19 => Self::WithdrawFees {
+                amount: data.read_u64()?,
+            },
+            20 => Self::Migrate,
+            21 => Self::WithdrawAll,
+            22 => Self::DelegateStake {
+                amount: data.read_u64()?,
+            },
+            23 => Self::DeactivateStake,
+            24 => Self::WithdrawStake,
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+
+        if !data.0.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        Ok(instruction)
+    }
+
+    // Encode the instruction for a client to send
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match *self {
+            Self::Deposit { amount } => {
+                data.push(0);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Withdraw { amount } => {
+                data.push(1);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Close => data.push(2),
+            Self::DepositToken { amount } => {
+                data.push(3);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::WithdrawToken {
+                amount,
+                signer_count,
+            } => {
+                data.push(4);
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.push(signer_count);
+            }
+            Self::DepositWithDelay {
+                amount,
+                withdraw_delay,
+            } => {
+                data.push(5);
+                data.extend_from_slice(&amount.to_le_bytes());
+                data.extend_from_slice(&withdraw_delay.to_le_bytes());
+            }
+            Self::RequestWithdraw { amount } => {
+                data.push(6);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::ClaimWithdraw => data.push(7),
+            Self::CancelWithdraw => data.push(8),
+            Self::SetMultisig { signers, threshold } => {
+                data.push(9);
+                data.extend_from_slice(&(signers.len() as u32).to_le_bytes());
+                for signer in signers {
+                    data.extend_from_slice(signer);
+                }
+                data.push(threshold);
+            }
+            Self::SetWithdrawAuthority {
+                authority,
+                allowance,
+            } => {
+                data.push(10);
+                data.extend_from_slice(&authority);
+                data.extend_from_slice(&allowance.to_le_bytes());
+            }
+            Self::RevokeWithdrawAuthority => data.push(11),
+            Self::DelegateWithdraw { amount } => {
+                data.push(12);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::DepositFor {
+                beneficiary,
+                amount,
+            } => {
+                data.push(13);
+                data.extend_from_slice(&beneficiary);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SetWithdrawLimit { limit, window } => {
+                data.push(14);
+                data.extend_from_slice(&limit.to_le_bytes());
+                data.extend_from_slice(&window.to_le_bytes());
+            }
+            Self::InitializeConfig { admin } => {
+                data.push(15);
+                data.extend_from_slice(&admin);
+            }
+            Self::SetPaused {
+                deposits,
+                withdrawals,
+            } => {
+                data.push(16);
+                data.push(deposits as u8);
+                data.push(withdrawals as u8);
+            }
+            Self::TransferAdmin { new_admin } => {
+                data.push(17);
+                data.extend_from_slice(&new_admin);
+            }
+            Self::SetFees {
+                deposit_bps,
+                withdraw_bps,
+            } => {
+                data.push(18);
+                data.extend_from_slice(&deposit_bps.to_le_bytes());
+                data.extend_from_slice(&withdraw_bps.to_le_bytes());
+            }
+            Self::WithdrawFees { amount } => {
+                data.push(19);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Migrate => data.push(20),
+            Self::WithdrawAll => data.push(21),
+            Self::DelegateStake { amount } => {
+                data.push(22);
+                data.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::DeactivateStake => data.push(23),
+            Self::WithdrawStake => data.push(24),
+        }
+        data
+    }
+
+    // Encode the instruction the way the Anchor program expects it
+    pub fn pack_anchor(&self) -> Vec<u8> {
+        let data = self.pack();
+        let mut anchor_data = ANCHOR_DISCRIMINATORS[data[0] as usize].to_vec();
+        anchor_data.extend_from_slice(&data[1..]);
+        anchor_data
+    }
+}
+
+// Variant index of the Anchor discriminator the input starts with, if any
+#[cfg(feature = "anchor-discriminators")]
+fn anchor_tag(input: &[u8]) -> Option<u8> {
+    ANCHOR_DISCRIMINATORS
+        .iter()
+        .position(|discriminator| input.starts_with(discriminator))
+        .map(|index| index as u8)
+}
+
+#[cfg(not(feature = "anchor-discriminators"))]
+fn anchor_tag(_input: &[u8]) -> Option<u8> {
+    None
+}
+
+// Cursor over the instruction fields, failing on short input
+struct InstructionData<'a>(&'a [u8]);
+
+impl<'a> InstructionData<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ProgramError> {
+        if self.0.len() < len {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], ProgramError> {
+        let (bytes, rest) = self
+            .0
+            .split_first_chunk::<N>()
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, ProgramError> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, ProgramError> {
+        Ok(u16::from_le_bytes(self.read()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, ProgramError> {
+        Ok(u64::from_le_bytes(self.read()?))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, ProgramError> {
+        Ok(i64::from_le_bytes(self.read()?))
+    }
+
+    // Borsh only accepts 0 and 1 for a bool
+    fn read_bool(&mut self) -> Result<bool, ProgramError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+
+    fn read_pubkey(&mut self) -> Result<Pubkey, ProgramError> {
+        self.read()
+    }
+
+    // The members are borrowed in place rather than copied into a Vec
+    fn read_pubkeys(&mut self) -> Result<&'a [Pubkey], ProgramError> {
+        let len = u32::from_le_bytes(self.read()?) as usize;
+        let bytes = self.read_bytes(
+            len.checked_mul(32)
+                .ok_or(ProgramError::InvalidInstructionData)?,
+        )?;
+        // SAFETY: Pubkey is a byte array, so it has alignment 1 and any 32 bytes are
+        // a valid Pubkey, and bytes holds exactly len of them
+        Ok(unsafe { core::slice::from_raw_parts(bytes.as_ptr() as *const Pubkey, len) })
+    }
+}
@@ -3,54 +3,553 @@
 // solana address -k ./target/deploy/pinocchio_program-keypair.json
 
 #![allow(unexpected_cfgs)]
-use borsh::{BorshDeserialize, BorshSerialize};
-use borsh_derive::{
-    BorshDeserialize as BorshDeserializeDerive, BorshSerialize as BorshSerializeDerive,
-};
 use pinocchio::{
-    account_info::AccountInfo,
-    entrypoint,
-    instruction::{Seed, Signer},
+    account_info::{AccountInfo, RefMut},
+    instruction::{Account, AccountMeta, Instruction, Seed, Signer},
+    log::sol_log_data,
     msg,
+    program::{invoke, invoke_signed, invoke_signed_unchecked},
     program_error::ProgramError,
     pubkey,
     pubkey::Pubkey,
-    sysvars::{rent::Rent, Sysvar},
+    sysvars::{clock::Clock, rent::Rent, Sysvar},
     ProgramResult,
 };
-use pinocchio_system::instructions::{CreateAccount, Transfer};
+#[cfg(not(feature = "no-entrypoint"))]
+use pinocchio::entrypoint;
+use pinocchio_system::instructions::{Allocate, Assign, CreateAccount, Transfer};
 use pinocchio_system::ID as SYSTEM_PROGRAM_ID;
+use pinocchio_token::ID as TOKEN_PROGRAM_ID;
+
+pub mod instruction;
+
+use instruction::ProgramInstruction;
+
+// Token-2022 program
+const TOKEN_2022_PROGRAM_ID: Pubkey =
+    pinocchio_pubkey::pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
 
-// Declare program entrypoint
+// Associated Token Account program
+const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    pinocchio_pubkey::pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+// Upgradeable BPF loader, owner of every upgradeable program's ProgramData account
+const BPF_LOADER_UPGRADEABLE_ID: Pubkey =
+    pinocchio_pubkey::pubkey!("BPFLoaderUpgradeab1e11111111111111111111111");
+
+// Stake program, and the size of the accounts it owns (StakeStateV2)
+const STAKE_PROGRAM_ID: Pubkey =
+    pinocchio_pubkey::pubkey!("Stake11111111111111111111111111111111111111");
+const STAKE_ACCOUNT_SIZE: u64 = 200;
+
+// Declare program entrypoint, left out when the crate is linked into a client. The
+// heap-usage build, for the benchmarks, also logs the heap each instruction used.
+#[cfg(not(any(feature = "no-entrypoint", feature = "heap-usage")))]
 entrypoint!(process_instruction);
+#[cfg(all(not(feature = "no-entrypoint"), feature = "heap-usage"))]
+entrypoint!(process_instruction_with_heap_usage);
+
+#[cfg(all(not(feature = "no-entrypoint"), feature = "heap-usage"))]
+fn process_instruction_with_heap_usage(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let result = process_instruction(program_id, accounts, instruction_data);
 
-// Program instruction enum
-#[derive(Debug, BorshDeserializeDerive)]
-enum ProgramInstruction {
-    Deposit { amount: u64 },
-    Withdraw { amount: u64 },
+    // The bump allocator hands out the heap from its end downwards and keeps its
+    // position in the first word, 0 until the first allocation
+    let position = unsafe { *(entrypoint::HEAP_START_ADDRESS as *const usize) };
+    let used = match position {
+        0 => 0,
+        position => entrypoint::HEAP_START_ADDRESS as usize + entrypoint::HEAP_LENGTH - position,
+    };
+    log_heap_used(used);
+
+    result
 }
 
-impl ProgramInstruction {
-    // Deserialize the instruction data using Borsh
-    fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        Self::try_from_slice(input).map_err(|_| ProgramError::InvalidInstructionData)
+// Log "Heap used: N bytes" from a stack buffer, as formatting the message with
+// format! would allocate on the heap being measured. The buffer is filled from the
+// end: the suffix, then the digits from the lowest, then the prefix.
+#[cfg(all(not(feature = "no-entrypoint"), feature = "heap-usage"))]
+fn log_heap_used(used: usize) {
+    const PREFIX: &[u8] = b"Heap used: ";
+    const SUFFIX: &[u8] = b" bytes";
+    let mut buffer = [0u8; PREFIX.len() + 20 + SUFFIX.len()];
+    let mut start = buffer.len() - SUFFIX.len();
+    buffer[start..].copy_from_slice(SUFFIX);
+    let mut value = used;
+    loop {
+        start -= 1;
+        buffer[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
     }
+    start -= PREFIX.len();
+    buffer[start..start + PREFIX.len()].copy_from_slice(PREFIX);
+    // The message is made of ASCII only
+    msg!(unsafe { core::str::from_utf8_unchecked(&buffer[start..]) });
+}
+
+// Getters and setters for fields stored as little-endian byte arrays
+macro_rules! le_fields {
+    ($($field:ident, $setter:ident: $ty:ty;)*) => {
+        $(
+            fn $field(&self) -> $ty {
+                <$ty>::from_le_bytes(self.$field)
+            }
+
+            fn $setter(&mut self, value: $ty) {
+                self.$field = value.to_le_bytes();
+            }
+        )*
+    };
 }
 
-// User account data structure compatible with borsh
-#[derive(Debug, BorshSerializeDerive, BorshDeserializeDerive)]
+// Zero-copy view of the user data account, read and written in place. Every field is
+// a byte or byte array, so the struct has alignment 1 and no padding and can be cast
+// from the account data at any offset. The layout is the one borsh wrote: integers in
+// little-endian order, followed by the multisig members as a u32 count and the keys,
+// and in the current layout by the lifetime totals.
+#[repr(C)]
 struct UserAccount {
     pub user: Pubkey,
     pub user_bump: u8,
     pub vault_bump: u8,
-    pub is_initialized: bool,
+    pub version: u8, // layout version, baseline accounts hold their always-true is_initialized flag here
+    withdraw_delay: [u8; 8], // i64, seconds between RequestWithdraw and ClaimWithdraw, 0 = disabled
+    pending_withdrawal: [u8; 8], // u64, lamports requested but not yet claimed
+    unlock_timestamp: [u8; 8], // i64, unix timestamp after which the pending withdrawal can be claimed
+    pub withdraw_authority: Pubkey, // delegate allowed to withdraw, all zeroes = none
+    withdraw_allowance: [u8; 8], // u64, lamports the delegate may still withdraw, u64::MAX = uncapped
+    withdraw_limit: [u8; 8],     // u64, lamports withdrawable per window, 0 = unlimited
+    withdraw_window: [u8; 8],    // i64, length of the rate limit window in seconds
+    window_start: [u8; 8],       // i64, unix timestamp at which the current window started
+    window_withdrawn: [u8; 8],   // u64, lamports withdrawn in the current window
+    pending_withdraw_limit: [u8; 8], // u64, raised limit waiting for its delay to pass
+    pending_withdraw_window: [u8; 8], // i64, window length that comes with the pending limit
+    limit_unlock_timestamp: [u8; 8], // i64, unix timestamp at which the pending limit applies, 0 = none
+    pub threshold: u8, // multisig approvals required, 0 = the user alone controls the vault
+    signers_len: [u8; 4], // u32, number of multisig members that follow
+}
+
+// Lifetime totals, behind the multisig members. Baseline accounts end long before, so
+// deposits and withdrawals leave them without totals until Migrate makes room.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LifetimeTotals {
+    total_deposited: [u8; 8], // u64, lamports ever credited to the vault, after fees
+    total_withdrawn: [u8; 8], // u64, lamports ever taken out of the vault, including fees
+    deposit_count: [u8; 8],   // u64, number of lamport deposits
+    last_deposit_slot: [u8; 8], // u64, slot of the latest lamport deposit, 0 = none
+    last_withdraw_slot: [u8; 8], // u64, slot of the latest lamport withdrawal, 0 = none
+}
+
+// The casts below rely on these
+const _: () = assert!(core::mem::align_of::<UserAccount>() == 1);
+const _: () = assert!(core::mem::size_of::<UserAccount>() == UserAccount::SIZE);
+const _: () = assert!(core::mem::align_of::<LifetimeTotals>() == 1);
+const _: () = assert!(core::mem::size_of::<LifetimeTotals>() == LifetimeTotals::SIZE);
+
+impl LifetimeTotals {
+    // total_deposited + total_withdrawn + deposit_count + last_deposit_slot
+    // + last_withdraw_slot
+    const SIZE: usize = 8 + 8 + 8 + 8 + 8;
+
+    le_fields! {
+        total_deposited, set_total_deposited: u64;
+        total_withdrawn, set_total_withdrawn: u64;
+        deposit_count, set_deposit_count: u64;
+    }
 }
 
 impl UserAccount {
-    const SIZE: usize = 32 + 1 + 1 + 1; // pubkey + user_bump + vault_bump + is_initialized
+    // pubkey + user_bump + vault_bump + version + withdraw_delay + pending_withdrawal
+    // + unlock_timestamp + withdraw_authority + withdraw_allowance + withdraw_limit
+    // + withdraw_window + window_start + window_withdrawn + pending_withdraw_limit
+    // + pending_withdraw_window + limit_unlock_timestamp + threshold + empty signers vec
+    const SIZE: usize = 32 + 1 + 1 + 1 + 8 + 8 + 8 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1 + 4;
+
+    // A new account: the header, the fields and the totals, without multisig members
+    const LEN: usize = USER_ACCOUNT_DISCRIMINATOR.len() + Self::SIZE + LifetimeTotals::SIZE;
+
+    // Accounts from before the layout was versioned: user + user_bump + vault_bump
+    // + is_initialized, the flag in the place of the version
+    const BASELINE_SIZE: usize = 32 + 1 + 1 + 1;
+
+    le_fields! {
+        withdraw_delay, set_withdraw_delay: i64;
+        pending_withdrawal, set_pending_withdrawal: u64;
+        unlock_timestamp, set_unlock_timestamp: i64;
+        withdraw_allowance, set_withdraw_allowance: u64;
+        withdraw_limit, set_withdraw_limit: u64;
+        withdraw_window, set_withdraw_window: i64;
+        window_start, set_window_start: i64;
+        window_withdrawn, set_window_withdrawn: u64;
+        pending_withdraw_limit, set_pending_withdraw_limit: u64;
+        pending_withdraw_window, set_pending_withdraw_window: i64;
+        limit_unlock_timestamp, set_limit_unlock_timestamp: i64;
+    }
+
+    fn signers_len(&self) -> usize {
+        u32::from_le_bytes(self.signers_len) as usize
+    }
+
+    fn set_signers_len(&mut self, len: usize) {
+        self.signers_len = (len as u32).to_le_bytes();
+    }
+
+    // Account size including the header, the multisig members and the totals
+    fn size(&self) -> usize {
+        Self::LEN + self.signers_len() * 32
+    }
+
+    // Whether the data is a baseline account, which has no header and is too short to
+    // view in place
+    fn is_baseline(data: &[u8]) -> bool {
+        data.len() == Self::BASELINE_SIZE
+            && data[core::mem::offset_of!(Self, version)] == BASELINE_USER_ACCOUNT_VERSION
+    }
+
+    // Check the header and the version of an account in the current layout, and its
+    // length against the member count
+    fn check(data: &[u8]) -> ProgramResult {
+        let header_size = USER_ACCOUNT_DISCRIMINATOR.len();
+        if !data.starts_with(&USER_ACCOUNT_DISCRIMINATOR)
+            || data.len() < header_size + Self::SIZE
+            || data[header_size + core::mem::offset_of!(Self, version)] != USER_ACCOUNT_VERSION
+        {
+            return Err(VaultError::DeserializationFailed.into());
+        }
+        let signers_len = header_size + core::mem::offset_of!(Self, signers_len);
+        let signers_len =
+            u32::from_le_bytes(data[signers_len..signers_len + 4].try_into().unwrap());
+        if data.len() != Self::LEN + signers_len as usize * 32 {
+            return Err(VaultError::DeserializationFailed.into());
+        }
+        Ok(())
+    }
+
+    // View an account in the current layout
+    fn from_bytes(data: &[u8]) -> Result<(&Self, &[Pubkey]), ProgramError> {
+        Self::check(data)?;
+        let end = data.len() - LifetimeTotals::SIZE;
+        let (fields, signers) = data[USER_ACCOUNT_DISCRIMINATOR.len()..end].split_at(Self::SIZE);
+
+        // SAFETY: fields is SIZE bytes long and UserAccount has alignment 1 and no
+        // invalid bit patterns
+        let user_data = unsafe { &*(fields.as_ptr() as *const Self) };
+
+        // SAFETY: Pubkey is a byte array, so any 32 bytes are a valid Pubkey
+        let signers = unsafe {
+            core::slice::from_raw_parts(signers.as_ptr() as *const Pubkey, signers.len() / 32)
+        };
+        Ok((user_data, signers))
+    }
+
+    // Mutable view, with the same checks as from_bytes
+    fn from_bytes_mut(data: &mut [u8]) -> Result<(&mut Self, &mut [Pubkey]), ProgramError> {
+        Self::check(data)?;
+        let end = data.len() - LifetimeTotals::SIZE;
+        let (fields, signers) =
+            data[USER_ACCOUNT_DISCRIMINATOR.len()..end].split_at_mut(Self::SIZE);
+
+        // SAFETY: as in from_bytes
+        unsafe {
+            Ok((
+                &mut *(fields.as_mut_ptr() as *mut Self),
+                core::slice::from_raw_parts_mut(
+                    signers.as_mut_ptr() as *mut Pubkey,
+                    signers.len() / 32,
+                ),
+            ))
+        }
+    }
+
+    // Mutable view of an account in the current layout, or of a baseline account
+    // through a copy of it in the buffer, padded with zeroes for the later fields.
+    // Changes to a baseline account are not written back.
+    fn from_bytes_or_baseline_mut<'a>(
+        data: &'a mut [u8],
+        buffer: &'a mut [u8; Self::SIZE],
+    ) -> Result<(&'a mut Self, &'a mut [Pubkey]), ProgramError> {
+        if !Self::is_baseline(data) {
+            return Self::from_bytes_mut(data);
+        }
+        buffer[..Self::BASELINE_SIZE].copy_from_slice(data);
+
+        // SAFETY: as in from_bytes, and the zeroed member count matches the empty slice
+        Ok((unsafe { &mut *(buffer.as_mut_ptr() as *mut Self) }, &mut []))
+    }
+
+    // The lifetime totals at the end of the account, None for baseline accounts
+    fn totals_mut(data: &mut [u8]) -> Result<Option<&mut LifetimeTotals>, ProgramError> {
+        if Self::is_baseline(data) {
+            return Ok(None);
+        }
+        Self::check(data)?;
+        let start = data.len() - LifetimeTotals::SIZE;
+        let totals = &mut data[start..];
+
+        // SAFETY: totals is LifetimeTotals::SIZE bytes long and LifetimeTotals has
+        // alignment 1 and no invalid bit patterns
+        Ok(Some(unsafe {
+            &mut *(totals.as_mut_ptr() as *mut LifetimeTotals)
+        }))
+    }
+
+    // Stamp the discriminator and the current version onto the fields that follow
+    fn write_header(data: &mut [u8]) {
+        let header_size = USER_ACCOUNT_DISCRIMINATOR.len();
+        data[..header_size].copy_from_slice(&USER_ACCOUNT_DISCRIMINATOR);
+        data[header_size + core::mem::offset_of!(Self, version)] = USER_ACCOUNT_VERSION;
+    }
+
+    // View a freshly created, zeroed account in the current layout
+    fn init(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        if data.len() != Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::write_header(data);
+        Ok(Self::from_bytes_mut(data)?.0)
+    }
+}
+
+// Anchor-style account discriminator, the first 8 bytes of sha256("account:UserAccount"),
+// so current accounts share the Anchor program's layout
+const USER_ACCOUNT_DISCRIMINATOR: [u8; 8] = [211, 33, 136, 16, 186, 110, 242, 127];
+
+// Accounts created before the layout was versioned. Their version byte is the old
+// is_initialized flag, which was always true.
+const BASELINE_USER_ACCOUNT_VERSION: u8 = 1;
+
+// Layout written for new accounts and by Migrate
+const USER_ACCOUNT_VERSION: u8 = 2;
+
+// Anchor-style account discriminator, the first 8 bytes of sha256("account:Config")
+const CONFIG_DISCRIMINATOR: [u8; 8] = [155, 12, 170, 224, 30, 250, 204, 130];
+
+// Global program settings, a singleton PDA seeded with b"config". Cast from the
+// account data like UserAccount, with the bools stored as 0 or 1.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Config {
+    pub admin: Pubkey,      // may pause the program and hand over the admin role
+    paused: u8,             // blocks deposits
+    withdrawals_paused: u8, // also blocks withdrawals, only set together with paused
+    pub bump: u8,
+    pub treasury_bump: u8,     // bump of the fee treasury PDA
+    deposit_fee_bps: [u8; 2],  // u16, deposit fee in basis points
+    withdraw_fee_bps: [u8; 2], // u16, withdraw fee in basis points
+}
+
+const _: () = assert!(core::mem::align_of::<Config>() == 1);
+const _: () = assert!(core::mem::size_of::<Config>() == Config::SIZE);
+
+impl Config {
+    // admin + paused + withdrawals_paused + bump + treasury_bump + deposit_fee_bps
+    // + withdraw_fee_bps
+    const SIZE: usize = 32 + 1 + 1 + 1 + 1 + 2 + 2;
+
+    // Account size including the discriminator
+    const LEN: usize = CONFIG_DISCRIMINATOR.len() + Self::SIZE;
+
+    le_fields! {
+        deposit_fee_bps, set_deposit_fee_bps: u16;
+        withdraw_fee_bps, set_withdraw_fee_bps: u16;
+    }
+
+    fn paused(&self) -> bool {
+        self.paused != 0
+    }
+
+    fn withdrawals_paused(&self) -> bool {
+        self.withdrawals_paused != 0
+    }
+
+    fn set_paused(&mut self, deposits: bool, withdrawals: bool) {
+        self.paused = deposits as u8;
+        self.withdrawals_paused = withdrawals as u8;
+    }
+
+    // Cast the fields after the discriminator
+    fn from_bytes(data: &[u8]) -> Result<&Self, ProgramError> {
+        match data.strip_prefix(&CONFIG_DISCRIMINATOR) {
+            Some(fields) if fields.len() == Self::SIZE => {
+                // SAFETY: fields is SIZE bytes long and Config has alignment 1
+                Ok(unsafe { &*(fields.as_ptr() as *const Self) })
+            }
+            _ => Err(VaultError::DeserializationFailed.into()),
+        }
+    }
+
+    // Like from_bytes, and writes the discriminator of a freshly created account
+    fn from_bytes_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        if data.len() != Self::LEN {
+            return Err(VaultError::DeserializationFailed.into());
+        }
+        let (header, fields) = data.split_at_mut(CONFIG_DISCRIMINATOR.len());
+        header.copy_from_slice(&CONFIG_DISCRIMINATOR);
+        // SAFETY: as in from_bytes
+        Ok(unsafe { &mut *(fields.as_mut_ptr() as *mut Self) })
+    }
+}
+
+// Typed events, logged with sol_log_data as an 8-byte discriminator followed by the
+// borsh encoded event. The discriminator is the first 8 bytes of
+// sha256("event:<name>"), the same encoding Anchor's emit! uses, so one decoder
+// reads the events of every implementation.
+trait Event {
+    const DISCRIMINATOR: [u8; 8];
+
+    // Append the fields in borsh order
+    fn write_fields(&self, data: &mut Vec<u8>);
+
+    fn emit(&self) -> ProgramResult {
+        let mut data = Self::DISCRIMINATOR.to_vec();
+        self.write_fields(&mut data);
+        sol_log_data(&[&data]);
+        Ok(())
+    }
+}
+
+// Emitted when lamports are deposited into a vault
+#[derive(Debug)]
+struct DepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports paid in, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the deposit
+    pub slot: u64,
 }
 
+impl Event for DepositEvent {
+    const DISCRIMINATOR: [u8; 8] = [120, 248, 61, 83, 31, 142, 107, 144];
+
+    fn write_fields(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.user);
+        data.extend_from_slice(&self.vault);
+        data.extend_from_slice(&self.amount.to_le_bytes());
+        data.extend_from_slice(&self.fee.to_le_bytes());
+        data.extend_from_slice(&self.vault_balance.to_le_bytes());
+        data.extend_from_slice(&self.slot.to_le_bytes());
+    }
+}
+
+// Emitted when lamports leave a vault, whether withdrawn, claimed, drained on close
+// or paid to a delegate
+#[derive(Debug)]
+struct WithdrawEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports taken out of the vault, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the withdrawal
+    pub slot: u64,
+}
+
+impl Event for WithdrawEvent {
+    const DISCRIMINATOR: [u8; 8] = [22, 9, 133, 26, 160, 44, 71, 192];
+
+    fn write_fields(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.user);
+        data.extend_from_slice(&self.vault);
+        data.extend_from_slice(&self.amount.to_le_bytes());
+        data.extend_from_slice(&self.fee.to_le_bytes());
+        data.extend_from_slice(&self.vault_balance.to_le_bytes());
+        data.extend_from_slice(&self.slot.to_le_bytes());
+    }
+}
+
+// Emitted when tokens are deposited into a vault token account
+#[derive(Debug)]
+struct TokenDepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA, owner of the vault token account
+    pub mint: Pubkey,       // mint of the deposited tokens
+    pub amount: u64,        // tokens the user sent
+    pub received: u64,      // tokens that reached the vault, less any transfer fee
+    pub vault_balance: u64, // vault token account balance after the deposit
+    pub slot: u64,
+}
+
+impl Event for TokenDepositEvent {
+    const DISCRIMINATOR: [u8; 8] = [27, 43, 27, 81, 3, 244, 105, 252];
+
+    fn write_fields(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.user);
+        data.extend_from_slice(&self.vault);
+        data.extend_from_slice(&self.mint);
+        data.extend_from_slice(&self.amount.to_le_bytes());
+        data.extend_from_slice(&self.received.to_le_bytes());
+        data.extend_from_slice(&self.vault_balance.to_le_bytes());
+        data.extend_from_slice(&self.slot.to_le_bytes());
+    }
+}
+
+// Upper bound on multisig members, keeps the account small and signer checks cheap
+const MAX_MULTISIG_SIGNERS: usize = 10;
+
+// Program errors, returned as ProgramError::Custom. The codes match the Anchor
+// program's VaultError so clients can decode failures from every implementation.
+// Append new variants at the end, reordering changes the codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum VaultError {
+    InsufficientFunds = 6000,
+    VaultTimelocked,
+    InvalidWithdrawDelay,
+    InvalidAmount,
+    WithdrawalAlreadyPending,
+    NoPendingWithdrawal,
+    WithdrawalStillLocked,
+    NotEnoughSigners,
+    InvalidMultisig,
+    InvalidWithdrawAuthority,
+    AllowanceExceeded,
+    WithdrawLimitExceeded,
+    InvalidWithdrawLimit,
+    ProgramPaused,
+    NotUpgradeAuthority,
+    InvalidAdmin,
+    InvalidPauseFlags,
+    InvalidFee,
+    InsufficientFees,
+    InvalidSystemProgram,
+    InvalidUserAccount,
+    InvalidVault,
+    UserMismatch,
+    InvalidAccountOwner,
+    DeserializationFailed,
+    InvalidConfig,
+    InvalidTreasury,
+    InvalidVaultTokenAccount,
+    InvalidTokenProgram,
+    InvalidAssociatedTokenProgram,
+    InvalidProgramData,
+    NotAdmin,
+    VaultBelowRentExempt,
+    InvalidStakeAccount,
+    InvalidStakeProgram,
+    StakeAccountInUse,
+}
+
+impl From<VaultError> for ProgramError {
+    fn from(e: VaultError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+// Upper bound on protocol fees, so a compromised admin cannot take a whole deposit
+const MAX_FEE_BPS: u16 = 1_000;
+
+// Basis points in one whole
+const BPS_DENOMINATOR: u128 = 10_000;
+
 pub fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
@@ -59,135 +558,352 @@ pub fn process_instruction(
     let instruction = ProgramInstruction::unpack(instruction_data)?;
 
     match instruction {
-        ProgramInstruction::Deposit { amount } => process_deposit(program_id, accounts, amount),
-        ProgramInstruction::Withdraw { amount } => process_withdraw(program_id, accounts, amount),
+        ProgramInstruction::Deposit { amount } => {
+            process_deposit(program_id, accounts, amount, None)
+        }
+        ProgramInstruction::Withdraw { amount } => {
+            process_withdraw(program_id, accounts, Some(amount))
+        }
+        ProgramInstruction::Close => process_close(program_id, accounts),
+        ProgramInstruction::DepositToken { amount } => {
+            process_deposit_token(program_id, accounts, amount)
+        }
+        ProgramInstruction::WithdrawToken {
+            amount,
+            signer_count,
+        } => process_withdraw_token(program_id, accounts, amount, signer_count),
+        ProgramInstruction::DepositWithDelay {
+            amount,
+            withdraw_delay,
+        } => process_deposit(program_id, accounts, amount, Some(withdraw_delay)),
+        ProgramInstruction::RequestWithdraw { amount } => {
+            process_request_withdraw(program_id, accounts, amount)
+        }
+        ProgramInstruction::ClaimWithdraw => process_claim_withdraw(program_id, accounts),
+        ProgramInstruction::CancelWithdraw => process_cancel_withdraw(program_id, accounts),
+        ProgramInstruction::SetMultisig { signers, threshold } => {
+            process_set_multisig(program_id, accounts, signers, threshold)
+        }
+        ProgramInstruction::SetWithdrawAuthority {
+            authority,
+            allowance,
+        } => process_set_withdraw_authority(program_id, accounts, authority, allowance),
+        ProgramInstruction::RevokeWithdrawAuthority => {
+            process_revoke_withdraw_authority(program_id, accounts)
+        }
+        ProgramInstruction::DelegateWithdraw { amount } => {
+            process_delegate_withdraw(program_id, accounts, amount)
+        }
+        ProgramInstruction::DepositFor {
+            beneficiary,
+            amount,
+        } => process_deposit_for(program_id, accounts, beneficiary, amount),
+        ProgramInstruction::SetWithdrawLimit { limit, window } => {
+            process_set_withdraw_limit(program_id, accounts, limit, window)
+        }
+        ProgramInstruction::InitializeConfig { admin } => {
+            process_initialize_config(program_id, accounts, admin)
+        }
+        ProgramInstruction::SetPaused {
+            deposits,
+            withdrawals,
+        } => process_set_paused(program_id, accounts, deposits, withdrawals),
+        ProgramInstruction::TransferAdmin { new_admin } => {
+            process_transfer_admin(program_id, accounts, new_admin)
+        }
+        ProgramInstruction::SetFees {
+            deposit_bps,
+            withdraw_bps,
+        } => process_set_fees(program_id, accounts, deposit_bps, withdraw_bps),
+        ProgramInstruction::WithdrawFees { amount } => {
+            process_withdraw_fees(program_id, accounts, amount)
+        }
+        ProgramInstruction::Migrate => process_migrate(program_id, accounts),
+        ProgramInstruction::WithdrawAll => process_withdraw(program_id, accounts, None),
+        ProgramInstruction::DelegateStake { amount } => {
+            process_delegate_stake(program_id, accounts, amount)
+        }
+        ProgramInstruction::DeactivateStake => process_deactivate_stake(program_id, accounts),
+        ProgramInstruction::WithdrawStake => process_withdraw_stake(program_id, accounts),
     }
 }
 
-// Process deposit instruction
-fn process_deposit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-    // We expect 4 accounts: user, user_data, vault, system_program
-    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info] =
+// Process deposit instruction, optionally setting the withdraw timelock on first deposit
+fn process_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    withdraw_delay: Option<i64>,
+) -> ProgramResult {
+    // We expect 6 accounts: user, user_data, vault, system_program, config, treasury
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info] =
         accounts
     else {
         return Err(ProgramError::NotEnoughAccountKeys);
     };
 
+    // Deposits are blocked while the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
     // Check that the user signed the transaction
     if !user_account_info.is_signer() {
         msg!("User must sign the transaction");
         return Err(ProgramError::MissingRequiredSignature);
     }
 
+    deposit_lamports(
+        program_id,
+        user_account_info,
+        user_account_info.key(),
+        user_data_account_info,
+        vault_account_info,
+        system_program_account_info,
+        treasury_account_info,
+        &config,
+        amount,
+        withdraw_delay,
+    )
+}
+
+// Process deposit for instruction, funding another user's vault without their signature
+fn process_deposit_for(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    beneficiary: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    // We expect 6 accounts: funder, user_data, vault, system_program, config, treasury
+    let [funder_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Deposits are blocked while the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
+    // Check that the funder signed the transaction
+    if !funder_account_info.is_signer() {
+        msg!("Funder must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    deposit_lamports(
+        program_id,
+        funder_account_info,
+        &beneficiary,
+        user_data_account_info,
+        vault_account_info,
+        system_program_account_info,
+        treasury_account_info,
+        &config,
+        amount,
+        None,
+    )
+}
+
+// Move lamports from the funder into the user's vault, creating the user data PDA
+// on first deposit. The PDAs are always derived from the user, who need not sign.
+#[allow(clippy::too_many_arguments)]
+fn deposit_lamports(
+    program_id: &Pubkey,
+    funder_account_info: &AccountInfo,
+    user: &Pubkey,
+    user_data_account_info: &AccountInfo,
+    vault_account_info: &AccountInfo,
+    system_program_account_info: &AccountInfo,
+    treasury_account_info: &AccountInfo,
+    config: &Config,
+    amount: u64,
+    withdraw_delay: Option<i64>,
+) -> ProgramResult {
     // Check that the system program is valid
     if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
         msg!("Invalid system program");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidSystemProgram.into());
     }
 
     // Find vault address and bump
-    let vault_seeds = &[b"vault", user_account_info.key().as_ref()];
+    let vault_seeds = &[b"vault", user.as_ref()];
     let (expected_vault_pubkey, vault_bump) = pubkey::find_program_address(vault_seeds, program_id);
 
     // Verify vault address
     if vault_account_info.key() != &expected_vault_pubkey {
         msg!("Invalid vault account address");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidVault.into());
+    }
+
+    // A negative delay would unlock withdrawals in the past
+    if withdraw_delay.is_some_and(|delay| delay < 0) {
+        msg!("Withdraw delay cannot be negative");
+        return Err(VaultError::InvalidWithdrawDelay.into());
     }
 
     // Initialize user data account if needed
     if user_data_account_info.owner() != program_id {
-        // Calculate rent for account
-        let rent = Rent::get()?;
-        let rent_lamports = rent.minimum_balance(UserAccount::SIZE);
-
-        // Create user data account using system program
-        let user_key_bytes = user_account_info.key().as_ref();
-        let user_seeds = &[user_key_bytes];
-        let (expected_user_data_pubkey, user_bump) =
-            pubkey::find_program_address(user_seeds, program_id);
-
-        // Check that provided user data account matches expected PDA
-        if user_data_account_info.key() != &expected_user_data_pubkey {
-            msg!("Invalid user data account address");
-            return Err(ProgramError::InvalidAccountData);
-        }
-
-        // Create seeds for PDA signing
-        let bump_bytes = [user_bump];
-        let seed1 = Seed::from(user_key_bytes);
-        let seed2 = Seed::from(&bump_bytes);
-        let seeds = [seed1, seed2];
-        let signer = Signer::from(&seeds);
-
-        // Create the account
-        CreateAccount {
-            from: user_account_info,
-            to: user_data_account_info,
-            lamports: rent_lamports,
-            space: UserAccount::SIZE as u64,
-            owner: program_id,
-        }
-        .invoke_signed(&[signer])?;
-
-        // Initialize user data account with vault info
-        let user_data = UserAccount {
-            user: *user_account_info.key(),
-            user_bump,
+        initialize_user_account(
+            program_id,
+            funder_account_info,
+            user,
+            user_data_account_info,
             vault_bump,
-            is_initialized: true,
-        };
-
-        // Serialize directly to the account data
+            withdraw_delay.unwrap_or(0),
+        )?;
+    } else {
+        // Only user data PDAs are owned by the program, so a matching user means this
+        // is the user's account, whose totals the deposit goes into. Baseline accounts
+        // are deposited into as they are.
         let mut data = user_data_account_info.try_borrow_mut_data()?;
-        user_data
-            .serialize(&mut &mut data[..])
-            .map_err(|_| ProgramError::InvalidAccountData)?;
+        let mut baseline = [0; UserAccount::SIZE];
+        let (user_data, _) = UserAccount::from_bytes_or_baseline_mut(&mut data, &mut baseline)?;
+        if user_data.user != *user {
+            msg!("User account does not belong to the requesting user");
+            return Err(VaultError::UserMismatch.into());
+        }
+        // The delay is fixed at first deposit
+        if withdraw_delay.is_some_and(|delay| delay != user_data.withdraw_delay()) {
+            msg!("Withdraw delay can only be set on first deposit");
+            return Err(VaultError::InvalidWithdrawDelay.into());
+        }
     }
 
-    // Transfer lamports to the vault using pinocchio_system
+    // Route the protocol fee to the treasury with a second transfer
+    let net_amount = collect_fee(
+        program_id,
+        funder_account_info,
+        treasury_account_info,
+        amount,
+        config.deposit_fee_bps(),
+        config.treasury_bump,
+        &[],
+    )?;
+
+    // Transfer the rest to the vault using pinocchio_system
     Transfer {
-        from: user_account_info,
+        from: funder_account_info,
         to: vault_account_info,
-        lamports: amount,
+        lamports: net_amount,
     }
     .invoke()?;
 
+    // Count the deposit in the user's lifetime totals
+    let mut data = user_data_account_info.try_borrow_mut_data()?;
+    record_deposit(&mut data, net_amount)?;
+    drop(data);
+
     msg!("Deposited to vault");
 
+    DepositEvent {
+        user: *user,
+        vault: *vault_account_info.key(),
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Create the user data PDA, paid for by the payer, and store the bumps on first deposit
+fn initialize_user_account(
+    program_id: &Pubkey,
+    payer_account_info: &AccountInfo,
+    user: &Pubkey,
+    user_data_account_info: &AccountInfo,
+    vault_bump: u8,
+    withdraw_delay: i64,
+) -> ProgramResult {
+    // Calculate rent for account
+    let rent = Rent::get()?;
+    let space = UserAccount::LEN;
+    let rent_lamports = rent.minimum_balance(space);
+
+    // Create user data account using system program
+    let user_key_bytes = user.as_ref();
+    let user_seeds = &[user_key_bytes];
+    let (expected_user_data_pubkey, user_bump) =
+        pubkey::find_program_address(user_seeds, program_id);
+
+    // Check that provided user data account matches expected PDA
+    if user_data_account_info.key() != &expected_user_data_pubkey {
+        msg!("Invalid user data account address");
+        return Err(VaultError::InvalidUserAccount.into());
+    }
+
+    // Create seeds for PDA signing
+    let bump_bytes = [user_bump];
+    let seed1 = Seed::from(user_key_bytes);
+    let seed2 = Seed::from(&bump_bytes);
+    let seeds = [seed1, seed2];
+    let signer = Signer::from(&seeds);
+
+    // Create the account
+    CreateAccount {
+        from: payer_account_info,
+        to: user_data_account_info,
+        lamports: rent_lamports,
+        space: space as u64,
+        owner: program_id,
+    }
+    .invoke_signed(&[signer])?;
+
+    // Initialize user data account with vault info, directly in the account data.
+    // The new account is zeroed, which leaves every other field unset.
+    let mut data = user_data_account_info.try_borrow_mut_data()?;
+    let user_data = UserAccount::init(&mut data)?;
+    user_data.user = *user;
+    user_data.user_bump = user_bump;
+    user_data.vault_bump = vault_bump;
+    user_data.set_withdraw_delay(withdraw_delay);
+
     Ok(())
 }
 
-// Process withdraw instruction
-fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-    // We expect 4 accounts: user, user_data, vault, system_program
-    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info] =
+// Process withdraw instruction, or withdraw the whole vault balance for WithdrawAll
+fn process_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: Option<u64>,
+) -> ProgramResult {
+    // We expect 6 accounts: user, user_data, vault, system_program, config, treasury,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info, multisig_signer_account_infos @ ..] =
         accounts
     else {
         return Err(ProgramError::NotEnoughAccountKeys);
     };
 
-    // Check that the user signed the transaction
-    if !user_account_info.is_signer() {
-        msg!("User must sign the transaction");
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
 
     // Check that the system program is valid
     if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
         msg!("Invalid system program");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidSystemProgram.into());
     }
 
-    // Read user data account
-    let data = user_data_account_info.try_borrow_data()?;
-    let user_data =
-        UserAccount::try_from_slice(&data).map_err(|_| ProgramError::InvalidAccountData)?;
+    // Baseline accounts are withdrawn from as they are
+    let mut data = load_user_account_any(program_id, user_account_info, user_data_account_info)?;
+    let mut baseline = [0; UserAccount::SIZE];
+    let (user_data, signers) = UserAccount::from_bytes_or_baseline_mut(&mut data, &mut baseline)?;
 
-    // Check that the user account belongs to the requesting user
-    if user_data.user != *user_account_info.key() {
-        msg!("User account does not belong to the requesting user");
-        return Err(ProgramError::InvalidAccountData);
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    // Timelocked vaults must go through RequestWithdraw and ClaimWithdraw
+    if user_data.withdraw_delay() > 0 {
+        msg!("Vault is timelocked, use RequestWithdraw instead");
+        return Err(VaultError::VaultTimelocked.into());
     }
 
     // Verify vault PDA
@@ -199,31 +915,2054 @@ fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64)
 
     let expected_vault = match pubkey::create_program_address(vault_seeds, program_id) {
         Ok(address) => address,
-        Err(_) => return Err(ProgramError::InvalidAccountData),
+        Err(_) => return Err(VaultError::InvalidVault.into()),
     };
 
     if vault_account_info.key() != &expected_vault {
         msg!("Invalid vault account address");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(VaultError::InvalidVault.into());
     }
 
-    // Create seeds for PDA signing
+    let vault_lamports = vault_account_info.lamports();
+    let amount = match amount {
+        Some(amount) => amount,
+        None if vault_lamports > 0 => vault_lamports,
+        None => {
+            msg!("Vault is empty");
+            return Err(VaultError::InsufficientFunds.into());
+        }
+    };
+
+    // Fail with a clear error instead of letting the system transfer run dry
+    if vault_lamports < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+    check_vault_remainder(vault_lamports - amount)?;
+
+    // Spend from the rate limit before moving funds
+    consume_withdraw_limit(user_data, amount)?;
     let vault_bump_bytes = [user_data.vault_bump];
+    record_withdraw(&mut data, amount)?;
+    drop(data);
+
+    // Create seeds for PDA signing
     let seed1 = Seed::from(b"vault");
     let seed2 = Seed::from(user_account_info.key().as_ref());
     let seed3 = Seed::from(&vault_bump_bytes);
     let seeds = [seed1, seed2, seed3];
     let signer = Signer::from(&seeds);
 
-    // Transfer lamports from vault to user with PDA signing
+    // Route the protocol fee to the treasury with a second signed transfer
+    let net_amount = collect_fee(
+        program_id,
+        vault_account_info,
+        treasury_account_info,
+        amount,
+        config.withdraw_fee_bps(),
+        config.treasury_bump,
+        &[Signer::from(&seeds)],
+    )?;
+
+    // Transfer the rest from vault to user with PDA signing
     Transfer {
         from: vault_account_info,
         to: user_account_info,
-        lamports: amount,
+        lamports: net_amount,
     }
     .invoke_signed(&[signer])?;
 
     msg!("Withdrew from vault");
 
+    WithdrawEvent {
+        user: *user_account_info.key(),
+        vault: *vault_account_info.key(),
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process close instruction
+fn process_close(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // We expect 6 accounts: user, user_data, vault, system_program, config, treasury,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+
+    // Draining the vault would bypass the timelock, so it must be emptied
+    // through RequestWithdraw and ClaimWithdraw first
+    let vault_lamports = vault_account_info.lamports();
+    if user_data.withdraw_delay() > 0 && vault_lamports > 0 {
+        msg!("Vault is timelocked, claim the remaining balance before closing");
+        return Err(VaultError::VaultTimelocked.into());
+    }
+
+    // Draining the vault counts against the rate limit like any other withdrawal
+    consume_withdraw_limit(user_data, vault_lamports)?;
+    let vault_bump_bytes = [user_data.vault_bump];
+    drop(data);
+
+    // Drain the vault back to the user with PDA signing, less the protocol fee
+    // which goes to the treasury
+    if vault_lamports > 0 {
+        let seed1 = Seed::from(b"vault");
+        let seed2 = Seed::from(user_account_info.key().as_ref());
+        let seed3 = Seed::from(&vault_bump_bytes);
+        let seeds = [seed1, seed2, seed3];
+        let signer = Signer::from(&seeds);
+
+        let net_lamports = collect_fee(
+            program_id,
+            vault_account_info,
+            treasury_account_info,
+            vault_lamports,
+            config.withdraw_fee_bps(),
+            config.treasury_bump,
+            &[Signer::from(&seeds)],
+        )?;
+
+        Transfer {
+            from: vault_account_info,
+            to: user_account_info,
+            lamports: net_lamports,
+        }
+        .invoke_signed(&[signer])?;
+
+        WithdrawEvent {
+            user: *user_account_info.key(),
+            vault: *vault_account_info.key(),
+            amount: vault_lamports,
+            fee: vault_lamports - net_lamports,
+            vault_balance: vault_account_info.lamports(),
+            slot: Clock::get()?.slot,
+        }
+        .emit()?;
+    }
+
+    // Return the user data account rent to the user
+    let rent_lamports = user_data_account_info.lamports();
+    let user_lamports = user_account_info
+        .lamports()
+        .checked_add(rent_lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    *user_account_info.try_borrow_mut_lamports()? = user_lamports;
+
+    // Zero the data, then clear the lamports, data length and owner so the
+    // account goes back to the system program and cannot be revived
+    user_data_account_info.try_borrow_mut_data()?.fill(0);
+    user_data_account_info.close()?;
+
+    msg!("Closed vault");
+
+    Ok(())
+}
+
+// Derive the associated token account address of a wallet for a mint
+fn find_associated_token_address(
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> (Pubkey, u8) {
+    pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+}
+
+// Process token deposit instruction
+fn process_deposit_token(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    // We expect 10 accounts: user, user_data, vault, mint, user_token, vault_token,
+    // token_program, associated_token_program, system_program, config, followed by any
+    // accounts required by a transfer hook
+    let [user_account_info, user_data_account_info, vault_account_info, mint_account_info, user_token_account_info, vault_token_account_info, token_program_account_info, associated_token_program_account_info, system_program_account_info, config_account_info, transfer_hook_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Deposits are blocked while the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
+    // Check that the user signed the transaction
+    if !user_account_info.is_signer() {
+        msg!("User must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    // Check that the token programs are valid
+    check_token_program(token_program_account_info, mint_account_info)?;
+
+    if associated_token_program_account_info.key() != &ASSOCIATED_TOKEN_PROGRAM_ID {
+        msg!("Invalid associated token program");
+        return Err(VaultError::InvalidAssociatedTokenProgram.into());
+    }
+
+    // Find vault address and bump
+    let vault_seeds = &[b"vault", user_account_info.key().as_ref()];
+    let (expected_vault_pubkey, vault_bump) = pubkey::find_program_address(vault_seeds, program_id);
+
+    // Verify vault address
+    if vault_account_info.key() != &expected_vault_pubkey {
+        msg!("Invalid vault account address");
+        return Err(VaultError::InvalidVault.into());
+    }
+
+    // The vault token account is the vault PDA's associated token account
+    let (expected_vault_token_pubkey, _) = find_associated_token_address(
+        vault_account_info.key(),
+        mint_account_info.key(),
+        token_program_account_info.key(),
+    );
+    if vault_token_account_info.key() != &expected_vault_token_pubkey {
+        msg!("Invalid vault token account address");
+        return Err(VaultError::InvalidVaultTokenAccount.into());
+    }
+
+    // Initialize user data account if needed
+    if user_data_account_info.owner() != program_id {
+        initialize_user_account(
+            program_id,
+            user_account_info,
+            user_account_info.key(),
+            user_data_account_info,
+            vault_bump,
+            0,
+        )?;
+    }
+
+    // Create the vault token account if it does not exist yet using the
+    // associated token program's CreateIdempotent instruction
+    if vault_token_account_info.data_is_empty() {
+        let account_metas = [
+            AccountMeta::writable_signer(user_account_info.key()),
+            AccountMeta::writable(vault_token_account_info.key()),
+            AccountMeta::readonly(vault_account_info.key()),
+            AccountMeta::readonly(mint_account_info.key()),
+            AccountMeta::readonly(system_program_account_info.key()),
+            AccountMeta::readonly(token_program_account_info.key()),
+        ];
+
+        let instruction = Instruction {
+            program_id: &ASSOCIATED_TOKEN_PROGRAM_ID,
+            accounts: &account_metas,
+            data: &[1],
+        };
+
+        invoke(
+            &instruction,
+            &[
+                user_account_info,
+                vault_token_account_info,
+                vault_account_info,
+                mint_account_info,
+                system_program_account_info,
+                token_program_account_info,
+            ],
+        )?;
+    }
+
+    // Read the mint decimals for the checked transfer
+    let decimals = mint_decimals(mint_account_info)?;
+
+    // Transfer tokens from the user to the vault, forwarding any transfer hook accounts
+    let balance_before = token_account_amount(vault_token_account_info)?;
+    transfer_checked(
+        token_program_account_info,
+        user_token_account_info,
+        mint_account_info,
+        vault_token_account_info,
+        user_account_info,
+        transfer_hook_account_infos,
+        amount,
+        decimals,
+        &[],
+    )?;
+
+    // Transfer fees are withheld in the vault token account, so record what
+    // actually arrived rather than the requested amount
+    let vault_balance = token_account_amount(vault_token_account_info)?;
+    let received = vault_balance
+        .checked_sub(balance_before)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    if received != amount {
+        msg!("Transfer fee withheld from deposit");
+    }
+
+    msg!("Deposited tokens to vault");
+
+    TokenDepositEvent {
+        user: *user_account_info.key(),
+        vault: *vault_account_info.key(),
+        mint: *mint_account_info.key(),
+        amount,
+        received,
+        vault_balance,
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process token withdraw instruction
+fn process_withdraw_token(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    signer_count: u8,
+) -> ProgramResult {
+    // We expect 8 accounts: user, user_data, vault, mint, user_token, vault_token, token_program,
+    // config, followed by signer_count multisig signers and any accounts required by a
+    // transfer hook
+    let [user_account_info, user_data_account_info, vault_account_info, mint_account_info, user_token_account_info, vault_token_account_info, token_program_account_info, config_account_info, remaining_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // The first signer_count extra accounts are multisig members, the rest are passed
+    // on to the token program for a transfer hook
+    if remaining_account_infos.len() < signer_count as usize {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let (multisig_signer_account_infos, transfer_hook_account_infos) =
+        remaining_account_infos.split_at(signer_count as usize);
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the token program is valid
+    check_token_program(token_program_account_info, mint_account_info)?;
+
+    let data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes(&data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+    let vault_bump_bytes = [user_data.vault_bump];
+    drop(data);
+
+    // The vault token account is the vault PDA's associated token account
+    let (expected_vault_token_pubkey, _) = find_associated_token_address(
+        vault_account_info.key(),
+        mint_account_info.key(),
+        token_program_account_info.key(),
+    );
+    if vault_token_account_info.key() != &expected_vault_token_pubkey {
+        msg!("Invalid vault token account address");
+        return Err(VaultError::InvalidVaultTokenAccount.into());
+    }
+
+    // Read the mint decimals for the checked transfer
+    let decimals = mint_decimals(mint_account_info)?;
+
+    // Create seeds for PDA signing
+    let seed1 = Seed::from(b"vault");
+    let seed2 = Seed::from(user_account_info.key().as_ref());
+    let seed3 = Seed::from(&vault_bump_bytes);
+    let seeds = [seed1, seed2, seed3];
+    let signer = Signer::from(&seeds);
+
+    // Transfer tokens from the vault to the user with PDA signing
+    transfer_checked(
+        token_program_account_info,
+        vault_token_account_info,
+        mint_account_info,
+        user_token_account_info,
+        vault_account_info,
+        transfer_hook_account_infos,
+        amount,
+        decimals,
+        &[signer],
+    )?;
+
+    msg!("Withdrew tokens from vault");
+
+    Ok(())
+}
+
+// Accept either the Token or Token-2022 program, as long as it owns the mint
+fn check_token_program(
+    token_program_account_info: &AccountInfo,
+    mint_account_info: &AccountInfo,
+) -> ProgramResult {
+    if token_program_account_info.key() != &TOKEN_PROGRAM_ID
+        && token_program_account_info.key() != &TOKEN_2022_PROGRAM_ID
+    {
+        msg!("Invalid token program");
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+
+    if mint_account_info.owner() != token_program_account_info.key() {
+        msg!("Mint is not owned by the token program");
+        return Err(VaultError::InvalidTokenProgram.into());
+    }
+
+    Ok(())
+}
+
+// Both token programs share the base mint layout, with decimals at offset 44
+fn mint_decimals(mint_account_info: &AccountInfo) -> Result<u8, ProgramError> {
+    let data = mint_account_info.try_borrow_data()?;
+    if data.len() < 82 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(data[44])
+}
+
+// Both token programs share the base account layout, with the amount at offset 64
+fn token_account_amount(token_account_info: &AccountInfo) -> Result<u64, ProgramError> {
+    let data = token_account_info.try_borrow_data()?;
+    if data.len() < 165 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(u64::from_le_bytes(data[64..72].try_into().unwrap()))
+}
+
+// TransferChecked CPI into whichever token program owns the mint. The
+// instruction layout is shared by Token and Token-2022, and any extra
+// accounts are appended so Token-2022 can resolve the mint's transfer hook.
+#[allow(clippy::too_many_arguments)]
+fn transfer_checked(
+    token_program_account_info: &AccountInfo,
+    from_account_info: &AccountInfo,
+    mint_account_info: &AccountInfo,
+    to_account_info: &AccountInfo,
+    authority_account_info: &AccountInfo,
+    transfer_hook_account_infos: &[AccountInfo],
+    amount: u64,
+    decimals: u8,
+    signers: &[Signer],
+) -> ProgramResult {
+    // Instruction data: [12 (TransferChecked), amount (u64 LE), decimals (u8)]
+    let mut instruction_data = [0u8; 10];
+    instruction_data[0] = 12;
+    instruction_data[1..9].copy_from_slice(&amount.to_le_bytes());
+    instruction_data[9] = decimals;
+
+    let mut account_metas = Vec::with_capacity(4 + transfer_hook_account_infos.len());
+    account_metas.push(AccountMeta::writable(from_account_info.key()));
+    account_metas.push(AccountMeta::readonly(mint_account_info.key()));
+    account_metas.push(AccountMeta::writable(to_account_info.key()));
+    account_metas.push(AccountMeta::readonly_signer(authority_account_info.key()));
+
+    let mut cpi_accounts = Vec::with_capacity(4 + transfer_hook_account_infos.len());
+    cpi_accounts.push(Account::from(from_account_info));
+    cpi_accounts.push(Account::from(mint_account_info));
+    cpi_accounts.push(Account::from(to_account_info));
+    cpi_accounts.push(Account::from(authority_account_info));
+
+    for account_info in transfer_hook_account_infos {
+        account_metas.push(AccountMeta::new(
+            account_info.key(),
+            account_info.is_writable(),
+            false,
+        ));
+        cpi_accounts.push(Account::from(account_info));
+    }
+
+    let instruction = Instruction {
+        program_id: token_program_account_info.key(),
+        accounts: &account_metas,
+        data: &instruction_data,
+    };
+
+    // SAFETY: no account data or lamports are borrowed across the CPI
+    unsafe { invoke_signed_unchecked(&instruction, &cpi_accounts, signers) };
+
+    Ok(())
+}
+
+// Process request withdraw instruction
+fn process_request_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    // We expect 2 accounts: user, user_data,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    if amount == 0 {
+        msg!("Withdraw amount must be greater than zero");
+        return Err(VaultError::InvalidAmount.into());
+    }
+
+    // Only one withdrawal can be pending at a time
+    if user_data.pending_withdrawal() > 0 {
+        msg!("A withdrawal is already pending");
+        return Err(VaultError::WithdrawalAlreadyPending.into());
+    }
+
+    // Start the timelock from the current cluster time
+    let clock = Clock::get()?;
+    user_data.set_pending_withdrawal(amount);
+    user_data.set_unlock_timestamp(
+        clock
+            .unix_timestamp
+            .checked_add(user_data.withdraw_delay())
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+    );
+
+    msg!("Requested withdrawal from vault");
+
+    Ok(())
+}
+
+// Process claim withdraw instruction
+fn process_claim_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // We expect 6 accounts: user, user_data, vault, system_program, config, treasury,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+
+    let amount = user_data.pending_withdrawal();
+    if amount == 0 {
+        msg!("No pending withdrawal");
+        return Err(VaultError::NoPendingWithdrawal.into());
+    }
+
+    // The timelock must have expired
+    let clock = Clock::get()?;
+    if clock.unix_timestamp < user_data.unlock_timestamp() {
+        msg!("Withdrawal is still timelocked");
+        return Err(VaultError::WithdrawalStillLocked.into());
+    }
+
+    // Fail with a clear error instead of letting the system transfer run dry
+    if vault_account_info.lamports() < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+    check_vault_remainder(vault_account_info.lamports() - amount)?;
+
+    // Clear the pending withdrawal and spend from the rate limit before moving funds
+    consume_withdraw_limit(user_data, amount)?;
+    user_data.set_pending_withdrawal(0);
+    user_data.set_unlock_timestamp(0);
+    let vault_bump_bytes = [user_data.vault_bump];
+    record_withdraw(&mut data, amount)?;
+    drop(data);
+
+    // Create seeds for PDA signing
+    let seed1 = Seed::from(b"vault");
+    let seed2 = Seed::from(user_account_info.key().as_ref());
+    let seed3 = Seed::from(&vault_bump_bytes);
+    let seeds = [seed1, seed2, seed3];
+    let signer = Signer::from(&seeds);
+
+    // Route the protocol fee to the treasury with a second signed transfer
+    let net_amount = collect_fee(
+        program_id,
+        vault_account_info,
+        treasury_account_info,
+        amount,
+        config.withdraw_fee_bps(),
+        config.treasury_bump,
+        &[Signer::from(&seeds)],
+    )?;
+
+    // Transfer the rest from vault to user with PDA signing
+    Transfer {
+        from: vault_account_info,
+        to: user_account_info,
+        lamports: net_amount,
+    }
+    .invoke_signed(&[signer])?;
+
+    msg!("Claimed withdrawal from vault");
+
+    WithdrawEvent {
+        user: *user_account_info.key(),
+        vault: *vault_account_info.key(),
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process cancel withdraw instruction
+fn process_cancel_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // We expect 2 accounts: user, user_data,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    if user_data.pending_withdrawal() == 0 {
+        msg!("No pending withdrawal");
+        return Err(VaultError::NoPendingWithdrawal.into());
+    }
+
+    user_data.set_pending_withdrawal(0);
+    user_data.set_unlock_timestamp(0);
+
+    msg!("Cancelled pending withdrawal");
+
+    Ok(())
+}
+
+// Process set multisig instruction, replacing the signer set and threshold
+fn process_set_multisig(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    signers: &[Pubkey],
+    threshold: u8,
+) -> ProgramResult {
+    // We expect 4 accounts: payer, user, user_data, system_program,
+    // followed by any multisig signers. The payer funds the extra rent when the
+    // account grows and receives the excess when it shrinks.
+    let [payer_account_info, user_account_info, user_data_account_info, system_program_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Check that the payer signed the transaction
+    if !payer_account_info.is_signer() {
+        msg!("Payer must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, current_signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // Membership changes need the same approval as a withdrawal
+    check_authority(
+        user_data,
+        current_signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    // An empty signer set with a zero threshold hands control back to the user
+    if signers.len() > MAX_MULTISIG_SIGNERS {
+        msg!("Too many multisig signers");
+        return Err(VaultError::InvalidMultisig.into());
+    }
+    if (threshold == 0) != signers.is_empty() || threshold as usize > signers.len() {
+        msg!("Threshold must be between 1 and the number of signers");
+        return Err(VaultError::InvalidMultisig.into());
+    }
+    for (i, signer) in signers.iter().enumerate() {
+        if signers[..i].contains(signer) {
+            msg!("Duplicate multisig signer");
+            return Err(VaultError::InvalidMultisig.into());
+        }
+    }
+
+    // Update the count ahead of the members themselves, which only fit once the
+    // account has been resized. The totals behind the members move with them.
+    let totals = UserAccount::totals_mut(&mut data)?.map(|totals| *totals);
+    let (user_data, _) = UserAccount::from_bytes_mut(&mut data)?;
+    user_data.threshold = threshold;
+    user_data.set_signers_len(signers.len());
+    let new_size = user_data.size();
+    drop(data);
+
+    // Resize the account to fit the new signer set and keep it rent exempt
+    let rent_lamports = Rent::get()?.minimum_balance(new_size);
+    let current_lamports = user_data_account_info.lamports();
+    if rent_lamports > current_lamports {
+        Transfer {
+            from: payer_account_info,
+            to: user_data_account_info,
+            lamports: rent_lamports - current_lamports,
+        }
+        .invoke()?;
+    }
+    user_data_account_info.realloc(new_size, false)?;
+    if current_lamports > rent_lamports {
+        let excess = current_lamports - rent_lamports;
+        *user_data_account_info.try_borrow_mut_lamports()? = rent_lamports;
+        let mut payer_lamports = payer_account_info.try_borrow_mut_lamports()?;
+        *payer_lamports = payer_lamports
+            .checked_add(excess)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    let mut data = user_data_account_info.try_borrow_mut_data()?;
+    UserAccount::from_bytes_mut(&mut data)?
+        .1
+        .copy_from_slice(signers);
+    if let (Some(totals), Some(moved)) = (totals, UserAccount::totals_mut(&mut data)?) {
+        *moved = totals;
+    }
+
+    msg!("Updated multisig signers");
+
+    Ok(())
+}
+
+// Process set withdraw authority instruction, delegating withdraw rights to another key
+fn process_set_withdraw_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority: Pubkey,
+    allowance: u64,
+) -> ProgramResult {
+    // We expect 2 accounts: user, user_data,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // Delegating needs the same approval as a withdrawal
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    if authority == Pubkey::default() {
+        msg!("Use RevokeWithdrawAuthority to remove the withdraw authority");
+        return Err(VaultError::InvalidWithdrawAuthority.into());
+    }
+
+    // Replacing the delegate also resets its allowance, u64::MAX leaves it uncapped
+    user_data.withdraw_authority = authority;
+    user_data.set_withdraw_allowance(allowance);
+
+    msg!("Set withdraw authority");
+
+    Ok(())
+}
+
+// Process revoke withdraw authority instruction
+fn process_revoke_withdraw_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // We expect 2 accounts: user, user_data,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    if user_data.withdraw_authority == Pubkey::default() {
+        msg!("No withdraw authority set");
+        return Err(VaultError::InvalidWithdrawAuthority.into());
+    }
+
+    user_data.withdraw_authority = Pubkey::default();
+    user_data.set_withdraw_allowance(0);
+
+    msg!("Revoked withdraw authority");
+
+    Ok(())
+}
+
+// Process delegate withdraw instruction, paying out to the withdraw authority
+fn process_delegate_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    // We expect 7 accounts: authority, user, user_data, vault, system_program, config,
+    // treasury
+    let [authority_account_info, user_account_info, user_data_account_info, vault_account_info, system_program_account_info, config_account_info, treasury_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Withdrawals stay open during a pause unless the admin also paused them
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, true)?;
+
+    // Check that the withdraw authority signed the transaction
+    if !authority_account_info.is_signer() {
+        msg!("Withdraw authority must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, _) = UserAccount::from_bytes_mut(&mut data)?;
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+
+    // Only the current delegate may withdraw
+    if user_data.withdraw_authority == Pubkey::default()
+        || user_data.withdraw_authority != *authority_account_info.key()
+    {
+        msg!("Signer is not the withdraw authority");
+        return Err(VaultError::InvalidWithdrawAuthority.into());
+    }
+
+    // Timelocked vaults must go through RequestWithdraw and ClaimWithdraw
+    if user_data.withdraw_delay() > 0 {
+        msg!("Vault is timelocked, use RequestWithdraw instead");
+        return Err(VaultError::VaultTimelocked.into());
+    }
+
+    // Fail with a clear error instead of letting the system transfer run dry
+    if vault_account_info.lamports() < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+    check_vault_remainder(vault_account_info.lamports() - amount)?;
+
+    // Spend from the allowance unless the delegate is uncapped
+    let allowance = user_data.withdraw_allowance();
+    if allowance != u64::MAX {
+        if amount > allowance {
+            msg!("Amount exceeds the withdraw allowance");
+            return Err(VaultError::AllowanceExceeded.into());
+        }
+        user_data.set_withdraw_allowance(allowance - amount);
+    }
+
+    // Delegated withdrawals share the vault's rate limit
+    consume_withdraw_limit(user_data, amount)?;
+    let vault_bump_bytes = [user_data.vault_bump];
+    record_withdraw(&mut data, amount)?;
+    drop(data);
+
+    // The vault is still seeded on the depositing user
+    let seed1 = Seed::from(b"vault");
+    let seed2 = Seed::from(user_account_info.key().as_ref());
+    let seed3 = Seed::from(&vault_bump_bytes);
+    let seeds = [seed1, seed2, seed3];
+    let signer = Signer::from(&seeds);
+
+    // Route the protocol fee to the treasury with a second signed transfer
+    let net_amount = collect_fee(
+        program_id,
+        vault_account_info,
+        treasury_account_info,
+        amount,
+        config.withdraw_fee_bps(),
+        config.treasury_bump,
+        &[Signer::from(&seeds)],
+    )?;
+
+    // Transfer the rest from vault to the delegate with PDA signing
+    Transfer {
+        from: vault_account_info,
+        to: authority_account_info,
+        lamports: net_amount,
+    }
+    .invoke_signed(&[signer])?;
+
+    msg!("Delegate withdrew from vault");
+
+    WithdrawEvent {
+        user: *user_account_info.key(),
+        vault: *vault_account_info.key(),
+        amount,
+        fee: amount - net_amount,
+        vault_balance: vault_account_info.lamports(),
+        slot: Clock::get()?.slot,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+// Process set withdraw limit instruction. Tightening the limit applies immediately,
+// loosening it only once the current window length has passed.
+fn process_set_withdraw_limit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    limit: u64,
+    window: i64,
+) -> ProgramResult {
+    let [user_account_info, user_data_account_info, multisig_signer_account_infos @ ..] = accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes_mut(&mut data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    // A limit needs a positive window, and no limit needs no window
+    if (limit == 0 && window != 0) || (limit > 0 && window <= 0) {
+        msg!("Withdraw window must be positive when a limit is set");
+        return Err(VaultError::InvalidWithdrawLimit.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    apply_pending_withdraw_limit(user_data, now);
+
+    // A lower or equal limit over an equal or longer window never allows more
+    let tightens = limit > 0
+        && (user_data.withdraw_limit() == 0 || limit <= user_data.withdraw_limit())
+        && window >= user_data.withdraw_window();
+
+    if tightens {
+        // Also drops any pending raise
+        user_data.set_withdraw_limit(limit);
+        user_data.set_withdraw_window(window);
+        user_data.set_pending_withdraw_limit(0);
+        user_data.set_pending_withdraw_window(0);
+        user_data.set_limit_unlock_timestamp(0);
+        msg!("Withdraw limit set");
+    } else {
+        // Wait out a full window so a stolen key cannot lift the limit and drain at once
+        user_data.set_pending_withdraw_limit(limit);
+        user_data.set_pending_withdraw_window(window);
+        user_data.set_limit_unlock_timestamp(
+            now.checked_add(user_data.withdraw_window())
+                .ok_or(ProgramError::ArithmeticOverflow)?,
+        );
+        msg!("Withdraw limit change queued");
+    }
+
+    Ok(())
+}
+
+// Promote a pending withdraw limit once its delay has passed
+fn apply_pending_withdraw_limit(user_data: &mut UserAccount, now: i64) {
+    let unlock_timestamp = user_data.limit_unlock_timestamp();
+    if unlock_timestamp != 0 && now >= unlock_timestamp {
+        user_data.set_withdraw_limit(user_data.pending_withdraw_limit());
+        user_data.set_withdraw_window(user_data.pending_withdraw_window());
+        user_data.set_pending_withdraw_limit(0);
+        user_data.set_pending_withdraw_window(0);
+        user_data.set_limit_unlock_timestamp(0);
+    }
+}
+
+// Record a withdrawal against the rate limit, starting a new window when the current one
+// has elapsed. The user data is updated in place.
+fn consume_withdraw_limit(user_data: &mut UserAccount, amount: u64) -> ProgramResult {
+    let now = Clock::get()?.unix_timestamp;
+    apply_pending_withdraw_limit(user_data, now);
+
+    if user_data.withdraw_limit() == 0 {
+        return Ok(());
+    }
+
+    if now
+        >= user_data
+            .window_start()
+            .saturating_add(user_data.withdraw_window())
+    {
+        user_data.set_window_start(now);
+        user_data.set_window_withdrawn(0);
+    }
+
+    let remaining = user_data
+        .withdraw_limit()
+        .saturating_sub(user_data.window_withdrawn());
+    if amount > remaining {
+        msg!("Withdrawal exceeds the remaining limit");
+        return Err(VaultError::WithdrawLimitExceeded.into());
+    }
+    user_data.set_window_withdrawn(user_data.window_withdrawn() + amount);
+
+    Ok(())
+}
+
+// Count a deposit of lamports credited to the vault in the lifetime totals, in place.
+// Baseline accounts have no room for the totals until they are migrated.
+fn record_deposit(data: &mut [u8], amount: u64) -> ProgramResult {
+    let Some(totals) = UserAccount::totals_mut(data)? else {
+        return Ok(());
+    };
+    let total_deposited = totals
+        .total_deposited()
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let deposit_count = totals
+        .deposit_count()
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    totals.set_total_deposited(total_deposited);
+    totals.set_deposit_count(deposit_count);
+    totals.last_deposit_slot = Clock::get()?.slot.to_le_bytes();
+
+    Ok(())
+}
+
+// Count lamports taken out of the vault in the lifetime totals, in place
+fn record_withdraw(data: &mut [u8], amount: u64) -> ProgramResult {
+    let Some(totals) = UserAccount::totals_mut(data)? else {
+        return Ok(());
+    };
+    let total_withdrawn = totals
+        .total_withdrawn()
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    totals.set_total_withdrawn(total_withdrawn);
+    totals.last_withdraw_slot = Clock::get()?.slot.to_le_bytes();
+
+    Ok(())
+}
+
+// Process initialize config instruction, only the program's upgrade authority may
+// create the config and pick the first admin
+fn process_initialize_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    admin: Pubkey,
+) -> ProgramResult {
+    let [upgrade_authority_account_info, config_account_info, program_data_account_info, system_program_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Check that the upgrade authority signed the transaction
+    if !upgrade_authority_account_info.is_signer() {
+        msg!("Upgrade authority must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    // The signer must be the authority recorded in this program's ProgramData account
+    let upgrade_authority = read_upgrade_authority(program_id, program_data_account_info)?;
+    if upgrade_authority != Some(*upgrade_authority_account_info.key()) {
+        msg!("Signer is not the program upgrade authority");
+        return Err(VaultError::NotUpgradeAuthority.into());
+    }
+
+    if admin == Pubkey::default() {
+        msg!("Admin cannot be the default pubkey");
+        return Err(VaultError::InvalidAdmin.into());
+    }
+
+    // Derive and verify the config PDA
+    let (expected_config_pubkey, config_bump) =
+        pubkey::find_program_address(&[b"config"], program_id);
+    if config_account_info.key() != &expected_config_pubkey {
+        msg!("Invalid config account address");
+        return Err(VaultError::InvalidConfig.into());
+    }
+    if config_account_info.owner() == program_id {
+        msg!("Config is already initialized");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    // Create seeds for PDA signing
+    let bump_bytes = [config_bump];
+    let seed1 = Seed::from(b"config");
+    let seed2 = Seed::from(&bump_bytes);
+    let seeds = [seed1, seed2];
+    let signer = Signer::from(&seeds);
+
+    // Create the config account, paid for by the upgrade authority
+    let rent = Rent::get()?;
+    CreateAccount {
+        from: upgrade_authority_account_info,
+        to: config_account_info,
+        lamports: rent.minimum_balance(Config::LEN),
+        space: Config::LEN as u64,
+        owner: program_id,
+    }
+    .invoke_signed(&[signer])?;
+
+    // Fees start disabled, the treasury bump is stored so later checks stay cheap
+    let (_, treasury_bump) = pubkey::find_program_address(&[b"treasury"], program_id);
+    let config = Config {
+        admin,
+        bump: config_bump,
+        treasury_bump,
+        ..Config::default()
+    };
+    save_config(&config, config_account_info)?;
+
+    msg!("Initialized config");
+
+    Ok(())
+}
+
+// Process set paused instruction
+fn process_set_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    deposits: bool,
+    withdrawals: bool,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    // Withdrawals can only be paused on top of deposits, never on their own
+    if withdrawals && !deposits {
+        msg!("Withdrawals can only be paused together with deposits");
+        return Err(VaultError::InvalidPauseFlags.into());
+    }
+
+    config.set_paused(deposits, withdrawals);
+    save_config(&config, config_account_info)?;
+
+    msg!("Updated pause flags");
+
+    Ok(())
+}
+
+// Process transfer admin instruction
+fn process_transfer_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    if new_admin == Pubkey::default() {
+        msg!("Admin cannot be the default pubkey");
+        return Err(VaultError::InvalidAdmin.into());
+    }
+
+    config.admin = new_admin;
+    save_config(&config, config_account_info)?;
+
+    msg!("Transferred admin");
+
+    Ok(())
+}
+
+// Process set fees instruction. The treasury is topped up to the rent-exempt minimum
+// here, so that fees smaller than that can still be paid into it.
+fn process_set_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    deposit_bps: u16,
+    withdraw_bps: u16,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info, treasury_account_info, system_program_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let mut config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    if deposit_bps > MAX_FEE_BPS || withdraw_bps > MAX_FEE_BPS {
+        msg!("Fee exceeds the maximum");
+        return Err(VaultError::InvalidFee.into());
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    check_treasury_address(program_id, treasury_account_info, config.treasury_bump)?;
+
+    let rent_lamports = Rent::get()?.minimum_balance(0);
+    let treasury_lamports = treasury_account_info.lamports();
+    if treasury_lamports < rent_lamports {
+        Transfer {
+            from: admin_account_info,
+            to: treasury_account_info,
+            lamports: rent_lamports - treasury_lamports,
+        }
+        .invoke()?;
+    }
+
+    config.set_deposit_fee_bps(deposit_bps);
+    config.set_withdraw_fee_bps(withdraw_bps);
+    save_config(&config, config_account_info)?;
+
+    msg!("Set fees");
+
+    Ok(())
+}
+
+// Process withdraw fees instruction, paying collected fees out to the admin
+fn process_withdraw_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let [admin_account_info, config_account_info, treasury_account_info, system_program_account_info] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    let config = load_config(program_id, admin_account_info, config_account_info)?;
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    check_treasury_address(program_id, treasury_account_info, config.treasury_bump)?;
+
+    // The treasury keeps its rent-exempt minimum so it can keep receiving fees
+    let rent_lamports = Rent::get()?.minimum_balance(0);
+    let available = treasury_account_info
+        .lamports()
+        .saturating_sub(rent_lamports);
+    if amount > available {
+        msg!("Insufficient fees in treasury");
+        return Err(VaultError::InsufficientFees.into());
+    }
+
+    // Create seeds for PDA signing
+    let bump_bytes = [config.treasury_bump];
+    let seed1 = Seed::from(b"treasury");
+    let seed2 = Seed::from(&bump_bytes);
+    let seeds = [seed1, seed2];
+    let signer = Signer::from(&seeds);
+
+    Transfer {
+        from: treasury_account_info,
+        to: admin_account_info,
+        lamports: amount,
+    }
+    .invoke_signed(&[signer])?;
+
+    msg!("Withdrew fees");
+
+    Ok(())
+}
+
+// Rewrite a baseline user account, which holds nothing but the user and the bumps, in
+// the current layout. Deposits and withdrawals accept baseline accounts as they are,
+// but they only record the lifetime totals once migrated, and every other instruction
+// needs the fields added since. The account grows to the current size, with the user
+// covering the extra rent. The new fields and the totals start from zero.
+fn process_migrate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // We expect 3 accounts: user, user_data, system_program
+    let [user_account_info, user_data_account_info, system_program_account_info] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // The user pays for the larger account
+    if !user_account_info.is_signer() {
+        msg!("User must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Check that the system program is valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+
+    let data = load_user_account_any(program_id, user_account_info, user_data_account_info)?;
+
+    // Migrating twice is harmless
+    if !UserAccount::is_baseline(&data) {
+        msg!("User account is already up to date");
+        return Ok(());
+    }
+    drop(data);
+
+    // Grow the account for the new fields and keep it rent exempt
+    let new_size = UserAccount::LEN;
+    let rent_lamports = Rent::get()?.minimum_balance(new_size);
+    let current_lamports = user_data_account_info.lamports();
+    if rent_lamports > current_lamports {
+        Transfer {
+            from: user_account_info,
+            to: user_data_account_info,
+            lamports: rent_lamports - current_lamports,
+        }
+        .invoke()?;
+    }
+    user_data_account_info.realloc(new_size, false)?;
+
+    // Move the user and the bumps behind the header, zero everything that now follows
+    // them and stamp the header with the new version
+    let mut data = user_data_account_info.try_borrow_mut_data()?;
+    let header_size = USER_ACCOUNT_DISCRIMINATOR.len();
+    data.copy_within(..UserAccount::BASELINE_SIZE, header_size);
+    data[header_size + UserAccount::BASELINE_SIZE..].fill(0);
+    UserAccount::write_header(&mut data);
+
+    msg!("Migrated user account");
+
+    Ok(())
+}
+
+// Process delegate stake instruction, moving lamports from the vault into a stake
+// account owned by the vault and delegating it to a validator. The stake instructions
+// are bincode encoded: a u32 variant tag followed by the fields.
+fn process_delegate_stake(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    // We expect 12 accounts: user, user_data, vault, stake, vote, clock, rent,
+    // stake_history, stake_config, system_program, stake_program, config, followed by
+    // any multisig signers
+    let [user_account_info, user_data_account_info, vault_account_info, stake_account_info, vote_account_info, clock_sysvar_account_info, rent_sysvar_account_info, stake_history_sysvar_account_info, stake_config_account_info, system_program_account_info, stake_program_account_info, config_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    // Staking moves lamports out of the vault, so it stops whenever the program is paused
+    let config = read_config(program_id, config_account_info)?;
+    check_not_paused(&config, false)?;
+
+    // Check that the system and stake programs are valid
+    if system_program_account_info.key() != &SYSTEM_PROGRAM_ID {
+        msg!("Invalid system program");
+        return Err(VaultError::InvalidSystemProgram.into());
+    }
+    check_stake_program(stake_program_account_info)?;
+
+    let data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes(&data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+    let stake_bump = check_stake_address(program_id, vault_account_info, stake_account_info)?;
+
+    // A vault stakes through a single account, which must be withdrawn before the
+    // next delegation
+    if !stake_account_info.data_is_empty() {
+        msg!("Stake account already in use");
+        return Err(VaultError::StakeAccountInUse.into());
+    }
+
+    if amount == 0 {
+        msg!("Amount must be greater than zero");
+        return Err(VaultError::InvalidAmount.into());
+    }
+
+    // Lamports requested for a pending withdrawal stay in the vault
+    let vault_lamports = vault_account_info.lamports();
+    if vault_lamports.saturating_sub(user_data.pending_withdrawal()) < amount {
+        msg!("Insufficient funds in vault");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+    check_vault_remainder(vault_lamports - amount)?;
+
+    let vault_bump_bytes = [user_data.vault_bump];
+    drop(data);
+
+    // Create seeds for PDA signing
+    let stake_bump_bytes = [stake_bump];
+    let vault_seeds = [
+        Seed::from(b"vault"),
+        Seed::from(user_account_info.key().as_ref()),
+        Seed::from(&vault_bump_bytes),
+    ];
+    let stake_seeds = [
+        Seed::from(b"stake"),
+        Seed::from(vault_account_info.key().as_ref()),
+        Seed::from(&stake_bump_bytes),
+    ];
+
+    // Create the stake account with the lamports from the vault. The stake program
+    // keeps its rent-exempt reserve out of the delegation.
+    if stake_account_info.lamports() == 0 {
+        CreateAccount {
+            from: vault_account_info,
+            to: stake_account_info,
+            lamports: amount,
+            space: STAKE_ACCOUNT_SIZE,
+            owner: &STAKE_PROGRAM_ID,
+        }
+        .invoke_signed(&[Signer::from(&vault_seeds), Signer::from(&stake_seeds)])?;
+    } else {
+        // Anyone can send lamports to the predictable address, which would make
+        // CreateAccount fail forever. Top it up and take it over step by step instead.
+        Transfer {
+            from: vault_account_info,
+            to: stake_account_info,
+            lamports: amount,
+        }
+        .invoke_signed(&[Signer::from(&vault_seeds)])?;
+        Allocate {
+            account: stake_account_info,
+            space: STAKE_ACCOUNT_SIZE,
+        }
+        .invoke_signed(&[Signer::from(&stake_seeds)])?;
+        Assign {
+            account: stake_account_info,
+            owner: &STAKE_PROGRAM_ID,
+        }
+        .invoke_signed(&[Signer::from(&stake_seeds)])?;
+    }
+
+    // Initialize with the vault as staker and withdrawer and no lockup:
+    // tag 0, staker, withdrawer, lockup timestamp, epoch and custodian
+    let mut initialize_data = [0u8; 116];
+    initialize_data[4..36].copy_from_slice(vault_account_info.key());
+    initialize_data[36..68].copy_from_slice(vault_account_info.key());
+    let account_metas = [
+        AccountMeta::writable(stake_account_info.key()),
+        AccountMeta::readonly(rent_sysvar_account_info.key()),
+    ];
+    invoke(
+        &Instruction {
+            program_id: &STAKE_PROGRAM_ID,
+            accounts: &account_metas,
+            data: &initialize_data,
+        },
+        &[stake_account_info, rent_sysvar_account_info],
+    )?;
+
+    // Delegate to the vote account: tag 2
+    let account_metas = [
+        AccountMeta::writable(stake_account_info.key()),
+        AccountMeta::readonly(vote_account_info.key()),
+        AccountMeta::readonly(clock_sysvar_account_info.key()),
+        AccountMeta::readonly(stake_history_sysvar_account_info.key()),
+        AccountMeta::readonly(stake_config_account_info.key()),
+        AccountMeta::readonly_signer(vault_account_info.key()),
+    ];
+    invoke_signed(
+        &Instruction {
+            program_id: &STAKE_PROGRAM_ID,
+            accounts: &account_metas,
+            data: &2u32.to_le_bytes(),
+        },
+        &[
+            stake_account_info,
+            vote_account_info,
+            clock_sysvar_account_info,
+            stake_history_sysvar_account_info,
+            stake_config_account_info,
+            vault_account_info,
+        ],
+        &[Signer::from(&vault_seeds)],
+    )?;
+
+    msg!("Delegated vault lamports to validator");
+
+    Ok(())
+}
+
+// Process deactivate stake instruction. The stake cools down over the following epochs
+// and can be withdrawn once inactive.
+fn process_deactivate_stake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // We expect 6 accounts: user, user_data, vault, stake, clock, stake_program,
+    // followed by any multisig signers
+    let [user_account_info, user_data_account_info, vault_account_info, stake_account_info, clock_sysvar_account_info, stake_program_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    check_stake_program(stake_program_account_info)?;
+
+    let data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes(&data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+    check_stake_address(program_id, vault_account_info, stake_account_info)?;
+
+    let vault_bump_bytes = [user_data.vault_bump];
+    drop(data);
+
+    let vault_seeds = [
+        Seed::from(b"vault"),
+        Seed::from(user_account_info.key().as_ref()),
+        Seed::from(&vault_bump_bytes),
+    ];
+
+    // Deactivate: tag 5
+    let account_metas = [
+        AccountMeta::writable(stake_account_info.key()),
+        AccountMeta::readonly(clock_sysvar_account_info.key()),
+        AccountMeta::readonly_signer(vault_account_info.key()),
+    ];
+    invoke_signed(
+        &Instruction {
+            program_id: &STAKE_PROGRAM_ID,
+            accounts: &account_metas,
+            data: &5u32.to_le_bytes(),
+        },
+        &[
+            stake_account_info,
+            clock_sysvar_account_info,
+            vault_account_info,
+        ],
+        &[Signer::from(&vault_seeds)],
+    )?;
+
+    msg!("Deactivated vault stake");
+
+    Ok(())
+}
+
+// Process withdraw stake instruction, returning the whole stake account balance,
+// rewards included, to the vault. The stake program deletes the emptied account, so the
+// vault can delegate again afterwards.
+fn process_withdraw_stake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // We expect 7 accounts: user, user_data, vault, stake, clock, stake_history,
+    // stake_program, followed by any multisig signers
+    let [user_account_info, user_data_account_info, vault_account_info, stake_account_info, clock_sysvar_account_info, stake_history_sysvar_account_info, stake_program_account_info, multisig_signer_account_infos @ ..] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    check_stake_program(stake_program_account_info)?;
+
+    let data = load_user_account(program_id, user_account_info, user_data_account_info)?;
+    let (user_data, signers) = UserAccount::from_bytes(&data)?;
+
+    // The owner, or enough multisig members, must approve
+    check_authority(
+        user_data,
+        signers,
+        user_account_info,
+        multisig_signer_account_infos,
+    )?;
+
+    check_vault_address(
+        program_id,
+        user_account_info,
+        vault_account_info,
+        user_data.vault_bump,
+    )?;
+    check_stake_address(program_id, vault_account_info, stake_account_info)?;
+
+    let vault_bump_bytes = [user_data.vault_bump];
+    drop(data);
+
+    let stake_lamports = stake_account_info.lamports();
+    if stake_lamports == 0 {
+        msg!("Vault has no stake account");
+        return Err(VaultError::InsufficientFunds.into());
+    }
+
+    let vault_seeds = [
+        Seed::from(b"vault"),
+        Seed::from(user_account_info.key().as_ref()),
+        Seed::from(&vault_bump_bytes),
+    ];
+
+    // Withdraw: tag 4 and the lamports
+    let mut withdraw_data = [0u8; 12];
+    withdraw_data[..4].copy_from_slice(&4u32.to_le_bytes());
+    withdraw_data[4..].copy_from_slice(&stake_lamports.to_le_bytes());
+    let account_metas = [
+        AccountMeta::writable(stake_account_info.key()),
+        AccountMeta::writable(vault_account_info.key()),
+        AccountMeta::readonly(clock_sysvar_account_info.key()),
+        AccountMeta::readonly(stake_history_sysvar_account_info.key()),
+        AccountMeta::readonly_signer(vault_account_info.key()),
+    ];
+    invoke_signed(
+        &Instruction {
+            program_id: &STAKE_PROGRAM_ID,
+            accounts: &account_metas,
+            data: &withdraw_data,
+        },
+        &[
+            stake_account_info,
+            vault_account_info,
+            clock_sysvar_account_info,
+            stake_history_sysvar_account_info,
+            vault_account_info,
+        ],
+        &[Signer::from(&vault_seeds)],
+    )?;
+
+    msg!("Withdrew stake to vault");
+
+    Ok(())
+}
+
+// Read the upgrade authority from the program's ProgramData account. The account is
+// bincode encoded: a u32 variant tag (3 = ProgramData), the deploy slot as u64, then
+// the authority as an Option<Pubkey>.
+fn read_upgrade_authority(
+    program_id: &Pubkey,
+    program_data_account_info: &AccountInfo,
+) -> Result<Option<Pubkey>, ProgramError> {
+    let (expected_program_data_pubkey, _) =
+        pubkey::find_program_address(&[program_id.as_ref()], &BPF_LOADER_UPGRADEABLE_ID);
+    if program_data_account_info.key() != &expected_program_data_pubkey
+        || program_data_account_info.owner() != &BPF_LOADER_UPGRADEABLE_ID
+    {
+        msg!("Invalid program data account");
+        return Err(VaultError::InvalidProgramData.into());
+    }
+
+    let data = program_data_account_info.try_borrow_data()?;
+    if data.len() < 45 || data[0..4] != 3u32.to_le_bytes() {
+        msg!("Invalid program data account");
+        return Err(VaultError::InvalidProgramData.into());
+    }
+
+    // A program without an upgrade authority is immutable
+    if data[12] == 0 {
+        return Ok(None);
+    }
+    Ok(Some(
+        data[13..45]
+            .try_into()
+            .map_err(|_| VaultError::InvalidProgramData)?,
+    ))
+}
+
+// Load the config and check that the admin signed
+fn load_config(
+    program_id: &Pubkey,
+    admin_account_info: &AccountInfo,
+    config_account_info: &AccountInfo,
+) -> Result<Config, ProgramError> {
+    if !admin_account_info.is_signer() {
+        msg!("Admin must sign the transaction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if config_account_info.owner() != program_id {
+        msg!("Config is not initialized");
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let config = *Config::from_bytes(&config_account_info.try_borrow_data()?)?;
+
+    // The bump is only stored in the real config PDA
+    let expected_config = pubkey::create_program_address(&[b"config", &[config.bump]], program_id)
+        .map_err(|_| VaultError::InvalidConfig)?;
+    if config_account_info.key() != &expected_config {
+        msg!("Invalid config account address");
+        return Err(VaultError::InvalidConfig.into());
+    }
+
+    if config.admin != *admin_account_info.key() {
+        msg!("Signer is not the admin");
+        return Err(VaultError::NotAdmin.into());
+    }
+
+    Ok(config)
+}
+
+// Copy the config back into the account
+fn save_config(config: &Config, config_account_info: &AccountInfo) -> ProgramResult {
+    *Config::from_bytes_mut(&mut config_account_info.try_borrow_mut_data()?)? = *config;
+    Ok(())
+}
+
+// Read the config from its PDA. Until the config is initialized the program runs
+// unpaused and without fees.
+fn read_config(
+    program_id: &Pubkey,
+    config_account_info: &AccountInfo,
+) -> Result<Config, ProgramError> {
+    let (expected_config_pubkey, _) = pubkey::find_program_address(&[b"config"], program_id);
+    if config_account_info.key() != &expected_config_pubkey {
+        msg!("Invalid config account address");
+        return Err(VaultError::InvalidConfig.into());
+    }
+
+    if config_account_info.owner() != program_id {
+        return Ok(Config::default());
+    }
+
+    let data = config_account_info.try_borrow_data()?;
+    Ok(*Config::from_bytes(&data)?)
+}
+
+// Reject deposits, or withdrawals when those are paused too
+fn check_not_paused(config: &Config, is_withdrawal: bool) -> ProgramResult {
+    if config.paused() && (!is_withdrawal || config.withdrawals_paused()) {
+        msg!("Program is paused");
+        return Err(VaultError::ProgramPaused.into());
+    }
+
+    Ok(())
+}
+
+// Fee on an amount in basis points, rounded down so users are never overcharged
+fn calculate_fee(amount: u64, fee_bps: u16) -> Result<u64, ProgramError> {
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        / BPS_DENOMINATOR;
+    u64::try_from(fee).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+// Transfer the fee on an amount from the payer to the treasury and return what is
+// left. Pass the payer's signer when it is a PDA, or none when it signed itself.
+fn collect_fee(
+    program_id: &Pubkey,
+    from_account_info: &AccountInfo,
+    treasury_account_info: &AccountInfo,
+    amount: u64,
+    fee_bps: u16,
+    treasury_bump: u8,
+    signers: &[Signer],
+) -> Result<u64, ProgramError> {
+    let fee = calculate_fee(amount, fee_bps)?;
+    if fee == 0 {
+        return Ok(amount);
+    }
+
+    check_treasury_address(program_id, treasury_account_info, treasury_bump)?;
+
+    Transfer {
+        from: from_account_info,
+        to: treasury_account_info,
+        lamports: fee,
+    }
+    .invoke_signed(signers)?;
+
+    Ok(amount - fee)
+}
+
+// Verify the treasury PDA using the stored bump
+fn check_treasury_address(
+    program_id: &Pubkey,
+    treasury_account_info: &AccountInfo,
+    treasury_bump: u8,
+) -> ProgramResult {
+    let expected_treasury =
+        pubkey::create_program_address(&[b"treasury", &[treasury_bump]], program_id)
+            .map_err(|_| VaultError::InvalidTreasury)?;
+    if treasury_account_info.key() != &expected_treasury {
+        msg!("Invalid treasury account address");
+        return Err(VaultError::InvalidTreasury.into());
+    }
+
+    Ok(())
+}
+
+// Check that the user signed, or that enough distinct multisig members signed
+fn check_authority(
+    user_data: &UserAccount,
+    signers: &[Pubkey],
+    user_account_info: &AccountInfo,
+    multisig_signer_account_infos: &[AccountInfo],
+) -> ProgramResult {
+    if user_data.threshold == 0 {
+        if !user_account_info.is_signer() {
+            msg!("User must sign the transaction");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        return Ok(());
+    }
+
+    // Count each member once, no matter how often its account is repeated
+    let mut approvals: Vec<&Pubkey> = Vec::with_capacity(signers.len());
+    for account_info in core::iter::once(user_account_info).chain(multisig_signer_account_infos) {
+        if account_info.is_signer()
+            && signers.contains(account_info.key())
+            && !approvals.contains(&account_info.key())
+        {
+            approvals.push(account_info.key());
+        }
+    }
+
+    if approvals.len() < user_data.threshold as usize {
+        msg!("Not enough multisig signers approved");
+        return Err(VaultError::NotEnoughSigners.into());
+    }
+
+    Ok(())
+}
+
+// Borrow the user data account after verifying it belongs to the user. Callers view
+// the borrowed data with UserAccount::from_bytes or from_bytes_mut.
+fn load_user_account<'a>(
+    program_id: &Pubkey,
+    user_account_info: &AccountInfo,
+    user_data_account_info: &'a AccountInfo,
+) -> Result<RefMut<'a, [u8]>, ProgramError> {
+    let data = load_user_account_any(program_id, user_account_info, user_data_account_info)?;
+    UserAccount::from_bytes(&data)?;
+    Ok(data)
+}
+
+// As load_user_account, but also accept the baseline layout, for deposits, withdrawals
+// and Migrate. The user and the bump are at the same offsets behind the header in both
+// layouts.
+fn load_user_account_any<'a>(
+    program_id: &Pubkey,
+    user_account_info: &AccountInfo,
+    user_data_account_info: &'a AccountInfo,
+) -> Result<RefMut<'a, [u8]>, ProgramError> {
+    if user_data_account_info.owner() != program_id {
+        msg!("User data account is not owned by the program");
+        return Err(VaultError::InvalidAccountOwner.into());
+    }
+
+    let data = user_data_account_info.try_borrow_mut_data()?;
+    let header_size = if UserAccount::is_baseline(&data) {
+        0
+    } else {
+        UserAccount::check(&data)?;
+        USER_ACCOUNT_DISCRIMINATOR.len()
+    };
+    let user = &data[header_size..header_size + 32];
+    let user_bump = data[header_size + core::mem::offset_of!(UserAccount, user_bump)];
+
+    // Check that the user account belongs to the requesting user
+    if user != user_account_info.key().as_ref() {
+        msg!("User account does not belong to the requesting user");
+        return Err(VaultError::UserMismatch.into());
+    }
+
+    // Verify user data PDA
+    let user_seeds = &[user_account_info.key().as_ref(), &[user_bump]];
+    let expected_user_data = match pubkey::create_program_address(user_seeds, program_id) {
+        Ok(address) => address,
+        Err(_) => return Err(VaultError::InvalidUserAccount.into()),
+    };
+
+    if user_data_account_info.key() != &expected_user_data {
+        msg!("Invalid user data account address");
+        return Err(VaultError::InvalidUserAccount.into());
+    }
+
+    Ok(data)
+}
+
+// The vault is a system account, so a withdrawal must either drain it or leave it
+// rent-exempt. Anything in between would fail inside the system transfer with an
+// error that does not say how much may be withdrawn.
+fn check_vault_remainder(remaining: u64) -> ProgramResult {
+    if remaining > 0 && remaining < Rent::get()?.minimum_balance(0) {
+        msg!("Withdrawal would leave the vault below the rent-exempt minimum, withdraw everything or leave at least the minimum");
+        return Err(VaultError::VaultBelowRentExempt.into());
+    }
+
+    Ok(())
+}
+
+// Verify the vault PDA using the stored bump
+fn check_vault_address(
+    program_id: &Pubkey,
+    user_account_info: &AccountInfo,
+    vault_account_info: &AccountInfo,
+    vault_bump: u8,
+) -> ProgramResult {
+    let vault_seeds = &[
+        b"vault".as_ref(),
+        user_account_info.key().as_ref(),
+        &[vault_bump],
+    ];
+
+    let expected_vault = match pubkey::create_program_address(vault_seeds, program_id) {
+        Ok(address) => address,
+        Err(_) => return Err(VaultError::InvalidVault.into()),
+    };
+
+    if vault_account_info.key() != &expected_vault {
+        msg!("Invalid vault account address");
+        return Err(VaultError::InvalidVault.into());
+    }
+
+    Ok(())
+}
+
+// Verify the stake PDA of the vault and return its bump
+fn check_stake_address(
+    program_id: &Pubkey,
+    vault_account_info: &AccountInfo,
+    stake_account_info: &AccountInfo,
+) -> Result<u8, ProgramError> {
+    let (expected_stake_pubkey, stake_bump) =
+        pubkey::find_program_address(&[b"stake", vault_account_info.key().as_ref()], program_id);
+
+    if stake_account_info.key() != &expected_stake_pubkey {
+        msg!("Invalid stake account address");
+        return Err(VaultError::InvalidStakeAccount.into());
+    }
+
+    Ok(stake_bump)
+}
+
+fn check_stake_program(stake_program_account_info: &AccountInfo) -> ProgramResult {
+    if stake_program_account_info.key() != &STAKE_PROGRAM_ID {
+        msg!("Invalid stake program");
+        return Err(VaultError::InvalidStakeProgram.into());
+    }
+
     Ok(())
 }
@@ -0,0 +1,2290 @@
+// LiteSVM scenarios shared by the native and pinocchio clients. Both programs take the
+// same accounts and keep the same state, so every scenario is written once against a
+// differential::Implementation, which gives the program ID, the compiled program and
+// how instructions are encoded, and each client runs them from its test_litesvm.rs.
+//
+// Every scenario starts from a fresh SVM with fresh keypairs, which makes the balance
+// checks exact, transaction fees included.
+use anyhow::{anyhow, Result};
+use borsh::BorshDeserialize;
+use differential::{Implementation, IMPLEMENTATIONS};
+use litesvm::{types::TransactionMetadata, LiteSVM};
+use solana_program::{
+    bpf_loader_upgradeable,
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey,
+    pubkey::Pubkey,
+    stake::{self, state::StakeStateV2},
+    system_instruction, system_program,
+    vote::{
+        instruction::{self as vote_instruction, CreateVoteAccountConfig},
+        state::VoteInit,
+    },
+};
+use solana_sdk::{
+    account::Account,
+    native_token::LAMPORTS_PER_SOL,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account,
+};
+use spl_token::{
+    instruction as token_instruction,
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_2022::{
+    extension::{
+        transfer_hook::instruction as transfer_hook_instruction, ExtensionType, StateWithExtensions,
+    },
+    instruction as token_2022_instruction,
+    state::{Account as TokenAccount2022, Mint as Mint2022},
+};
+use vault_interface::{
+    event::decode_events, find_config_address, find_program_data_address, find_stake_address,
+    find_treasury_address, find_user_account_address, find_vault_address, instruction, Config,
+    UserAccount, VaultError, VaultEvent,
+};
+
+// LiteSVM charges the default fee per signature, with no priority fee
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+// Builtin that accepts any instruction, used as a transfer hook that does nothing
+const COMPUTE_BUDGET_PROGRAM_ID: Pubkey = pubkey!("ComputeBudget111111111111111111111111111111");
+
+// Look up an implementation of differential::IMPLEMENTATIONS by name
+pub fn implementation(name: &str) -> &'static Implementation {
+    IMPLEMENTATIONS
+        .iter()
+        .find(|implementation| implementation.name == name)
+        .expect("Unknown implementation")
+}
+
+// Load the program into a fresh SVM. InitializeConfig reads the upgrade authority
+// from the ProgramData account, so one is written next to the program with the
+// bincode encoded header of UpgradeableLoaderState::ProgramData: a u32 variant tag
+// (3), the deploy slot as u64, then the authority as an Option<Pubkey>.
+pub fn setup(implementation: &Implementation, upgrade_authority: &Pubkey) -> LiteSVM {
+    let mut svm = LiteSVM::new();
+    svm.add_program_from_file(implementation.program_id, implementation.path)
+        .unwrap_or_else(|error| {
+            panic!(
+                "Failed to load {}, build the program with cargo build-sbf first: {}",
+                implementation.path, error
+            )
+        });
+
+    let mut program_data = 3u32.to_le_bytes().to_vec();
+    program_data.extend_from_slice(&0u64.to_le_bytes());
+    program_data.push(1);
+    program_data.extend_from_slice(upgrade_authority.as_ref());
+    let (program_data_pubkey, _) = find_program_data_address(&implementation.program_id);
+    svm.set_account(
+        program_data_pubkey,
+        Account {
+            lamports: svm.minimum_balance_for_rent_exemption(program_data.len()),
+            data: program_data,
+            owner: bpf_loader_upgradeable::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    )
+    .expect("Failed to write the program data account");
+    svm
+}
+
+pub fn funded_keypair(svm: &mut LiteSVM, lamports: u64) -> Keypair {
+    let keypair = Keypair::new();
+    svm.airdrop(&keypair.pubkey(), lamports)
+        .expect("Airdrop failed");
+    keypair
+}
+
+// Transaction fee for the given number of signatures
+fn fee(signatures: u64) -> u64 {
+    signatures * LAMPORTS_PER_SIGNATURE
+}
+
+fn balance(svm: &LiteSVM, address: &Pubkey) -> u64 {
+    svm.get_balance(address).unwrap_or(0)
+}
+
+// Send an instruction paid for by the payer, other keys only sign. The blockhash is
+// expired first so that repeating an instruction is not rejected as a duplicate. A
+// vault error is surfaced by name, anything else with the program logs.
+pub fn send(
+    svm: &mut LiteSVM,
+    payer: &Keypair,
+    instruction: Instruction,
+    extra_signers: &[&Keypair],
+) -> Result<TransactionMetadata> {
+    let mut signers = vec![payer];
+    signers.extend_from_slice(extra_signers);
+    svm.expire_blockhash();
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &signers,
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(transaction).map_err(|failed| {
+        let vault_error = match failed.err {
+            TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+                VaultError::from_code(code)
+            }
+            _ => None,
+        };
+        match vault_error {
+            Some(vault_error) => vault_error.into(),
+            None => anyhow!("{}\n{}", failed.err, failed.meta.logs.join("\n")),
+        }
+    })
+}
+
+// Extract the vault error a transaction was rejected with, if any
+fn vault_error(result: Result<TransactionMetadata>) -> Option<VaultError> {
+    result.err()?.downcast_ref().copied()
+}
+
+// Move the clock forward, for timelocks and rate limit windows
+fn warp(svm: &mut LiteSVM, seconds: i64) {
+    let mut clock = svm.get_sysvar::<Clock>();
+    clock.unix_timestamp += seconds;
+    svm.set_sysvar(&clock);
+}
+
+// Move the clock to a later slot, for the slots recorded in the user account
+fn set_slot(svm: &mut LiteSVM, slot: u64) {
+    let mut clock = svm.get_sysvar::<Clock>();
+    clock.slot = slot;
+    svm.set_sysvar(&clock);
+}
+
+// Create a vote account for stake to be delegated to, with the payer as its node
+// identity and authorities
+fn create_vote_account(svm: &mut LiteSVM, payer: &Keypair) -> Pubkey {
+    let vote_account = Keypair::new();
+    let config = CreateVoteAccountConfig::default();
+    let instructions = vote_instruction::create_account_with_config(
+        &payer.pubkey(),
+        &vote_account.pubkey(),
+        &VoteInit {
+            node_pubkey: payer.pubkey(),
+            authorized_voter: payer.pubkey(),
+            authorized_withdrawer: payer.pubkey(),
+            commission: 0,
+        },
+        svm.minimum_balance_for_rent_exemption(config.space as usize),
+        config,
+    );
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer, &vote_account],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(transaction)
+        .expect("Failed to create the vote account");
+    vote_account.pubkey()
+}
+
+pub fn deposit_withdraw(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // The first deposit also pays the rent of the user account
+    let deposit_amount = LAMPORTS_PER_SOL;
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    let user_account_rent = svm.minimum_balance_for_rent_exemption(8 + UserAccount::SIZE);
+    assert_eq!(
+        balance(&svm, &user_account_pda),
+        user_account_rent,
+        "User account should hold exactly its rent-exempt minimum"
+    );
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount,
+        "Vault balance should increase by deposit amount"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before - deposit_amount - user_account_rent - fee(1),
+        "User should pay the deposit, the user account rent and the transaction fee"
+    );
+
+    // Withdraw half of what was deposited
+    let withdraw_amount = deposit_amount / 2;
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(
+            &program_id,
+            &user_pubkey,
+            withdraw_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount - withdraw_amount,
+        "Vault balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + withdraw_amount - fee(1),
+        "User should receive the withdraw amount less the transaction fee"
+    );
+
+    // Withdrawing more than the vault holds fails
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw(
+                &program_id,
+                &user_pubkey,
+                deposit_amount
+            )),
+            &[],
+        )),
+        Some(VaultError::InsufficientFunds),
+        "Withdraw should fail beyond the vault balance"
+    );
+
+    Ok(())
+}
+
+pub fn close(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Deposit so that both the user account and the vault exist
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    let balance_before = balance(&svm, &user_pubkey);
+    let user_account_rent = balance(&svm, &user_account_pda);
+
+    // Close the vault and the user account
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::close(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    assert_eq!(balance(&svm, &vault_pda), 0, "Vault should be drained");
+    assert_eq!(
+        balance(&svm, &user_account_pda),
+        0,
+        "User account rent should be reclaimed"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + deposit_amount + user_account_rent - fee(1),
+        "User should receive the vault balance and the user account rent"
+    );
+
+    Ok(())
+}
+
+pub fn withdraw_all(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let rent = svm.minimum_balance_for_rent_exemption(0);
+
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+
+    // Leaving a balance below the rent-exempt minimum is refused
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw(
+                &program_id,
+                &user_pubkey,
+                deposit_amount - 1
+            )),
+            &[],
+        )),
+        Some(VaultError::VaultBelowRentExempt),
+        "Withdraw should not leave the vault below rent"
+    );
+
+    // Leaving exactly the minimum is fine
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(
+            &program_id,
+            &user_pubkey,
+            deposit_amount - rent,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        rent,
+        "Vault should keep the rent-exempt minimum"
+    );
+
+    // WithdrawAll drains the vault and keeps the user account
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw_all(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    assert_eq!(balance(&svm, &vault_pda), 0, "Vault should be drained");
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + rent - fee(1),
+        "User should receive the whole vault balance less the transaction fee"
+    );
+    assert!(
+        svm.get_account(&user_account_pda).is_some(),
+        "User account should be kept"
+    );
+
+    // Nothing is left to withdraw
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw_all(&program_id, &user_pubkey)),
+            &[],
+        )),
+        Some(VaultError::InsufficientFunds),
+        "WithdrawAll should fail on an empty vault"
+    );
+
+    // Withdrawing the exact balance drains the vault as well
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(balance(&svm, &vault_pda), 0, "Vault should be drained");
+
+    Ok(())
+}
+
+pub fn lifetime_totals(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+    let funder = funded_keypair(&mut svm, LAMPORTS_PER_SOL);
+
+    // Get the user PDA
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let user_account = |svm: &LiteSVM| -> Result<UserAccount> {
+        let account = svm
+            .get_account(&user_account_pda)
+            .ok_or_else(|| anyhow!("User account not found"))?;
+        Ok(UserAccount::unpack(&account.data)?)
+    };
+
+    // Deposits by the user and by someone else both count
+    let amount = LAMPORTS_PER_SOL / 2;
+    set_slot(&mut svm, 10);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(&program_id, &user_pubkey, amount)),
+        &[],
+    )?;
+    set_slot(&mut svm, 20);
+    send(
+        &mut svm,
+        &funder,
+        implementation.encode(instruction::deposit_for(
+            &program_id,
+            &funder.pubkey(),
+            &user_pubkey,
+            amount,
+        )),
+        &[],
+    )?;
+    let totals = user_account(&svm)?;
+    assert_eq!(
+        totals.total_deposited,
+        2 * amount,
+        "Both deposits should count"
+    );
+    assert_eq!(totals.deposit_count, 2, "Deposit count should be 2");
+    assert_eq!(
+        totals.last_deposit_slot, 20,
+        "Last deposit slot should be the slot of the second deposit"
+    );
+    assert_eq!(totals.total_withdrawn, 0, "Nothing should be withdrawn yet");
+    assert_eq!(
+        totals.last_withdraw_slot, 0,
+        "No withdrawal should be recorded"
+    );
+
+    // Withdrawals add up, WithdrawAll included
+    set_slot(&mut svm, 30);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(&program_id, &user_pubkey, amount / 2)),
+        &[],
+    )?;
+    set_slot(&mut svm, 40);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw_all(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    let totals = user_account(&svm)?;
+    assert_eq!(
+        totals.total_withdrawn,
+        2 * amount,
+        "Everything deposited should be withdrawn"
+    );
+    assert_eq!(
+        totals.last_withdraw_slot, 40,
+        "Last withdraw slot should be the slot of WithdrawAll"
+    );
+    assert_eq!(
+        (
+            totals.total_deposited,
+            totals.deposit_count,
+            totals.last_deposit_slot
+        ),
+        (2 * amount, 2, 20),
+        "Withdrawals should not touch the deposit totals"
+    );
+
+    // Failed instructions record nothing
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw(&program_id, &user_pubkey, 1)),
+            &[],
+        )),
+        Some(VaultError::InsufficientFunds),
+        "Withdrawing from an empty vault should fail"
+    );
+    assert_eq!(
+        user_account(&svm)?,
+        totals,
+        "A failed withdrawal should leave the totals alone"
+    );
+
+    Ok(())
+}
+
+pub fn deposit_for(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let funder = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+    let beneficiary = funded_keypair(&mut svm, LAMPORTS_PER_SOL);
+
+    // Get the beneficiary's PDAs
+    let funder_pubkey = funder.pubkey();
+    let beneficiary_pubkey = beneficiary.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&beneficiary_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&beneficiary_pubkey, &program_id);
+
+    // The funder pays the deposit, the user account rent and the fee, the
+    // beneficiary does not sign and pays nothing
+    let amount = LAMPORTS_PER_SOL / 2;
+    let funder_before = balance(&svm, &funder_pubkey);
+    let beneficiary_before = balance(&svm, &beneficiary_pubkey);
+    send(
+        &mut svm,
+        &funder,
+        implementation.encode(instruction::deposit_for(
+            &program_id,
+            &funder_pubkey,
+            &beneficiary_pubkey,
+            amount,
+        )),
+        &[],
+    )?;
+    let user_account_rent = balance(&svm, &user_account_pda);
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        amount,
+        "Beneficiary's vault should hold the deposit"
+    );
+    assert_eq!(
+        balance(&svm, &funder_pubkey),
+        funder_before - amount - user_account_rent - fee(1),
+        "Funder should pay the deposit, the user account rent and the transaction fee"
+    );
+    assert_eq!(
+        balance(&svm, &beneficiary_pubkey),
+        beneficiary_before,
+        "Beneficiary should pay nothing"
+    );
+
+    // The user account belongs to the beneficiary, not to the funder
+    let user_account = svm
+        .get_account(&user_account_pda)
+        .ok_or_else(|| anyhow!("User account not found"))?;
+    assert_eq!(
+        UserAccount::unpack(&user_account.data)?.user,
+        beneficiary_pubkey,
+        "User account should record the beneficiary"
+    );
+
+    // The beneficiary can withdraw the funds
+    let withdraw_amount = amount / 2;
+    send(
+        &mut svm,
+        &beneficiary,
+        implementation.encode(instruction::withdraw(
+            &program_id,
+            &beneficiary_pubkey,
+            withdraw_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &beneficiary_pubkey),
+        beneficiary_before + withdraw_amount - fee(1),
+        "Beneficiary should receive the withdraw amount less the transaction fee"
+    );
+
+    Ok(())
+}
+
+pub fn timelocked_withdraw(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Deposit with a one minute withdraw delay
+    let withdraw_delay = 60;
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit_with_delay(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+            withdraw_delay,
+        )),
+        &[],
+    )?;
+
+    // Direct withdrawals and closing bypass the timelock, so both are rejected
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw(&program_id, &user_pubkey, 1)),
+            &[],
+        )),
+        Some(VaultError::VaultTimelocked),
+        "Withdraw should fail on a timelocked vault"
+    );
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::close(&program_id, &user_pubkey)),
+            &[],
+        )),
+        Some(VaultError::VaultTimelocked),
+        "Close should fail on a timelocked vault with funds"
+    );
+
+    // Request a withdrawal, which cannot be claimed before the delay has passed
+    let withdraw_amount = deposit_amount / 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::request_withdraw(
+            &program_id,
+            &user_pubkey,
+            withdraw_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::claim_withdraw(&program_id, &user_pubkey)),
+            &[],
+        )),
+        Some(VaultError::WithdrawalStillLocked),
+        "Claim should fail before the timelock expires"
+    );
+
+    // Move the clock past the unlock time and claim
+    warp(&mut svm, withdraw_delay);
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::claim_withdraw(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount - withdraw_amount,
+        "Vault balance should decrease by the claimed amount"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + withdraw_amount - fee(1),
+        "User should receive the claimed amount less the transaction fee"
+    );
+
+    // The pending withdrawal is cleared after claiming
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::claim_withdraw(&program_id, &user_pubkey)),
+            &[],
+        )),
+        Some(VaultError::NoPendingWithdrawal),
+        "A second claim should fail"
+    );
+
+    // A claim may not leave the vault below the rent-exempt minimum either
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::request_withdraw(
+            &program_id,
+            &user_pubkey,
+            deposit_amount - withdraw_amount - 1,
+        )),
+        &[],
+    )?;
+    warp(&mut svm, withdraw_delay);
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::claim_withdraw(&program_id, &user_pubkey)),
+            &[],
+        )),
+        Some(VaultError::VaultBelowRentExempt),
+        "Claim should not leave the vault below rent"
+    );
+
+    Ok(())
+}
+
+pub fn multisig_withdraw(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Three members, two of which must approve
+    let members = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let member_keys: Vec<Pubkey> = members.iter().map(|member| member.pubkey()).collect();
+
+    // Deposit so that both the user account and the vault exist
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+
+    // The user hands control to a 2-of-3 multisig and pays for the larger account
+    let size_before = 8 + UserAccount::SIZE;
+    let rent_before = balance(&svm, &user_account_pda);
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::set_multisig(
+            &program_id,
+            &user_pubkey,
+            &user_pubkey,
+            member_keys,
+            2,
+        )),
+        &[],
+    )?;
+    let rent_after = svm.minimum_balance_for_rent_exemption(size_before + 3 * 32);
+    assert_eq!(
+        balance(&svm, &user_account_pda),
+        rent_after,
+        "User account should be topped up to the rent of the signer set"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before - (rent_after - rent_before) - fee(1),
+        "User should pay the extra rent and the transaction fee"
+    );
+
+    // One approval does not satisfy a 2-of-3 multisig
+    let withdraw_amount = LAMPORTS_PER_SOL / 10;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            instruction::with_signers(
+                implementation.encode(instruction::withdraw(
+                    &program_id,
+                    &user_pubkey,
+                    withdraw_amount
+                )),
+                &[members[0].pubkey()],
+            ),
+            &[&members[0]],
+        )),
+        Some(VaultError::NotEnoughSigners),
+        "One approval should not satisfy a 2-of-3 multisig"
+    );
+
+    // Two distinct members can withdraw, the user pays every signature
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        instruction::with_signers(
+            implementation.encode(instruction::withdraw(
+                &program_id,
+                &user_pubkey,
+                withdraw_amount,
+            )),
+            &[members[0].pubkey(), members[2].pubkey()],
+        ),
+        &[&members[0], &members[2]],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount - withdraw_amount,
+        "Vault balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + withdraw_amount - fee(3),
+        "User should receive the withdraw amount less the fee for three signatures"
+    );
+
+    // Two members hand control back to the user, who gets the excess rent back
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        instruction::with_signers(
+            implementation.encode(instruction::set_multisig(
+                &program_id,
+                &user_pubkey,
+                &user_pubkey,
+                Vec::new(),
+                0,
+            )),
+            &[members[0].pubkey(), members[1].pubkey()],
+        ),
+        &[&members[0], &members[1]],
+    )?;
+    assert_eq!(
+        balance(&svm, &user_account_pda),
+        rent_before,
+        "User account should shrink back to its original rent"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + (rent_after - rent_before) - fee(3),
+        "User should get the excess rent back"
+    );
+
+    Ok(())
+}
+
+pub fn forged_user_account(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+
+    // Copy a user account to an address the program does not own, with the given edits
+    let forge = |svm: &mut LiteSVM, user: &Pubkey, edit: fn(&mut UserAccount)| -> Result<Pubkey> {
+        let (user_account_pda, _) = find_user_account_address(user, &program_id);
+        let account = svm
+            .get_account(&user_account_pda)
+            .ok_or_else(|| anyhow!("User account not found"))?;
+        let mut user_account = UserAccount::unpack(&account.data)?;
+        edit(&mut user_account);
+        let forged_pubkey = Pubkey::new_unique();
+        let data = user_account.pack();
+        svm.set_account(
+            forged_pubkey,
+            Account {
+                lamports: svm.minimum_balance_for_rent_exemption(data.len()),
+                data,
+                owner: system_program::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        )?;
+        Ok(forged_pubkey)
+    };
+
+    // Withdraw everything through the forged account in place of the user account
+    let forged_withdraw = |user: &Pubkey, forged_pubkey: Pubkey, amount: u64| {
+        let mut withdraw = implementation.encode(instruction::withdraw(&program_id, user, amount));
+        withdraw.accounts[1].pubkey = forged_pubkey;
+        withdraw
+    };
+
+    // A timelocked vault cannot be emptied by dropping the delay in a copy
+    let timelocked = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+    let timelocked_pubkey = timelocked.pubkey();
+    let (timelocked_vault, _) = find_vault_address(&timelocked_pubkey, &program_id);
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(
+        &mut svm,
+        &timelocked,
+        implementation.encode(instruction::deposit_with_delay(
+            &program_id,
+            &timelocked_pubkey,
+            deposit_amount,
+            3600,
+        )),
+        &[],
+    )?;
+    let forged_pubkey = forge(&mut svm, &timelocked_pubkey, |user_account| {
+        user_account.withdraw_delay = 0
+    })?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &timelocked,
+            forged_withdraw(&timelocked_pubkey, forged_pubkey, deposit_amount),
+            &[],
+        )),
+        Some(VaultError::InvalidAccountOwner),
+        "A forged account should not lift the timelock"
+    );
+    assert_eq!(
+        balance(&svm, &timelocked_vault),
+        deposit_amount,
+        "Timelocked vault should keep its balance"
+    );
+
+    // A multisig vault cannot be emptied by the user alone with a copy without members
+    let multisig = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+    let multisig_pubkey = multisig.pubkey();
+    let (multisig_vault, _) = find_vault_address(&multisig_pubkey, &program_id);
+    send(
+        &mut svm,
+        &multisig,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &multisig_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    let members = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+    send(
+        &mut svm,
+        &multisig,
+        implementation.encode(instruction::set_multisig(
+            &program_id,
+            &multisig_pubkey,
+            &multisig_pubkey,
+            members,
+            2,
+        )),
+        &[],
+    )?;
+    let forged_pubkey = forge(&mut svm, &multisig_pubkey, |user_account| {
+        user_account.threshold = 0;
+        user_account.signers.clear();
+    })?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &multisig,
+            forged_withdraw(&multisig_pubkey, forged_pubkey, deposit_amount),
+            &[],
+        )),
+        Some(VaultError::InvalidAccountOwner),
+        "A forged account should not bypass the multisig"
+    );
+    assert_eq!(
+        balance(&svm, &multisig_vault),
+        deposit_amount,
+        "Multisig vault should keep its balance"
+    );
+
+    Ok(())
+}
+
+pub fn delegate_withdraw(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+    let delegate = funded_keypair(&mut svm, LAMPORTS_PER_SOL);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let delegate_pubkey = delegate.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Deposit and let the delegate withdraw up to an allowance
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    let allowance = LAMPORTS_PER_SOL / 5;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::set_withdraw_authority(
+            &program_id,
+            &user_pubkey,
+            &delegate_pubkey,
+            allowance,
+        )),
+        &[],
+    )?;
+
+    // The delegate receives the withdrawal and pays its own fee
+    let withdraw_amount = LAMPORTS_PER_SOL / 10;
+    let delegate_before = balance(&svm, &delegate_pubkey);
+    send(
+        &mut svm,
+        &delegate,
+        implementation.encode(instruction::delegate_withdraw(
+            &program_id,
+            &delegate_pubkey,
+            &user_pubkey,
+            withdraw_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount - withdraw_amount,
+        "Vault balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        balance(&svm, &delegate_pubkey),
+        delegate_before + withdraw_amount - fee(1),
+        "Delegate should receive the withdraw amount less the transaction fee"
+    );
+
+    // Withdrawals beyond the remaining allowance fail
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &delegate,
+            implementation.encode(instruction::delegate_withdraw(
+                &program_id,
+                &delegate_pubkey,
+                &user_pubkey,
+                allowance - withdraw_amount + 1,
+            )),
+            &[],
+        )),
+        Some(VaultError::AllowanceExceeded),
+        "Delegate withdraw should fail beyond the allowance"
+    );
+
+    // Once revoked the delegate can no longer withdraw
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::revoke_withdraw_authority(
+            &program_id,
+            &user_pubkey,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &delegate,
+            implementation.encode(instruction::delegate_withdraw(
+                &program_id,
+                &delegate_pubkey,
+                &user_pubkey,
+                1
+            )),
+            &[],
+        )),
+        Some(VaultError::InvalidWithdrawAuthority),
+        "A revoked delegate should not be able to withdraw"
+    );
+
+    Ok(())
+}
+
+pub fn withdraw_limit(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Deposit and limit withdrawals to a tenth of a SOL per hour
+    let limit = LAMPORTS_PER_SOL / 10;
+    let window = 3_600;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            LAMPORTS_PER_SOL,
+        )),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::set_withdraw_limit(
+            &program_id,
+            &user_pubkey,
+            limit,
+            window,
+        )),
+        &[],
+    )?;
+
+    // Withdrawals count against the limit until the window is spent
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(&program_id, &user_pubkey, limit / 2)),
+        &[],
+    )?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw(
+                &program_id,
+                &user_pubkey,
+                limit / 2 + 1
+            )),
+            &[],
+        )),
+        Some(VaultError::WithdrawLimitExceeded),
+        "Withdraw should fail beyond the remaining limit"
+    );
+
+    // Raising the limit only applies once the current window length has passed
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::set_withdraw_limit(
+            &program_id,
+            &user_pubkey,
+            limit * 2,
+            window,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw(&program_id, &user_pubkey, limit)),
+            &[],
+        )),
+        Some(VaultError::WithdrawLimitExceeded),
+        "A raised limit should not apply immediately"
+    );
+
+    // After a window the raised limit applies in full
+    warp(&mut svm, window);
+    let vault_before = balance(&svm, &vault_pda);
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(&program_id, &user_pubkey, limit * 2)),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        vault_before - limit * 2,
+        "Vault balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + limit * 2 - fee(1),
+        "User should receive the withdraw amount less the transaction fee"
+    );
+
+    Ok(())
+}
+
+pub fn config_pause(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let upgrade_authority = Keypair::new();
+    let mut svm = setup(implementation, &upgrade_authority.pubkey());
+    svm.airdrop(&upgrade_authority.pubkey(), LAMPORTS_PER_SOL)
+        .expect("Airdrop failed");
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+    let admin = funded_keypair(&mut svm, LAMPORTS_PER_SOL);
+    let outsider = funded_keypair(&mut svm, LAMPORTS_PER_SOL);
+
+    // Get the config PDA
+    let user_pubkey = payer.pubkey();
+    let (config_pda, _) = find_config_address(&program_id);
+
+    // Deposit so that both the user account and the vault exist
+    let amount = LAMPORTS_PER_SOL / 10;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(&program_id, &user_pubkey, amount)),
+        &[],
+    )?;
+
+    // Only the upgrade authority can create the config, naming the admin
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &outsider,
+            implementation.encode(instruction::initialize_config(
+                &program_id,
+                &outsider.pubkey(),
+                &outsider.pubkey()
+            )),
+            &[],
+        )),
+        Some(VaultError::NotUpgradeAuthority),
+        "A non-upgrade authority should not be able to initialize the config"
+    );
+    send(
+        &mut svm,
+        &upgrade_authority,
+        implementation.encode(instruction::initialize_config(
+            &program_id,
+            &upgrade_authority.pubkey(),
+            &admin.pubkey(),
+        )),
+        &[],
+    )?;
+    let config = svm
+        .get_account(&config_pda)
+        .ok_or_else(|| anyhow!("Config not found"))?;
+    assert_eq!(
+        Config::unpack(&config.data)?.admin,
+        admin.pubkey(),
+        "Config should record the admin"
+    );
+
+    // Only the admin can pause
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &outsider,
+            implementation.encode(instruction::set_paused(
+                &program_id,
+                &outsider.pubkey(),
+                true,
+                false
+            )),
+            &[],
+        )),
+        Some(VaultError::NotAdmin),
+        "A non-admin should not be able to pause"
+    );
+
+    // A deposit pause still lets users withdraw
+    send(
+        &mut svm,
+        &admin,
+        implementation.encode(instruction::set_paused(
+            &program_id,
+            &admin.pubkey(),
+            true,
+            false,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::deposit(&program_id, &user_pubkey, amount)),
+            &[],
+        )),
+        Some(VaultError::ProgramPaused),
+        "Deposit should fail while paused"
+    );
+
+    // Staking moves lamports out of the vault too, so any pause blocks it
+    let vote_account = create_vote_account(&mut svm, &payer);
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::delegate_stake(
+                &program_id,
+                &user_pubkey,
+                &vote_account,
+                amount / 4
+            )),
+            &[],
+        )),
+        Some(VaultError::ProgramPaused),
+        "Delegating stake should fail while paused"
+    );
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(&program_id, &user_pubkey, amount / 4)),
+        &[],
+    )?;
+
+    // A full pause blocks withdrawals too
+    send(
+        &mut svm,
+        &admin,
+        implementation.encode(instruction::set_paused(
+            &program_id,
+            &admin.pubkey(),
+            true,
+            true,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw(&program_id, &user_pubkey, amount / 4)),
+            &[],
+        )),
+        Some(VaultError::ProgramPaused),
+        "Withdraw should fail while withdrawals are paused"
+    );
+
+    // Unpausing restores both
+    send(
+        &mut svm,
+        &admin,
+        implementation.encode(instruction::set_paused(
+            &program_id,
+            &admin.pubkey(),
+            false,
+            false,
+        )),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(&program_id, &user_pubkey, amount)),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(&program_id, &user_pubkey, amount / 4)),
+        &[],
+    )?;
+
+    Ok(())
+}
+
+pub fn fees(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let admin = Keypair::new();
+    let mut svm = setup(implementation, &admin.pubkey());
+    svm.airdrop(&admin.pubkey(), LAMPORTS_PER_SOL)
+        .expect("Airdrop failed");
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user, vault and treasury PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (treasury_pda, _) = find_treasury_address(&program_id);
+
+    // The upgrade authority creates the config and stays its admin
+    send(
+        &mut svm,
+        &admin,
+        implementation.encode(instruction::initialize_config(
+            &program_id,
+            &admin.pubkey(),
+            &admin.pubkey(),
+        )),
+        &[],
+    )?;
+
+    // Fees above the cap are rejected
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &admin,
+            implementation.encode(instruction::set_fees(
+                &program_id,
+                &admin.pubkey(),
+                1_001,
+                0
+            )),
+            &[],
+        )),
+        Some(VaultError::InvalidFee),
+        "A fee above the cap should be rejected"
+    );
+
+    // 1% on deposits and 2% on withdrawals. The admin funds the treasury up to
+    // its rent-exempt minimum.
+    let admin_before = balance(&svm, &admin.pubkey());
+    send(
+        &mut svm,
+        &admin,
+        implementation.encode(instruction::set_fees(
+            &program_id,
+            &admin.pubkey(),
+            100,
+            200,
+        )),
+        &[],
+    )?;
+    let treasury_rent = svm.minimum_balance_for_rent_exemption(0);
+    assert_eq!(
+        balance(&svm, &treasury_pda),
+        treasury_rent,
+        "Treasury should start at its rent-exempt minimum"
+    );
+    assert_eq!(
+        balance(&svm, &admin.pubkey()),
+        admin_before - treasury_rent - fee(1),
+        "Admin should pay the treasury rent and the transaction fee"
+    );
+
+    // The vault receives the deposit less the fee, the treasury receives the fee,
+    // and the user pays the whole amount
+    let amount = LAMPORTS_PER_SOL / 10;
+    let deposit_fee = amount / 100;
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(&program_id, &user_pubkey, amount)),
+        &[],
+    )?;
+    let user_account_rent = balance(&svm, &user_account_pda);
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        amount - deposit_fee,
+        "Vault should receive the deposit less the fee"
+    );
+    assert_eq!(
+        balance(&svm, &treasury_pda),
+        treasury_rent + deposit_fee,
+        "Treasury should receive the deposit fee"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before - amount - user_account_rent - fee(1),
+        "User should pay the deposit, the user account rent and the transaction fee"
+    );
+
+    // The whole withdrawal leaves the vault, the user receives it less the fee
+    let vault_balance = balance(&svm, &vault_pda);
+    let withdraw_amount = vault_balance / 2;
+    let withdraw_fee = withdraw_amount * 200 / 10_000;
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(
+            &program_id,
+            &user_pubkey,
+            withdraw_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        vault_balance - withdraw_amount,
+        "Vault balance should decrease by the withdraw amount"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before + withdraw_amount - withdraw_fee - fee(1),
+        "User should receive the withdraw amount less both fees"
+    );
+    let user_account = svm
+        .get_account(&user_account_pda)
+        .ok_or_else(|| anyhow!("User account not found"))?;
+    let user_account = UserAccount::unpack(&user_account.data)?;
+    assert_eq!(
+        (user_account.total_deposited, user_account.total_withdrawn),
+        (amount - deposit_fee, withdraw_amount),
+        "Totals should count deposits after the fee and withdrawals with it"
+    );
+    let collected = deposit_fee + withdraw_fee;
+    assert_eq!(
+        balance(&svm, &treasury_pda),
+        treasury_rent + collected,
+        "Treasury should receive the withdraw fee"
+    );
+
+    // The treasury stays rent-exempt, the collected fees go to the admin
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &admin,
+            implementation.encode(instruction::withdraw_fees(
+                &program_id,
+                &admin.pubkey(),
+                collected + 1
+            )),
+            &[],
+        )),
+        Some(VaultError::InsufficientFees),
+        "Withdrawing fees should not drain the treasury below rent exemption"
+    );
+    let admin_before = balance(&svm, &admin.pubkey());
+    send(
+        &mut svm,
+        &admin,
+        implementation.encode(instruction::withdraw_fees(
+            &program_id,
+            &admin.pubkey(),
+            collected,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &treasury_pda),
+        treasury_rent,
+        "Treasury should pay out the collected fees"
+    );
+    assert_eq!(
+        balance(&svm, &admin.pubkey()),
+        admin_before + collected - fee(1),
+        "Admin should receive the collected fees less the transaction fee"
+    );
+
+    Ok(())
+}
+
+pub fn token_deposit_withdraw(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Create a mint, with the user as its authority, and fund the user's associated
+    // token account. LiteSVM ships with the SPL programs.
+    let mint = Keypair::new();
+    let user_token_account = get_associated_token_address(&user_pubkey, &mint.pubkey());
+    let vault_token_account = get_associated_token_address(&vault_pda, &mint.pubkey());
+    let minted_amount = 1_000_000;
+    let setup_instructions = [
+        system_instruction::create_account(
+            &user_pubkey,
+            &mint.pubkey(),
+            svm.minimum_balance_for_rent_exemption(Mint::LEN),
+            Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        token_instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &user_pubkey,
+            None,
+            6,
+        )?,
+        create_associated_token_account(
+            &user_pubkey,
+            &user_pubkey,
+            &mint.pubkey(),
+            &spl_token::id(),
+        ),
+        token_instruction::mint_to(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &user_token_account,
+            &user_pubkey,
+            &[],
+            minted_amount,
+        )?,
+    ];
+    let setup_transaction = Transaction::new_signed_with_payer(
+        &setup_instructions,
+        Some(&user_pubkey),
+        &[&payer, &mint],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(setup_transaction)
+        .map_err(|failed| anyhow!("Token setup failed: {}", failed.err))?;
+
+    let token_balance = |svm: &LiteSVM, address: &Pubkey| -> Result<u64> {
+        let account = svm
+            .get_account(address)
+            .ok_or_else(|| anyhow!("Token account {} not found", address))?;
+        Ok(TokenAccount::unpack(&account.data)?.amount)
+    };
+
+    // The first token deposit creates the user account and the vault token account,
+    // both paid for by the user
+    let deposit_amount = 400_000;
+    let balance_before = balance(&svm, &user_pubkey);
+    let metadata = send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit_token(
+            &program_id,
+            &user_pubkey,
+            &mint.pubkey(),
+            &user_token_account,
+            &spl_token::id(),
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    let token_account_rent = svm.minimum_balance_for_rent_exemption(TokenAccount::LEN);
+    assert_eq!(
+        token_balance(&svm, &vault_token_account)?,
+        deposit_amount,
+        "Vault token balance should increase by deposit amount"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before - balance(&svm, &user_account_pda) - token_account_rent - fee(1),
+        "User should pay the rent of both accounts and the transaction fee"
+    );
+
+    // The deposit event records what reached the vault, which without a transfer fee
+    // is the whole amount
+    let events = decode_events(&program_id, &metadata.logs);
+    let [VaultEvent::TokenDeposit(deposit_event)] = events.as_slice() else {
+        panic!("Expected a single token deposit event, got {:?}", events);
+    };
+    assert_eq!(deposit_event.user, user_pubkey);
+    assert_eq!(deposit_event.vault, vault_pda);
+    assert_eq!(deposit_event.mint, mint.pubkey());
+    assert_eq!(deposit_event.amount, deposit_amount);
+    assert_eq!(deposit_event.received, deposit_amount);
+    assert_eq!(deposit_event.vault_balance, deposit_amount);
+
+    // Withdraw part of the tokens back to the user, which only costs the fee
+    let withdraw_amount = 150_000;
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw_token(
+            &program_id,
+            &user_pubkey,
+            &mint.pubkey(),
+            &user_token_account,
+            &spl_token::id(),
+            withdraw_amount,
+            &[],
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        token_balance(&svm, &vault_token_account)?,
+        deposit_amount - withdraw_amount,
+        "Vault token balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        token_balance(&svm, &user_token_account)?,
+        minted_amount - deposit_amount + withdraw_amount,
+        "User token balance should reflect the deposit and withdrawal"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before - fee(1),
+        "User should only pay the transaction fee"
+    );
+
+    Ok(())
+}
+
+pub fn token_hook_multisig(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // A Token-2022 mint with a transfer hook. The compute budget program accepts any
+    // instruction, so it stands in for a hook program without extra accounts of its
+    // own, and only has to be passed along with every transfer.
+    let hook_program_id = COMPUTE_BUDGET_PROGRAM_ID;
+    let hook_accounts = [AccountMeta::new_readonly(hook_program_id, false)];
+    let token_program_id = spl_token_2022::id();
+    let mint = Keypair::new();
+    let mint_len =
+        ExtensionType::try_calculate_account_len::<Mint2022>(&[ExtensionType::TransferHook])?;
+    let user_token_account = get_associated_token_address_with_program_id(
+        &user_pubkey,
+        &mint.pubkey(),
+        &token_program_id,
+    );
+    let vault_token_account =
+        get_associated_token_address_with_program_id(&vault_pda, &mint.pubkey(), &token_program_id);
+    let minted_amount = 1_000_000;
+    let setup_instructions = [
+        system_instruction::create_account(
+            &user_pubkey,
+            &mint.pubkey(),
+            svm.minimum_balance_for_rent_exemption(mint_len),
+            mint_len as u64,
+            &token_program_id,
+        ),
+        transfer_hook_instruction::initialize(
+            &token_program_id,
+            &mint.pubkey(),
+            None,
+            Some(hook_program_id),
+        )?,
+        token_2022_instruction::initialize_mint2(
+            &token_program_id,
+            &mint.pubkey(),
+            &user_pubkey,
+            None,
+            6,
+        )?,
+        create_associated_token_account(
+            &user_pubkey,
+            &user_pubkey,
+            &mint.pubkey(),
+            &token_program_id,
+        ),
+    ];
+    let setup_transaction = Transaction::new_signed_with_payer(
+        &setup_instructions,
+        Some(&user_pubkey),
+        &[&payer, &mint],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(setup_transaction)
+        .map_err(|failed| anyhow!("Token setup failed: {}", failed.err))?;
+    send(
+        &mut svm,
+        &payer,
+        token_2022_instruction::mint_to(
+            &token_program_id,
+            &mint.pubkey(),
+            &user_token_account,
+            &user_pubkey,
+            &[],
+            minted_amount,
+        )?,
+        &[],
+    )?;
+
+    let token_balance = |svm: &LiteSVM, address: &Pubkey| -> Result<u64> {
+        let account = svm
+            .get_account(address)
+            .ok_or_else(|| anyhow!("Token account {} not found", address))?;
+        Ok(
+            StateWithExtensions::<TokenAccount2022>::unpack(&account.data)?
+                .base
+                .amount,
+        )
+    };
+
+    let deposit_amount = 400_000;
+    let mut deposit = implementation.encode(instruction::deposit_token(
+        &program_id,
+        &user_pubkey,
+        &mint.pubkey(),
+        &user_token_account,
+        &token_program_id,
+        deposit_amount,
+    ));
+    deposit.accounts.extend_from_slice(&hook_accounts);
+    send(&mut svm, &payer, deposit, &[])?;
+
+    // The user hands control to a 2-of-2 multisig
+    let members = [Keypair::new(), Keypair::new()];
+    let member_keys: Vec<Pubkey> = members.iter().map(|member| member.pubkey()).collect();
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::set_multisig(
+            &program_id,
+            &user_pubkey,
+            &user_pubkey,
+            member_keys.clone(),
+            2,
+        )),
+        &[],
+    )?;
+
+    // Approvals among the hook accounts are forwarded to the token program, not counted
+    let withdraw_amount = 150_000;
+    let mut withdraw = implementation.encode(instruction::withdraw_token(
+        &program_id,
+        &user_pubkey,
+        &mint.pubkey(),
+        &user_token_account,
+        &token_program_id,
+        withdraw_amount,
+        &[],
+    ));
+    withdraw.accounts.extend_from_slice(&hook_accounts);
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            instruction::with_signers(withdraw, &member_keys),
+            &[&members[0], &members[1]],
+        )),
+        Some(VaultError::NotEnoughSigners),
+        "Approvals after the counted signers should not satisfy the multisig"
+    );
+
+    // The counted approvals come first and the hook accounts after them
+    let mut withdraw = implementation.encode(instruction::withdraw_token(
+        &program_id,
+        &user_pubkey,
+        &mint.pubkey(),
+        &user_token_account,
+        &token_program_id,
+        withdraw_amount,
+        &member_keys,
+    ));
+    withdraw.accounts.extend_from_slice(&hook_accounts);
+    send(&mut svm, &payer, withdraw, &[&members[0], &members[1]])?;
+    assert_eq!(
+        token_balance(&svm, &vault_token_account)?,
+        deposit_amount - withdraw_amount,
+        "Vault token balance should decrease by withdraw amount"
+    );
+    assert_eq!(
+        token_balance(&svm, &user_token_account)?,
+        minted_amount - deposit_amount + withdraw_amount,
+        "User token balance should reflect the deposit and withdrawal"
+    );
+
+    Ok(())
+}
+
+pub fn migrate_baseline(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 2);
+
+    // Get the user and vault PDAs
+    let user_pubkey = payer.pubkey();
+    let (user_account_pda, _) = find_user_account_address(&user_pubkey, &program_id);
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+
+    // Create a current account, then rewrite it the way the first release stored it:
+    // the user, the bumps and an is_initialized flag, 35 bytes in all
+    let deposit_amount = LAMPORTS_PER_SOL / 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    let current = svm
+        .get_account(&user_account_pda)
+        .ok_or_else(|| anyhow!("User account not found"))?;
+    let mut baseline_data = current.data[8..8 + 32 + 1 + 1].to_vec();
+    baseline_data.push(1);
+    let baseline_rent = svm.minimum_balance_for_rent_exemption(baseline_data.len());
+    svm.set_account(
+        user_account_pda,
+        Account {
+            lamports: baseline_rent,
+            data: baseline_data.clone(),
+            ..current.clone()
+        },
+    )?;
+    assert!(
+        UserAccount::unpack(&baseline_data)?.is_baseline(),
+        "User account should read back as baseline"
+    );
+
+    // Deposits and withdrawals accept the baseline layout as it is, without recording
+    // lifetime totals
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount / 2,
+        )),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount / 2,
+        "Vault balance should reflect the deposit and the withdrawal"
+    );
+    let baseline = svm
+        .get_account(&user_account_pda)
+        .ok_or_else(|| anyhow!("User account not found"))?;
+    assert_eq!(
+        baseline.data, baseline_data,
+        "Deposit and withdraw should leave a baseline account as it was"
+    );
+
+    // The other instructions need the fields added since
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::request_withdraw(&program_id, &user_pubkey, 1)),
+            &[],
+        )),
+        Some(VaultError::DeserializationFailed),
+        "RequestWithdraw should refuse a baseline account"
+    );
+
+    // Migrating grows the account to the current layout with the new fields zeroed,
+    // the user pays the extra rent
+    let balance_before = balance(&svm, &user_pubkey);
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::migrate(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    let migrated = svm
+        .get_account(&user_account_pda)
+        .ok_or_else(|| anyhow!("User account not found"))?;
+    let expected = UserAccount {
+        total_deposited: 0,
+        deposit_count: 0,
+        last_deposit_slot: 0,
+        ..UserAccount::unpack(&current.data)?
+    };
+    assert_eq!(
+        migrated.data,
+        expected.pack(),
+        "Migrated account should match the current layout, with zero totals"
+    );
+    assert_eq!(
+        migrated.lamports, current.lamports,
+        "Migrated account should hold the rent of the current layout"
+    );
+    assert_eq!(
+        balance(&svm, &user_pubkey),
+        balance_before - (current.lamports - baseline_rent) - fee(1),
+        "User should pay the extra rent and the transaction fee"
+    );
+
+    // Migrating again changes nothing, and the vault now records its totals
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::migrate(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw(
+            &program_id,
+            &user_pubkey,
+            deposit_amount / 4,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount / 4,
+        "Vault balance should decrease by withdraw amount"
+    );
+    let migrated = svm
+        .get_account(&user_account_pda)
+        .ok_or_else(|| anyhow!("User account not found"))?;
+    assert_eq!(
+        UserAccount::unpack(&migrated.data)?.total_withdrawn,
+        deposit_amount / 4,
+        "Only the withdrawal after the migration should be counted"
+    );
+
+    Ok(())
+}
+
+pub fn stake(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 5);
+    let vote_account = create_vote_account(&mut svm, &payer);
+
+    // Get the user, vault and stake PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (stake_pda, _) = find_stake_address(&user_pubkey, &program_id);
+
+    let deposit_amount = LAMPORTS_PER_SOL * 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+
+    // Nothing, or more than the vault holds, cannot be staked
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::delegate_stake(
+                &program_id,
+                &user_pubkey,
+                &vote_account,
+                0
+            )),
+            &[],
+        )),
+        Some(VaultError::InvalidAmount),
+        "Delegating nothing should fail"
+    );
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::delegate_stake(
+                &program_id,
+                &user_pubkey,
+                &vote_account,
+                deposit_amount + 1
+            )),
+            &[],
+        )),
+        Some(VaultError::InsufficientFunds),
+        "Delegating more than the vault holds should fail"
+    );
+
+    // The stake account is created with the lamports from the vault, owned and
+    // withdrawable by the vault only
+    let stake_amount = LAMPORTS_PER_SOL;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::delegate_stake(
+            &program_id,
+            &user_pubkey,
+            &vote_account,
+            stake_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount - stake_amount,
+        "Vault balance should decrease by the stake amount"
+    );
+    let stake_account = svm
+        .get_account(&stake_pda)
+        .ok_or_else(|| anyhow!("Stake account not found"))?;
+    assert_eq!(stake_account.owner, stake::program::id());
+    assert_eq!(stake_account.lamports, stake_amount);
+    let stake_state = StakeStateV2::deserialize(&mut stake_account.data.as_slice())?;
+    let authorized = stake_state
+        .authorized()
+        .ok_or_else(|| anyhow!("Stake account is not initialized"))?;
+    assert_eq!(authorized.staker, vault_pda, "Vault should be the staker");
+    assert_eq!(
+        authorized.withdrawer, vault_pda,
+        "Vault should be the withdrawer"
+    );
+    let delegation = stake_state
+        .delegation()
+        .ok_or_else(|| anyhow!("Stake account is not delegated"))?;
+    assert_eq!(delegation.voter_pubkey, vote_account);
+    assert_eq!(
+        delegation.stake,
+        stake_amount - svm.minimum_balance_for_rent_exemption(StakeStateV2::size_of()),
+        "Everything above the stake account rent should be delegated"
+    );
+
+    // The vault stakes through a single account, so delegating again is refused
+    assert_eq!(
+        vault_error(send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::delegate_stake(
+                &program_id,
+                &user_pubkey,
+                &vote_account,
+                stake_amount / 2
+            )),
+            &[],
+        )),
+        Some(VaultError::StakeAccountInUse),
+        "Delegating while the stake account is in use should fail"
+    );
+
+    // Another user cannot act on the stake account through their own vault
+    let other = funded_keypair(&mut svm, LAMPORTS_PER_SOL);
+    send(
+        &mut svm,
+        &other,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &other.pubkey(),
+            LAMPORTS_PER_SOL / 2,
+        )),
+        &[],
+    )?;
+    let mut deactivate_other =
+        implementation.encode(instruction::deactivate_stake(&program_id, &other.pubkey()));
+    deactivate_other.accounts[3].pubkey = stake_pda;
+    assert_eq!(
+        vault_error(send(&mut svm, &other, deactivate_other, &[])),
+        Some(VaultError::InvalidStakeAccount),
+        "Another vault's stake account should be rejected"
+    );
+
+    // Active stake cannot be withdrawn
+    assert!(
+        send(
+            &mut svm,
+            &payer,
+            implementation.encode(instruction::withdraw_stake(&program_id, &user_pubkey)),
+            &[],
+        )
+        .is_err(),
+        "Withdrawing active stake should fail"
+    );
+
+    // Stake deactivated in the epoch it was delegated in never activates, so it can be
+    // withdrawn right away, and the emptied stake account is deleted
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deactivate_stake(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::withdraw_stake(&program_id, &user_pubkey)),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount,
+        "Vault should get the whole stake back"
+    );
+    assert_eq!(
+        balance(&svm, &stake_pda),
+        0,
+        "Stake account should be emptied"
+    );
+
+    // The vault can delegate again afterwards
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::delegate_stake(
+            &program_id,
+            &user_pubkey,
+            &vote_account,
+            stake_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(balance(&svm, &stake_pda), stake_amount);
+
+    Ok(())
+}
+
+pub fn stake_prefunded(name: &str) -> Result<()> {
+    let implementation = implementation(name);
+    let program_id = implementation.program_id;
+    let mut svm = setup(implementation, &Pubkey::new_unique());
+    let payer = funded_keypair(&mut svm, LAMPORTS_PER_SOL * 5);
+    let vote_account = create_vote_account(&mut svm, &payer);
+
+    // Get the user, vault and stake PDAs
+    let user_pubkey = payer.pubkey();
+    let (vault_pda, _) = find_vault_address(&user_pubkey, &program_id);
+    let (stake_pda, _) = find_stake_address(&user_pubkey, &program_id);
+
+    let deposit_amount = LAMPORTS_PER_SOL * 2;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::deposit(
+            &program_id,
+            &user_pubkey,
+            deposit_amount,
+        )),
+        &[],
+    )?;
+
+    // Someone else funds the stake address before the vault ever delegates
+    let prefund = svm.minimum_balance_for_rent_exemption(0);
+    svm.airdrop(&stake_pda, prefund).expect("Airdrop failed");
+
+    // Delegating still works and the lamports already there are staked as well
+    let stake_amount = LAMPORTS_PER_SOL;
+    send(
+        &mut svm,
+        &payer,
+        implementation.encode(instruction::delegate_stake(
+            &program_id,
+            &user_pubkey,
+            &vote_account,
+            stake_amount,
+        )),
+        &[],
+    )?;
+    assert_eq!(
+        balance(&svm, &vault_pda),
+        deposit_amount - stake_amount,
+        "Vault balance should decrease by the stake amount"
+    );
+    let stake_account = svm
+        .get_account(&stake_pda)
+        .ok_or_else(|| anyhow!("Stake account not found"))?;
+    assert_eq!(stake_account.owner, stake::program::id());
+    assert_eq!(stake_account.lamports, stake_amount + prefund);
+    let stake_state = StakeStateV2::deserialize(&mut stake_account.data.as_slice())?;
+    let delegation = stake_state
+        .delegation()
+        .ok_or_else(|| anyhow!("Stake account is not delegated"))?;
+    assert_eq!(delegation.voter_pubkey, vote_account);
+    assert_eq!(
+        delegation.stake,
+        stake_amount + prefund - svm.minimum_balance_for_rent_exemption(StakeStateV2::size_of()),
+        "Everything above the stake account rent should be delegated"
+    );
+
+    Ok(())
+}
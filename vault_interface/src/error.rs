@@ -0,0 +1,104 @@
+// Custom error codes of the vault program. Every implementation returns the same
+// codes, starting at 6000 as in Anchor, so a failed transaction can be mapped back to
+// a VaultError regardless of which program processed it.
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum VaultError {
+    InsufficientFunds = 6000,
+    VaultTimelocked,
+    InvalidWithdrawDelay,
+    InvalidAmount,
+    WithdrawalAlreadyPending,
+    NoPendingWithdrawal,
+    WithdrawalStillLocked,
+    NotEnoughSigners,
+    InvalidMultisig,
+    InvalidWithdrawAuthority,
+    AllowanceExceeded,
+    WithdrawLimitExceeded,
+    InvalidWithdrawLimit,
+    ProgramPaused,
+    NotUpgradeAuthority,
+    InvalidAdmin,
+    InvalidPauseFlags,
+    InvalidFee,
+    InsufficientFees,
+    InvalidSystemProgram,
+    InvalidUserAccount,
+    InvalidVault,
+    UserMismatch,
+    InvalidAccountOwner,
+    DeserializationFailed,
+    InvalidConfig,
+    InvalidTreasury,
+    InvalidVaultTokenAccount,
+    InvalidTokenProgram,
+    InvalidAssociatedTokenProgram,
+    InvalidProgramData,
+    NotAdmin,
+    VaultBelowRentExempt,
+    InvalidStakeAccount,
+    InvalidStakeProgram,
+    StakeAccountInUse,
+}
+
+impl VaultError {
+    // Map a custom error code back to its variant
+    pub fn from_code(code: u32) -> Option<Self> {
+        use VaultError::*;
+        let error = match code {
+            6000 => InsufficientFunds,
+            6001 => VaultTimelocked,
+            6002 => InvalidWithdrawDelay,
+            6003 => InvalidAmount,
+            6004 => WithdrawalAlreadyPending,
+            6005 => NoPendingWithdrawal,
+            6006 => WithdrawalStillLocked,
+            6007 => NotEnoughSigners,
+            6008 => InvalidMultisig,
+            6009 => InvalidWithdrawAuthority,
+            6010 => AllowanceExceeded,
+            6011 => WithdrawLimitExceeded,
+            6012 => InvalidWithdrawLimit,
+            6013 => ProgramPaused,
+            6014 => NotUpgradeAuthority,
+            6015 => InvalidAdmin,
+            6016 => InvalidPauseFlags,
+            6017 => InvalidFee,
+            6018 => InsufficientFees,
+            6019 => InvalidSystemProgram,
+            6020 => InvalidUserAccount,
+            6021 => InvalidVault,
+            6022 => UserMismatch,
+            6023 => InvalidAccountOwner,
+            6024 => DeserializationFailed,
+            6025 => InvalidConfig,
+            6026 => InvalidTreasury,
+            6027 => InvalidVaultTokenAccount,
+            6028 => InvalidTokenProgram,
+            6029 => InvalidAssociatedTokenProgram,
+            6030 => InvalidProgramData,
+            6031 => NotAdmin,
+            6032 => VaultBelowRentExempt,
+            6033 => InvalidStakeAccount,
+            6034 => InvalidStakeProgram,
+            6035 => StakeAccountInUse,
+            _ => return None,
+        };
+        Some(error)
+    }
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} ({})", self, self.code())
+    }
+}
+
+impl std::error::Error for VaultError {}
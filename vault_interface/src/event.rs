@@ -0,0 +1,159 @@
+// Decoder for the typed events the vault program logs. Each event is logged with
+// sol_log_data as an 8-byte discriminator followed by the borsh encoded event, which
+// the runtime prints as "Program data: <base64>". The discriminator is the first 8
+// bytes of sha256("event:<name>"), as in Anchor, so this decodes every implementation.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::BorshDeserialize;
+use solana_program::pubkey::Pubkey;
+use std::fmt;
+
+const DEPOSIT_EVENT_DISCRIMINATOR: [u8; 8] = [120, 248, 61, 83, 31, 142, 107, 144];
+const WITHDRAW_EVENT_DISCRIMINATOR: [u8; 8] = [22, 9, 133, 26, 160, 44, 71, 192];
+const TOKEN_DEPOSIT_EVENT_DISCRIMINATOR: [u8; 8] = [27, 43, 27, 81, 3, 244, 105, 252];
+
+// Emitted when lamports are deposited into a vault
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize)]
+pub struct DepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports paid in, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the deposit
+    pub slot: u64,
+}
+
+// Emitted when lamports leave a vault, whether withdrawn, claimed, drained on close
+// or paid to a delegate
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize)]
+pub struct WithdrawEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA
+    pub amount: u64,        // lamports taken out of the vault, including the fee
+    pub fee: u64,           // part of the amount routed to the treasury
+    pub vault_balance: u64, // vault lamports after the withdrawal
+    pub slot: u64,
+}
+
+// Emitted when tokens are deposited into a vault token account
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize)]
+pub struct TokenDepositEvent {
+    pub user: Pubkey,       // owner of the vault
+    pub vault: Pubkey,      // vault PDA, owner of the vault token account
+    pub mint: Pubkey,       // mint of the deposited tokens
+    pub amount: u64,        // tokens the user sent
+    pub received: u64,      // tokens that reached the vault, less any transfer fee
+    pub vault_balance: u64, // vault token account balance after the deposit
+    pub slot: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VaultEvent {
+    Deposit(DepositEvent),
+    Withdraw(WithdrawEvent),
+    TokenDeposit(TokenDepositEvent),
+}
+
+impl VaultEvent {
+    // Decode one event from its logged bytes, None for data that is not a vault event
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (discriminator, payload) = data.split_first_chunk::<8>()?;
+        match *discriminator {
+            DEPOSIT_EVENT_DISCRIMINATOR => DepositEvent::try_from_slice(payload)
+                .ok()
+                .map(VaultEvent::Deposit),
+            WITHDRAW_EVENT_DISCRIMINATOR => WithdrawEvent::try_from_slice(payload)
+                .ok()
+                .map(VaultEvent::Withdraw),
+            TOKEN_DEPOSIT_EVENT_DISCRIMINATOR => TokenDepositEvent::try_from_slice(payload)
+                .ok()
+                .map(VaultEvent::TokenDeposit),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VaultEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, user, vault, amount, fee, vault_balance, slot) = match self {
+            VaultEvent::Deposit(event) => (
+                "Deposit",
+                event.user,
+                event.vault,
+                event.amount,
+                event.fee,
+                event.vault_balance,
+                event.slot,
+            ),
+            VaultEvent::Withdraw(event) => (
+                "Withdraw",
+                event.user,
+                event.vault,
+                event.amount,
+                event.fee,
+                event.vault_balance,
+                event.slot,
+            ),
+            VaultEvent::TokenDeposit(event) => {
+                return write!(
+                    f,
+                    "TokenDeposit of {} tokens ({} received) of mint {} by {} on vault {}, \
+                     balance {} at slot {}",
+                    event.amount,
+                    event.received,
+                    event.mint,
+                    event.user,
+                    event.vault,
+                    event.vault_balance,
+                    event.slot
+                );
+            }
+        };
+        write!(
+            f,
+            "{} of {} lamports ({} fee) by {} on vault {}, balance {} at slot {}",
+            kind, amount, fee, user, vault, vault_balance, slot
+        )
+    }
+}
+
+// Pull the vault's events out of a transaction's log messages, in emission order.
+// Only data logged while the vault program itself is running is decoded, so events
+// of programs it invokes, or that invoke it, are skipped.
+pub fn decode_events(program_id: &Pubkey, logs: &[String]) -> Vec<VaultEvent> {
+    let program_id = program_id.to_string();
+    let mut invocations: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+
+    for log in logs {
+        let Some(message) = log.strip_prefix("Program ") else {
+            continue;
+        };
+
+        if let Some(data) = message.strip_prefix("data: ") {
+            if invocations.last() != Some(&program_id.as_str()) {
+                continue;
+            }
+            // A single field per log line, the discriminator and event together
+            let event = data
+                .split_whitespace()
+                .next()
+                .and_then(|field| STANDARD.decode(field).ok())
+                .and_then(|bytes| VaultEvent::decode(&bytes));
+            events.extend(event);
+            continue;
+        }
+
+        // Track the invocation stack from "Program <id> invoke [n]" and the matching
+        // "Program <id> success" or "Program <id> failed: <error>"
+        let mut words = message.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(id), Some("invoke")) => invocations.push(id),
+            (Some(_), Some("success" | "failed:")) => {
+                invocations.pop();
+            }
+            _ => {}
+        }
+    }
+
+    events
+}
@@ -0,0 +1,574 @@
+// Instructions of the vault program and builders for them. The encoding is the one the
+// native and pinocchio programs decode: the borsh variant index followed by the fields.
+// The Anchor program takes the same fields behind an 8-byte discriminator instead, see
+// pack_anchor. The builders fill in the accounts in the order every implementation
+// expects and mark the user as the signer. Multisig approvers go after the listed
+// accounts, see with_signers. WithdrawToken counts its approvers in the instruction
+// data instead, so that transfer hook accounts can follow them.
+use crate::pda::{
+    find_config_address, find_program_data_address, find_stake_address, find_treasury_address,
+    find_user_account_address, find_vault_address, find_vault_token_address,
+    ASSOCIATED_TOKEN_PROGRAM_ID, STAKE_CONFIG_ID, STAKE_PROGRAM_ID,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+
+// In program order so the borsh tags line up
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub enum ProgramInstruction {
+    Deposit { amount: u64 },
+    Withdraw { amount: u64 },
+    Close,
+    DepositToken { amount: u64 },
+    WithdrawToken { amount: u64, signer_count: u8 },
+    DepositWithDelay { amount: u64, withdraw_delay: i64 },
+    RequestWithdraw { amount: u64 },
+    ClaimWithdraw,
+    CancelWithdraw,
+    SetMultisig { signers: Vec<Pubkey>, threshold: u8 },
+    SetWithdrawAuthority { authority: Pubkey, allowance: u64 },
+    RevokeWithdrawAuthority,
+    DelegateWithdraw { amount: u64 },
+    DepositFor { beneficiary: Pubkey, amount: u64 },
+    SetWithdrawLimit { limit: u64, window: i64 },
+    InitializeConfig { admin: Pubkey },
+    SetPaused { deposits: bool, withdrawals: bool },
+    TransferAdmin { new_admin: Pubkey },
+    SetFees { deposit_bps: u16, withdraw_bps: u16 },
+    WithdrawFees { amount: u64 },
+    Migrate,
+    WithdrawAll,
+    DelegateStake { amount: u64 },
+    DeactivateStake,
+    WithdrawStake,
+}
+
+// Anchor instruction discriminators, the first 8 bytes of sha256("global:<name>"), in
+// variant order
+pub const ANCHOR_DISCRIMINATORS: [[u8; 8]; 25] = [
+    [242, 35, 198, 137, 82, 225, 242, 182],  // deposit
+    [183, 18, 70, 156, 148, 109, 161, 34],   // withdraw
+    [98, 165, 201, 177, 108, 65, 206, 96],   // close
+    [11, 156, 96, 218, 39, 163, 180, 19],    // deposit_token
+    [136, 235, 181, 5, 101, 109, 57, 81],    // withdraw_token
+    [110, 109, 250, 169, 189, 179, 8, 163],  // deposit_with_delay
+    [137, 95, 187, 96, 250, 138, 31, 182],   // request_withdraw
+    [232, 89, 154, 117, 16, 204, 182, 224],  // claim_withdraw
+    [112, 53, 226, 58, 158, 30, 37, 168],    // cancel_withdraw
+    [251, 6, 245, 35, 115, 42, 77, 186],     // set_multisig
+    [199, 146, 140, 67, 1, 90, 8, 222],      // set_withdraw_authority
+    [192, 28, 85, 211, 241, 241, 81, 159],   // revoke_withdraw_authority
+    [89, 6, 67, 206, 216, 140, 118, 63],     // delegate_withdraw
+    [193, 39, 228, 88, 160, 254, 92, 53],    // deposit_for
+    [198, 158, 141, 22, 248, 120, 105, 42],  // set_withdraw_limit
+    [208, 127, 21, 1, 194, 190, 196, 70],    // initialize_config
+    [91, 60, 125, 192, 176, 225, 166, 218],  // set_paused
+    [42, 242, 66, 106, 228, 10, 111, 156],   // transfer_admin
+    [137, 178, 49, 58, 0, 245, 242, 190],    // set_fees
+    [198, 212, 171, 109, 144, 215, 174, 89], // withdraw_fees
+    [155, 234, 231, 146, 236, 158, 162, 30], // migrate
+    [96, 246, 166, 130, 229, 50, 43, 70],    // withdraw_all
+    [50, 110, 95, 179, 194, 75, 140, 246],   // delegate_stake
+    [165, 158, 229, 97, 168, 220, 187, 225], // deactivate_stake
+    [153, 8, 22, 138, 105, 176, 87, 66],     // withdraw_stake
+];
+
+impl ProgramInstruction {
+    pub fn pack(&self) -> Vec<u8> {
+        borsh::to_vec(self).unwrap()
+    }
+
+    // Encode the instruction the way the Anchor program expects it
+    pub fn pack_anchor(&self) -> Vec<u8> {
+        let data = self.pack();
+        let mut anchor_data = ANCHOR_DISCRIMINATORS[data[0] as usize].to_vec();
+        anchor_data.extend_from_slice(&data[1..]);
+        anchor_data
+    }
+
+    // Decode instruction data in the borsh encoding
+    pub fn unpack(data: &[u8]) -> Option<Self> {
+        Self::try_from_slice(data).ok()
+    }
+}
+
+// Append signer accounts, the multisig members approving the instruction
+pub fn with_signers(mut instruction: Instruction, signers: &[Pubkey]) -> Instruction {
+    instruction.accounts.extend(
+        signers
+            .iter()
+            .map(|signer| AccountMeta::new_readonly(*signer, true)),
+    );
+    instruction
+}
+
+// Accounts of the instructions that move lamports in or out of a vault. The first
+// account is the user, or whoever funds a deposit for them.
+fn vault_accounts(program_id: &Pubkey, signer: &Pubkey, user: &Pubkey) -> Vec<AccountMeta> {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    let (vault_pda, _) = find_vault_address(user, program_id);
+    let (config_pda, _) = find_config_address(program_id);
+    let (treasury_pda, _) = find_treasury_address(program_id);
+    vec![
+        AccountMeta::new(*signer, true),           // User (signer, writable)
+        AccountMeta::new(user_account_pda, false), // User account PDA (writable)
+        AccountMeta::new(vault_pda, false),        // Vault PDA (writable)
+        AccountMeta::new_readonly(system_program::id(), false), // System program
+        AccountMeta::new_readonly(config_pda, false), // Config PDA
+        AccountMeta::new(treasury_pda, false),     // Treasury PDA (writable)
+    ]
+}
+
+// Accounts of the instructions that only update the user account
+fn user_account_accounts(program_id: &Pubkey, user: &Pubkey) -> Vec<AccountMeta> {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    vec![
+        AccountMeta::new_readonly(*user, true),    // User (signer)
+        AccountMeta::new(user_account_pda, false), // User account PDA (writable)
+    ]
+}
+
+// Accounts of the admin instructions that move lamports in or out of the treasury
+fn treasury_accounts(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    config_writable: bool,
+) -> Vec<AccountMeta> {
+    let (config_pda, _) = find_config_address(program_id);
+    let (treasury_pda, _) = find_treasury_address(program_id);
+    vec![
+        AccountMeta::new(*admin, true), // Admin (signer, writable)
+        AccountMeta {
+            pubkey: config_pda,
+            is_signer: false,
+            is_writable: config_writable,
+        }, // Config PDA
+        AccountMeta::new(treasury_pda, false), // Treasury PDA (writable)
+        AccountMeta::new_readonly(system_program::id(), false), // System program
+    ]
+}
+
+// Accounts of the admin instructions that only update the config
+fn config_accounts(program_id: &Pubkey, admin: &Pubkey) -> Vec<AccountMeta> {
+    let (config_pda, _) = find_config_address(program_id);
+    vec![
+        AccountMeta::new_readonly(*admin, true), // Admin (signer)
+        AccountMeta::new(config_pda, false),     // Config PDA (writable)
+    ]
+}
+
+fn instruction(
+    program_id: &Pubkey,
+    accounts: Vec<AccountMeta>,
+    data: ProgramInstruction,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.pack(),
+    }
+}
+
+// Deposit lamports, creating the user account and vault on first use
+pub fn deposit(program_id: &Pubkey, user: &Pubkey, amount: u64) -> Instruction {
+    instruction(
+        program_id,
+        vault_accounts(program_id, user, user),
+        ProgramInstruction::Deposit { amount },
+    )
+}
+
+pub fn withdraw(program_id: &Pubkey, user: &Pubkey, amount: u64) -> Instruction {
+    instruction(
+        program_id,
+        vault_accounts(program_id, user, user),
+        ProgramInstruction::Withdraw { amount },
+    )
+}
+
+// Withdraw the whole vault balance, leaving the user account open
+pub fn withdraw_all(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        vault_accounts(program_id, user, user),
+        ProgramInstruction::WithdrawAll,
+    )
+}
+
+// Drain the vault and reclaim the user account rent
+pub fn close(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        vault_accounts(program_id, user, user),
+        ProgramInstruction::Close,
+    )
+}
+
+// Deposit tokens into the vault's associated token account, which is created on first
+// use. Token-2022 transfer hook accounts are appended by the caller.
+pub fn deposit_token(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    mint: &Pubkey,
+    user_token_account: &Pubkey,
+    token_program_id: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    let (vault_pda, _) = find_vault_address(user, program_id);
+    let vault_token_account = find_vault_token_address(user, mint, token_program_id, program_id);
+    let (config_pda, _) = find_config_address(program_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*user, true),                // User (signer, writable)
+            AccountMeta::new(user_account_pda, false),    // User account PDA (writable)
+            AccountMeta::new_readonly(vault_pda, false),  // Vault PDA
+            AccountMeta::new_readonly(*mint, false),      // Mint
+            AccountMeta::new(*user_token_account, false), // User token account (writable)
+            AccountMeta::new(vault_token_account, false), // Vault token account (writable)
+            AccountMeta::new_readonly(*token_program_id, false), // Token program
+            AccountMeta::new_readonly(ASSOCIATED_TOKEN_PROGRAM_ID, false), // Associated token program
+            AccountMeta::new_readonly(system_program::id(), false),        // System program
+            AccountMeta::new_readonly(config_pda, false),                  // Config PDA
+        ],
+        ProgramInstruction::DepositToken { amount },
+    )
+}
+
+// Withdraw tokens with the approval of the given multisig members, if any. Accounts
+// for a transfer hook are appended after the members.
+pub fn withdraw_token(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    mint: &Pubkey,
+    user_token_account: &Pubkey,
+    token_program_id: &Pubkey,
+    amount: u64,
+    signers: &[Pubkey],
+) -> Instruction {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    let (vault_pda, _) = find_vault_address(user, program_id);
+    let vault_token_account = find_vault_token_address(user, mint, token_program_id, program_id);
+    let (config_pda, _) = find_config_address(program_id);
+    let signer_count = u8::try_from(signers.len()).expect("Too many multisig signers");
+    let instruction = instruction(
+        program_id,
+        vec![
+            AccountMeta::new_readonly(*user, true), // User (signer)
+            AccountMeta::new_readonly(user_account_pda, false), // User account PDA
+            AccountMeta::new_readonly(vault_pda, false), // Vault PDA
+            AccountMeta::new_readonly(*mint, false), // Mint
+            AccountMeta::new(*user_token_account, false), // User token account (writable)
+            AccountMeta::new(vault_token_account, false), // Vault token account (writable)
+            AccountMeta::new_readonly(*token_program_id, false), // Token program
+            AccountMeta::new_readonly(config_pda, false), // Config PDA
+        ],
+        ProgramInstruction::WithdrawToken {
+            amount,
+            signer_count,
+        },
+    );
+    with_signers(instruction, signers)
+}
+
+// Deposit and, on first deposit only, set the delay between requesting and claiming
+// a withdrawal
+pub fn deposit_with_delay(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    amount: u64,
+    withdraw_delay: i64,
+) -> Instruction {
+    instruction(
+        program_id,
+        vault_accounts(program_id, user, user),
+        ProgramInstruction::DepositWithDelay {
+            amount,
+            withdraw_delay,
+        },
+    )
+}
+
+pub fn request_withdraw(program_id: &Pubkey, user: &Pubkey, amount: u64) -> Instruction {
+    instruction(
+        program_id,
+        user_account_accounts(program_id, user),
+        ProgramInstruction::RequestWithdraw { amount },
+    )
+}
+
+pub fn claim_withdraw(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        vault_accounts(program_id, user, user),
+        ProgramInstruction::ClaimWithdraw,
+    )
+}
+
+pub fn cancel_withdraw(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        user_account_accounts(program_id, user),
+        ProgramInstruction::CancelWithdraw,
+    )
+}
+
+// Replace the multisig members. The payer covers the rent when the user account grows
+// and receives the excess when it shrinks.
+pub fn set_multisig(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    user: &Pubkey,
+    signers: Vec<Pubkey>,
+    threshold: u8,
+) -> Instruction {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*payer, true),            // Payer (signer, writable)
+            AccountMeta::new_readonly(*user, true),    // User (signer)
+            AccountMeta::new(user_account_pda, false), // User account PDA (writable)
+            AccountMeta::new_readonly(system_program::id(), false), // System program
+        ],
+        ProgramInstruction::SetMultisig { signers, threshold },
+    )
+}
+
+// Let a delegate withdraw up to the allowance, u64::MAX for no cap
+pub fn set_withdraw_authority(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    authority: &Pubkey,
+    allowance: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        user_account_accounts(program_id, user),
+        ProgramInstruction::SetWithdrawAuthority {
+            authority: *authority,
+            allowance,
+        },
+    )
+}
+
+pub fn revoke_withdraw_authority(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        user_account_accounts(program_id, user),
+        ProgramInstruction::RevokeWithdrawAuthority,
+    )
+}
+
+// Withdraw from the user's vault to the delegate, who signs instead of the user
+pub fn delegate_withdraw(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    user: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vault_accounts(program_id, user, user);
+    accounts[0] = AccountMeta::new_readonly(*user, false); // User
+    accounts.insert(0, AccountMeta::new(*authority, true)); // Withdraw authority (signer, writable)
+    instruction(
+        program_id,
+        accounts,
+        ProgramInstruction::DelegateWithdraw { amount },
+    )
+}
+
+// Deposit into the beneficiary's vault, funded by the funder, who also pays the user
+// account rent on first deposit. The beneficiary does not need to sign.
+pub fn deposit_for(
+    program_id: &Pubkey,
+    funder: &Pubkey,
+    beneficiary: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        vault_accounts(program_id, funder, beneficiary),
+        ProgramInstruction::DepositFor {
+            beneficiary: *beneficiary,
+            amount,
+        },
+    )
+}
+
+// Cap withdrawals per window, 0 for no limit. Raising the limit is delayed by the
+// withdraw delay.
+pub fn set_withdraw_limit(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    limit: u64,
+    window: i64,
+) -> Instruction {
+    instruction(
+        program_id,
+        user_account_accounts(program_id, user),
+        ProgramInstruction::SetWithdrawLimit { limit, window },
+    )
+}
+
+// Create the config, signed by the program's upgrade authority
+pub fn initialize_config(
+    program_id: &Pubkey,
+    upgrade_authority: &Pubkey,
+    admin: &Pubkey,
+) -> Instruction {
+    let (config_pda, _) = find_config_address(program_id);
+    let (program_data, _) = find_program_data_address(program_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*upgrade_authority, true), // Upgrade authority (signer, writable)
+            AccountMeta::new(config_pda, false),        // Config PDA (writable)
+            AccountMeta::new_readonly(program_data, false), // Program data
+            AccountMeta::new_readonly(system_program::id(), false), // System program
+        ],
+        ProgramInstruction::InitializeConfig { admin: *admin },
+    )
+}
+
+pub fn set_paused(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    deposits: bool,
+    withdrawals: bool,
+) -> Instruction {
+    instruction(
+        program_id,
+        config_accounts(program_id, admin),
+        ProgramInstruction::SetPaused {
+            deposits,
+            withdrawals,
+        },
+    )
+}
+
+pub fn transfer_admin(program_id: &Pubkey, admin: &Pubkey, new_admin: &Pubkey) -> Instruction {
+    instruction(
+        program_id,
+        config_accounts(program_id, admin),
+        ProgramInstruction::TransferAdmin {
+            new_admin: *new_admin,
+        },
+    )
+}
+
+// Set the fees, creating the treasury on first use
+pub fn set_fees(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    deposit_bps: u16,
+    withdraw_bps: u16,
+) -> Instruction {
+    instruction(
+        program_id,
+        treasury_accounts(program_id, admin, true),
+        ProgramInstruction::SetFees {
+            deposit_bps,
+            withdraw_bps,
+        },
+    )
+}
+
+// Move collected fees from the treasury to the admin
+pub fn withdraw_fees(program_id: &Pubkey, admin: &Pubkey, amount: u64) -> Instruction {
+    instruction(
+        program_id,
+        treasury_accounts(program_id, admin, false),
+        ProgramInstruction::WithdrawFees { amount },
+    )
+}
+
+// Rewrite a baseline user account in the current layout, the user covers the extra rent
+pub fn migrate(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new(*user, true),             // User (signer, writable)
+            AccountMeta::new(user_account_pda, false), // User account PDA (writable)
+            AccountMeta::new_readonly(system_program::id(), false), // System program
+        ],
+        ProgramInstruction::Migrate,
+    )
+}
+
+// Move lamports from the vault into its stake account and delegate them to a vote
+// account. The stake account is created here, so a vault delegates to one validator at
+// a time.
+pub fn delegate_stake(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    vote_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    let (vault_pda, _) = find_vault_address(user, program_id);
+    let (stake_pda, _) = find_stake_address(user, program_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new_readonly(*user, true), // User (signer)
+            AccountMeta::new_readonly(user_account_pda, false), // User account PDA
+            AccountMeta::new(vault_pda, false),     // Vault PDA (writable)
+            AccountMeta::new(stake_pda, false),     // Stake PDA (writable)
+            AccountMeta::new_readonly(*vote_account, false), // Vote account
+            AccountMeta::new_readonly(sysvar::clock::id(), false), // Clock sysvar
+            AccountMeta::new_readonly(sysvar::rent::id(), false), // Rent sysvar
+            AccountMeta::new_readonly(sysvar::stake_history::id(), false), // Stake history sysvar
+            AccountMeta::new_readonly(STAKE_CONFIG_ID, false), // Stake config
+            AccountMeta::new_readonly(system_program::id(), false), // System program
+            AccountMeta::new_readonly(STAKE_PROGRAM_ID, false), // Stake program
+            AccountMeta::new_readonly(find_config_address(program_id).0, false), // Config PDA
+        ],
+        ProgramInstruction::DelegateStake { amount },
+    )
+}
+
+// Start cooling down the vault's stake, after which it can be withdrawn
+pub fn deactivate_stake(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    let (vault_pda, _) = find_vault_address(user, program_id);
+    let (stake_pda, _) = find_stake_address(user, program_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new_readonly(*user, true), // User (signer)
+            AccountMeta::new_readonly(user_account_pda, false), // User account PDA
+            AccountMeta::new_readonly(vault_pda, false), // Vault PDA
+            AccountMeta::new(stake_pda, false),     // Stake PDA (writable)
+            AccountMeta::new_readonly(sysvar::clock::id(), false), // Clock sysvar
+            AccountMeta::new_readonly(STAKE_PROGRAM_ID, false), // Stake program
+        ],
+        ProgramInstruction::DeactivateStake,
+    )
+}
+
+// Move everything in the stake account, rewards included, back into the vault. The
+// stake must be inactive, and the emptied stake account is deleted.
+pub fn withdraw_stake(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    let (user_account_pda, _) = find_user_account_address(user, program_id);
+    let (vault_pda, _) = find_vault_address(user, program_id);
+    let (stake_pda, _) = find_stake_address(user, program_id);
+    instruction(
+        program_id,
+        vec![
+            AccountMeta::new_readonly(*user, true), // User (signer)
+            AccountMeta::new_readonly(user_account_pda, false), // User account PDA
+            AccountMeta::new(vault_pda, false),     // Vault PDA (writable)
+            AccountMeta::new(stake_pda, false),     // Stake PDA (writable)
+            AccountMeta::new_readonly(sysvar::clock::id(), false), // Clock sysvar
+            AccountMeta::new_readonly(sysvar::stake_history::id(), false), // Stake history sysvar
+            AccountMeta::new_readonly(STAKE_PROGRAM_ID, false), // Stake program
+        ],
+        ProgramInstruction::WithdrawStake,
+    )
+}
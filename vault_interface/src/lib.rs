@@ -0,0 +1,25 @@
+// Client-side interface of the vault program, shared by the clients and tests of every
+// implementation: the instruction encoding and typed instruction builders, the PDA
+// derivations, decoders for the account layouts and the logged events, and the custom
+// error codes. A change to the instruction set, the seeds, the account layout, the
+// events or the errors only needs to be made here.
+#[cfg(all(feature = "solana-v1", feature = "solana-v2"))]
+compile_error!("features solana-v1 and solana-v2 are mutually exclusive");
+
+#[cfg(all(feature = "solana-v1", not(feature = "solana-v2")))]
+extern crate solana_program_v1 as solana_program;
+
+pub mod error;
+pub mod event;
+pub mod instruction;
+pub mod pda;
+pub mod state;
+
+#[cfg(test)]
+mod test;
+
+pub use error::VaultError;
+pub use event::VaultEvent;
+pub use instruction::ProgramInstruction;
+pub use pda::*;
+pub use state::{Config, UserAccount};
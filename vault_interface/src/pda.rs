@@ -0,0 +1,59 @@
+// Program derived addresses used by every implementation of the vault program
+use solana_program::{bpf_loader_upgradeable, pubkey, pubkey::Pubkey};
+
+// Associated token account program, which owns the vault token accounts
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+// Stake program, which owns the vault stake accounts
+pub const STAKE_PROGRAM_ID: Pubkey = pubkey!("Stake11111111111111111111111111111111111111");
+
+// Stake config account, unused by the stake program but still part of DelegateStake
+pub const STAKE_CONFIG_ID: Pubkey = pubkey!("StakeConfig11111111111111111111111111111111");
+
+// Per-user account holding the vault settings, seeded with the user key
+pub fn find_user_account_address(user_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[user_pubkey.as_ref()], program_id)
+}
+
+// System account holding the user's lamports
+pub fn find_vault_address(user_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", user_pubkey.as_ref()], program_id)
+}
+
+// Stake account the vault delegates from, seeded with the vault key. Its staker and
+// withdrawer are the vault PDA.
+pub fn find_stake_address(user_pubkey: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    let (vault, _) = find_vault_address(user_pubkey, program_id);
+    Pubkey::find_program_address(&[b"stake", vault.as_ref()], program_id)
+}
+
+// Singleton holding the admin, pause flags and fees
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], program_id)
+}
+
+// Singleton system account collecting the fees
+pub fn find_treasury_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"treasury"], program_id)
+}
+
+// Program data account of the upgradeable loader, which records the upgrade authority
+pub fn find_program_data_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id())
+}
+
+// Associated token account of the user's vault for a mint, under either token program
+pub fn find_vault_token_address(
+    user_pubkey: &Pubkey,
+    mint: &Pubkey,
+    token_program_id: &Pubkey,
+    program_id: &Pubkey,
+) -> Pubkey {
+    let (vault, _) = find_vault_address(user_pubkey, program_id);
+    Pubkey::find_program_address(
+        &[vault.as_ref(), token_program_id.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
@@ -0,0 +1,134 @@
+// Decoders for the accounts of the vault program. Every implementation stores the same
+// borsh encoded fields, with a few differences in the header:
+// - user accounts start with the Anchor account discriminator in every implementation
+// - baseline user accounts, from before the layout was versioned, end after the version
+//   byte (then the is_initialized flag), and only Anchor's have the discriminator
+// - the config starts with the Anchor account discriminator in every implementation
+use borsh::{io, BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+// The first 8 bytes of sha256("account:UserAccount")
+pub const USER_ACCOUNT_DISCRIMINATOR: [u8; 8] = [211, 33, 136, 16, 186, 110, 242, 127];
+
+// The first 8 bytes of sha256("account:Config")
+pub const CONFIG_DISCRIMINATOR: [u8; 8] = [155, 12, 170, 224, 30, 250, 204, 130];
+
+// Accounts created before the layout was versioned. Their version byte is the old
+// is_initialized flag, which was always true.
+pub const BASELINE_USER_ACCOUNT_VERSION: u8 = 1;
+
+// Layout written for new accounts and by Migrate
+pub const USER_ACCOUNT_VERSION: u8 = 2;
+
+// Offset of the version after the header, the size of the fields before the multisig
+// members and the size of the lifetime totals after them
+const VERSION_OFFSET: usize = 32 + 1 + 1;
+const FIELDS_SIZE: usize = 32 + 1 + 1 + 1 + 8 + 8 + 8 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+const TOTALS_SIZE: usize = 8 + 8 + 8 + 8 + 8;
+
+// user + user_bump + vault_bump + is_initialized
+const BASELINE_SIZE: usize = 32 + 1 + 1 + 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct UserAccount {
+    pub user: Pubkey,
+    pub user_bump: u8,
+    pub vault_bump: u8,
+    pub version: u8,                  // layout version
+    pub withdraw_delay: i64, // seconds between RequestWithdraw and ClaimWithdraw, 0 = disabled
+    pub pending_withdrawal: u64, // lamports requested but not yet claimed
+    pub unlock_timestamp: i64, // unix timestamp after which the pending withdrawal can be claimed
+    pub withdraw_authority: Pubkey, // delegate allowed to withdraw, Pubkey::default() = none
+    pub withdraw_allowance: u64, // lamports the delegate may still withdraw, u64::MAX = uncapped
+    pub withdraw_limit: u64, // lamports withdrawable per window, 0 = unlimited
+    pub withdraw_window: i64, // length of the rate limit window in seconds
+    pub window_start: i64,   // unix timestamp at which the current window started
+    pub window_withdrawn: u64, // lamports withdrawn in the current window
+    pub pending_withdraw_limit: u64, // raised limit waiting for its delay to pass
+    pub pending_withdraw_window: i64, // window length that comes with the pending limit
+    pub limit_unlock_timestamp: i64, // unix timestamp at which the pending limit applies, 0 = none
+    pub threshold: u8,       // multisig approvals required, 0 = the user alone controls the vault
+    pub signers: Vec<Pubkey>, // multisig members
+    pub total_deposited: u64, // lamports ever credited to the vault, after fees
+    pub total_withdrawn: u64, // lamports ever taken out of the vault, including fees
+    pub deposit_count: u64,  // number of lamport deposits
+    pub last_deposit_slot: u64, // slot of the latest lamport deposit, 0 = none
+    pub last_withdraw_slot: u64, // slot of the latest lamport withdrawal, 0 = none
+}
+
+impl UserAccount {
+    // Fields of an account without multisig members
+    pub const SIZE: usize = FIELDS_SIZE + 4 + TOTALS_SIZE;
+
+    // Read a user account written by any implementation, in either layout. Baseline
+    // accounts read as version 1 with every later field zero.
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        let baseline = data
+            .strip_prefix(&USER_ACCOUNT_DISCRIMINATOR)
+            .unwrap_or(data);
+        if baseline.len() == BASELINE_SIZE
+            && baseline[VERSION_OFFSET] == BASELINE_USER_ACCOUNT_VERSION
+        {
+            let mut current = baseline.to_vec();
+            current.resize(Self::SIZE, 0);
+            return Self::deserialize(&mut &current[..]);
+        }
+
+        match data.strip_prefix(&USER_ACCOUNT_DISCRIMINATOR) {
+            Some(fields) if fields.get(VERSION_OFFSET) == Some(&USER_ACCOUNT_VERSION) => {
+                Self::deserialize(&mut &fields[..])
+            }
+            _ => Err(unknown_layout()),
+        }
+    }
+
+    // Write the account in the layout of its version, as the programs would have:
+    // baseline accounts keep only the user and the bumps, without the discriminator like
+    // the native and pinocchio programs wrote them
+    pub fn pack(&self) -> Vec<u8> {
+        let fields = borsh::to_vec(self).expect("Serializing to a vector cannot fail");
+        if self.is_baseline() {
+            return fields[..BASELINE_SIZE].to_vec();
+        }
+        let mut data = USER_ACCOUNT_DISCRIMINATOR.to_vec();
+        data.extend(fields);
+        data
+    }
+
+    // Accounts from before the layout was versioned, which deposits and withdrawals
+    // accept as they are but which only record the lifetime totals once Migrate has
+    // rewritten them in the current layout
+    pub fn is_baseline(&self) -> bool {
+        self.version == BASELINE_USER_ACCOUNT_VERSION
+    }
+}
+
+fn unknown_layout() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Unknown user account layout")
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct Config {
+    pub admin: Pubkey,            // may pause the program and hand over the admin role
+    pub paused: bool,             // blocks deposits
+    pub withdrawals_paused: bool, // also blocks withdrawals, only set together with paused
+    pub bump: u8,
+    pub treasury_bump: u8,     // bump of the fee treasury PDA
+    pub deposit_fee_bps: u16,  // deposit fee in basis points
+    pub withdraw_fee_bps: u16, // withdraw fee in basis points
+}
+
+impl Config {
+    pub const SIZE: usize = 32 + 1 + 1 + 1 + 1 + 2 + 2;
+
+    // Account size including the discriminator
+    pub const LEN: usize = CONFIG_DISCRIMINATOR.len() + Self::SIZE;
+
+    // Read the config of any implementation
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        let fields = data.strip_prefix(&CONFIG_DISCRIMINATOR).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Missing config discriminator")
+        })?;
+        Self::try_from_slice(fields)
+    }
+}
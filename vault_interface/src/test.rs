@@ -0,0 +1,337 @@
+use crate::{
+    error::VaultError,
+    event::{decode_events, DepositEvent, VaultEvent},
+    instruction::{self, ProgramInstruction, ANCHOR_DISCRIMINATORS},
+    pda::{
+        find_config_address, find_stake_address, find_user_account_address, find_vault_address,
+        STAKE_PROGRAM_ID,
+    },
+    state::{
+        Config, UserAccount, BASELINE_USER_ACCOUNT_VERSION, CONFIG_DISCRIMINATOR,
+        USER_ACCOUNT_DISCRIMINATOR, USER_ACCOUNT_VERSION,
+    },
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_program::{hash::hash, pubkey::Pubkey, stake};
+
+fn sighash(preimage: &str) -> [u8; 8] {
+    hash(preimage.as_bytes()).to_bytes()[..8]
+        .try_into()
+        .unwrap()
+}
+
+fn user_account(version: u8) -> UserAccount {
+    UserAccount {
+        user: Pubkey::new_unique(),
+        user_bump: 254,
+        vault_bump: 253,
+        version,
+        withdraw_delay: 60,
+        pending_withdrawal: 0,
+        unlock_timestamp: 0,
+        withdraw_authority: Pubkey::default(),
+        withdraw_allowance: u64::MAX,
+        withdraw_limit: 0,
+        withdraw_window: 0,
+        window_start: 0,
+        window_withdrawn: 0,
+        pending_withdraw_limit: 0,
+        pending_withdraw_window: 0,
+        limit_unlock_timestamp: 0,
+        threshold: 1,
+        total_deposited: 0,
+        total_withdrawn: 0,
+        deposit_count: 0,
+        last_deposit_slot: 0,
+        last_withdraw_slot: 0,
+        signers: vec![Pubkey::new_unique()],
+    }
+}
+
+#[test]
+fn test_discriminators() {
+    let names = [
+        "deposit",
+        "withdraw",
+        "close",
+        "deposit_token",
+        "withdraw_token",
+        "deposit_with_delay",
+        "request_withdraw",
+        "claim_withdraw",
+        "cancel_withdraw",
+        "set_multisig",
+        "set_withdraw_authority",
+        "revoke_withdraw_authority",
+        "delegate_withdraw",
+        "deposit_for",
+        "set_withdraw_limit",
+        "initialize_config",
+        "set_paused",
+        "transfer_admin",
+        "set_fees",
+        "withdraw_fees",
+        "migrate",
+        "withdraw_all",
+        "delegate_stake",
+        "deactivate_stake",
+        "withdraw_stake",
+    ];
+    assert_eq!(names.len(), ANCHOR_DISCRIMINATORS.len());
+    for (name, discriminator) in names.iter().zip(ANCHOR_DISCRIMINATORS) {
+        assert_eq!(
+            sighash(&format!("global:{name}")),
+            discriminator,
+            "Discriminator of {} should be its Anchor sighash",
+            name
+        );
+    }
+    assert_eq!(sighash("account:UserAccount"), USER_ACCOUNT_DISCRIMINATOR);
+    assert_eq!(sighash("account:Config"), CONFIG_DISCRIMINATOR);
+}
+
+#[test]
+fn test_instruction_encoding() {
+    let instruction = ProgramInstruction::DepositWithDelay {
+        amount: 5,
+        withdraw_delay: -1,
+    };
+    let mut expected = vec![5];
+    expected.extend_from_slice(&5u64.to_le_bytes());
+    expected.extend_from_slice(&(-1i64).to_le_bytes());
+    assert_eq!(instruction.pack(), expected);
+    assert_eq!(
+        ProgramInstruction::unpack(&expected),
+        Some(instruction.clone())
+    );
+
+    // The Anchor encoding swaps the variant index for the discriminator
+    let anchor_data = instruction.pack_anchor();
+    assert_eq!(anchor_data[..8], ANCHOR_DISCRIMINATORS[5]);
+    assert_eq!(anchor_data[8..], expected[1..]);
+
+    // Trailing bytes are rejected
+    expected.push(0);
+    assert_eq!(ProgramInstruction::unpack(&expected), None);
+}
+
+#[test]
+fn test_builders() {
+    let program_id = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let (user_account_pda, _) = find_user_account_address(&user, &program_id);
+    let (vault_pda, _) = find_vault_address(&user, &program_id);
+
+    let deposit = instruction::deposit(&program_id, &user, 7);
+    assert_eq!(deposit.program_id, program_id);
+    assert_eq!(deposit.accounts.len(), 6);
+    assert!(deposit.accounts[0].is_signer && deposit.accounts[0].pubkey == user);
+    assert_eq!(deposit.accounts[1].pubkey, user_account_pda);
+    assert_eq!(deposit.accounts[2].pubkey, vault_pda);
+    assert_eq!(
+        ProgramInstruction::unpack(&deposit.data),
+        Some(ProgramInstruction::Deposit { amount: 7 })
+    );
+
+    // A sponsored deposit targets the beneficiary's accounts, only the funder signs
+    let funder = Pubkey::new_unique();
+    let deposit_for = instruction::deposit_for(&program_id, &funder, &user, 7);
+    assert_eq!(deposit_for.accounts[0].pubkey, funder);
+    assert_eq!(deposit_for.accounts[1].pubkey, user_account_pda);
+    assert_eq!(deposit_for.accounts[2].pubkey, vault_pda);
+    assert_eq!(
+        deposit_for
+            .accounts
+            .iter()
+            .filter(|account| account.is_signer)
+            .count(),
+        1
+    );
+
+    // The delegate signs in place of the user
+    let authority = Pubkey::new_unique();
+    let delegate_withdraw = instruction::delegate_withdraw(&program_id, &authority, &user, 7);
+    assert!(delegate_withdraw.accounts[0].is_signer);
+    assert_eq!(delegate_withdraw.accounts[0].pubkey, authority);
+    assert!(!delegate_withdraw.accounts[1].is_signer);
+    assert_eq!(delegate_withdraw.accounts[2].pubkey, user_account_pda);
+
+    // The stake account hangs off the vault, which pays into it and signs for it
+    let vote_account = Pubkey::new_unique();
+    let (stake_pda, _) = find_stake_address(&user, &program_id);
+    assert_eq!(
+        stake_pda,
+        Pubkey::find_program_address(&[b"stake", vault_pda.as_ref()], &program_id).0
+    );
+    assert_eq!(STAKE_PROGRAM_ID, stake::program::id());
+    let delegate_stake = instruction::delegate_stake(&program_id, &user, &vote_account, 7);
+    assert!(
+        delegate_stake.accounts[2].is_writable && delegate_stake.accounts[2].pubkey == vault_pda
+    );
+    assert!(
+        delegate_stake.accounts[3].is_writable && delegate_stake.accounts[3].pubkey == stake_pda
+    );
+    assert_eq!(delegate_stake.accounts[4].pubkey, vote_account);
+    assert_eq!(
+        delegate_stake.accounts[11].pubkey,
+        find_config_address(&program_id).0
+    );
+    assert_eq!(
+        ProgramInstruction::unpack(&delegate_stake.data),
+        Some(ProgramInstruction::DelegateStake { amount: 7 })
+    );
+    for instruction in [
+        instruction::deactivate_stake(&program_id, &user),
+        instruction::withdraw_stake(&program_id, &user),
+    ] {
+        assert_eq!(instruction.accounts[3].pubkey, stake_pda);
+        assert_eq!(
+            instruction.accounts.last().unwrap().pubkey,
+            STAKE_PROGRAM_ID
+        );
+    }
+
+    // Approvers go after the listed accounts
+    let approver = Pubkey::new_unique();
+    let withdraw =
+        instruction::with_signers(instruction::withdraw(&program_id, &user, 7), &[approver]);
+    assert_eq!(withdraw.accounts.len(), 7);
+    assert!(withdraw.accounts[6].is_signer && withdraw.accounts[6].pubkey == approver);
+}
+
+#[test]
+fn test_user_account_layouts() {
+    // Current accounts in every implementation
+    let current = UserAccount {
+        total_deposited: 5,
+        total_withdrawn: 2,
+        deposit_count: 3,
+        last_deposit_slot: 40,
+        last_withdraw_slot: 41,
+        ..user_account(USER_ACCOUNT_VERSION)
+    };
+    let data = current.pack();
+    assert_eq!(data[..8], USER_ACCOUNT_DISCRIMINATOR);
+    assert_eq!(data.len(), 8 + UserAccount::SIZE + 32);
+    assert_eq!(UserAccount::unpack(&data).unwrap(), current);
+
+    // The totals follow the multisig members
+    let totals = data.len() - 40;
+    assert_eq!(data[totals - 32..totals], current.signers[0].to_bytes());
+    assert_eq!(data[totals..totals + 8], 5u64.to_le_bytes());
+    assert!(!current.is_baseline());
+
+    // Baseline accounts hold only the user and the bumps, without the discriminator in
+    // the native and pinocchio programs
+    let baseline = UserAccount {
+        withdraw_delay: 0,
+        withdraw_allowance: 0,
+        threshold: 0,
+        signers: vec![],
+        ..user_account(BASELINE_USER_ACCOUNT_VERSION)
+    };
+    let data = baseline.pack();
+    assert_eq!(data.len(), 32 + 1 + 1 + 1);
+    assert_eq!(UserAccount::unpack(&data).unwrap(), baseline);
+    assert!(baseline.is_baseline());
+
+    // Baseline Anchor accounts do have the discriminator
+    let mut anchor_data = USER_ACCOUNT_DISCRIMINATOR.to_vec();
+    anchor_data.extend(&data);
+    assert_eq!(UserAccount::unpack(&anchor_data).unwrap(), baseline);
+
+    // Current fields without the discriminator are not a known layout
+    assert!(UserAccount::unpack(&borsh::to_vec(&current).unwrap()).is_err());
+    assert!(UserAccount::unpack(&USER_ACCOUNT_DISCRIMINATOR).is_err());
+    let mut truncated = current.pack();
+    truncated.truncate(truncated.len() - 40);
+    assert!(UserAccount::unpack(&truncated).is_err());
+    let unknown = user_account(USER_ACCOUNT_VERSION + 1);
+    assert!(UserAccount::unpack(&unknown.pack()).is_err());
+}
+
+#[test]
+fn test_config_layouts() {
+    let config = Config {
+        admin: Pubkey::new_unique(),
+        paused: true,
+        withdrawals_paused: false,
+        bump: 255,
+        treasury_bump: 254,
+        deposit_fee_bps: 25,
+        withdraw_fee_bps: 50,
+    };
+    let fields = borsh::to_vec(&config).unwrap();
+    assert_eq!(fields.len(), Config::SIZE);
+
+    let mut data = CONFIG_DISCRIMINATOR.to_vec();
+    data.extend(&fields);
+    assert_eq!(data.len(), Config::LEN);
+    assert_eq!(Config::unpack(&data).unwrap(), config);
+
+    // Configs without the discriminator are rejected
+    assert!(Config::unpack(&fields).is_err());
+}
+
+#[test]
+fn test_error_codes() {
+    // The codes are contiguous from 6000, as Anchor numbers them
+    let mut code = 6000;
+    while let Some(error) = VaultError::from_code(code) {
+        assert_eq!(error.code(), code);
+        code += 1;
+    }
+    assert_eq!(
+        VaultError::from_code(code - 1),
+        Some(VaultError::StakeAccountInUse)
+    );
+    assert_eq!(VaultError::from_code(5999), None);
+    assert_eq!(
+        VaultError::DeserializationFailed.to_string(),
+        "DeserializationFailed (6024)"
+    );
+}
+
+#[test]
+fn test_decode_events() {
+    let program_id = Pubkey::new_unique();
+    let other_program_id = Pubkey::new_unique();
+    let event = DepositEvent {
+        user: Pubkey::new_unique(),
+        vault: Pubkey::new_unique(),
+        amount: 1_000,
+        fee: 10,
+        vault_balance: 990,
+        slot: 42,
+    };
+    let mut data = sighash("event:DepositEvent").to_vec();
+    data.extend(
+        borsh::to_vec(&(
+            event.user,
+            event.vault,
+            event.amount,
+            event.fee,
+            event.vault_balance,
+            event.slot,
+        ))
+        .unwrap(),
+    );
+    let logged = format!("Program data: {}", STANDARD.encode(&data));
+
+    // Only data logged by the vault program itself is decoded, not that of the
+    // programs it invokes
+    let logs = [
+        format!("Program {} invoke [1]", program_id),
+        format!("Program {} invoke [2]", other_program_id),
+        logged.clone(),
+        format!("Program {} success", other_program_id),
+        logged,
+        "Program data: bm90IGFuIGV2ZW50".to_string(),
+        format!("Program {} success", program_id),
+    ];
+    assert_eq!(
+        decode_events(&program_id, &logs),
+        vec![VaultEvent::Deposit(event)]
+    );
+    assert!(decode_events(&Pubkey::new_unique(), &logs).is_empty());
+}